@@ -0,0 +1,69 @@
+use std::io::{stdout, BufWriter, Write};
+use anyhow::Result;
+use crate::audio::Detector;
+use crate::input::AudioSource;
+use crate::replay::WavSource;
+
+// Run a recording through the detector as fast as it decodes and write one CSV row per block to stdout, showing
+// where the tone would (or wouldn't) have been detected. Nothing is sent to the sinks.
+pub(crate) fn analyze(path: &str, detector: &mut Detector) -> Result<()> {
+    write_analysis(path, detector, &mut BufWriter::new(stdout().lock()))
+}
+
+fn write_analysis(path: &str, detector: &mut Detector, out: &mut impl Write) -> Result<()> {
+    let mut source = WavSource::open(path, false)?;
+    let unit = detector.config().power_unit;
+    detector.record_blocks();
+    detector.start_call();
+
+    writeln!(out, "offset_s,level_{},snr_db,peak_hz,tone", unit.name())?;
+    while let Some(samples) = source.next_samples()? {
+        detector.process(&samples);
+        for report in detector.take_reports() {
+            writeln!(
+                out,
+                "{:.3},{},{:.1},{},{}",
+                report.offset.as_secs_f32(),
+                report.level,
+                report.snr_db,
+                report.frequency.map(|f| f.to_string()).unwrap_or_default(),
+                report.tone as u8
+            )?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::Arc;
+    use crate::config::test_config;
+    use crate::wav::encode_wav;
+
+    // A row per block, the tone marked only in the blocks that end within it.
+    #[test]
+    fn writes_a_row_per_block() {
+        let mut recording = vec![0; 4096];
+        let tone = (0..4096).map(|i| (1000.0 * (2.0 * std::f32::consts::PI * 1665.0 * i as f32 / 8000.0).sin()) as i16);
+        recording.extend(tone);
+        recording.extend(vec![0; 4096]);
+        let path = std::env::temp_dir().join(format!("alarm-modem-{}-analyze.wav", std::process::id()));
+        fs::write(&path, encode_wav(&recording, 8000)).unwrap();
+        let config = test_config(&[("ALARM_TONE_MIN_POWER", "1e6"), ("ALARM_TONE_MAX_POWER", "1e20")]).unwrap();
+        let mut detector = Detector::new(Arc::new(config)).unwrap();
+        let mut csv = Vec::new();
+        write_analysis(path.to_str().unwrap(), &mut detector, &mut csv).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("offset_s,level_power,snr_db,peak_hz,tone"));
+        let rows: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows.len(), 12);
+        let toned: Vec<&str> = rows.iter().filter(|row| row[4] == "1").map(|row| row[0]).collect();
+        assert_eq!(toned, ["0.640", "0.768", "0.896", "1.024"]);
+    }
+}
@@ -0,0 +1,121 @@
+use std::io::ErrorKind;
+use std::sync::atomic::Ordering;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serialport::SerialPort;
+use crate::dle::{DleDecoder, DleEvent};
+use crate::trace::trace_serial;
+use crate::{READ_TIMEOUT, SHUTDOWN};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PinStatus {
+    Pending,
+    Accepted,
+    Rejected
+}
+
+// Collects DTMF digits from the caller, checking each entry once it reaches the PIN length.
+// `*` clears a partial entry, and the gate rejects once the allowed attempts are used up.
+pub(crate) struct PinGate {
+    pin: String,
+    entry: String,
+    attempts_left: u32,
+    status: PinStatus
+}
+
+impl PinGate {
+    pub fn new(pin: &str, attempts: u32) -> Self {
+        PinGate { pin: pin.to_string(), entry: String::new(), attempts_left: attempts.max(1), status: PinStatus::Pending }
+    }
+
+    pub fn feed(&mut self, digit: char) -> PinStatus {
+        if self.status != PinStatus::Pending {
+            return self.status;
+        }
+        if digit == '*' {
+            self.entry.clear();
+            return self.status;
+        }
+
+        self.entry.push(digit);
+        if self.entry.len() < self.pin.len() {
+            return self.status;
+        }
+
+        if self.entry == self.pin {
+            self.status = PinStatus::Accepted;
+        } else {
+            self.attempts_left -= 1;
+            warn!("Incorrect PIN entered, {} attempts left", self.attempts_left);
+            if self.attempts_left == 0 {
+                self.status = PinStatus::Rejected;
+            }
+        }
+        self.entry.clear();
+        self.status
+    }
+}
+
+// Read the voice stream until the caller enters the PIN, exhausts their attempts or runs out of time.
+pub(crate) fn authenticate(port: &mut dyn SerialPort, pin: &str, attempts: u32, timeout: Duration) -> Result<bool> {
+    info!("Waiting for PIN");
+    let mut gate = PinGate::new(pin, attempts);
+    // Only the DTMF events are used here, so flow control bytes left in the audio don't matter.
+    let mut dle = DleDecoder::new(false);
+    let mut audio = Vec::new();
+    let start = Instant::now();
+
+    while start.elapsed() < timeout && !SHUTDOWN.load(Ordering::Relaxed) {
+        let mut buffer = vec![0; 1024];
+        match port.read(&mut buffer) {
+            Ok(n) => {
+                trace_serial("Read", &buffer[..n]);
+                audio.clear();
+                for event in dle.feed(&buffer[..n], &mut audio) {
+                    let DleEvent::Dtmf(digit) = event else {
+                        continue;
+                    };
+                    match gate.feed(digit) {
+                        PinStatus::Pending => {}
+                        PinStatus::Accepted => {
+                            info!("PIN accepted");
+                            return Ok(true);
+                        },
+                        PinStatus::Rejected => return Ok(false)
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == ErrorKind::TimedOut => sleep(READ_TIMEOUT),
+            Err(e) => return Err(anyhow!(e))
+        }
+    }
+
+    warn!("No valid PIN within {:#?}", timeout);
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dle::DLE;
+    use crate::mock_port::MockPort;
+
+    // Digits as the modem reports them in voice mode, each shielded by a DLE among the audio.
+    fn keyed(digits: &str) -> MockPort {
+        MockPort::with_reads(digits.bytes().map(|digit| vec![0x80, 0x80, DLE, digit]))
+    }
+
+    #[test]
+    fn accepts_the_correct_pin() {
+        assert!(authenticate(&mut keyed("12*1234"), "1234", 1, Duration::from_secs(1)).unwrap());
+    }
+
+    #[test]
+    fn rejects_once_the_attempts_are_used_up() {
+        assert!(!authenticate(&mut keyed("999988881234"), "1234", 2, Duration::from_secs(1)).unwrap());
+        // A wrong entry with attempts to spare still lets the right one through.
+        assert!(authenticate(&mut keyed("99991234"), "1234", 2, Duration::from_secs(1)).unwrap());
+    }
+}
@@ -1,103 +1,3500 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::thread::sleep;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use anyhow::anyhow;
-use log::debug;
-use rustfft::FftPlanner;
-use rustfft::num_complex::Complex;
+use log::{debug, error, info, log_enabled, warn, Level};
+use realfft::{RealFftPlanner, RealToComplex};
+use realfft::num_complex::Complex;
 use serialport::SerialPort;
 use anyhow::Result;
-use crate::READ_TIMEOUT;
+use crate::cadence::{CadenceDetector, CallProgress};
+use crate::chipset::vgr_compensation;
+use crate::cid::{normalize_number, parse_cid, parse_fsk_cid, CallerId};
+use crate::config::{AgcSettings, Config, ControlLine, DetectMode, FrequencyBand, InputFilter, LineGate, PowerUnit, RunMode, ToneDetector, ToneProfile};
+use crate::contact_id::{self, ContactIdEvent};
+use crate::dle::{DleDecoder, DleEvent};
+use crate::dtmf::DtmfDecoder;
+use crate::fsk::FskDemodulator;
+use crate::pipe::PcmPipe;
+use crate::raw::RawCapture;
+use crate::reload;
+use crate::response::{LineStatus, ResultCodeFilter};
+use crate::sequence::SequenceMatcher;
+use crate::sink::{dispatch, DetectionSink};
+use crate::snapshot::SpectrumSnapshot;
+use crate::spectrum::{format_peaks, top_peaks};
+use crate::state::StateFile;
+use crate::stats::SessionStats;
+use crate::telemetry::Span;
+use crate::template::ToneTemplate;
+use crate::trace::trace_serial;
+use crate::vad::{Vad, VadEvent};
+use crate::watchdog::Watchdog;
+use crate::wav::encode_wav;
+use crate::webhook::AlertPayload;
+use crate::SHUTDOWN;
 
 // The target tone is average 1665 Hz, 150 power
 
-const FFT_SAMPLE_SIZE: usize = 1024;  // Buffer size for FFT
-const HIGH_PASS_CUTOFF: f32 = 3000.0; // High pass filter cut off
-const TONE_MIN_FREQ: f32 = 1640.0;    // Minimum frequency (Hz) for tones
-const TONE_MAX_FREQ: f32 = 1720.0;    // Maximum frequency (Hz) for tones
+pub(crate) const FFT_SAMPLE_SIZE: usize = 1024; // Buffer size for FFT
 const TONE_MIN_POWER: f32 = 100.0;    // Minimum power for a tone
 const TONE_MAX_POWER: f32 = 300.0;    // Maximum power for a tone
-const DETECTION_INTERVAL: Duration = Duration::from_secs(5);
+const MIN_NOISE_POWER: f32 = 1.0;     // One LSB squared, below any real noise floor, so SNR never divides by zero
+const MIN_DBFS: f32 = -120.0;         // Reported for digital silence rather than negative infinity
+pub(crate) const CALL_PROGRESS_FREQ: f32 = 440.0; // Ringback/busy tones all include energy near 440 Hz
+const CALL_PROGRESS_MIN_POWER: f32 = 100.0; // Minimum Goertzel power for the progress tone
+pub(crate) const SAMPLE_RATE: f32 = 8000.0;
+const MAX_FFT_LEN: usize = 65536; // Largest transform, zero padding included
+const DECIMATION_TAPS_PER_FACTOR: usize = 16; // FIR length grows with the factor to keep the transition band narrow
 
-fn high_pass_filter(samples: &mut [i16], cutoff: f32) {
+const FORMAT_CHECK_BYTES: usize = 4096; // Audio looked at before trusting the configured PCM format
+const FORMAT_MAX_DC: f32 = 0.5;          // Mean, as a fraction of full scale, beyond which audio is implausible
+const FORMAT_MAX_RMS: f32 = 0.7;         // Level, as a fraction of full scale, beyond which audio is implausible
+pub(crate) const NO_CARRIER: &[u8] = b"NO CARRIER";
+const CONTACT_ID_DIGITS: usize = 16;
+const CID_WINDOW: usize = 64; // Bytes kept across reads when looking for a caller ID line
+
+// Convert to i16, clamping out-of-range values instead of wrapping, and report whether it clipped.
+fn saturate_i16(value: f32) -> (i16, bool) {
+    let clamped = value.clamp(i16::MIN as f32, i16::MAX as f32);
+    (clamped as i16, clamped != value)
+}
+
+// Returns the number of samples that clipped during filtering.
+pub fn high_pass_filter(samples: &mut [i16], cutoff: f32) -> usize {
     let rc = 1.0 / (cutoff * 2.0 * std::f32::consts::PI);
-    let dt = 1.0 / 8000.0;
+    let dt = 1.0 / SAMPLE_RATE;
     let alpha = dt / (rc + dt);
 
-    let mut previous = samples[0] as f32;
+    // A read can hold nothing but DLE events, leaving no samples at all.
+    let Some(&first) = samples.first() else {
+        return 0;
+    };
+    let mut previous = first as f32;
+    let mut clipped = 0;
     for sample in samples.iter_mut() {
         let filtered = alpha * ((*sample as f32) - previous);
         previous = *sample as f32;
-        *sample = filtered as i16;
+
+        let (value, clip) = saturate_i16(filtered);
+        *sample = value;
+        clipped += clip as usize;
+    }
+    clipped
+}
+
+// Second order band-pass (the RBJ cookbook's, 0 dB at the centre) with the band's edges at -3 dB, its state
+// carried across blocks so the stream is filtered as one.
+struct BandPass {
+    band: FrequencyBand,
+    coefficients: (f32, f32, f32), // b0 (b2 being -b0), a1, a2, normalised by a0
+    inputs: (f32, f32),
+    outputs: (f32, f32)
+}
+
+impl BandPass {
+    fn new(band: FrequencyBand) -> Self {
+        let centre = (band.min * band.max).sqrt();
+        let w0 = 2.0 * std::f32::consts::PI * centre / SAMPLE_RATE;
+        let alpha = w0.sin() / (2.0 * centre / (band.max - band.min));
+        let a0 = 1.0 + alpha;
+        BandPass {
+            band,
+            coefficients: (alpha / a0, -2.0 * w0.cos() / a0, (1.0 - alpha) / a0),
+            inputs: (0.0, 0.0),
+            outputs: (0.0, 0.0)
+        }
+    }
+
+    // Returns the number of samples that clipped during filtering.
+    fn process(&mut self, samples: &mut [i16]) -> usize {
+        let (b0, a1, a2) = self.coefficients;
+        let mut clipped = 0;
+        for sample in samples.iter_mut() {
+            let x = *sample as f32;
+            let y = b0 * (x - self.inputs.1) - a1 * self.outputs.0 - a2 * self.outputs.1;
+            self.inputs = (x, self.inputs.0);
+            self.outputs = (y, self.outputs.0);
+
+            let (value, clip) = saturate_i16(y);
+            *sample = value;
+            clipped += clip as usize;
+        }
+        clipped
+    }
+}
+
+// First-order pre-emphasis `y[n] = x[n] - a*x[n-1]`, boosting high frequencies over low rumble.
+// `state` carries the last input sample across blocks.
+fn pre_emphasis(samples: &mut [f32], a: f32, state: &mut f32) {
+    for sample in samples.iter_mut() {
+        let input = *sample;
+        *sample = input - a * *state;
+        *state = input;
+    }
+}
+
+// Windowed-sinc (Hamming) low-pass taps, `cutoff` as a fraction of the input sample rate, normalised to unity gain.
+fn low_pass_taps(len: usize, cutoff: f32) -> Vec<f32> {
+    let middle = (len / 2) as f32;
+    let mut taps: Vec<f32> = (0..len).map(|i| {
+        let x = i as f32 - middle;
+        let sinc = if x == 0.0 {
+            2.0 * cutoff
+        } else {
+            (2.0 * std::f32::consts::PI * cutoff * x).sin() / (std::f32::consts::PI * x)
+        };
+        let window = 0.54 - 0.46 * (2.0 * std::f32::consts::PI * i as f32 / (len - 1) as f32).cos();
+        sinc * window
+    }).collect();
+
+    let sum: f32 = taps.iter().sum();
+    taps.iter_mut().for_each(|t| *t /= sum);
+    taps
+}
+
+// Resample from any rate to another by linear interpolation, low-passing below the output Nyquist first when
+// going down so a 44.1 or 48 kHz recording can't alias into the tone bands.
+pub(crate) fn resample(samples: &[i16], from: u32, to: u32) -> Vec<i16> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let ratio = from as f64 / to as f64;
+    let filtered = anti_alias(samples, ratio);
+
+    let len = (samples.len() as f64 / ratio).floor() as usize;
+    (0..len).map(|i| {
+        let position = i as f64 * ratio;
+        let index = position as usize;
+        let fraction = (position - index as f64) as f32;
+        let next = filtered.get(index + 1).copied().unwrap_or(filtered[index]);
+        saturate_i16(filtered[index] + (next - filtered[index]) * fraction).0
+    }).collect()
+}
+
+// Low-pass below the output Nyquist when going down by `ratio`, so nothing above it aliases into the tone bands.
+fn anti_alias(samples: &[i16], ratio: f64) -> Vec<f32> {
+    if ratio <= 1.0 {
+        return samples.iter().map(|&s| s as f32).collect();
+    }
+    let taps = low_pass_taps(DECIMATION_TAPS_PER_FACTOR * ratio.ceil() as usize + 1, 0.4 / ratio as f32);
+    let half = taps.len() / 2;
+    (0..samples.len()).map(|i| {
+        taps.iter().enumerate()
+            .filter_map(|(k, &tap)| (i + k).checked_sub(half).and_then(|j| samples.get(j)).map(|&s| tap * s as f32))
+            .sum()
+    }).collect()
+}
+
+// Downsample by an integer factor, low-passing below the new Nyquist first so higher frequencies can't alias into the
+// tone bands. The end of each block is kept as the filter's history, along with where in the factor the next output
+// falls, so the filter runs on unbroken across reads as if the stream had come in one piece.
+#[derive(Debug, Clone)]
+pub(crate) struct Decimator {
+    factor: usize,
+    taps: Vec<f32>,
+    history: Vec<f32>, // The last taps.len() - 1 input samples, then the block being filtered
+    phase: usize       // Input samples to skip before the next output
+}
+
+impl Decimator {
+    pub fn new(factor: usize) -> Self {
+        // Cut off a little under the output Nyquist (0.5 / factor) to leave room for the filter's roll-off.
+        let taps = if factor > 1 {
+            low_pass_taps(DECIMATION_TAPS_PER_FACTOR * factor + 1, 0.4 / factor as f32)
+        } else {
+            vec![1.0]
+        };
+        Decimator { factor: factor.max(1), history: vec![0.0; taps.len() - 1], taps, phase: 0 }
+    }
+
+    pub fn process(&mut self, samples: &[i16]) -> Vec<i16> {
+        if self.factor == 1 {
+            return samples.to_vec();
+        }
+        let len = self.taps.len();
+        self.history.extend(samples.iter().map(|&s| s as f32));
+        let output: Vec<i16> = (self.phase..samples.len()).step_by(self.factor).map(|i| {
+            let acc: f32 = self.history[i..i + len].iter().zip(&self.taps).map(|(s, tap)| s * tap).sum();
+            saturate_i16(acc).0
+        }).collect();
+        self.phase = (self.phase + output.len() * self.factor).saturating_sub(samples.len());
+        self.history.drain(..samples.len());
+        output
+    }
+}
+
+// The low-pass `anti_alias` applies, for a stream arriving a read at a time. As with the decimator the end of each
+// read is kept as the filter's history, so reads filter on unbroken rather than each starting again from silence.
+#[derive(Debug, Clone)]
+struct AntiAliasFilter {
+    taps: Vec<f32>,
+    history: Vec<f32> // The last taps.len() - 1 input samples, then the read being filtered
+}
+
+impl AntiAliasFilter {
+    fn new(ratio: f64) -> Self {
+        let taps = if ratio > 1.0 {
+            low_pass_taps(DECIMATION_TAPS_PER_FACTOR * ratio.ceil() as usize + 1, 0.4 / ratio as f32)
+        } else {
+            vec![1.0]
+        };
+        AntiAliasFilter { history: vec![0.0; taps.len() - 1], taps }
+    }
+
+    fn process(&mut self, samples: &[i16]) -> Vec<f32> {
+        let len = self.taps.len();
+        self.history.extend(samples.iter().map(|&s| s as f32));
+        let output = (0..samples.len())
+            .map(|i| self.history[i..i + len].iter().zip(&self.taps).map(|(s, tap)| s * tap).sum())
+            .collect();
+        self.history.drain(..samples.len());
+        output
+    }
+}
+
+// Cached FFT plan with its input and scratch buffers, reused for every block rather than reallocated per read.
+// Always transforms a fixed power-of-two length, zero-padding (or truncating) the input to fit, so a short or odd
+// sized block can never hand the planner a slow mixed-radix size. The input is real, so a real-to-complex
+// transform gives the N/2+1 non-redundant bins for half the work of a complex one.
+struct Spectrum {
+    fft: Arc<dyn RealToComplex<f32>>,
+    gain: f32, // Applied to the samples so thresholds hold across receive gain settings
+    window_fn: WindowFn,
+    window: Vec<f32>, // For a full block, with the input gain folded in
+    input: Vec<f32>,
+    output: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>
+}
+
+impl Spectrum {
+    // Refuses sizes that would make a slow or enormous plan rather than attempting them.
+    fn new(zero_pad: usize, gain: f32, window_fn: WindowFn) -> Result<Self> {
+        let fft_len = FFT_SAMPLE_SIZE.saturating_mul(zero_pad);
+        if !fft_len.is_power_of_two() || !(FFT_SAMPLE_SIZE..=MAX_FFT_LEN).contains(&fft_len) {
+            return Err(anyhow!(
+                "FFT size {} x {} samples is invalid, ALARM_FFT_ZERO_PAD must be a power of two up to {}",
+                zero_pad, FFT_SAMPLE_SIZE, MAX_FFT_LEN / FFT_SAMPLE_SIZE
+            ));
+        }
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(fft_len);
+        let window = window_fn.scaled(FFT_SAMPLE_SIZE, gain);
+        Ok(Spectrum {
+            input: fft.make_input_vec(),
+            output: fft.make_output_vec(),
+            scratch: fft.make_scratch_vec(),
+            fft,
+            gain,
+            window_fn,
+            window
+        })
+    }
+
+    // Bins 0 to N/2, with the zero-padding past the samples giving finer bin spacing.
+    fn transform(&mut self, samples: &[i16]) -> &[Complex<f32>] {
+        // The transform uses the input as scratch space, so it's refilled in full every block.
+        let len = samples.len().min(self.input.len());
+        // A partial block judged early gets a window of its own length, scaled up to a full block's bin levels.
+        let partial;
+        let window = if len == self.window.len() {
+            &self.window
+        } else {
+            partial = self.window_fn.scaled(len, self.gain * block_scale(len).sqrt());
+            &partial
+        };
+        for ((slot, &sample), coefficient) in self.input.iter_mut().zip(&samples[..len]).zip(window) {
+            *slot = sample as f32 * coefficient;
+        }
+        self.input[len..].fill(0.0);
+        if let Err(e) = self.fft.process_with_scratch(&mut self.input, &mut self.output, &mut self.scratch) {
+            // Only possible with mismatched buffer lengths, which are all sized from the plan.
+            warn!("FFT failed: {}", e);
+        }
+        &self.output
+    }
+}
+
+// Taper applied to each block before the transform. A rectangular window (none) leaks a tone's energy into bins far
+// from it, the others trade a wider main lobe for much lower leakage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WindowFn {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman
+}
+
+impl FromStr for WindowFn {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "rectangular" | "none" => Ok(WindowFn::Rectangular),
+            "hann" => Ok(WindowFn::Hann),
+            "hamming" => Ok(WindowFn::Hamming),
+            "blackman" => Ok(WindowFn::Blackman),
+            _ => Err(anyhow!("Unknown window {}, expected rectangular, hann, hamming or blackman", s))
+        }
+    }
+}
+
+impl WindowFn {
+    // Divided by the window's coherent gain (its mean), so a tone on a bin keeps the level it had without a window
+    // and the power thresholds mean the same whichever is chosen.
+    fn coefficients(&self, len: usize) -> Vec<f32> {
+        let span = len.saturating_sub(1).max(1) as f32;
+        let phase = |i: usize, harmonic: f32| (harmonic * std::f32::consts::PI * i as f32 / span).cos();
+        let raw: Vec<f32> = (0..len).map(|i| match self {
+            WindowFn::Rectangular => 1.0,
+            WindowFn::Hann => 0.5 - 0.5 * phase(i, 2.0),
+            WindowFn::Hamming => 0.54 - 0.46 * phase(i, 2.0),
+            WindowFn::Blackman => 0.42 - 0.5 * phase(i, 2.0) + 0.08 * phase(i, 4.0)
+        }).collect();
+        let coherent_gain = raw.iter().sum::<f32>() / len.max(1) as f32;
+        raw.into_iter().map(|coefficient| coefficient / coherent_gain).collect()
+    }
+
+    // The coefficients with the input gain folded in, ready to multiply the samples by.
+    fn scaled(&self, len: usize, gain: f32) -> Vec<f32> {
+        self.coefficients(len).into_iter().map(|coefficient| coefficient * gain).collect()
+    }
+}
+
+// Moving average of the power spectrum over the last few blocks. Random noise averages towards its mean while a steady
+// tone holds its level, so the tone stands further clear of the noise at the cost of a few blocks of latency.
+// The averaged spectrum carries each bin's mean power as a real magnitude, which is all detection looks at.
+struct SpectralAverage {
+    blocks: usize,
+    history: VecDeque<Vec<f32>>,
+    output: Vec<Complex<f32>>
+}
+
+impl SpectralAverage {
+    fn new(blocks: usize) -> Self {
+        SpectralAverage { blocks, history: VecDeque::with_capacity(blocks), output: Vec::new() }
+    }
+
+    fn update(&mut self, fft_output: &[Complex<f32>]) -> &[Complex<f32>] {
+        // A different transform size (zero padding reloaded) starts the average over.
+        if self.history.front().is_some_and(|powers| powers.len() != fft_output.len()) {
+            self.history.clear();
+        }
+        if self.history.len() == self.blocks {
+            self.history.pop_front();
+        }
+        self.history.push_back(fft_output.iter().map(|bin| bin.norm_sqr()).collect());
+
+        // Summed afresh each block rather than kept as a running total, which would drift over a long call.
+        let count = self.history.len() as f32;
+        self.output = (0..fft_output.len())
+            .map(|i| Complex::new((self.history.iter().map(|powers| powers[i]).sum::<f32>() / count).sqrt(), 0.0))
+            .collect();
+        &self.output
+    }
+
+    fn reset(&mut self) {
+        self.history.clear();
+    }
+}
+
+// Frequency spacing of the bins in a real transform's N/2+1 outputs.
+pub(crate) fn bin_width(fft_output: &[Complex<f32>]) -> f32 {
+    SAMPLE_RATE / ((fft_output.len() - 1) * 2) as f32
+}
+
+// The built-in tone band, with its thresholds expressed in the configured unit.
+fn default_tone(config: &Config) -> ToneProfile {
+    let unit = config.power_unit;
+    ToneProfile {
+        band: FrequencyBand { min: config.tone_min_freq, max: config.tone_max_freq },
+        min_power: config.tone_min_power.unwrap_or(unit.level(TONE_MIN_POWER)),
+        max_power: config.tone_max_power.unwrap_or(unit.level(TONE_MAX_POWER))
+    }
+}
+
+// Band the noise floor is measured within. The band-pass leaves little beyond its own passband, so bins past it would
+// put the floor well below the noise the tone competes with.
+fn floor_band(config: &Config) -> Option<FrequencyBand> {
+    (config.filter == InputFilter::BandPass).then_some(config.band_pass)
+}
+
+// Peak in-band bin power and mean power of the bins outside the band, within `floor_band` when given.
+fn band_peak_and_floor(fft_output: &[Complex<f32>], band: &FrequencyBand, floor_band: Option<FrequencyBand>) -> (f32, f32) {
+    let bin_width = bin_width(fft_output);
+    let (mut peak, mut floor_sum, mut floor_bins) = (0.0f32, 0.0f32, 0);
+    for (i, sample) in fft_output.iter().enumerate() {
+        let power = sample.re.powi(2) + sample.im.powi(2);
+        let frequency = i as f32 * bin_width;
+        if band.contains(frequency) {
+            peak = peak.max(power);
+        } else if floor_band.is_none_or(|floor_band| floor_band.contains(frequency)) {
+            floor_sum += power;
+            floor_bins += 1;
+        }
+    }
+    (peak, floor_sum / floor_bins.max(1) as f32)
+}
+
+// Whether any harmonic of the fundamental, within `tolerance` Hz and below Nyquist, carries at least `min_ratio`
+// of the fundamental's power. A tone through the modem path picks up harmonics, a clean narrowband interferer doesn't.
+fn has_harmonic(fft_output: &[Complex<f32>], fundamental: f32, tolerance: f32, min_ratio: f32) -> bool {
+    let bin_width = bin_width(fft_output);
+    let power = |i: usize| fft_output.get(i).map_or(0.0, |sample| sample.re.powi(2) + sample.im.powi(2));
+    let fundamental_power = power((fundamental / bin_width).round() as usize);
+    let nyquist = SAMPLE_RATE / 2.0;
+
+    (2..).map(|k| k as f32 * fundamental)
+        .take_while(|&harmonic| harmonic - tolerance < nyquist)
+        .any(|harmonic| {
+            let first = ((harmonic - tolerance) / bin_width).floor() as usize;
+            let last = ((harmonic + tolerance) / bin_width).ceil() as usize;
+            (first..=last).map(power).fold(0.0, f32::max) >= min_ratio * fundamental_power
+        })
+}
+
+// Level relative to full scale of an RMS or peak sample magnitude.
+fn dbfs(magnitude: f32, full_scale: f32) -> f32 {
+    (20.0 * (magnitude / full_scale).log10()).max(MIN_DBFS)
+}
+
+// RMS level about the mean, so a DC offset on the line doesn't count as signal.
+fn rms_dbfs(samples: &[i16], full_scale: f32) -> f32 {
+    if samples.is_empty() {
+        return MIN_DBFS;
+    }
+    let count = samples.len() as f64;
+    let mean = samples.iter().map(|&s| s as f64).sum::<f64>() / count;
+    let variance = samples.iter().map(|&s| (s as f64 - mean).powi(2)).sum::<f64>() / count;
+    dbfs(variance.sqrt() as f32, full_scale)
+}
+
+// Signal to noise ratio in dB. The floor is clamped so a silent line gives a large but finite margin, never inf or NaN.
+fn snr_db(signal: f32, noise: f32) -> f32 {
+    let ratio = signal.max(0.0) / noise.max(MIN_NOISE_POWER);
+    10.0 * ratio.max(f32::MIN_POSITIVE).log10()
+}
+
+// Running average of the out-of-band power, updated every block so the floor follows changing line noise.
+struct NoiseFloor {
+    power: Option<f32>,
+    alpha: f32
+}
+
+impl NoiseFloor {
+    fn new(blocks: usize) -> Self {
+        NoiseFloor { power: None, alpha: 1.0 / blocks as f32 }
     }
+
+    fn update(&mut self, noise: f32) -> f32 {
+        let power = match self.power {
+            Some(power) => power + self.alpha * (noise - power),
+            None => noise
+        };
+        self.power = Some(power);
+        power
+    }
+}
+
+// Flattens a sloped noise floor by judging each bin against a running average of its own power, so a tone has to
+// stand out from what is normal at its frequency rather than clear one fixed threshold everywhere.
+struct Whitener {
+    average: Vec<f32>,
+    alpha: f32,
+    min_ratio: f32
 }
 
-fn calculate_fft(planner: &mut FftPlanner<f32>, samples: &[i16]) -> Vec<Complex<f32>> {
-    let fft = planner.plan_fft_forward(samples.len());
+impl Whitener {
+    fn new(min_ratio: f32, blocks: usize) -> Self {
+        Whitener { average: Vec::new(), alpha: 1.0 / blocks as f32, min_ratio }
+    }
+
+    // Frequency of the in-band bin standing furthest above its average, if it's at least `min_ratio` times it.
+    // The first block only seeds the averages.
+    fn detect(&mut self, fft_output: &[Complex<f32>], band: &FrequencyBand, exclude: &[FrequencyBand]) -> Option<f32> {
+        let bin_width = bin_width(fft_output);
+        let powers = fft_output.iter().map(|sample| sample.re.powi(2) + sample.im.powi(2));
+        if self.average.len() != fft_output.len() {
+            self.average = powers.collect();
+            return None;
+        }
 
-    // Convert samples to Complex numbers (Real is sample, Imaginary is 0) & process.
-    let mut input: Vec<Complex<f32>> = samples.iter().map(|&s| Complex::new(s as f32, 0.0)).collect();
-    fft.process(&mut input);
+        let mut peak: Option<(f32, f32)> = None;
+        for (i, (power, average)) in powers.zip(self.average.iter_mut()).enumerate() {
+            let frequency = i as f32 * bin_width;
+            if *average > 0.0 && band.contains(frequency) && !exclude.iter().any(|band| band.contains(frequency)) {
+                let ratio = power / *average;
+                if ratio >= self.min_ratio && peak.is_none_or(|(_, best)| ratio > best) {
+                    peak = Some((frequency, ratio));
+                }
+            }
+            *average += self.alpha * (power - *average);
+        }
 
-    input
+        let (frequency, ratio) = peak?;
+        debug!("Detected tone at {} Hz, {:.1}x its average power", frequency, ratio);
+        Some(frequency)
+    }
 }
 
-fn detect_tone(fft_output: &[Complex<f32>]) -> bool {
-    let num_samples = fft_output.len();
-    let bin_width = 8000.0 / num_samples as f32;
+// Frequency of the strongest in-band bin whose level falls within the profile's power range, if any.
+pub fn detect_tone(fft_output: &[Complex<f32>], profile: &ToneProfile, unit: PowerUnit, exclude: &[FrequencyBand]) -> Option<f32> {
+    let bin_width = bin_width(fft_output);
 
-    // Loop over the FFT output and look for frequencies in the modem tone range.
-    for (i, &sample) in fft_output.iter().enumerate() {
+    // Loop over the FFT output and look for frequencies in the modem tone range, only visiting the bins around it.
+    let first = (profile.band.min / bin_width).floor() as usize;
+    let last = ((profile.band.max / bin_width).ceil() as usize + 1).min(fft_output.len());
+    let mut peak: Option<(usize, f32)> = None;
+    for (i, &sample) in fft_output.iter().enumerate().take(last).skip(first) {
         let frequency = i as f32 * bin_width;
 
-        // If the frequency is within the tone range, check if the power is above threshold.
-        if frequency >= TONE_MIN_FREQ && frequency <= TONE_MAX_FREQ {
-            let power = sample.re.powi(2) + sample.im.powi(2);
-            if power > TONE_MIN_POWER && power < TONE_MAX_POWER {
-                debug!("Detected tone at {} Hz with power: {}", frequency, power);
-                return true;
+        // If the frequency is within the tone range (and not a known interferer), check if the power is within the thresholds.
+        if profile.band.contains(frequency) && !exclude.iter().any(|band| band.contains(frequency)) {
+            let level = unit.level(sample.re.powi(2) + sample.im.powi(2));
+            if level > profile.min_power && level < profile.max_power && peak.is_none_or(|(_, best)| level > best) {
+                peak = Some((i, level));
             }
         }
     }
 
+    let (i, level) = peak?;
+    let magnitude = |frequency: f32| {
+        let bin = (frequency / bin_width).round();
+        fft_output.get(bin as usize).filter(|_| bin >= 0.0).map_or(0.0, |sample| sample.norm())
+    };
+    refined_peak(interpolate_peak(magnitude, i as f32 * bin_width, bin_width), level, profile, unit, exclude)
+}
+
+// The true frequency of a peak found at `frequency` on a grid `step` apart, from the parabola through the magnitudes
+// around it. A peak beside a stronger point is that point's leakage, so the fit is centred there instead.
+fn interpolate_peak(magnitude: impl Fn(f32) -> f32, frequency: f32, step: f32) -> f32 {
+    let around = |centre: f32| [centre - step, centre, centre + step].map(&magnitude);
+    let [before, peak, after] = around(frequency);
+    let centre = if after > peak && after >= before {
+        frequency + step
+    } else if before > peak {
+        frequency - step
+    } else {
+        frequency
+    };
+    let [before, peak, after] = if centre == frequency { [before, peak, after] } else { around(centre) };
+
+    let curvature = before - 2.0 * peak + after;
+    if peak < before || peak < after || curvature >= 0.0 {
+        return centre;
+    }
+    centre + (0.5 * (before - after) / curvature).clamp(-0.5, 0.5) * step
+}
+
+// An interpolated peak, held to the band too so the band can be set tighter than a bin.
+fn refined_peak(frequency: f32, level: f32, profile: &ToneProfile, unit: PowerUnit, exclude: &[FrequencyBand]) -> Option<f32> {
+    if !profile.band.contains(frequency) || exclude.iter().any(|band| band.contains(frequency)) {
+        debug!("Peak at {:.2} Hz falls outside the tone band once interpolated", frequency);
+        return None;
+    }
+    debug!("Detected tone at {:.2} Hz with level: {} ({:?})", frequency, level, unit);
+    Some(frequency)
+}
+
+// Level of every bin within the band, lowest frequency first.
+fn band_levels(fft_output: &[Complex<f32>], band: &FrequencyBand, unit: PowerUnit) -> Vec<f32> {
+    let bin_width = bin_width(fft_output);
+    fft_output.iter()
+        .enumerate()
+        .filter(|(i, _)| band.contains(*i as f32 * bin_width))
+        .map(|(_, sample)| unit.level(sample.re.powi(2) + sample.im.powi(2)))
+        .collect()
+}
+
+// Bin power grows with the square of the samples transformed, so a block shorter than FFT_SAMPLE_SIZE has its power
+// multiplied by this to read on the same scale, keeping the thresholds independent of block length.
+fn block_scale(len: usize) -> f32 {
+    (FFT_SAMPLE_SIZE as f32 / len.max(1) as f32).powi(2)
+}
+
+// Power of a single frequency using the Goertzel algorithm, on the same scale as a full block's FFT bin.
+fn goertzel(samples: &[i16], frequency: f32) -> f32 {
+    let coeff = 2.0 * (2.0 * std::f32::consts::PI * frequency / SAMPLE_RATE).cos();
+    let (mut s1, mut s2) = (0.0f32, 0.0f32);
+    for &sample in samples {
+        let s0 = sample as f32 + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+    }
+    (s1.powi(2) + s2.powi(2) - coeff * s1 * s2) * block_scale(samples.len())
+}
+
+// `goertzel` for several frequencies in one pass over the samples. Each recurrence depends on its last step, so
+// running them side by side keeps the CPU busy where one at a time would wait on every step.
+fn goertzel_bank(samples: &[i16], frequencies: &[f32]) -> Vec<f32> {
+    let coeffs: Vec<f32> = frequencies.iter().map(|frequency| 2.0 * (2.0 * std::f32::consts::PI * frequency / SAMPLE_RATE).cos()).collect();
+    let (mut s1, mut s2) = (vec![0.0f32; coeffs.len()], vec![0.0f32; coeffs.len()]);
+    for &sample in samples {
+        let sample = sample as f32;
+        for ((coeff, s1), s2) in coeffs.iter().zip(s1.iter_mut()).zip(s2.iter_mut()) {
+            let s0 = sample + coeff * *s1 - *s2;
+            *s2 = *s1;
+            *s1 = s0;
+        }
+    }
+    let scale = block_scale(samples.len());
+    coeffs.iter().zip(s1).zip(s2).map(|((coeff, s1), s2)| (s1.powi(2) + s2.powi(2) - coeff * s1 * s2) * scale).collect()
+}
+
+// Goertzel counterpart of `detect_tone`, stepping through the band at the unpadded FFT's resolution. `gain` is the
+// input gain the spectrum would have applied.
+fn detect_tone_goertzel(samples: &[i16], gain: f32, profile: &ToneProfile, unit: PowerUnit, exclude: &[FrequencyBand]) -> Option<f32> {
+    if samples.is_empty() {
+        return None;
+    }
+
+    let step = SAMPLE_RATE / FFT_SAMPLE_SIZE as f32;
+    let count = ((profile.band.max - profile.band.min) / step).floor() as usize + 1;
+    let frequencies: Vec<f32> = (0..count)
+        .map(|i| profile.band.min + i as f32 * step)
+        .filter(|&frequency| !exclude.iter().any(|band| band.contains(frequency)))
+        .collect();
+    let mut peak: Option<(f32, f32)> = None;
+    for (&frequency, power) in frequencies.iter().zip(goertzel_bank(samples, &frequencies)) {
+        let level = unit.level(power * gain * gain);
+        if level > profile.min_power && level < profile.max_power && peak.is_none_or(|(_, best)| level > best) {
+            peak = Some((frequency, level));
+        }
+    }
+
+    let (frequency, level) = peak?;
+    let magnitude = |frequency: f32| (goertzel(samples, frequency) * gain * gain).sqrt();
+    refined_peak(interpolate_peak(magnitude, frequency, step), level, profile, unit, exclude)
+}
+
+fn detect_answer_tone(samples: &[i16], config: &Config) -> bool {
+    if samples.is_empty() {
+        return false;
+    }
+
+    // Step through the band at the resolution the block length allows.
+    let step = SAMPLE_RATE / samples.len() as f32;
+    let mut frequency = config.answer_tone_min_freq;
+    while frequency <= config.answer_tone_max_freq {
+        let level = config.power_unit.level(goertzel(samples, frequency));
+        if level > config.answer_tone_min_power {
+            debug!("Detected answer tone at {} Hz with level: {} ({:?})", frequency, level, config.power_unit);
+            return true;
+        }
+        frequency += step;
+    }
+
     false
 }
 
-pub(crate) fn listen<F>(port: &mut dyn SerialPort, callback: F) -> Result<()>
-where
-    F: Fn()
-{
-    let mut planner = FftPlanner::<f32>::new();
-    planner.plan_fft_forward(FFT_SAMPLE_SIZE);
+// Rising-edge tracking so a sustained tone only fires once, and never more often than the interval.
+// With a minimum gap configured, any edge following at least that long without the tone fires instead.
+struct EdgeState {
+    active: bool,
+    last_fired: Instant,
+    last_seen: Option<Instant>
+}
 
-    let mut prev_tone_detected = false;
-    let mut prev_time_detected = Instant::now();
+impl EdgeState {
+    fn new() -> Self {
+        EdgeState { active: false, last_fired: Instant::now(), last_seen: None }
+    }
 
-    loop {
-        let mut buffer = vec![0; 1024];
-        match port.read(&mut buffer) {
-            Ok(n) if n > 0 => {
+    // Resume from a wall-clock fire time, such as one persisted before a restart.
+    fn fired_at(time: SystemTime) -> Self {
+        let age = SystemTime::now().duration_since(time).unwrap_or_default();
+        let last_fired = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+        EdgeState { active: false, last_fired, last_seen: Some(last_fired) }
+    }
 
-                // Process the samples using FFT.
-                let mut samples: Vec<i16> = buffer.iter().map(|&b| b as i16).collect();
-                high_pass_filter(&mut samples, HIGH_PASS_CUTOFF);
-                let fft_output = calculate_fft(&mut planner, &samples);
-
-                // Check for non-repeated tone triggers (exceeding detection interval).
-                let tone_detected = detect_tone(&fft_output);
-                if tone_detected && !prev_tone_detected {
-                    if prev_time_detected.elapsed() >= DETECTION_INTERVAL {
-                        debug!("Tone detected!");
-                        callback();
-                        prev_time_detected = Instant::now();
-                    }
-                    prev_tone_detected = true;
-                } else if !tone_detected {
-                    prev_tone_detected = false;
-                }
+    fn update(&mut self, detected: bool, min_gap: Option<Duration>, interval: Duration) -> bool {
+        let mut fire = false;
+        if detected && !self.active {
+            let ready = match min_gap {
+                Some(gap) => self.last_seen.is_none_or(|seen| seen.elapsed() >= gap),
+                None => self.last_fired.elapsed() >= interval
+            };
+            if ready {
+                fire = true;
+                self.last_fired = Instant::now();
             }
-            Ok(_) => {}
-            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => sleep(READ_TIMEOUT),
-            Err(e) => return Err(anyhow!(e))
+            self.active = true;
+        } else if !detected {
+            self.active = false;
+        }
+
+        if detected {
+            self.last_seen = Some(Instant::now());
+        }
+        fire
+    }
+}
+
+// Sample encoding of the modem's voice stream. The G.711 companded formats decode to 16-bit linear.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcmFormat {
+    Unsigned8,
+    SignedLe16,
+    MuLaw,
+    ALaw
+}
+
+// μ-law bytes are stored inverted, each a sign, a 3 bit segment and a 4 bit step within it, biased by 0x84.
+const fn mu_law_table() -> [i16; 256] {
+    let mut table = [0i16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let byte = !(i as u8);
+        let magnitude = ((((byte & 0x0f) as i32) << 3) + 0x84) << ((byte >> 4) & 0x07);
+        table[i] = if byte & 0x80 != 0 { (0x84 - magnitude) as i16 } else { (magnitude - 0x84) as i16 };
+        i += 1;
+    }
+    table
+}
+
+// A-law bytes have their even bits inverted, and the sign bit is set for positive samples.
+const fn a_law_table() -> [i16; 256] {
+    let mut table = [0i16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let byte = i as u8 ^ 0x55;
+        let step = ((byte & 0x0f) as i32) << 4;
+        let magnitude = match (byte >> 4) & 0x07 {
+            0 => step + 8,
+            segment => (step + 0x108) << (segment - 1)
+        };
+        table[i] = if byte & 0x80 != 0 { magnitude as i16 } else { -magnitude as i16 };
+        i += 1;
+    }
+    table
+}
+
+const MU_LAW: [i16; 256] = mu_law_table();
+const A_LAW: [i16; 256] = a_law_table();
+
+// The segment of a companded magnitude, `ends` holding the largest magnitude of each.
+fn g711_segment(magnitude: i32, ends: &[i32; 8]) -> u8 {
+    ends.iter().position(|&end| magnitude <= end).unwrap_or(8) as u8
+}
+
+fn encode_mu_law(sample: i16) -> u8 {
+    const ENDS: [i32; 8] = [0x3f, 0x7f, 0xff, 0x1ff, 0x3ff, 0x7ff, 0xfff, 0x1fff];
+    let value = sample as i32 >> 2;
+    let (value, mask) = if value < 0 { (-value, 0x7f) } else { (value, 0xff) };
+    let value = value.min(8159) + 0x21;
+    match g711_segment(value, &ENDS) {
+        8 => 0x7f ^ mask,
+        segment => ((segment << 4) | ((value >> (segment + 1)) & 0x0f) as u8) ^ mask
+    }
+}
+
+fn encode_a_law(sample: i16) -> u8 {
+    const ENDS: [i32; 8] = [0x1f, 0x3f, 0x7f, 0xff, 0x1ff, 0x3ff, 0x7ff, 0xfff];
+    let value = sample as i32 >> 3;
+    let (value, mask) = if value >= 0 { (value, 0xd5) } else { (-value - 1, 0x55) };
+    match g711_segment(value, &ENDS) {
+        8 => 0x7f ^ mask,
+        segment => {
+            let step = if segment < 2 { value >> 1 } else { value >> segment };
+            ((segment << 4) | (step & 0x0f) as u8) ^ mask
+        }
+    }
+}
+
+// How captured audio arrives: its coding, how many channels are interleaved, the rate multiple to decimate by and the
+// rate it's actually streamed at.
+#[derive(Debug, Clone)]
+pub(crate) struct Capture {
+    pub format: PcmFormat,
+    pub channels: usize,
+    pub channel: usize,
+    pub decimation: usize,
+    pub rate: u32,
+    format_checked: bool,
+    decimator: Decimator,
+    anti_alias: Option<AntiAliasFilter>, // Built on the first read needing it, once the streamed rate is known
+    resample_at: f64,          // Where the next resampled output falls, in input samples from the next read's start
+    resample_last: Option<f32> // The input sample before the next read, interpolated from across the boundary
+}
+
+impl Capture {
+    // Audio at the configured capture rate, a multiple of the processing rate.
+    pub fn new(format: PcmFormat, config: &Config) -> Self {
+        Capture {
+            format,
+            channels: config.channels,
+            channel: config.channel,
+            decimation: config.decimation,
+            rate: SAMPLE_RATE as u32 * config.decimation as u32,
+            format_checked: false,
+            decimator: Decimator::new(config.decimation),
+            anti_alias: None,
+            resample_at: 0.0,
+            resample_last: None
+        }
+    }
+
+    // Decode the whole frames at the front of `raw`, leaving a partial frame for the next read, and return the
+    // analysed channel at the processing rate. The first linear audio is held back until there's enough to check the
+    // format, companded audio has no check to wait for.
+    pub fn take_samples(&mut self, raw: &mut Vec<u8>) -> Vec<i16> {
+        if !self.format_checked {
+            if raw.len() < FORMAT_CHECK_BYTES && !matches!(self.format, PcmFormat::MuLaw | PcmFormat::ALaw) {
+                return Vec::new();
+            }
+            self.check_format(raw);
+            self.format_checked = true;
+        }
+
+        let frame = self.format.sample_bytes() * self.channels;
+        let whole = raw.len() / frame * frame;
+        let mut samples = self.format.decode(&raw[..whole]);
+        raw.drain(..whole);
+        if self.channels > 1 {
+            samples = samples.into_iter().skip(self.channel).step_by(self.channels).collect();
+        }
+        // A stream at some other rate than was asked for is interpolated to the processing rate instead.
+        if self.rate != SAMPLE_RATE as u32 * self.decimation as u32 {
+            return self.resample(&samples);
+        }
+        self.decimator.process(&samples)
+    }
+
+    // As `resample` does, but carrying on from the last read, so the output keeps its spacing across read boundaries
+    // rather than restarting each read on an input sample, and the low-pass runs on through them.
+    fn resample(&mut self, samples: &[i16]) -> Vec<i16> {
+        if samples.is_empty() {
+            return Vec::new();
+        }
+        let ratio = self.rate as f64 / SAMPLE_RATE as f64;
+        let filtered = self.anti_alias.get_or_insert_with(|| AntiAliasFilter::new(ratio)).process(samples);
+        let input: Vec<f32> = self.resample_last.into_iter().chain(filtered).collect();
+        let mut position = self.resample_at + self.resample_last.map_or(0.0, |_| 1.0);
+        let mut output = Vec::new();
+        while (position as usize) + 1 < input.len() {
+            let index = position as usize;
+            let fraction = (position - index as f64) as f32;
+            output.push(saturate_i16(input[index] + (input[index + 1] - input[index]) * fraction).0);
+            position += ratio;
         }
+        self.resample_at = position - input.len() as f64;
+        self.resample_last = input.last().copied();
+        output
     }
-}
\ No newline at end of file
+
+    // At the end of a call, so one shorter than the format check still has its audio heard, the format is decided on
+    // what has arrived.
+    pub fn finish(&mut self, raw: &mut Vec<u8>) -> Vec<i16> {
+        if !self.format_checked && !raw.is_empty() {
+            self.check_format(raw);
+            self.format_checked = true;
+        }
+        self.take_samples(raw)
+    }
+
+    // A modem streaming the other sample width decodes as garbage (8-bit silence read as 16-bit is a near full scale
+    // DC offset, 16-bit read as 8-bit swings rail to rail), so switch when only the other format looks like audio.
+    // Companded audio can't be told from linear this way, so a G.711 coder is trusted as negotiated.
+    fn check_format(&mut self, raw: &[u8]) {
+        let other = match self.format {
+            PcmFormat::Unsigned8 => PcmFormat::SignedLe16,
+            PcmFormat::SignedLe16 => PcmFormat::Unsigned8,
+            PcmFormat::MuLaw | PcmFormat::ALaw => return
+        };
+        if !self.format.plausible(raw) && other.plausible(raw) {
+            warn!("Audio doesn't look like {:?} PCM but does look like {:?}, decoding it as {:?} instead", self.format, other, other);
+            self.format = other;
+        }
+    }
+}
+
+impl FromStr for PcmFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "u8" => Ok(PcmFormat::Unsigned8),
+            "s16le" => Ok(PcmFormat::SignedLe16),
+            "ulaw" | "mulaw" => Ok(PcmFormat::MuLaw),
+            "alaw" => Ok(PcmFormat::ALaw),
+            _ => Err(anyhow!("Unknown PCM format {}, expected u8, s16le, ulaw or alaw", s))
+        }
+    }
+}
+
+impl PcmFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PcmFormat::Unsigned8 => "u8",
+            PcmFormat::SignedLe16 => "s16le",
+            PcmFormat::MuLaw => "ulaw",
+            PcmFormat::ALaw => "alaw"
+        }
+    }
+
+    // Largest swing from zero, as decoded.
+    pub fn full_scale(&self) -> f32 {
+        match self {
+            PcmFormat::Unsigned8 => 128.0,
+            PcmFormat::SignedLe16 | PcmFormat::MuLaw | PcmFormat::ALaw => 32768.0
+        }
+    }
+
+    pub fn sample_bytes(&self) -> usize {
+        match self {
+            PcmFormat::Unsigned8 | PcmFormat::MuLaw | PcmFormat::ALaw => 1,
+            PcmFormat::SignedLe16 => 2
+        }
+    }
+
+    // Signed samples about zero, at the format's own scale. 8-bit audio is offset binary, its silence sits at 128.
+    pub fn decode(&self, raw: &[u8]) -> Vec<i16> {
+        match self {
+            PcmFormat::Unsigned8 => raw.iter().map(|&b| b as i16 - 128).collect(),
+            PcmFormat::SignedLe16 => raw.chunks_exact(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect(),
+            PcmFormat::MuLaw => raw.iter().map(|&b| MU_LAW[b as usize]).collect(),
+            PcmFormat::ALaw => raw.iter().map(|&b| A_LAW[b as usize]).collect()
+        }
+    }
+
+    // Whether bytes decoded in this format have the DC offset and level of real line audio.
+    fn plausible(&self, raw: &[u8]) -> bool {
+        let normalized: Vec<f32> = self.decode(raw).into_iter().map(|s| s as f32 / self.full_scale()).collect();
+        let len = normalized.len().max(1) as f32;
+        let mean = normalized.iter().sum::<f32>() / len;
+        let rms = (normalized.iter().map(|s| s * s).sum::<f32>() / len).sqrt();
+        mean.abs() < FORMAT_MAX_DC && rms < FORMAT_MAX_RMS
+    }
+
+    // Signed samples to the modem's wire format, keeping the top byte for 8-bit audio.
+    pub fn encode(&self, samples: &[i16]) -> Vec<u8> {
+        match self {
+            PcmFormat::Unsigned8 => samples.iter().map(|&s| ((s >> 8) + 128) as u8).collect(),
+            PcmFormat::SignedLe16 => samples.iter().flat_map(|s| s.to_le_bytes()).collect(),
+            PcmFormat::MuLaw => samples.iter().map(|&s| encode_mu_law(s)).collect(),
+            PcmFormat::ALaw => samples.iter().map(|&s| encode_a_law(s)).collect()
+        }
+    }
+}
+
+// Fires once when an expected tone has been missing for longer than the timeout, re-arming when it returns.
+struct AbsenceState {
+    last_seen: Instant,
+    alerted: bool
+}
+
+impl AbsenceState {
+    fn new() -> Self {
+        AbsenceState { last_seen: Instant::now(), alerted: false }
+    }
+
+    fn update(&mut self, detected: bool, timeout: Duration) -> bool {
+        if detected {
+            self.last_seen = Instant::now();
+            self.alerted = false;
+            return false;
+        }
+        if !self.alerted && self.last_seen.elapsed() >= timeout {
+            self.alerted = true;
+            return true;
+        }
+        false
+    }
+}
+
+// Sleep between reads that bring no audio, doubling while the line stays quiet so an idle modem costs little CPU,
+// and dropping back to the minimum as soon as audio arrives so an active call isn't slowed.
+struct IdleBackoff {
+    min: Duration,
+    max: Duration,
+    current: Duration
+}
+
+impl IdleBackoff {
+    fn new(min: Duration, max: Duration) -> Self {
+        IdleBackoff { min, max, current: min }
+    }
+
+    fn idle(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    fn active(&mut self) {
+        self.current = self.min;
+    }
+}
+
+// Logs the RMS and peak level of the raw audio over each interval, for judging the receive gain (AT+VGR) on an idle line.
+struct LevelMeter {
+    interval: Duration,
+    started: Instant,
+    sum: f64,
+    sum_squares: f64,
+    samples: u64,
+    min: i16,
+    max: i16
+}
+
+impl LevelMeter {
+    fn new(interval: Duration) -> Self {
+        LevelMeter { interval, started: Instant::now(), sum: 0.0, sum_squares: 0.0, samples: 0, min: i16::MAX, max: i16::MIN }
+    }
+
+    fn update(&mut self, samples: &[i16], full_scale: f32) {
+        for &sample in samples {
+            self.sum += sample as f64;
+            self.sum_squares += sample as f64 * sample as f64;
+            self.min = self.min.min(sample);
+            self.max = self.max.max(sample);
+        }
+        self.samples += samples.len() as u64;
+        if self.started.elapsed() < self.interval || self.samples == 0 {
+            return;
+        }
+        // Both measured about the mean, as for a detection's level.
+        let mean = self.sum / self.samples as f64;
+        let rms = (self.sum_squares / self.samples as f64 - mean * mean).max(0.0).sqrt() as f32;
+        let peak = (self.max as f64 - mean).max(mean - self.min as f64) as f32;
+        info!("Line level {:.1} dBFS RMS, {:.1} dBFS peak", dbfs(rms, full_scale), dbfs(peak, full_scale));
+        *self = LevelMeter::new(self.interval);
+    }
+}
+
+// Confirms a tone only once its peak has held for a run of consecutive blocks, each within the drift tolerance of
+// the last. A genuine handshake tone may wander slowly, an interferer landing at random in-band frequencies won't hold.
+struct DriftTracker {
+    tolerance: f32,
+    blocks: usize,
+    last_peak: Option<f32>,
+    stable: usize
+}
+
+impl DriftTracker {
+    fn new(tolerance: f32, blocks: usize) -> Self {
+        DriftTracker { tolerance, blocks, last_peak: None, stable: 0 }
+    }
+
+    fn update(&mut self, peak: Option<f32>) -> bool {
+        self.stable = match (self.last_peak, peak) {
+            (Some(last), Some(peak)) if (peak - last).abs() <= self.tolerance => self.stable + 1,
+            (_, Some(_)) => 1,
+            (_, None) => 0
+        };
+        self.last_peak = peak;
+        self.stable >= self.blocks
+    }
+}
+
+// N-of-M debounce over per-block results: asserts once `required` of the last `window` blocks found the tone, and
+// only deasserts once `required` of them missed it, so the odd missed or spurious block doesn't flip the state.
+struct MajorityVote {
+    votes: VecDeque<bool>,
+    window: usize,
+    required: usize,
+    asserted: bool
+}
+
+impl MajorityVote {
+    fn new(required: usize, window: usize) -> Self {
+        MajorityVote { votes: VecDeque::with_capacity(window), window, required, asserted: false }
+    }
+
+    fn update(&mut self, detected: bool) -> bool {
+        if self.votes.len() == self.window {
+            self.votes.pop_front();
+        }
+        self.votes.push_back(detected);
+
+        let present = self.votes.iter().filter(|&&vote| vote).count();
+        if self.asserted {
+            self.asserted = self.votes.len() - present < self.required;
+        } else {
+            self.asserted = present >= self.required;
+        }
+        self.asserted
+    }
+}
+
+// Automatic gain control ahead of the filters. The input's mean square is tracked over the attack time, and the gain
+// that would put it at the target follows it down at the attack rate and back up at the slower release rate, never
+// beyond the maximum so a quiet line isn't amplified up into its noise. Tone levels are then relative to the line's.
+struct Agc {
+    settings: AgcSettings,
+    target: f32,  // Target RMS in sample units
+    max_gain: f32,
+    attack: f32,  // Per-sample smoothing coefficients
+    release: f32,
+    mean_square: f32,
+    gain: f32
+}
+
+impl Agc {
+    fn new(settings: AgcSettings) -> Self {
+        let coefficient = |time: Duration| (-1.0 / (time.as_secs_f32() * SAMPLE_RATE).max(1.0)).exp();
+        Agc {
+            settings,
+            target: i16::MAX as f32 * 10f32.powf(settings.target_dbfs / 20.0),
+            max_gain: 10f32.powf(settings.max_gain_db / 20.0),
+            attack: coefficient(settings.attack),
+            release: coefficient(settings.release),
+            mean_square: 0.0,
+            gain: 1.0
+        }
+    }
+
+    // Returns the number of samples that clipped.
+    fn process(&mut self, samples: &mut [i16]) -> usize {
+        let mut clipped = 0;
+        for sample in samples {
+            let value = *sample as f32;
+            self.mean_square = self.attack * self.mean_square + (1.0 - self.attack) * value * value;
+            let wanted = (self.target / self.mean_square.sqrt().max(1.0)).min(self.max_gain);
+            let coefficient = if wanted < self.gain { self.attack } else { self.release };
+            self.gain = coefficient * self.gain + (1.0 - coefficient) * wanted;
+            let (value, clip) = saturate_i16(value * self.gain);
+            *sample = value;
+            clipped += clip as usize;
+        }
+        clipped
+    }
+}
+
+// Hysteresis over per-block results: asserts once the tone has been found in `on` blocks in a row, and only
+// deasserts, re-arming the trigger, once it has been missing from `off` in a row.
+struct Hysteresis {
+    on: usize,
+    off: usize,
+    run: usize, // Blocks in a row disagreeing with the current state
+    asserted: bool
+}
+
+impl Hysteresis {
+    fn new(on: usize, off: usize) -> Self {
+        Hysteresis { on, off, run: 0, asserted: false }
+    }
+
+    fn from_config(config: &Config) -> Option<Self> {
+        (config.detection_min_frames > 1 || config.detection_release_frames > 1)
+            .then(|| Hysteresis::new(config.detection_min_frames, config.detection_release_frames))
+    }
+
+    fn update(&mut self, detected: bool) -> bool {
+        if detected == self.asserted {
+            self.run = 0;
+            return self.asserted;
+        }
+        self.run += 1;
+        if self.run >= if self.asserted { self.off } else { self.on } {
+            self.asserted = detected;
+            self.run = 0;
+        }
+        self.asserted
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Detection {
+    Trigger,
+    AnswerTone,
+    ToneAbsent,
+    ContactId,
+    CallProgress(CallProgress),
+    FskData,
+    Panic,
+    Stalled, // No audio has arrived for the stall timeout, so nothing else would be detected either
+    LineFault(LineStatus) // The modem reported the line busy, without dial tone or otherwise unusable
+}
+
+impl Detection {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Detection::Trigger => "trigger",
+            Detection::AnswerTone => "answer_tone",
+            Detection::ToneAbsent => "tone_absent",
+            Detection::ContactId => "contact_id",
+            Detection::CallProgress(progress) => progress.label(),
+            Detection::FskData => "fsk_data",
+            Detection::Panic => "panic",
+            Detection::Stalled => "monitoring_stalled",
+            Detection::LineFault(status) => status.label()
+        }
+    }
+
+    // Duress transmissions need to reach someone even when the alert path is throttled or batching.
+    pub fn is_high_priority(&self) -> bool {
+        *self == Detection::Panic
+    }
+}
+
+pub struct DetectionEvent {
+    pub detection: Detection,
+    pub timestamp: SystemTime,
+    pub audio: Option<Vec<i16>>, // Recent audio leading up to the detection, when retained
+    pub contact_id: Option<ContactIdEvent>,
+    pub fsk_data: Option<Vec<u8>>, // Bytes of an FSK data message
+    pub frequency: Option<f32>,  // Peak frequency heard when the target tone triggered
+    pub offset: Option<Duration>, // Position in the call's audio, counted in samples so processing latency doesn't skew it
+    pub level_dbfs: Option<f32>, // RMS level of the audio read the detection came from
+    pub caller: Option<CallerId>  // Who called, when caller ID was received for the call
+}
+
+// Audio kept around a detection: the pre-trigger history, then everything until tones have been gone for the post window.
+struct Recording {
+    detections: Vec<(Detection, SystemTime, Duration)>,
+    audio: Vec<i16>,
+    post_remaining: usize,
+    frequency: Option<f32>,
+    level_dbfs: Option<f32>
+}
+
+impl Recording {
+    fn into_events(self) -> Vec<DetectionEvent> {
+        to_events(self.detections, Some(self.audio), self.frequency, self.level_dbfs)
+    }
+}
+
+// Time into the call of a sample index, from the stream's own rate rather than the wall clock.
+fn sample_offset(index: u64) -> Duration {
+    Duration::from_secs_f64(index as f64 / SAMPLE_RATE as f64)
+}
+
+fn to_events(
+    detections: Vec<(Detection, SystemTime, Duration)>,
+    audio: Option<Vec<i16>>,
+    frequency: Option<f32>,
+    level_dbfs: Option<f32>
+) -> Vec<DetectionEvent> {
+    detections.into_iter()
+        .map(|(detection, timestamp, offset)| DetectionEvent {
+            detection,
+            timestamp,
+            audio: audio.clone(),
+            contact_id: None,
+            fsk_data: None,
+            frequency: frequency.filter(|_| detection == Detection::Trigger),
+            offset: Some(offset),
+            level_dbfs,
+            caller: None
+        })
+        .collect()
+}
+
+// How one block was judged, for analysing a recording: the peak in-band level (in the configured unit), its margin
+// over the noise floor, the qualifying peak frequency and whether the tone counted as present after every check.
+pub(crate) struct BlockReport {
+    pub offset: Duration,
+    pub level: f32,
+    pub snr_db: f32,
+    pub frequency: Option<f32>,
+    pub tone: bool
+}
+
+pub struct Detector {
+    config: Arc<Config>,
+    spectrum: Spectrum,
+    average: Option<SpectralAverage>,
+    trigger: EdgeState,
+    answer_tone: EdgeState,
+    absence: AbsenceState,
+    drift: Option<DriftTracker>,
+    vote: Option<MajorityVote>,
+    hysteresis: Option<Hysteresis>,
+    agc: Option<Agc>,
+    whitener: Option<Whitener>,
+    template: Option<ToneTemplate>,
+    noise_floor: Option<NoiseFloor>,
+    peak_frequency: Option<f32>, // Most recent in-band peak, reported with the next trigger
+    state_file: Option<StateFile>,
+    last_fired: HashMap<String, SystemTime>,
+    vad: Option<Vad>,
+    history: VecDeque<i16>,
+    history_len: usize,
+    post_len: usize,
+    recording: Option<Recording>,
+    clipped: usize,
+    pre_emphasis_state: f32,
+    band_pass: BandPass,
+    pcm_pipe: Option<PcmPipe>,
+    pending: Vec<i16>, // Filtered samples waiting to fill the next FFT_SAMPLE_SIZE block
+    framed_to: usize,  // How far into `pending` the last frame reached
+    pending_start: u64, // Samples of the call that came before `pending`
+    last_frame: Instant,
+    profile: ToneProfile,
+    panic_profile: Option<ToneProfile>,
+    panic: EdgeState,
+    cadence: Option<CadenceDetector>,
+    sequence: Option<SequenceMatcher>,
+    snapshot: Option<SpectrumSnapshot>,
+    reports: Option<Vec<BlockReport>>,
+    level_meter: Option<LevelMeter>,
+    fsk: Option<FskDemodulator>,
+    dtmf: Option<DtmfDecoder>,
+    caller: Option<CallerId>,
+    full_scale: f32, // Of the format the samples were decoded from, for reporting levels in dBFS
+    input_rate: u32  // That the modem streams at, resampled to SAMPLE_RATE when it isn't the configured capture rate
+}
+
+// Whether detections capture audio, to attach to the webhook or to save as a WAV file.
+fn records_audio(config: &Config) -> bool {
+    config.webhook_attach_audio || config.record_dir.is_some()
+}
+
+// Audio kept before and after a detection, in samples.
+fn record_lens(config: &Config) -> (usize, usize) {
+    let samples_for = |ms: u64| (ms as f32 * SAMPLE_RATE / 1000.0) as usize;
+    if records_audio(config) {
+        (samples_for(config.record_pre_ms), samples_for(config.record_post_ms))
+    } else {
+        (0, 0)
+    }
+}
+
+// The panic tone band, taking the default thresholds when it has no power range of its own.
+fn panic_profile(config: &Config) -> Option<ToneProfile> {
+    config.panic_tone.map(|spec| {
+        let default = default_tone(config);
+        let (min_power, max_power) = spec.power.unwrap_or((default.min_power, default.max_power));
+        ToneProfile { band: spec.band, min_power, max_power }
+    })
+}
+
+// Bins of a transform `zero_pad` times the block size whose frequencies fall within the band.
+fn bins_in_band(band: &FrequencyBand, zero_pad: usize) -> usize {
+    let bin_width = SAMPLE_RATE / (FFT_SAMPLE_SIZE * zero_pad) as f32;
+    ((band.max / bin_width).floor() - (band.min / bin_width).ceil() + 1.0).max(0.0) as usize
+}
+
+// The zero padding to transform with. When ALARM_MIN_BAND_BINS is set the narrowest tone band is checked against it,
+// doubling the padding until it's met if ALARM_FFT_AUTO_PAD allows, or warning that the bins are too coarse.
+fn zero_pad(config: &Config) -> usize {
+    let Some(min_bins) = config.min_band_bins else {
+        return config.fft_zero_pad;
+    };
+    let bands = [default_tone(config).band].into_iter()
+        .chain(config.panic_tone.map(|spec| spec.band))
+        .chain(config.tone_profiles.iter().map(|profile| profile.band));
+    let Some(narrowest) = bands.min_by(|a, b| (a.max - a.min).total_cmp(&(b.max - b.min))) else {
+        return config.fft_zero_pad;
+    };
+
+    let mut zero_pad = config.fft_zero_pad;
+    while config.fft_auto_pad && bins_in_band(&narrowest, zero_pad) < min_bins && FFT_SAMPLE_SIZE * zero_pad * 2 <= MAX_FFT_LEN {
+        zero_pad *= 2;
+    }
+    let bins = bins_in_band(&narrowest, zero_pad);
+    if bins < min_bins {
+        warn!(
+            "The {}-{} Hz tone band spans {} bins of {:.2} Hz, fewer than ALARM_MIN_BAND_BINS ({}){}",
+            narrowest.min, narrowest.max, bins, SAMPLE_RATE / (FFT_SAMPLE_SIZE * zero_pad) as f32, min_bins,
+            if config.fft_auto_pad { ", even at the largest transform" } else { ", set ALARM_FFT_AUTO_PAD to zero pad until it does" }
+        );
+    } else if zero_pad != config.fft_zero_pad {
+        info!("Zero padding the FFT {}x so the {}-{} Hz tone band spans {} bins", zero_pad, narrowest.min, narrowest.max, bins);
+    }
+    zero_pad
+}
+
+// The learned tone template, which must match the transform size it will be correlated with.
+fn load_template(config: &Config, zero_pad: usize) -> Result<Option<ToneTemplate>> {
+    let Some(path) = &config.tone_template else {
+        return Ok(None);
+    };
+    let template = ToneTemplate::load(path)?;
+    let bins = FFT_SAMPLE_SIZE * zero_pad / 2 + 1;
+    if template.len() != bins {
+        return Err(anyhow!("Tone template {} has {} bins but the transform has {}, it was learned with a different ALARM_FFT_ZERO_PAD", path, template.len(), bins));
+    }
+    Ok(Some(template))
+}
+
+// Software gain undoing a receive gain set away from the one the thresholds were tuned at.
+fn input_gain(config: &Config) -> f32 {
+    match (config.modem_vgr.or(config.modem_profile.vgr()), config.vgr_reference) {
+        (Some(vgr), Some(reference)) => vgr_compensation(vgr, reference, config.vgr_db_per_step),
+        _ => 1.0
+    }
+}
+
+impl Detector {
+    pub fn new(config: Arc<Config>) -> Result<Self> {
+        if let (Some(vgr), Some(reference)) = (config.modem_vgr.or(config.modem_profile.vgr()), config.vgr_reference) {
+            if vgr != reference {
+                let gain_db = 20.0 * input_gain(&config).log10();
+                info!("Applying {:+.1} dB of gain to offset AT+VGR={} against the AT+VGR={} thresholds", gain_db, vgr, reference);
+            }
+        }
+        let state_file = config.state_file.as_ref().map(StateFile::new);
+        let last_fired = match state_file.as_ref().map(StateFile::load) {
+            Some(Ok(entries)) => entries,
+            Some(Err(e)) => {
+                warn!("Ignoring detection state file: {:#}", e);
+                HashMap::new()
+            },
+            None => HashMap::new()
+        };
+
+        let (history_len, post_len) = record_lens(&config);
+        let zero_pad = zero_pad(&config);
+
+        let edge_state = |detection: Detection| last_fired.get(detection.label())
+            .map(|&time| EdgeState::fired_at(time))
+            .unwrap_or_else(EdgeState::new);
+
+        Ok(Detector {
+            config: config.clone(),
+            spectrum: Spectrum::new(zero_pad, input_gain(&config), config.fft_window)?,
+            average: config.fft_average_blocks.map(SpectralAverage::new),
+            trigger: edge_state(Detection::Trigger),
+            answer_tone: edge_state(Detection::AnswerTone),
+            panic: edge_state(Detection::Panic),
+            absence: AbsenceState::new(),
+            drift: config.freq_drift_hz.map(|tolerance| DriftTracker::new(tolerance, config.freq_drift_blocks)),
+            vote: config.vote.map(|(required, window)| MajorityVote::new(required, window)),
+            hysteresis: Hysteresis::from_config(&config),
+            agc: config.agc.map(Agc::new),
+            whitener: config.whiten_min_ratio.map(|ratio| Whitener::new(ratio, config.whiten_blocks)),
+            template: load_template(&config, zero_pad)?,
+            noise_floor: config.min_snr_db.map(|_| NoiseFloor::new(config.noise_floor_blocks)),
+            peak_frequency: None,
+            state_file,
+            last_fired,
+            vad: config.vad_threshold.map(|threshold| Vad::new(threshold, config.vad_hangover, SAMPLE_RATE)),
+            history: VecDeque::new(),
+            history_len,
+            post_len,
+            recording: None,
+            clipped: 0,
+            pre_emphasis_state: 0.0,
+            band_pass: BandPass::new(config.band_pass),
+            // Observe mode has no side effects, so nothing is fed audio either.
+            pcm_pipe: match config.mode {
+                RunMode::Active => config.pcm_pipe_command.as_deref().map(PcmPipe::spawn).transpose()?,
+                RunMode::Observe => None
+            },
+            pending: Vec::with_capacity(FFT_SAMPLE_SIZE),
+            framed_to: 0,
+            pending_start: 0,
+            last_frame: Instant::now(),
+            profile: default_tone(&config),
+            panic_profile: panic_profile(&config),
+            cadence: config.call_progress.then(CadenceDetector::new),
+            sequence: config.tone_sequence.clone().map(SequenceMatcher::new),
+            snapshot: None,
+            reports: None,
+            level_meter: config.level_report.map(LevelMeter::new),
+            fsk: config.fsk.map(FskDemodulator::new),
+            dtmf: config.dtmf.then(|| DtmfDecoder::new(config.dtmf_gap)),
+            caller: None,
+            full_scale: PcmFormat::SignedLe16.full_scale(),
+            input_rate: SAMPLE_RATE as u32 * config.decimation as u32
+        })
+    }
+
+    pub fn config(&self) -> Arc<Config> {
+        self.config.clone()
+    }
+
+    // Swap in a reloaded config, rebuilding everything derived from its tunables. Cooldowns, recordings and the
+    // partial block carry over, so a reload mid-call neither re-fires a tone nor drops audio.
+    pub fn reload(&mut self, config: Arc<Config>) -> Result<()> {
+        let zero_pad = zero_pad(&config);
+        self.spectrum = Spectrum::new(zero_pad, input_gain(&config), config.fft_window)?;
+        if config.fft_average_blocks != self.average.as_ref().map(|average| average.blocks) {
+            self.average = config.fft_average_blocks.map(SpectralAverage::new);
+        }
+        self.drift = config.freq_drift_hz.map(|tolerance| DriftTracker::new(tolerance, config.freq_drift_blocks));
+        self.vote = config.vote.map(|(required, window)| MajorityVote::new(required, window));
+        self.hysteresis = Hysteresis::from_config(&config);
+        // Kept through a reload that leaves it alone, so the gain doesn't start over mid-call.
+        if config.agc != self.agc.as_ref().map(|agc| agc.settings) {
+            self.agc = config.agc.map(Agc::new);
+        }
+        self.whitener = config.whiten_min_ratio.map(|ratio| Whitener::new(ratio, config.whiten_blocks));
+        self.template = load_template(&config, zero_pad)?;
+        self.noise_floor = config.min_snr_db.map(|_| NoiseFloor::new(config.noise_floor_blocks));
+        self.vad = config.vad_threshold.map(|threshold| Vad::new(threshold, config.vad_hangover, SAMPLE_RATE));
+        if config.level_report != self.level_meter.as_ref().map(|meter| meter.interval) {
+            self.level_meter = config.level_report.map(LevelMeter::new);
+        }
+        if config.band_pass != self.band_pass.band {
+            self.band_pass = BandPass::new(config.band_pass);
+        }
+        if config.fsk != self.config.fsk {
+            self.fsk = config.fsk.map(FskDemodulator::new);
+        }
+        if (config.dtmf, config.dtmf_gap) != (self.config.dtmf, self.config.dtmf_gap) {
+            self.dtmf = config.dtmf.then(|| DtmfDecoder::new(config.dtmf_gap));
+        }
+        if config.call_progress != self.cadence.is_some() {
+            self.cadence = config.call_progress.then(CadenceDetector::new);
+        }
+        if config.tone_sequence.as_ref() != self.sequence.as_ref().map(SequenceMatcher::sequence) {
+            self.sequence = config.tone_sequence.clone().map(SequenceMatcher::new);
+        }
+        (self.history_len, self.post_len) = record_lens(&config);
+        self.profile = default_tone(&config);
+        self.panic_profile = panic_profile(&config);
+        self.config = config;
+        Ok(())
+    }
+
+    // The samples that follow were decoded from this format.
+    pub fn set_format(&mut self, format: PcmFormat) {
+        self.full_scale = format.full_scale();
+    }
+
+    // The rate the modem negotiated, which captures from it are resampled from.
+    pub(crate) fn set_input_rate(&mut self, rate: u32) {
+        self.input_rate = rate;
+    }
+
+    pub(crate) fn input_rate(&self) -> u32 {
+        self.input_rate
+    }
+
+    // A capture of the modem's audio in `format` at its negotiated rate.
+    pub(crate) fn capture(&self, format: PcmFormat) -> Capture {
+        Capture { rate: self.input_rate, ..Capture::new(format, &self.config) }
+    }
+
+    // Keep a report of every block judged from now on, collected with `take_reports`.
+    pub fn record_blocks(&mut self) {
+        self.reports.get_or_insert_with(Vec::new);
+    }
+
+    pub(crate) fn take_reports(&mut self) -> Vec<BlockReport> {
+        self.reports.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    // Publish the tone band's bin levels to the snapshot after every block.
+    pub(crate) fn publish_spectrum(&mut self, snapshot: SpectrumSnapshot) {
+        self.snapshot = Some(snapshot);
+    }
+
+    // Attach the caller to the call's detections from here on, switching to the tone profile configured for them. Unknown
+    // or absent numbers fall back to the default.
+    pub fn set_caller(&mut self, caller: Option<CallerId>) {
+        let matched = caller.as_ref()
+            .map(|caller| normalize_number(&caller.number))
+            .and_then(|number| self.config.tone_profiles.iter().find(|p| p.number == number));
+        self.caller = caller;
+
+        let default = default_tone(&self.config);
+        self.profile = match matched {
+            Some(caller) => {
+                info!("Using tone profile for caller {}: {}-{} Hz", caller.number, caller.band.min, caller.band.max);
+                let (min_power, max_power) = caller.power.unwrap_or((default.min_power, default.max_power));
+                ToneProfile { band: caller.band, min_power, max_power }
+            },
+            None => default
+        };
+    }
+
+    // Centre of the tone band currently being detected.
+    pub fn tone_frequency(&self) -> f32 {
+        (self.profile.band.min + self.profile.band.max) / 2.0
+    }
+
+    // Number of full blocks in which the tone was found, ignoring cooldowns and detect mode.
+    pub fn tone_blocks(&mut self, samples: &[i16]) -> usize {
+        let samples = self.filter(samples);
+        self.pending.extend_from_slice(&samples);
+
+        let mut found = 0;
+        let mut offset = 0;
+        while self.pending.len() - offset >= FFT_SAMPLE_SIZE {
+            let fft_output = self.spectrum.transform(&self.pending[offset..offset + FFT_SAMPLE_SIZE]);
+            offset += FFT_SAMPLE_SIZE;
+            if detect_tone(fft_output, &self.profile, self.config.power_unit, &self.config.tone_exclude_bands).is_some() {
+                found += 1;
+            }
+        }
+        self.pending.drain(..offset);
+        found
+    }
+
+    // Peak in-band and mean out-of-band bin power for each full block, used when calibrating thresholds.
+    pub fn band_powers(&mut self, samples: &[i16]) -> Vec<(f32, f32)> {
+        let samples = self.filter(samples);
+        self.pending.extend_from_slice(&samples);
+
+        let mut powers = Vec::new();
+        let mut offset = 0;
+        while self.pending.len() - offset >= FFT_SAMPLE_SIZE {
+            let fft_output = self.spectrum.transform(&self.pending[offset..offset + FFT_SAMPLE_SIZE]);
+            offset += FFT_SAMPLE_SIZE;
+            powers.push(band_peak_and_floor(fft_output, &self.profile.band, floor_band(&self.config)));
+        }
+        self.pending.drain(..offset);
+        powers
+    }
+
+    // Magnitude spectrum of each full block, used when learning a tone template.
+    pub fn block_spectra(&mut self, samples: &[i16]) -> Vec<Vec<f32>> {
+        let samples = self.filter(samples);
+        self.pending.extend_from_slice(&samples);
+
+        let mut spectra = Vec::new();
+        let mut offset = 0;
+        while self.pending.len() - offset >= FFT_SAMPLE_SIZE {
+            let fft_output = self.spectrum.transform(&self.pending[offset..offset + FFT_SAMPLE_SIZE]);
+            offset += FFT_SAMPLE_SIZE;
+            spectra.push(fft_output.iter().map(|bin| bin.norm()).collect());
+        }
+        self.pending.drain(..offset);
+        spectra
+    }
+
+    // Apply the configured front-end filters, counting any samples that clip.
+    fn filter(&mut self, samples: &[i16]) -> Vec<i16> {
+        let mut samples = samples.to_vec();
+        let mut clipped = 0;
+
+        if let Some(agc) = self.agc.as_mut() {
+            clipped += agc.process(&mut samples);
+        }
+        if let Some(coefficient) = self.config.pre_emphasis {
+            let mut block: Vec<f32> = samples.iter().map(|&s| s as f32).collect();
+            pre_emphasis(&mut block, coefficient, &mut self.pre_emphasis_state);
+            for (sample, value) in samples.iter_mut().zip(block) {
+                let (value, clip) = saturate_i16(value);
+                *sample = value;
+                clipped += clip as usize;
+            }
+        }
+        match self.config.filter {
+            InputFilter::BandPass => clipped += self.band_pass.process(&mut samples),
+            InputFilter::HighPass => clipped += high_pass_filter(&mut samples, self.config.high_pass_cutoff),
+            InputFilter::None => {}
+        }
+
+        if clipped > 0 {
+            debug!("Clipped {} samples, input gain may be too high", clipped);
+            self.clipped += clipped;
+        }
+        samples
+    }
+
+    // Number of samples clipped while filtering since the last call.
+    pub fn take_clipped(&mut self) -> usize {
+        std::mem::take(&mut self.clipped)
+    }
+
+    // The spectrum is skipped when the Goertzel detector is all that would look at it.
+    fn needs_spectrum(&self) -> bool {
+        self.config.tone_detector == ToneDetector::Fft
+            || self.average.is_some()
+            || self.snapshot.is_some()
+            || self.template.is_some()
+            || self.whitener.is_some()
+            || self.reports.is_some()
+            || self.config.harmonic.is_some()
+            || self.config.min_snr_db.is_some()
+            || self.config.spectrum_peaks > 0
+    }
+
+    fn retain_history(&mut self, samples: &[i16]) {
+        if self.history_len == 0 {
+            return;
+        }
+        self.history.extend(samples);
+        let excess = self.history.len().saturating_sub(self.history_len);
+        self.history.drain(..excess);
+    }
+
+    // Run detection over a block of decoded samples, returning the events that should fire.
+    pub fn process(&mut self, samples: &[i16]) -> Vec<DetectionEvent> {
+        let mut events = self.detect_tones(samples);
+        // FSK data is decoded from the samples as they arrive, without the tone filters or waiting on line activity.
+        let messages = self.fsk.as_mut().map(|fsk| fsk.feed(samples)).unwrap_or_default();
+        for message in messages {
+            // Type I caller ID is FSK too, sent between the rings, so it identifies the caller rather than reports.
+            match parse_fsk_cid(&message) {
+                Some(caller) => {
+                    info!("Caller ID {} from FSK", caller.number);
+                    self.set_caller(Some(caller));
+                },
+                None => events.push(self.fsk_event(message))
+            }
+        }
+        // DTMF too, so in-band digits are logged even from a modem that doesn't report them shielded.
+        for digits in self.dtmf.as_mut().map(|dtmf| dtmf.feed(samples)).unwrap_or_default() {
+            info!("DTMF digits {}", digits);
+        }
+        self.tag_caller(&mut events);
+        self.save_recordings(&mut events);
+        events
+    }
+
+    fn detect_tones(&mut self, samples: &[i16]) -> Vec<DetectionEvent> {
+        self.retain_history(samples);
+        if let Some(meter) = self.level_meter.as_mut() {
+            meter.update(samples, self.full_scale);
+        }
+        if let Some(pipe) = self.pcm_pipe.as_mut() {
+            pipe.forward(samples);
+        }
+        let filtered = self.filter(samples);
+
+        // Skip detection entirely on dead air when voice activity detection is enabled.
+        let mut line_active = true;
+        if let Some(vad) = self.vad.as_mut() {
+            match vad.process(&filtered) {
+                Some(VadEvent::SpeechStart) => info!("Line activity started"),
+                Some(VadEvent::SpeechEnd) => info!("Line activity ended"),
+                None => {}
+            }
+            line_active = vad.is_active();
+        }
+
+        // Tones are judged per fixed-size block accumulated across reads, so reads of any length can't change the transform size.
+        // Blocks start every `frame_hop` samples, overlapping when the hop is shorter than a block.
+        self.pending.extend_from_slice(&filtered);
+        let mut detections = Vec::new();
+        let mut tone_heard = false;
+        let mut offset = 0;
+        loop {
+            let full = self.pending.len() - offset >= FFT_SAMPLE_SIZE;
+            // When samples trickle in, judge the partial block early rather than hold new audio past the latency bound.
+            let overdue = !full && self.pending.len() > self.framed_to
+                && self.config.max_frame_latency.is_some_and(|latency| self.last_frame.elapsed() >= latency);
+            if !full && !overdue {
+                break;
+            }
+            let end = (offset + FFT_SAMPLE_SIZE).min(self.pending.len());
+            let at = sample_offset(self.pending_start + end as u64);
+            let block = &self.pending[offset..end];
+            self.framed_to = end;
+            self.last_frame = Instant::now();
+            let hop = Duration::from_secs_f32(self.config.frame_hop as f32 / SAMPLE_RATE);
+            let (tone_present, answer_tone_present, panic_present, sequence_peak) = if line_active {
+                let needs_spectrum = self.needs_spectrum();
+                let unit = self.config.power_unit;
+                let exclude = &self.config.tone_exclude_bands;
+                let gain = self.spectrum.gain;
+                let mut fft_output = needs_spectrum.then(|| self.spectrum.transform(block));
+                if let (Some(average), Some(output)) = (self.average.as_mut(), fft_output) {
+                    fft_output = Some(average.update(output));
+                }
+                if let Some(fft_output) = fft_output.filter(|_| self.config.spectrum_peaks > 0 && log_enabled!(Level::Debug)) {
+                    let peaks = top_peaks(fft_output, self.config.spectrum_peaks);
+                    debug!("Spectrum peaks ({}): {}", unit.name(), format_peaks(&peaks, unit));
+                }
+                if let (Some(snapshot), Some(fft_output)) = (&self.snapshot, fft_output) {
+                    let band = &self.profile.band;
+                    snapshot.update(band.min, band.max, unit.name(), &band_levels(fft_output, band, unit));
+                }
+                let detect = |profile: &ToneProfile| match fft_output.filter(|_| self.config.tone_detector == ToneDetector::Fft) {
+                    Some(fft_output) => detect_tone(fft_output, profile, unit, exclude),
+                    None => detect_tone_goertzel(block, gain, profile, unit, exclude)
+                };
+                let panic_present = self.panic_profile.is_some_and(|profile| detect(&profile).is_some());
+                let mut peak = match (&self.template, self.whitener.as_mut(), fft_output) {
+                    (Some(template), _, Some(fft_output)) => template.detect(fft_output, self.config.template_min_correlation),
+                    (None, Some(whitener), Some(fft_output)) => whitener.detect(fft_output, &self.profile.band, exclude),
+                    // Without the power gate any in-band peak is a candidate, the SNR margin alone deciding.
+                    _ if !self.config.power_gate => {
+                        detect(&ToneProfile { min_power: f32::MIN, max_power: f32::MAX, ..self.profile })
+                    },
+                    _ => detect(&self.profile)
+                };
+                // Optionally insist on a harmonic too, rejecting single frequency interferers.
+                if let (Some((tolerance, min_ratio)), Some(frequency), Some(fft_output)) = (self.config.harmonic, peak, fft_output) {
+                    if !has_harmonic(fft_output, frequency, tolerance, min_ratio) {
+                        debug!("Tone at {} Hz has no harmonic, ignoring it", frequency);
+                        peak = None;
+                    }
+                }
+                // A tone must also clear the noise floor by the configured margin.
+                if let (Some(min_snr), Some(floor), Some(fft_output)) = (self.config.min_snr_db, self.noise_floor.as_mut(), fft_output) {
+                    let (signal, noise) = band_peak_and_floor(fft_output, &self.profile.band, floor_band(&self.config));
+                    let snr = snr_db(signal, floor.update(noise));
+                    if peak.is_some() && snr < min_snr {
+                        debug!("Tone SNR {:.1} dB is below {} dB", snr, min_snr);
+                        peak = None;
+                    }
+                }
+                if peak.is_some() {
+                    self.peak_frequency = peak;
+                }
+                let mut tone_present = match self.drift.as_mut() {
+                    Some(drift) => drift.update(peak),
+                    None => peak.is_some()
+                };
+                if let Some(vote) = self.vote.as_mut() {
+                    tone_present = vote.update(tone_present);
+                }
+                if let Some(hysteresis) = self.hysteresis.as_mut() {
+                    tone_present = hysteresis.update(tone_present);
+                }
+                if let (Some(reports), Some(fft_output)) = (self.reports.as_mut(), fft_output) {
+                    let (signal, noise) = band_peak_and_floor(fft_output, &self.profile.band, floor_band(&self.config));
+                    let floor = self.noise_floor.as_ref().and_then(|floor| floor.power).unwrap_or(noise);
+                    reports.push(BlockReport {
+                        offset: at,
+                        level: self.config.power_unit.level(signal),
+                        snr_db: snr_db(signal, floor),
+                        frequency: peak,
+                        tone: tone_present
+                    });
+                }
+                // Each step is looked for with the target tone's thresholds, and like cadence only full blocks advance it.
+                let gated = !self.config.power_gate;
+                let sequence_peak = self.sequence.as_mut().filter(|_| full).and_then(|sequence| sequence.update(|band| {
+                    let profile = ToneProfile { band: *band, ..self.profile };
+                    detect(&if gated { ToneProfile { min_power: f32::MIN, max_power: f32::MAX, ..profile } } else { profile })
+                }, hop));
+                (tone_present, detect_answer_tone(block, &self.config), panic_present, sequence_peak)
+            } else {
+                let sequence_peak = self.sequence.as_mut().filter(|_| full).and_then(|sequence| sequence.update(|_| None, hop));
+                (false, false, false, sequence_peak)
+            };
+            tone_heard |= tone_present || answer_tone_present || panic_present;
+
+            // With a sequence configured the trigger waits for all of it, the tone alone no longer being enough.
+            let triggered = match self.sequence {
+                Some(_) => sequence_peak.is_some(),
+                None => tone_present
+            };
+            if let Some(frequency) = sequence_peak {
+                debug!("Tone sequence completed");
+                self.peak_frequency = Some(frequency);
+            }
+            match self.config.detect_mode {
+                DetectMode::Presence => if self.trigger.update(triggered, self.config.min_gap, self.config.detection_interval) {
+                    debug!("Tone detected!");
+                    detections.push((Detection::Trigger, at));
+                },
+                DetectMode::Absence => if self.absence.update(tone_present, self.config.absence_timeout) {
+                    debug!("Tone missing for {:#?}!", self.config.absence_timeout);
+                    detections.push((Detection::ToneAbsent, at));
+                }
+            }
+            if self.answer_tone.update(answer_tone_present, self.config.min_gap, self.config.detection_interval) {
+                debug!("Answer tone detected!");
+                detections.push((Detection::AnswerTone, at));
+            }
+            if self.panic.update(panic_present, self.config.min_gap, self.config.detection_interval) {
+                warn!("Panic tone detected!");
+                detections.push((Detection::Panic, at));
+            }
+
+            // Cadence runs on stream time, which only full blocks advance.
+            if let Some(cadence) = self.cadence.as_mut().filter(|_| full) {
+                let unit = self.config.power_unit;
+                let present = line_active && unit.level(goertzel(block, CALL_PROGRESS_FREQ)) > unit.level(CALL_PROGRESS_MIN_POWER);
+                if let Some(progress) = cadence.update(present, hop) {
+                    debug!("Call progress: {:?}", progress);
+                    detections.push((Detection::CallProgress(progress), at));
+                }
+            }
+
+            if !full {
+                break;
+            }
+            offset += self.config.frame_hop;
+        }
+        self.pending.drain(..offset);
+        self.pending_start += offset as u64;
+        self.framed_to = self.framed_to.saturating_sub(offset);
+
+        for (detection, _) in &detections {
+            self.record_fired(*detection);
+        }
+
+        let now = SystemTime::now();
+        let level_dbfs = (!detections.is_empty()).then(|| rms_dbfs(samples, self.full_scale));
+        let mut detections: Vec<_> = detections.into_iter().map(|(detection, at)| (detection, now, at)).collect();
+        if !records_audio(&self.config) || self.post_len == 0 {
+            let audio = records_audio(&self.config).then(|| self.history.iter().copied().collect());
+            return to_events(detections, audio, self.peak_frequency, level_dbfs);
+        }
+
+        // With a post window the events are held back until the tail has been recorded.
+        match self.recording.as_mut() {
+            Some(recording) => {
+                recording.audio.extend_from_slice(samples);
+                recording.detections.append(&mut detections);
+                recording.post_remaining = if tone_heard {
+                    self.post_len
+                } else {
+                    recording.post_remaining.saturating_sub(samples.len())
+                };
+                if recording.post_remaining == 0 {
+                    return self.recording.take().map(Recording::into_events).unwrap_or_default();
+                }
+            },
+            None if !detections.is_empty() => self.recording = Some(Recording {
+                detections,
+                audio: self.history.iter().copied().collect(),
+                post_remaining: self.post_len,
+                frequency: self.peak_frequency,
+                level_dbfs
+            }),
+            None => {}
+        }
+        Vec::new()
+    }
+
+    // The line has been silent (reads timing out) for the configured duration: drop any partial block and tone state,
+    // and let absence detection see the missing tone since no blocks are arriving to drive it.
+    pub fn silence(&mut self) -> Vec<DetectionEvent> {
+        self.pending_start += self.pending.len() as u64;
+        self.pending.clear();
+        self.framed_to = 0;
+        if let Some(drift) = self.drift.as_mut() {
+            drift.update(None);
+        }
+        if let Some(vote) = self.vote.as_mut() {
+            *vote = MajorityVote::new(vote.required, vote.window);
+        }
+        if let Some(hysteresis) = self.hysteresis.as_mut() {
+            *hysteresis = Hysteresis::new(hysteresis.on, hysteresis.off);
+        }
+        self.trigger.update(false, self.config.min_gap, self.config.detection_interval);
+        self.answer_tone.update(false, self.config.min_gap, self.config.detection_interval);
+        self.panic.update(false, self.config.min_gap, self.config.detection_interval);
+
+        if self.config.detect_mode != DetectMode::Absence || !self.absence.update(false, self.config.absence_timeout) {
+            return Vec::new();
+        }
+        debug!("Tone missing for {:#?}!", self.config.absence_timeout);
+        self.record_fired(Detection::ToneAbsent);
+        let mut events = to_events(vec![(Detection::ToneAbsent, SystemTime::now(), self.call_offset())], None, None, None);
+        self.tag_caller(&mut events);
+        events
+    }
+
+    // Start counting samples and classifying call progress afresh for a new call.
+    pub fn start_call(&mut self) {
+        self.pending_start = 0;
+        self.pending.clear();
+        self.framed_to = 0;
+        if let Some(average) = self.average.as_mut() {
+            average.reset();
+        }
+        if let Some(sequence) = self.sequence.as_mut() {
+            sequence.reset();
+        }
+        if let Some(cadence) = self.cadence.as_mut() {
+            *cadence = CadenceDetector::new();
+        }
+        if let Some(fsk) = self.fsk.as_mut() {
+            fsk.reset();
+        }
+        if let Some(dtmf) = self.dtmf.as_mut() {
+            dtmf.reset();
+        }
+        if let Some(agc) = self.agc.as_mut() {
+            *agc = Agc::new(agc.settings);
+        }
+    }
+
+    // How far into the call's audio detection has reached.
+    pub fn call_offset(&self) -> Duration {
+        sample_offset(self.pending_start + self.pending.len() as u64)
+    }
+
+    // Release any events still waiting on their post-trigger recording, e.g. when the call ends.
+    pub fn flush(&mut self) -> Vec<DetectionEvent> {
+        let mut events = self.recording.take().map(Recording::into_events).unwrap_or_default();
+        if let Some(message) = self.fsk.as_mut().and_then(FskDemodulator::finish) {
+            events.push(self.fsk_event(message));
+        }
+        if let Some(digits) = self.dtmf.as_mut().and_then(DtmfDecoder::finish) {
+            info!("DTMF digits {}", digits);
+        }
+        self.tag_caller(&mut events);
+        self.save_recordings(&mut events);
+        events
+    }
+
+    pub fn caller(&self) -> Option<CallerId> {
+        self.caller.clone()
+    }
+
+    fn tag_caller(&self, events: &mut [DetectionEvent]) {
+        for event in events.iter_mut() {
+            event.caller = self.caller.clone();
+        }
+    }
+
+    // Write each detection's audio to the record directory as a WAV file, keeping it on the event only when the
+    // webhook attaches it too. Clips are written whole, so even one saved just before shutdown is complete.
+    fn save_recordings(&self, events: &mut [DetectionEvent]) {
+        let Some(dir) = self.config.record_dir.as_ref().filter(|_| self.config.mode == RunMode::Active) else {
+            return;
+        };
+        for event in events.iter_mut() {
+            let Some(audio) = event.audio.as_ref() else {
+                continue;
+            };
+            let millis = event.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+            let path = Path::new(dir).join(format!("{}-{}.wav", millis, event.detection.label()));
+            match fs::create_dir_all(dir).and_then(|_| fs::write(&path, encode_wav(audio, SAMPLE_RATE as u32))) {
+                Ok(()) => info!("Saved {} recording to {}", event.detection.label(), path.display()),
+                Err(e) => error!("Failed to save recording {}: {}", path.display(), e)
+            }
+            if !self.config.webhook_attach_audio {
+                event.audio = None;
+            }
+        }
+    }
+
+    fn fsk_event(&mut self, message: Vec<u8>) -> DetectionEvent {
+        self.record_fired(Detection::FskData);
+        DetectionEvent {
+            detection: Detection::FskData,
+            timestamp: SystemTime::now(),
+            audio: None,
+            contact_id: None,
+            fsk_data: Some(message),
+            frequency: None,
+            offset: Some(self.call_offset()),
+            level_dbfs: None,
+            caller: None
+        }
+    }
+
+    fn record_fired(&mut self, detection: Detection) {
+        if let Some(state_file) = &self.state_file {
+            self.last_fired.insert(detection.label().to_string(), SystemTime::now());
+            if let Err(e) = state_file.save(&self.last_fired) {
+                warn!("Failed to save detection state: {:#}", e);
+            }
+        }
+    }
+}
+
+
+fn line_active(port: &mut dyn SerialPort, gate: &LineGate) -> Result<bool> {
+    let level = match gate.line {
+        ControlLine::Dsr => port.read_data_set_ready()?,
+        ControlLine::Cts => port.read_clear_to_send()?,
+        ControlLine::Ri => port.read_ring_indicator()?,
+        ControlLine::Cd => port.read_carrier_detect()?
+    };
+    Ok(level != gate.active_low)
+}
+
+// A fault the modem reported on the line, outside any tone.
+pub(crate) fn line_fault(status: LineStatus, detector: &Detector) -> DetectionEvent {
+    DetectionEvent {
+        detection: Detection::LineFault(status),
+        timestamp: SystemTime::now(),
+        audio: None,
+        contact_id: None,
+        fsk_data: None,
+        frequency: None,
+        offset: Some(detector.call_offset()),
+        level_dbfs: None,
+        caller: detector.caller()
+    }
+}
+
+pub(crate) fn deliver(events: Vec<DetectionEvent>, sinks: &mut [Box<dyn DetectionSink>], stats: &mut SessionStats) {
+    for event in events {
+        let span = Span::start("detection");
+        span.set_str("event", event.detection.label());
+        match (&event.contact_id, event.frequency) {
+            // Already warned about where the stall or fault was noticed.
+            _ if matches!(event.detection, Detection::Stalled | Detection::LineFault(_)) => {},
+            (Some(contact_id), _) => {
+                info!("Received Contact ID event {} ({}) from account {}", contact_id.code, contact_id.description, contact_id.account);
+                span.set_str("contact_id.code", &contact_id.code.to_string());
+            },
+            (None, _) if event.fsk_data.is_some() => {
+                let data = event.fsk_data.as_deref().unwrap_or_default();
+                info!("Received {} bytes of FSK data: {}", data.len(), String::from_utf8_lossy(data).escape_debug());
+                span.set_f64("fsk.bytes", data.len() as f64);
+            },
+            (None, Some(frequency)) => {
+                info!("Detected {} tone at {:.2} Hz", event.detection.label(), frequency);
+                span.set_f64("frequency", frequency as f64);
+            },
+            (None, None) => info!("Detected {} tone", event.detection.label())
+        }
+        stats.record_detection(event.detection.label(), event.frequency, event.level_dbfs);
+        for sink in dispatch(sinks, &AlertPayload::from_event(&event, SAMPLE_RATE as u32)) {
+            stats.record_sink_failure(sink);
+        }
+    }
+}
+
+// Returns once the call ends or a shutdown is requested. Reads time out after the short ALARM_READ_TIMEOUT_MS while
+// listening, so a quiet line still turns the loop for the shutdown flag, reloads and the watchdogs, and commands get
+// their own longer timeout back afterwards.
+pub(crate) fn listen(
+    port: &mut dyn SerialPort,
+    format: PcmFormat,
+    detector: &mut Detector,
+    sinks: &mut [Box<dyn DetectionSink>],
+    stats: &mut SessionStats,
+    watchdog: &mut Watchdog,
+    caller: Option<CallerId>
+) -> Result<()> {
+    let command_timeout = port.timeout();
+    port.set_timeout(detector.config().read_timeout)?;
+    let result = listen_call(port, format, detector, sinks, stats, watchdog, caller);
+    // A port that has gone away fails this too, its error is the one worth reporting.
+    if let Err(e) = port.set_timeout(command_timeout) {
+        debug!("Failed to restore the command read timeout: {}", e);
+    }
+    result
+}
+
+fn listen_call(
+    port: &mut dyn SerialPort,
+    format: PcmFormat,
+    detector: &mut Detector,
+    sinks: &mut [Box<dyn DetectionSink>],
+    stats: &mut SessionStats,
+    watchdog: &mut Watchdog,
+    caller: Option<CallerId>
+) -> Result<()> {
+    let call_start = Instant::now();
+    detector.take_clipped();
+    let watch_cid = caller.is_none() && (detector.config().caller_id || !detector.config().tone_profiles.is_empty());
+    detector.set_caller(caller);
+    detector.start_call();
+    let mut line_was_active = true;
+
+    // Keep the end of the previous read so a NO CARRIER split across reads is still seen.
+    let mut tail: Vec<u8> = Vec::with_capacity(NO_CARRIER.len());
+    let mut cid_window: Option<Vec<u8>> = watch_cid.then(Vec::new);
+    let mut dle = DleDecoder::new(detector.config().strip_xon_xoff);
+    let mut results = ResultCodeFilter::new();
+    let mut audio: Vec<u8> = Vec::new(); // Unescaped audio, holding back a partial sample until the rest arrives
+    let mut capture = detector.capture(format);
+    let mut silent_since: Option<Instant> = None;
+    let mut silence_reported = false;
+    let mut contact_id_digits = String::new();
+    let mut backoff = IdleBackoff::new(detector.config().idle_backoff_min, detector.config().idle_backoff_max);
+    let mut raw = detector.config().capture_raw.as_deref().map(|path| RawCapture::start(path, format)).transpose()?;
+    let mut last_data = Instant::now();
+    let mut stall_reported = false;
+
+    let mut buffer = vec![0; 1024];
+    while !SHUTDOWN.load(Ordering::Relaxed) {
+        watchdog.tick();
+        reload::apply_pending(detector, sinks);
+        sinks.iter_mut().for_each(|sink| sink.tick(stats));
+        // A line that has dropped or a modem that has frozen only ever times out, so the quiet is alerted on itself.
+        if let Some(timeout) = detector.config().stall_timeout.filter(|_| !stall_reported) {
+            if last_data.elapsed() >= timeout {
+                warn!("No audio from the modem for {:#?}, monitoring has stalled", last_data.elapsed());
+                stall_reported = true;
+                deliver(vec![DetectionEvent {
+                    detection: Detection::Stalled,
+                    timestamp: SystemTime::now(),
+                    audio: None,
+                    contact_id: None,
+                    fsk_data: None,
+                    frequency: None,
+                    offset: Some(detector.call_offset()),
+                    level_dbfs: None,
+                    caller: detector.caller()
+                }], sinks, stats);
+            }
+        }
+        match port.read(&mut buffer) {
+            Ok(n) if n > 0 => {
+                trace_serial("Read", &buffer[..n]);
+                if let Some(raw) = raw.as_mut() {
+                    raw.write(&buffer[..n]);
+                }
+                stats.add_bytes(n);
+                watchdog.data_received();
+                backoff.active();
+                if std::mem::replace(&mut stall_reported, false) {
+                    info!("Audio resumed after {:#?} without any", last_data.elapsed());
+                }
+                last_data = Instant::now();
+                silent_since = None;
+                silence_reported = false;
+                // The far end hanging up is reported either as a DLE shielded end-of-stream or a text result code.
+                let mut unescaped = Vec::with_capacity(n);
+                let events = dle.feed(&buffer[..n], &mut unescaped);
+                for code in results.feed(&unescaped, &mut audio) {
+                    info!("Modem sent {} within the audio stream, dropped it from the audio", code);
+                    // NO CARRIER is how a call ends, anything else unhealthy means the line itself has a fault.
+                    let status = LineStatus::from_result(code);
+                    if !matches!(status, LineStatus::Healthy | LineStatus::NoCarrier) {
+                        warn!("Modem reported {} during the call, the line may be faulty", code);
+                        deliver(vec![line_fault(status, detector)], sinks, stats);
+                    }
+                }
+                tail.extend_from_slice(&buffer[..n]);
+                if events.contains(&DleEvent::EndOfStream) || tail.windows(NO_CARRIER.len()).any(|w| w == NO_CARRIER) {
+                    info!("Call ended after {:#?}, {} samples clipped", call_start.elapsed(), detector.take_clipped());
+                    // As for any other read, audio with the gated line inactive isn't processed.
+                    if !line_was_active {
+                        audio.clear();
+                    }
+                    end_call(&mut capture, &mut audio, detector, sinks, stats);
+                    return Ok(());
+                }
+                tail.drain(..tail.len().saturating_sub(NO_CARRIER.len() - 1));
+
+                // Watch for the caller ID until one arrives, then report it and use that panel's tone profile for the call.
+                if let Some(window) = cid_window.as_mut() {
+                    window.extend_from_slice(&buffer[..n]);
+                    if let Some(caller) = parse_cid(&String::from_utf8_lossy(window)) {
+                        info!("Caller ID {}", caller.number);
+                        detector.set_caller(Some(caller));
+                        cid_window = None;
+                    } else {
+                        window.drain(..window.len().saturating_sub(CID_WINDOW));
+                    }
+                }
+
+                // A panel reporting with Contact ID sends a 16 digit DTMF message, which the modem passes on shielded.
+                let digits = events.iter().filter_map(|event| match event {
+                    DleEvent::Dtmf(digit) => Some(*digit),
+                    _ => None
+                });
+                for digit in digits {
+                    contact_id_digits.push(digit);
+                    if contact_id_digits.len() == CONTACT_ID_DIGITS {
+                        match contact_id::parse(&contact_id_digits) {
+                            Ok(event) => deliver(vec![DetectionEvent {
+                                detection: Detection::ContactId,
+                                timestamp: SystemTime::now(),
+                                audio: None,
+                                contact_id: Some(event),
+                                fsk_data: None,
+                                frequency: None,
+                                offset: Some(detector.call_offset()),
+                                level_dbfs: None,
+                                caller: detector.caller()
+                            }], sinks, stats),
+                            Err(e) => warn!("Ignoring DTMF digits {}: {:#}", contact_id_digits, e)
+                        }
+                        contact_id_digits.clear();
+                    }
+                }
+
+                // Only process audio while the configured control line reports the line as active.
+                if let Some(gate) = &detector.config().line_gate {
+                    let active = line_active(port, gate)?;
+                    if active != line_was_active {
+                        info!("Line {:?} is now {}", gate.line, if active { "active" } else { "inactive" });
+                        line_was_active = active;
+                    }
+                    if !active {
+                        audio.clear();
+                        continue;
+                    }
+                }
+
+                // Process the samples using FFT, checking for non-repeated tone triggers (exceeding detection interval).
+                let started = Instant::now();
+                let samples = capture.take_samples(&mut audio);
+                // A read of nothing but DLE events decodes to no audio, leaving nothing to detect in.
+                if samples.is_empty() {
+                    continue;
+                }
+                detector.set_format(capture.format);
+                let events = detector.process(&samples);
+                stats.record_processing(started.elapsed(), sample_offset(samples.len() as u64));
+                deliver(events, sinks, stats);
+            }
+            Ok(_) => sleep(backoff.idle()),
+            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                // Consecutive timeouts add up to silence, reported once then fed to the detector while it lasts.
+                if let Some(threshold) = detector.config().silence {
+                    let since = *silent_since.get_or_insert_with(Instant::now);
+                    if since.elapsed() >= threshold {
+                        if !std::mem::replace(&mut silence_reported, true) {
+                            info!("Line silent for {} ms", since.elapsed().as_millis());
+                        }
+                        deliver(detector.silence(), sinks, stats);
+                    }
+                }
+                sleep(backoff.idle());
+            },
+            Err(e) => return Err(anyhow!(e))
+        }
+    }
+    end_call(&mut capture, &mut audio, detector, sinks, stats);
+    Ok(())
+}
+
+// Detect in whatever audio the call ended with, still held back or arriving alongside the end, then flush.
+fn end_call(
+    capture: &mut Capture,
+    audio: &mut Vec<u8>,
+    detector: &mut Detector,
+    sinks: &mut [Box<dyn DetectionSink>],
+    stats: &mut SessionStats
+) {
+    let samples = capture.finish(audio);
+    if !samples.is_empty() {
+        detector.set_format(capture.format);
+        deliver(detector.process(&samples), sinks, stats);
+    }
+    deliver(detector.flush(), sinks, stats);
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::Mutex;
+    use crate::config::test_config;
+    use crate::dle::{DLE, ETX};
+    use crate::mock_port::MockPort;
+    use crate::sink::CallbackSink;
+    use crate::wav::decode_wav;
+
+    const BAND: FrequencyBand = FrequencyBand { min: 1640.0, max: 1720.0 };
+    const AMPLITUDE: f32 = 1000.0;
+
+    fn sine(frequency: f32, amplitude: f32) -> Vec<f32> {
+        (0..FFT_SAMPLE_SIZE)
+            .map(|i| amplitude * (2.0 * std::f32::consts::PI * frequency * i as f32 / SAMPLE_RATE).sin())
+            .collect()
+    }
+
+    // Uniform noise from a fixed seed, so every run hears the same line.
+    fn noise(amplitude: f32) -> Vec<f32> {
+        let mut state = 0x2545_f491_u32;
+        (0..FFT_SAMPLE_SIZE)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                amplitude * (state as f32 / u32::MAX as f32 * 2.0 - 1.0)
+            })
+            .collect()
+    }
+
+    fn mix(signals: &[Vec<f32>]) -> Vec<i16> {
+        (0..FFT_SAMPLE_SIZE).map(|i| saturate_i16(signals.iter().map(|signal| signal[i]).sum()).0).collect()
+    }
+
+    // The block as detection sees it: high-passed at the default cutoff, then transformed with the default window.
+    fn spectrum(mut samples: Vec<i16>) -> Vec<Complex<f32>> {
+        high_pass_filter(&mut samples, 3000.0);
+        Spectrum::new(1, 1.0, WindowFn::Hann).unwrap().transform(&samples).to_vec()
+    }
+
+    // Thresholds around the level of an AMPLITUDE tone, whose bin power is about (AMPLITUDE * N / 2)^2 once the
+    // high-pass has taken its share.
+    fn profile() -> ToneProfile {
+        ToneProfile { band: BAND, min_power: 1e10, max_power: 1e12 }
+    }
+
+    #[test]
+    fn detects_tone_at_1665_hz() {
+        let fft = spectrum(mix(&[sine(1665.0, AMPLITUDE)]));
+        let frequency = detect_tone(&fft, &profile(), PowerUnit::Power, &[]).expect("tone not detected");
+        assert!((frequency - 1665.0).abs() < 1.0, "detected at {} Hz", frequency);
+    }
+
+    // A modem that settled on 11025 Hz rather than 8000 still has its tone found where it is once captured.
+    #[test]
+    fn capture_resamples_a_negotiated_rate() {
+        let rate = 11025;
+        let samples: Vec<i16> = (0..FFT_SAMPLE_SIZE * 3)
+            .map(|i| (AMPLITUDE * (2.0 * std::f32::consts::PI * 1665.0 * i as f32 / rate as f32).sin()) as i16)
+            .collect();
+        let mut raw: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        let mut capture = Capture {
+            format: PcmFormat::SignedLe16,
+            channels: 1,
+            channel: 0,
+            decimation: 1,
+            rate,
+            format_checked: true,
+            decimator: Decimator::new(1),
+            anti_alias: None,
+            resample_at: 0.0,
+            resample_last: None
+        };
+        let captured = capture.take_samples(&mut raw);
+        assert_eq!(captured.len(), FFT_SAMPLE_SIZE * 3 * SAMPLE_RATE as usize / rate as usize);
+        let fft = spectrum(captured[..FFT_SAMPLE_SIZE].to_vec());
+        let frequency = detect_tone(&fft, &profile(), PowerUnit::Power, &[]).expect("tone not detected");
+        assert!((frequency - 1665.0).abs() < 2.0, "detected at {} Hz", frequency);
+    }
+
+    // Reads that don't split evenly into output samples carry on where the last left off, rather than each restarting
+    // on an input sample and shifting the tone's phase at every boundary.
+    #[test]
+    fn capture_resamples_across_reads() {
+        // Up from 7200 Hz is interpolated only, down from 11025 Hz is low-passed first.
+        for rate in [7200, 11025] {
+            let samples: Vec<i16> = (0..FFT_SAMPLE_SIZE * 3)
+                .map(|i| (AMPLITUDE * (2.0 * std::f32::consts::PI * 1665.0 * i as f32 / rate as f32).sin()) as i16)
+                .collect();
+            let raw: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+            let capture = || Capture {
+                format: PcmFormat::SignedLe16,
+                channels: 1,
+                channel: 0,
+                decimation: 1,
+                rate,
+                format_checked: true,
+                decimator: Decimator::new(1),
+                anti_alias: None,
+                resample_at: 0.0,
+                resample_last: None
+            };
+
+            let whole = capture().take_samples(&mut raw.clone());
+            let mut reading = capture();
+            let read: Vec<i16> = raw.chunks(74).flat_map(|chunk| reading.take_samples(&mut chunk.to_vec())).collect();
+            assert_eq!(read, whole, "reads at {} Hz resampled differently from one read", rate);
+            let fft = spectrum(read[FFT_SAMPLE_SIZE..FFT_SAMPLE_SIZE * 2].to_vec());
+            let frequency = detect_tone(&fft, &profile(), PowerUnit::Power, &[]).expect("tone not detected");
+            assert!((frequency - 1665.0).abs() < 2.0, "detected at {} Hz from {} Hz", frequency, rate);
+        }
+    }
+
+    // A tone midway between two of the unpadded bins, which interpolation alone can only place so well.
+    #[test]
+    fn zero_padding_locates_a_tone_between_bins() {
+        let frequency = 1667.9;
+        let samples = mix(&[sine(frequency, AMPLITUDE)]);
+        let error = |zero_pad: usize| {
+            let mut filtered = samples.clone();
+            high_pass_filter(&mut filtered, 3000.0);
+            let fft = Spectrum::new(zero_pad, 1.0, WindowFn::Hann).unwrap().transform(&filtered).to_vec();
+            let detected = detect_tone(&fft, &profile(), PowerUnit::Power, &[]).expect("tone not detected");
+            (detected - frequency).abs()
+        };
+        let (unpadded, padded) = (error(1), error(4));
+        assert!(padded < unpadded / 4.0, "padded {} Hz off, unpadded {} Hz off", padded, unpadded);
+    }
+
+    // Off the bin grid the peak is placed well within a bin, and a band narrower than a bin holds the tone only when
+    // the interpolated frequency falls inside it.
+    #[test]
+    fn interpolation_places_the_peak_between_bins() {
+        let bin_hz = SAMPLE_RATE / FFT_SAMPLE_SIZE as f32;
+        for frequency in [1665.0, 1670.5, 1667.9] {
+            let detected = detect_tone(&spectrum(mix(&[sine(frequency, AMPLITUDE)])), &profile(), PowerUnit::Power, &[]);
+            let error = (detected.expect("tone not detected") - frequency).abs();
+            assert!(error < bin_hz / 8.0, "{} Hz detected {} Hz off", frequency, error);
+        }
+        let narrow = ToneProfile { band: FrequencyBand { min: 1660.0, max: 1668.0 }, ..profile() };
+        assert!(detect_tone(&spectrum(mix(&[sine(1665.0, AMPLITUDE)])), &narrow, PowerUnit::Power, &[]).is_some());
+        assert_eq!(detect_tone(&spectrum(mix(&[sine(1670.5, AMPLITUDE)])), &narrow, PowerUnit::Power, &[]), None);
+    }
+
+    #[test]
+    fn ignores_tone_outside_band() {
+        let fft = spectrum(mix(&[sine(800.0, AMPLITUDE)]));
+        assert_eq!(detect_tone(&fft, &profile(), PowerUnit::Power, &[]), None);
+    }
+
+    #[test]
+    fn detects_tone_in_noise_and_interference() {
+        let fft = spectrum(mix(&[sine(1665.0, AMPLITUDE), sine(800.0, AMPLITUDE), noise(2000.0)]));
+        let frequency = detect_tone(&fft, &profile(), PowerUnit::Power, &[]).expect("tone not detected");
+        assert!((frequency - 1665.0).abs() < 2.0, "detected at {} Hz", frequency);
+    }
+
+    #[test]
+    fn ignores_noise_alone() {
+        let fft = spectrum(mix(&[sine(800.0, AMPLITUDE), noise(2000.0)]));
+        assert_eq!(detect_tone(&fft, &profile(), PowerUnit::Power, &[]), None);
+    }
+
+    #[test]
+    fn rejects_quiet_tone() {
+        let fft = spectrum(mix(&[sine(1665.0, AMPLITUDE / 100.0)]));
+        assert_eq!(detect_tone(&fft, &profile(), PowerUnit::Power, &[]), None);
+    }
+
+    #[test]
+    fn ignores_excluded_band() {
+        let fft = spectrum(mix(&[sine(1665.0, AMPLITUDE)]));
+        let exclude = [FrequencyBand { min: 1650.0, max: 1680.0 }];
+        assert_eq!(detect_tone(&fft, &profile(), PowerUnit::Power, &exclude), None);
+    }
+
+    // A stronger hum inside the band is passed over for the real tone, and alone triggers nothing.
+    #[test]
+    fn excluded_interferer_leaves_the_tone_detectable() {
+        let exclude = [FrequencyBand { min: 1695.0, max: 1705.0 }];
+        let profile = ToneProfile { band: BAND, min_power: 1e10, max_power: 1e13 };
+        let fft = spectrum(mix(&[sine(1665.0, AMPLITUDE), sine(1700.0, AMPLITUDE * 2.0)]));
+        let frequency = detect_tone(&fft, &profile, PowerUnit::Power, &exclude).expect("tone not detected");
+        assert!((frequency - 1665.0).abs() < 2.0, "detected at {} Hz", frequency);
+        let fft = spectrum(mix(&[sine(1700.0, AMPLITUDE * 2.0)]));
+        assert!(detect_tone(&fft, &profile, PowerUnit::Power, &[]).is_some());
+        assert_eq!(detect_tone(&fft, &profile, PowerUnit::Power, &exclude), None);
+    }
+
+    // The default thresholds, on a spectrum whose one in-band bin has exactly the given power. A real tone too loud for
+    // the maximum can still be found on its leakage into the bins beside it.
+    #[test]
+    fn default_thresholds_are_exclusive() {
+        let profile = ToneProfile { band: BAND, min_power: TONE_MIN_POWER, max_power: TONE_MAX_POWER };
+        let detect = |power: f32| {
+            let mut fft = vec![Complex::new(0.0, 0.0); FFT_SAMPLE_SIZE / 2 + 1];
+            fft[213] = Complex::new(power.sqrt(), 0.0);
+            detect_tone(&fft, &profile, PowerUnit::Power, &[])
+        };
+        assert_eq!(detect(200.0), Some(213.0 * SAMPLE_RATE / FFT_SAMPLE_SIZE as f32));
+        assert_eq!(detect(99.0), None);
+        assert_eq!(detect(TONE_MIN_POWER), None);
+        assert_eq!(detect(TONE_MAX_POWER), None);
+        assert_eq!(detect(301.0), None);
+    }
+
+    #[test]
+    fn answer_tone_is_told_apart_from_the_alarm_tone() {
+        let config = test_config(&[("ALARM_ANSWER_TONE_MIN_POWER", "1e10")]).unwrap();
+        let answer = mix(&[sine(2100.0, AMPLITUDE)]);
+        assert!(detect_answer_tone(&answer, &config));
+        assert_eq!(detect_tone(&spectrum(answer), &profile(), PowerUnit::Power, &[]), None);
+        assert!(!detect_answer_tone(&mix(&[sine(1665.0, AMPLITUDE)]), &config));
+    }
+
+    // The same thresholds written as power, magnitude or dB pass and reject the same tones.
+    #[test]
+    fn thresholds_are_equivalent_in_every_unit() {
+        let fft = spectrum(mix(&[sine(1665.0, AMPLITUDE)]));
+        let expected = detect_tone(&fft, &profile(), PowerUnit::Power, &[]).expect("tone not detected");
+        for unit in [PowerUnit::Power, PowerUnit::Magnitude, PowerUnit::Db] {
+            let within = ToneProfile { band: BAND, min_power: unit.level(1e10), max_power: unit.level(1e12) };
+            assert_eq!(detect_tone(&fft, &within, unit, &[]), Some(expected), "{}", unit.name());
+            let above = ToneProfile { band: BAND, min_power: unit.level(1e12), max_power: unit.level(1e14) };
+            assert_eq!(detect_tone(&fft, &above, unit, &[]), None, "{}", unit.name());
+        }
+    }
+
+    // Buffers and plan reused block after block give exactly what a transform planned afresh for each block would,
+    // however much of them the previous block filled.
+    #[test]
+    fn reused_spectrum_is_bit_identical_to_a_fresh_one() {
+        let blocks = [
+            mix(&[sine(1665.0, AMPLITUDE), noise(AMPLITUDE)]),
+            mix(&[sine(1400.0, AMPLITUDE * 4.0)]),
+            mix(&[sine(1665.0, AMPLITUDE)])[..300].to_vec(),
+            mix(&[noise(AMPLITUDE)])
+        ];
+        let mut reused = Spectrum::new(4, 1.0, WindowFn::Hann).unwrap();
+        for block in &blocks {
+            let fresh = Spectrum::new(4, 1.0, WindowFn::Hann).unwrap().transform(block).to_vec();
+            let output = reused.transform(block).to_vec();
+            let bits = |bins: &[Complex<f32>]| {
+                bins.iter().map(|bin| (bin.re.to_bits(), bin.im.to_bits())).collect::<Vec<_>>()
+            };
+            assert_eq!(bits(&output), bits(&fresh));
+            let detect = |bins: &[Complex<f32>]| detect_tone(bins, &profile(), PowerUnit::Power, &[]);
+            assert_eq!(detect(&output), detect(&fresh));
+        }
+    }
+
+    // Only bins up to Nyquist are judged: a tone below it is found in a band reaching past it, while a band above it,
+    // where a full complex transform would mirror that tone, finds nothing.
+    #[test]
+    fn bins_above_nyquist_are_not_inspected() {
+        let fft = spectrum(mix(&[sine(3900.0, AMPLITUDE)]));
+        assert_eq!(fft.len(), FFT_SAMPLE_SIZE / 2 + 1);
+        let past_nyquist = ToneProfile { band: FrequencyBand { min: 3800.0, max: 5000.0 }, ..profile() };
+        let frequency = detect_tone(&fft, &past_nyquist, PowerUnit::Power, &[]).expect("tone not detected");
+        assert!((frequency - 3900.0).abs() < 1.0, "detected at {} Hz", frequency);
+        let mirror = ToneProfile { band: FrequencyBand { min: 4050.0, max: 4150.0 }, ..profile() };
+        assert_eq!(detect_tone(&fft, &mirror, PowerUnit::Power, &[]), None);
+    }
+
+    #[test]
+    fn goertzel_detects_tone_at_1665_hz() {
+        let mut samples = mix(&[sine(1665.0, AMPLITUDE), noise(2000.0)]);
+        high_pass_filter(&mut samples, 3000.0);
+        let frequency = detect_tone_goertzel(&samples, 1.0, &profile(), PowerUnit::Power, &[]).expect("tone not detected");
+        assert!((frequency - 1665.0).abs() < 5.0, "detected at {} Hz", frequency);
+        let mut samples = mix(&[sine(800.0, AMPLITUDE)]);
+        high_pass_filter(&mut samples, 3000.0);
+        assert_eq!(detect_tone_goertzel(&samples, 1.0, &profile(), PowerUnit::Power, &[]), None);
+    }
+
+    // Goertzel at a bin's frequency is that bin of the unwindowed FFT, so either detector judges the same levels.
+    #[test]
+    fn goertzel_matches_the_fft_bin_it_stands_in_for() {
+        let bin = 213;
+        let frequency = bin as f32 * SAMPLE_RATE / FFT_SAMPLE_SIZE as f32;
+        let samples = mix(&[sine(frequency, AMPLITUDE), noise(AMPLITUDE)]);
+        let fft = Spectrum::new(1, 1.0, WindowFn::Rectangular).unwrap().transform(&samples).to_vec();
+        let (expected, power) = (fft[bin].norm_sqr(), goertzel(&samples, frequency));
+        assert!((power / expected - 1.0).abs() < 0.01, "Goertzel {} against FFT {}", power, expected);
+
+        let mut filtered = mix(&[sine(1671.0, AMPLITUDE)]);
+        high_pass_filter(&mut filtered, 3000.0);
+        let fft = Spectrum::new(1, 1.0, WindowFn::Rectangular).unwrap().transform(&filtered).to_vec();
+        let by_fft = detect_tone(&fft, &profile(), PowerUnit::Power, &[]).expect("tone not detected by the FFT");
+        let by_goertzel = detect_tone_goertzel(&filtered, 1.0, &profile(), PowerUnit::Power, &[])
+            .expect("tone not detected by Goertzel");
+        assert!((by_fft - by_goertzel).abs() < bin_width(&fft), "FFT {} Hz, Goertzel {} Hz", by_fft, by_goertzel);
+    }
+
+    // A tone midway between bins leaks far less into distant bins under a window, while a tone on a bin keeps its level
+    // whichever window is chosen.
+    #[test]
+    fn windowing_cuts_leakage_and_keeps_the_peak_level() {
+        let bin_hz = SAMPLE_RATE / FFT_SAMPLE_SIZE as f32;
+        let transform = |frequency: f32, window_fn| {
+            Spectrum::new(1, 1.0, window_fn).unwrap().transform(&mix(&[sine(frequency, AMPLITUDE)])).to_vec()
+        };
+        let leakage = |window_fn| transform(213.5 * bin_hz, window_fn)[223].norm_sqr();
+        let rectangular = leakage(WindowFn::Rectangular);
+        for window_fn in [WindowFn::Hann, WindowFn::Hamming, WindowFn::Blackman] {
+            let reduction = 10.0 * (rectangular / leakage(window_fn)).log10();
+            assert!(reduction > 12.0, "{:?} only cuts leakage by {:.1} dB", window_fn, reduction);
+            let on_bin = |window_fn| transform(213.0 * bin_hz, window_fn)[213].norm_sqr();
+            let ratio = on_bin(window_fn) / on_bin(WindowFn::Rectangular);
+            assert!((ratio - 1.0).abs() < 0.01, "{:?} changes the peak by {}x", window_fn, ratio);
+        }
+    }
+
+    // A tone's level doesn't depend on how many samples the block held, by FFT or by Goertzel.
+    #[test]
+    fn partial_blocks_read_at_full_block_levels() {
+        let frequency = 213.0 * SAMPLE_RATE / FFT_SAMPLE_SIZE as f32;
+        let samples = mix(&[sine(frequency, AMPLITUDE)]);
+        let peak = |len: usize| {
+            let fft = Spectrum::new(1, 1.0, WindowFn::Hann).unwrap().transform(&samples[..len]).to_vec();
+            fft.iter().map(|bin| bin.norm_sqr()).fold(0.0, f32::max)
+        };
+        let half = FFT_SAMPLE_SIZE / 2;
+        let full = peak(FFT_SAMPLE_SIZE);
+        for (name, power) in [("FFT", peak(half)), ("Goertzel", goertzel(&samples[..half], frequency))] {
+            let db = 10.0 * (power / full).log10();
+            assert!(db.abs() < 0.5, "{} half block reads {:.2} dB from a full one", name, db);
+        }
+    }
+
+    #[test]
+    fn hysteresis_needs_runs_to_change_state() {
+        let mut hysteresis = Hysteresis::new(3, 2);
+        let states: Vec<bool> = [true, true, false, true, true, true, false, true, false, false, true]
+            .into_iter()
+            .map(|detected| hysteresis.update(detected))
+            .collect();
+        assert_eq!(states, [false, false, false, false, false, true, true, true, true, false, false]);
+    }
+
+    #[test]
+    fn absence_alerts_once_the_tone_has_been_gone_for_the_timeout() {
+        let timeout = Duration::from_millis(30);
+        let mut absence = AbsenceState::new();
+        assert!(!absence.update(true, timeout));
+        assert!(!absence.update(false, timeout));
+        sleep(timeout);
+        assert!(absence.update(false, timeout));
+        assert!(!absence.update(false, timeout), "alerted twice for one absence");
+        assert!(!absence.update(true, timeout));
+        sleep(timeout);
+        assert!(absence.update(false, timeout), "did not re-arm when the tone returned");
+    }
+
+    // Four of every five blocks hearing the tone holds the vote asserted throughout, and it only releases once three
+    // of the last five have missed.
+    #[test]
+    fn majority_vote_rides_out_intermittent_misses() {
+        let mut vote = MajorityVote::new(3, 5);
+        let hits = [true, true, true, false, true].repeat(4);
+        let states: Vec<bool> = hits.into_iter().map(|hit| vote.update(hit)).collect();
+        assert_eq!(states[..2], [false, false]);
+        assert!(states[2..].iter().all(|&asserted| asserted), "dropped out: {:?}", states);
+        assert!(vote.update(false), "released with two misses of five");
+        assert!(!vote.update(false));
+    }
+
+    fn agc_settings() -> AgcSettings {
+        AgcSettings {
+            target_dbfs: -20.0,
+            attack: Duration::from_millis(10),
+            release: Duration::from_millis(100),
+            max_gain_db: 20.0
+        }
+    }
+
+    // RMS in dBFS of the last block once the AGC has had a second of the tone to settle.
+    fn agc_output_dbfs(amplitude: f32) -> f32 {
+        let mut agc = Agc::new(agc_settings());
+        let mut samples: Vec<i16> = (0..SAMPLE_RATE as usize)
+            .map(|i| (amplitude * (2.0 * std::f32::consts::PI * 1665.0 * i as f32 / SAMPLE_RATE).sin()) as i16)
+            .collect();
+        agc.process(&mut samples);
+        let tail = &samples[samples.len() - FFT_SAMPLE_SIZE..];
+        let rms = (tail.iter().map(|&s| (s as f32).powi(2)).sum::<f32>() / tail.len() as f32).sqrt();
+        20.0 * (rms / i16::MAX as f32).log10()
+    }
+
+    #[test]
+    fn agc_brings_quiet_and_loud_lines_to_the_target() {
+        for amplitude in [1000.0, 20000.0] {
+            let dbfs = agc_output_dbfs(amplitude);
+            assert!((dbfs + 20.0).abs() < 1.0, "amplitude {} came out at {} dBFS", amplitude, dbfs);
+        }
+    }
+
+    #[test]
+    fn agc_gain_is_capped_on_a_quiet_line() {
+        // Peaks 60 dB below full scale, more than the 20 dB the AGC may add, so its RMS stays 43 dB under.
+        let dbfs = agc_output_dbfs(i16::MAX as f32 / 1000.0);
+        assert!((dbfs + 43.0).abs() < 1.0, "came out at {} dBFS", dbfs);
+    }
+
+    #[test]
+    fn decodes_linear_pcm_about_zero() {
+        assert_eq!(PcmFormat::Unsigned8.decode(&[0x00, 0x80, 0xff]), [-128, 0, 127]);
+        // A trailing odd byte is half a sample, left for the capture to complete from the next read.
+        assert_eq!(PcmFormat::SignedLe16.decode(&[0x00, 0x80, 0xff, 0x7f, 0x01]), [-32768, 32767]);
+    }
+
+    #[test]
+    fn decodes_g711() {
+        assert_eq!(PcmFormat::MuLaw.decode(&[0xff, 0x7f, 0x80, 0x00, 0xfe]), [0, 0, 32124, -32124, 8]);
+        assert_eq!(PcmFormat::ALaw.decode(&[0xd5, 0x55, 0xaa, 0x2a]), [8, -8, 32256, -32256]);
+    }
+
+    #[test]
+    fn g711_round_trips() {
+        for format in [PcmFormat::MuLaw, PcmFormat::ALaw] {
+            // μ-law's negative zero is the one code that comes back as the other zero.
+            let bytes: Vec<u8> = (0..=255).filter(|&b| !(format == PcmFormat::MuLaw && b == 0x7f)).collect();
+            assert_eq!(format.encode(&format.decode(&bytes)), bytes, "{:?}", format);
+        }
+        let samples = [-32768, -1000, -1, 0, 1, 1000, 32767];
+        for format in [PcmFormat::MuLaw, PcmFormat::ALaw] {
+            for (sample, decoded) in samples.iter().zip(format.decode(&format.encode(&samples))) {
+                let error = (*sample as i32 - decoded as i32).abs();
+                assert!(error <= (*sample as i32).abs() / 16 + 8, "{:?} decoded {} as {}", format, sample, decoded);
+            }
+        }
+    }
+
+    #[test]
+    fn silence_has_no_tone() {
+        let fft = spectrum(vec![0; FFT_SAMPLE_SIZE]);
+        assert!(fft.iter().all(|bin| bin.norm() == 0.0));
+        assert_eq!(detect_tone(&fft, &profile(), PowerUnit::Power, &[]), None);
+    }
+
+    #[test]
+    fn high_pass_filter_handles_empty_buffer() {
+        let mut samples: Vec<i16> = Vec::new();
+        assert_eq!(high_pass_filter(&mut samples, 3000.0), 0);
+    }
+
+    #[test]
+    fn spectrum_of_empty_block_is_silent() {
+        let fft = spectrum(Vec::new());
+        assert_eq!(fft.len(), FFT_SAMPLE_SIZE / 2 + 1);
+        assert_eq!(detect_tone(&fft, &profile(), PowerUnit::Power, &[]), None);
+        assert_eq!(detect_tone_goertzel(&[], 1.0, &profile(), PowerUnit::Power, &[]), None);
+    }
+
+    #[test]
+    fn high_pass_filter_keeps_zeros() {
+        let mut samples = vec![0i16; FFT_SAMPLE_SIZE];
+        assert_eq!(high_pass_filter(&mut samples, 3000.0), 0);
+        assert!(samples.iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn high_pass_filter_handles_single_sample() {
+        let mut samples = vec![1234i16];
+        assert_eq!(high_pass_filter(&mut samples, 3000.0), 0);
+        assert_eq!(samples, [0]);
+        let fft = spectrum(samples);
+        assert_eq!(detect_tone(&fft, &profile(), PowerUnit::Power, &[]), None);
+    }
+
+    #[test]
+    fn high_pass_filter_attenuates_low_frequencies() {
+        let rms = |samples: &[i16]| (samples.iter().map(|&s| (s as f32).powi(2)).sum::<f32>() / samples.len() as f32).sqrt();
+        let mut low = mix(&[sine(100.0, AMPLITUDE)]);
+        let before = rms(&low);
+        high_pass_filter(&mut low, 3000.0);
+        assert!(rms(&low) < before * 0.1, "100 Hz kept {} of {}", rms(&low), before);
+
+        let mut tone = mix(&[sine(1665.0, AMPLITUDE)]);
+        let before = rms(&tone);
+        high_pass_filter(&mut tone, 3000.0);
+        assert!(rms(&tone) > before * 0.5, "1665 Hz kept {} of {}", rms(&tone), before);
+    }
+
+    // The default band-pass spans every detected band, keeping the tone at its level while cutting hum and
+    // interferers above the band, and filtering a block in pieces just as it would whole.
+    #[test]
+    fn band_pass_keeps_the_tone_and_cuts_either_side() {
+        let config = test_config(&[]).unwrap();
+        assert_eq!(config.filter, InputFilter::BandPass);
+        assert_eq!(config.band_pass, FrequencyBand { min: 1440.0, max: 2320.0 });
+        let rms = |samples: &[i16]| (samples.iter().map(|&s| (s as f32).powi(2)).sum::<f32>() / samples.len() as f32).sqrt();
+        let gain_db = |frequency: f32| {
+            let mut samples = mix(&[sine(frequency, AMPLITUDE)]);
+            let before = rms(&samples[256..]);
+            BandPass::new(config.band_pass).process(&mut samples);
+            20.0 * (rms(&samples[256..]) / before).log10()
+        };
+        assert!(gain_db(1665.0).abs() < 2.0, "1665 Hz changed by {:.1} dB", gain_db(1665.0));
+        for frequency in [157.0, 3400.0] {
+            assert!(gain_db(frequency) < -10.0, "{} Hz only cut by {:.1} dB", frequency, -gain_db(frequency));
+        }
+
+        let samples = mix(&[sine(1665.0, AMPLITUDE), noise(AMPLITUDE)]);
+        let (mut whole, mut pieces) = (samples.clone(), samples);
+        BandPass::new(config.band_pass).process(&mut whole);
+        let mut filter = BandPass::new(config.band_pass);
+        for piece in pieces.chunks_mut(100) {
+            filter.process(piece);
+        }
+        assert_eq!(whole, pieces);
+    }
+
+    // Split across blocks so the state is carried, the 1665 Hz tone comes out far louder relative to the 100 Hz one.
+    #[test]
+    fn pre_emphasis_favours_high_frequencies() {
+        let gain = |frequency: f32| {
+            let mut samples = sine(frequency, AMPLITUDE);
+            let mut state = 0.0;
+            let (first, second) = samples.split_at_mut(FFT_SAMPLE_SIZE / 3);
+            pre_emphasis(first, 0.95, &mut state);
+            pre_emphasis(second, 0.95, &mut state);
+            let rms = (samples[64..].iter().map(|s| s.powi(2)).sum::<f32>() / (FFT_SAMPLE_SIZE - 64) as f32).sqrt();
+            rms / (AMPLITUDE / 2f32.sqrt())
+        };
+        let (low, high) = (gain(100.0), gain(1665.0));
+        assert!(low < 0.2 && high > 1.0, "100 Hz gain {}, 1665 Hz gain {}", low, high);
+    }
+
+    #[test]
+    fn over_range_samples_saturate_rather_than_wrap() {
+        assert_eq!(saturate_i16(40000.0), (i16::MAX, true));
+        assert_eq!(saturate_i16(-40000.0), (i16::MIN, true));
+        assert_eq!(saturate_i16(1234.0), (1234, false));
+        assert_eq!(saturate_i16(i16::MIN as f32), (i16::MIN, false));
+    }
+
+    #[test]
+    fn high_pass_filter_counts_clipped_samples() {
+        let mut samples: Vec<i16> = (0..100).map(|i| if i % 2 == 0 { i16::MIN } else { i16::MAX }).collect();
+        assert_eq!(high_pass_filter(&mut samples, 3000.0), 99);
+        assert!(samples[1..].iter().all(|&sample| sample == i16::MIN || sample == i16::MAX));
+    }
+
+    // Run a call through listen on the mock port, returning its result and the events the sinks were given.
+    fn listen_on(port: &mut MockPort, config: Config) -> (Result<()>, Vec<&'static str>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let mut sinks: Vec<Box<dyn DetectionSink>> =
+            vec![Box::new(CallbackSink(move |payload: &AlertPayload| seen.lock().unwrap().push(payload.event)))];
+        let mut detector = Detector::new(Arc::new(config)).unwrap();
+        let mut stats = SessionStats::new(None);
+        let result = listen(port, PcmFormat::SignedLe16, &mut detector, &mut sinks, &mut stats, &mut Watchdog::new(), None);
+        let events = events.lock().unwrap().clone();
+        (result, events)
+    }
+
+    // Reads past the end of a call fail, so a call that isn't ended where it should be shows as an error.
+    fn call_port(chunks: Vec<Vec<u8>>) -> MockPort {
+        let mut port = MockPort::with_reads(chunks);
+        port.reads.push_back(Err(io::Error::new(io::ErrorKind::BrokenPipe, "read past the end of the call")));
+        port
+    }
+
+    // The far end hanging up ends the call where the modem says so, whether shielded or as a result code.
+    #[test]
+    fn listen_ends_the_call_on_loss_of_carrier() {
+        for end in [vec![DLE, ETX], b"\r\nNO CARRIER\r\n".to_vec()] {
+            let mut port = call_port(vec![vec![0; 2048], end, vec![0; 2048]]);
+            let (result, _) = listen_on(&mut port, test_config(&[]).unwrap());
+            assert!(result.is_ok(), "{:?}", result);
+            assert_eq!(port.reads.len(), 2, "read on past the end of the call");
+        }
+    }
+
+    fn tone(frequency: f32, amplitude: f32, len: usize) -> Vec<i16> {
+        (0..len).map(|i| saturate_i16(amplitude * (2.0 * std::f32::consts::PI * frequency * i as f32 / SAMPLE_RATE).sin()).0).collect()
+    }
+
+    // Thresholds any AMPLITUDE tone clears through the detector's own filtering, and a gap short enough that it fires
+    // straight away, so tests can focus on what follows.
+    const DETECTABLE: [(&str, &str); 4] = [
+        ("ALARM_TONE_MIN_POWER", "1e6"),
+        ("ALARM_TONE_MAX_POWER", "1e20"),
+        ("ALARM_ANSWER_TONE_MIN_POWER", "1e10"),
+        ("ALARM_MIN_GAP_MS", "100")
+    ];
+
+    fn detectable(settings: &[(&str, &str)]) -> Config {
+        let settings: Vec<_> = DETECTABLE.iter().chain(settings).copied().collect();
+        test_config(&settings).unwrap()
+    }
+
+    fn test_detector(settings: &[(&str, &str)]) -> Detector {
+        Detector::new(Arc::new(detectable(settings))).unwrap()
+    }
+
+    // Samples as the modem streams them in voice mode, little-endian with any DLE shielded by doubling it.
+    fn voice_bytes(samples: &[i16]) -> Vec<u8> {
+        samples.iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .flat_map(|byte| if byte == DLE { vec![DLE, DLE] } else { vec![byte] })
+            .collect()
+    }
+
+    fn labels(events: &[DetectionEvent]) -> Vec<&'static str> {
+        events.iter().map(|event| event.detection.label()).collect()
+    }
+
+    // A file of this test's own in the temp dir, so tests running at once don't share one.
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("alarm-modem-{}-{}", std::process::id(), name))
+    }
+
+    // A tone still within its minimum gap when the service went down stays quiet once it's back.
+    #[test]
+    fn persisted_fire_time_suppresses_a_re_fire() {
+        let path = temp_path("state");
+        let settings = [("ALARM_MIN_GAP_MS", "60000"), ("ALARM_STATE_FILE", path.to_str().unwrap())];
+        let samples = tone(1665.0, AMPLITUDE, FFT_SAMPLE_SIZE * 4);
+
+        let _ = fs::remove_file(&path);
+        assert_eq!(labels(&test_detector(&settings).process(&samples)), ["trigger"]);
+        assert!(fs::read_to_string(&path).unwrap().starts_with("trigger="));
+        assert!(test_detector(&settings).process(&samples).is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    // Audio only counts while the gating line is active, which an active-low line is when it reads low.
+    #[test]
+    fn control_line_gates_detection() {
+        let call = || call_port(vec![voice_bytes(&tone(1665.0, AMPLITUDE, FFT_SAMPLE_SIZE * 4)), vec![DLE, ETX]]);
+        for (active_low, expected) in [(false, vec![]), (true, vec!["trigger"])] {
+            let mut port = call();
+            port.dsr = false;
+            let low = if active_low { "1" } else { "0" };
+            let config = detectable(&[("ALARM_LINE_SIGNAL", "dsr"), ("ALARM_LINE_SIGNAL_ACTIVE_LOW", low)]);
+            let (result, events) = listen_on(&mut port, config);
+            result.unwrap();
+            assert_eq!(events, expected, "active low {}", active_low);
+        }
+    }
+
+    // A burst after at least the minimum gap without the tone is a new detection, one straight after is the same one.
+    #[test]
+    fn bursts_separated_by_the_gap_fire_separately() {
+        let burst = tone(1665.0, AMPLITUDE, FFT_SAMPLE_SIZE * 2);
+        let gap = vec![0; FFT_SAMPLE_SIZE * 2];
+        let mut detector = test_detector(&[("ALARM_MIN_GAP_MS", "100")]);
+        assert_eq!(labels(&detector.process(&burst)), ["trigger"]);
+        assert!(detector.process(&gap).is_empty());
+        assert!(detector.process(&burst).is_empty(), "fired again within the gap");
+        detector.process(&gap);
+        sleep(Duration::from_millis(150));
+        assert_eq!(labels(&detector.process(&burst)), ["trigger"]);
+    }
+
+    // Silence on the line ends a panic tone as it does the others, so the next burst once it's back is a new one.
+    #[test]
+    fn panic_tone_fires_again_after_silence() {
+        let burst = tone(2030.0, AMPLITUDE, FFT_SAMPLE_SIZE * 2);
+        let mut detector = test_detector(&[("ALARM_PANIC_TONE", "2000-2060")]);
+        assert_eq!(labels(&detector.process(&burst)), ["panic"]);
+        assert!(detector.silence().is_empty());
+        sleep(Duration::from_millis(150));
+        assert_eq!(labels(&detector.process(&burst)), ["panic"]);
+    }
+
+    // A 2100 Hz tone comes through 16 to 8 kHz decimation while 6 kHz, which would alias to 2 kHz, is filtered out.
+    // Read in odd sized blocks it filters just as it would in one piece.
+    #[test]
+    fn decimation_filters_aliases_across_reads() {
+        let rms = |samples: &[i16]| (samples.iter().map(|&s| (s as f32).powi(2)).sum::<f32>() / samples.len() as f32).sqrt();
+        let decimated = |frequency: f32| {
+            let input: Vec<i16> = (0..16000)
+                .map(|i| (AMPLITUDE * (2.0 * std::f32::consts::PI * frequency * i as f32 / 16000.0).sin()) as i16)
+                .collect();
+            let mut decimator = Decimator::new(2);
+            let output: Vec<i16> = input.chunks(333).flat_map(|block| decimator.process(block)).collect();
+            assert_eq!(output, Decimator::new(2).process(&input));
+            assert_eq!(output.len(), 8000);
+            rms(&output[100..])
+        };
+        let full = AMPLITUDE / 2f32.sqrt();
+        assert!(decimated(2100.0) > full * 0.9, "2100 Hz came through at {}", decimated(2100.0));
+        assert!(decimated(6000.0) < full * 0.05, "6 kHz came through at {}", decimated(6000.0));
+    }
+
+    // Reads of awkward lengths are gathered into whole blocks, each judged by the same power-of-two transform as soon
+    // as its last sample arrives.
+    #[test]
+    fn odd_length_reads_make_whole_power_of_two_blocks() {
+        let mut detector = test_detector(&[]);
+        detector.record_blocks();
+        let samples = tone(1665.0, AMPLITUDE, FFT_SAMPLE_SIZE * 5);
+        let mut blocks = Vec::new();
+        let mut read = 0;
+        for len in [1, 333, 517, 173, 1023, 1, 2047, 1025].into_iter().cycle() {
+            let end = (read + len).min(samples.len());
+            detector.process(&samples[read..end]);
+            read = end;
+            blocks.extend(detector.take_reports().iter().map(|report| report.offset));
+            assert_eq!(blocks.len(), read / FFT_SAMPLE_SIZE, "a block was held back after {} samples", read);
+            if read == samples.len() {
+                break;
+            }
+        }
+        let ends: Vec<_> = (1..=5).map(|block| sample_offset((block * FFT_SAMPLE_SIZE) as u64)).collect();
+        assert_eq!(blocks, ends);
+        assert!(detector.spectrum.input.len().is_power_of_two());
+        assert_eq!(detector.spectrum.output.len(), FFT_SAMPLE_SIZE / 2 + 1);
+    }
+
+    // A panel known by its caller ID is listened for on its own band, anyone else on the default one.
+    #[test]
+    fn caller_id_selects_the_tone_profile() {
+        let caller = |number: &str| Some(CallerId { number: number.to_string(), name: None, date: None, time: None });
+        let mut detector = test_detector(&[("ALARM_TONE_PROFILES", "555-0100=1390-1410")]);
+        let low = tone(1400.0, AMPLITUDE, FFT_SAMPLE_SIZE * 2);
+        let high = tone(1665.0, AMPLITUDE, FFT_SAMPLE_SIZE * 2);
+
+        detector.set_caller(caller("(555) 0100"));
+        assert_eq!(detector.tone_frequency(), 1400.0);
+        assert_eq!(detector.tone_blocks(&low), 2);
+        assert_eq!(detector.tone_blocks(&high), 0);
+
+        detector.set_caller(caller("5550199"));
+        assert_eq!(detector.tone_frequency(), 1680.0);
+        assert_eq!(detector.tone_blocks(&low), 0);
+        assert_eq!(detector.tone_blocks(&high), 2);
+    }
+
+    // A saved clip runs from the pre-trigger window before the tone to the post-trigger window after it ends.
+    #[test]
+    fn recording_spans_the_pre_and_post_windows() {
+        let dir = temp_path("recordings");
+        let _ = fs::remove_dir_all(&dir);
+        let mut detector = test_detector(&[
+            ("ALARM_RECORD_DIR", dir.to_str().unwrap()),
+            ("ALARM_RECORD_PRE_MS", "256"),
+            ("ALARM_RECORD_POST_MS", "256")
+        ]);
+        let window = FFT_SAMPLE_SIZE * 2; // 256 ms
+        let mut events = Vec::new();
+        for block in [vec![0; window * 2], tone(1665.0, AMPLITUDE, window), vec![0; window * 2]] {
+            for read in block.chunks(256) {
+                events.extend(detector.process(read));
+            }
+        }
+        assert_eq!(labels(&events), ["trigger"]);
+        assert!(events[0].audio.is_none(), "kept audio the webhook doesn't attach");
+
+        let files: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(files.len(), 1);
+        let samples = decode_wav(&fs::read(&files[0]).unwrap()).unwrap().samples.len();
+        let expected = window * 3;
+        assert!(samples.abs_diff(expected) <= FFT_SAMPLE_SIZE, "recorded {} samples, expected {}", samples, expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // A clip whose post window is still filling when the call ends is saved by the flush, as a playable WAV file.
+    #[test]
+    fn a_recording_cut_short_is_saved_on_flush() {
+        let dir = temp_path("cut-short");
+        let _ = fs::remove_dir_all(&dir);
+        let mut detector = test_detector(&[
+            ("ALARM_RECORD_DIR", dir.to_str().unwrap()),
+            ("ALARM_RECORD_POST_MS", "5000")
+        ]);
+        let mut events = Vec::new();
+        for read in tone(1665.0, AMPLITUDE, FFT_SAMPLE_SIZE * 4).chunks(256) {
+            events.extend(detector.process(read));
+        }
+        assert!(events.is_empty(), "the recording ended before the post window");
+        assert!(fs::read_dir(&dir).is_err(), "saved before the recording ended");
+
+        assert_eq!(labels(&detector.flush()), ["trigger"]);
+        let files: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(files.len(), 1);
+        let wav = decode_wav(&fs::read(&files[0]).unwrap()).unwrap();
+        assert_eq!(wav.sample_rate, SAMPLE_RATE as u32);
+        assert!(!wav.samples.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Reads timing out for the silence duration count as the tone being gone, so absence is noticed without audio.
+    #[test]
+    fn timeouts_add_up_to_silence() {
+        for (timeouts, expected) in [(2, vec![]), (20, vec!["tone_absent"])] {
+            let mut port = MockPort::new();
+            for _ in 0..timeouts {
+                port.reads.push_back(Err(io::Error::new(io::ErrorKind::TimedOut, "Operation timed out")));
+            }
+            port.reads.push_back(Ok(vec![DLE, ETX]));
+            let config = detectable(&[
+                ("ALARM_SILENCE_MS", "100"),
+                ("ALARM_DETECT_MODE", "absence"),
+                ("ALARM_ABSENCE_TIMEOUT_SECS", "0"),
+                ("ALARM_IDLE_BACKOFF_MIN_MS", "10"),
+                ("ALARM_IDLE_BACKOFF_MAX_MS", "10")
+            ]);
+            let (result, events) = listen_on(&mut port, config);
+            result.unwrap();
+            assert_eq!(events, expected, "after {} timeouts", timeouts);
+        }
+    }
+
+    // A call going quiet for the stall timeout is alerted on once however long it stays quiet, and again only after
+    // audio has resumed and stalled a second time.
+    #[test]
+    fn a_stalled_call_is_alerted_once_per_stall() {
+        let timeouts = |port: &mut MockPort, count: usize| {
+            for _ in 0..count {
+                port.reads.push_back(Err(io::Error::new(io::ErrorKind::TimedOut, "Operation timed out")));
+            }
+        };
+        let mut port = MockPort::new();
+        timeouts(&mut port, 130);
+        port.reads.push_back(Ok(vec![0; 256]));
+        timeouts(&mut port, 130);
+        port.reads.push_back(Ok(vec![0; 256]));
+        timeouts(&mut port, 20);
+        port.reads.push_back(Ok(vec![DLE, ETX]));
+        let config = detectable(&[
+            ("ALARM_STALL_TIMEOUT_SECS", "1"),
+            ("ALARM_IDLE_BACKOFF_MIN_MS", "10"),
+            ("ALARM_IDLE_BACKOFF_MAX_MS", "10")
+        ]);
+        let (result, events) = listen_on(&mut port, config);
+        result.unwrap();
+        assert_eq!(events, ["monitoring_stalled", "monitoring_stalled"]);
+    }
+
+    // On a quiet line each read gives up after the short listening timeout rather than the command one, the loop
+    // carrying on to the end of the call, and commands get their own timeout back afterwards.
+    #[test]
+    fn listening_reads_time_out_after_the_read_timeout() {
+        let mut port = MockPort::new();
+        port.wait_out_timeouts = true;
+        port.timeout = Duration::from_secs(2);
+        for _ in 0..5 {
+            port.reads.push_back(Err(io::Error::new(io::ErrorKind::TimedOut, "Operation timed out")));
+        }
+        port.reads.push_back(Ok(vec![DLE, ETX]));
+        let config = detectable(&[
+            ("ALARM_READ_TIMEOUT_MS", "20"),
+            ("ALARM_IDLE_BACKOFF_MIN_MS", "1"),
+            ("ALARM_IDLE_BACKOFF_MAX_MS", "1")
+        ]);
+        let started = Instant::now();
+        let (result, _) = listen_on(&mut port, config);
+        result.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1), "reads waited {:?}", started.elapsed());
+        assert!(started.elapsed() >= Duration::from_millis(100), "reads didn't wait at all");
+        assert_eq!(port.timeout, Duration::from_secs(2));
+    }
+
+    // Tiny reads are gathered until a whole block is in, one starting every hop however the audio was split up.
+    #[test]
+    fn small_reads_make_a_block_per_hop() {
+        let mut detector = test_detector(&[("ALARM_FRAME_HOP", "256")]);
+        detector.record_blocks();
+        let samples = tone(1665.0, AMPLITUDE, FFT_SAMPLE_SIZE * 4);
+        let mut blocks = 0;
+        for read in samples.chunks(7) {
+            detector.process(read);
+            blocks += detector.take_reports().len();
+        }
+        assert_eq!(blocks, (samples.len() - FFT_SAMPLE_SIZE) / 256 + 1);
+    }
+
+    // A tone holding its frequency block after block is confirmed, one hopping about the band never is.
+    #[test]
+    fn drift_tracking_tells_a_steady_tone_from_a_hopping_one() {
+        let settings = [("ALARM_FREQ_DRIFT_HZ", "5"), ("ALARM_FREQ_DRIFT_BLOCKS", "3")];
+        let steady = tone(1665.0, AMPLITUDE, FFT_SAMPLE_SIZE * 4);
+        assert_eq!(labels(&test_detector(&settings).process(&steady)), ["trigger"]);
+
+        let hopping: Vec<i16> = [1645.0, 1700.0, 1660.0, 1715.0, 1650.0, 1690.0].iter()
+            .flat_map(|&frequency| tone(frequency, AMPLITUDE, FFT_SAMPLE_SIZE))
+            .collect();
+        assert!(test_detector(&settings).process(&hopping).is_empty());
+        assert_eq!(labels(&test_detector(&[]).process(&hopping)), ["trigger"], "hops weren't in band");
+    }
+
+    #[test]
+    fn invalid_fft_sizes_are_refused_before_planning() {
+        for zero_pad in [0, 3, 1_000_003, usize::MAX] {
+            let error = Spectrum::new(zero_pad, 1.0, WindowFn::Hann).err().expect("planned an invalid size");
+            assert!(error.to_string().contains("ALARM_FFT_ZERO_PAD must be a power of two"), "{}", error);
+        }
+        let error = Detector::new(Arc::new(detectable(&[("ALARM_FFT_ZERO_PAD", "3")]))).err().unwrap();
+        assert!(error.to_string().contains("FFT size 3 x 1024 samples is invalid"), "{}", error);
+    }
+
+    // Noise twice differenced, so its power climbs steeply with frequency like a line with a rising noise floor.
+    fn sloped_noise(seed: u32, amplitude: f32) -> Vec<f32> {
+        let mut state = seed;
+        let white: Vec<f32> = (0..FFT_SAMPLE_SIZE + 2)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                amplitude * (state as f32 / u32::MAX as f32 * 2.0 - 1.0)
+            })
+            .collect();
+        white.windows(3).map(|w| w[2] - 2.0 * w[1] + w[0]).collect()
+    }
+
+    #[test]
+    fn whitening_finds_a_tone_under_a_sloped_noise_floor() {
+        let band = FrequencyBand { min: 900.0, max: 3500.0 };
+        let wide_open = ToneProfile { band, min_power: 0.0, max_power: f32::INFINITY };
+        let mut spectrum = Spectrum::new(1, 1.0, WindowFn::Hann).unwrap();
+        let mut whitener = Whitener::new(20.0, 32);
+        for seed in 1..=128 {
+            let fft = spectrum.transform(&mix(&[sloped_noise(seed, 1000.0)]));
+            let found = whitener.detect(fft, &band, &[]);
+            // Once the averages have settled, noise alone never stands out.
+            assert!(seed <= 64 || found.is_none(), "noise alone stood out at {:?} Hz in block {}", found, seed);
+        }
+
+        let fft = spectrum.transform(&mix(&[sloped_noise(500, 1000.0), sine(1000.0, 250.0)]));
+        // The loud top of the band outweighs the tone in absolute power, so a fixed threshold can't pick it out.
+        let strongest = detect_tone(fft, &wide_open, PowerUnit::Power, &[]).unwrap();
+        assert!(strongest > 2000.0, "the tone was the strongest bin at {} Hz", strongest);
+        let whitened = whitener.detect(fft, &band, &[]).expect("whitening missed the tone");
+        assert!((whitened - 1000.0).abs() < 10.0, "{}", whitened);
+    }
+
+    // Without the power gate tones far apart in level both fire on their SNR alone, which no one power window
+    // allows, while the noise under them alone never does.
+    #[test]
+    fn snr_alone_detects_tones_at_any_level() {
+        // Noise alone long enough for the floor to settle, then the tone over it if there is one.
+        let fires = |settings: &[(&str, &str)], level: f32, tone: bool| {
+            let mut detector = Detector::new(Arc::new(test_config(settings).unwrap())).unwrap();
+            let hiss = noise(level / 40.0);
+            let line: Vec<i16> = (0..FFT_SAMPLE_SIZE * 16).map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * 1665.0 * i as f32 / SAMPLE_RATE;
+                let signal = if tone && i >= FFT_SAMPLE_SIZE * 8 { level * phase.sin() } else { 0.0 };
+                saturate_i16(signal + hiss[i % FFT_SAMPLE_SIZE]).0
+            }).collect();
+            let events: Vec<_> = line.chunks(256).flat_map(|read| detector.process(read)).collect();
+            labels(&events).contains(&"trigger")
+        };
+        let gated = [("ALARM_MIN_SNR_DB", "15"), ("ALARM_MIN_GAP_MS", "100")];
+        let snr_only = [&gated[..], &[("ALARM_POWER_GATE", "0")]].concat();
+        for level in [200.0, 12000.0] {
+            assert!(fires(&snr_only, level, true), "missed the tone at {}", level);
+            assert!(!fires(&snr_only, level, false), "noise alone fired at {}", level);
+        }
+        assert!(!(fires(&gated, 200.0, true) && fires(&gated, 12000.0, true)), "one power window fit both levels");
+        assert!(test_config(&[("ALARM_POWER_GATE", "0")]).is_err(), "SNR-only detection without an SNR margin");
+    }
+
+    // A faint tone among loud white noise is lost in any one block's random peaks, but averaging the spectrum over
+    // many blocks settles the noise to its mean and leaves the tone standing above it.
+    #[test]
+    fn spectral_averaging_finds_a_tone_buried_in_noise() {
+        let band = FrequencyBand { min: 900.0, max: 3500.0 };
+        let strongest = ToneProfile { band, min_power: 0.0, max_power: f32::INFINITY };
+        let mut spectrum = Spectrum::new(1, 1.0, WindowFn::Hann).unwrap();
+        let mut average = SpectralAverage::new(64);
+        let mut state = 7_u32;
+        let (mut single, mut averaged) = (None, None);
+        for _ in 0..64 {
+            let white: Vec<f32> = (0..FFT_SAMPLE_SIZE)
+                .map(|_| {
+                    state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    4000.0 * (state as f32 / u32::MAX as f32 * 2.0 - 1.0)
+                })
+                .collect();
+            let fft = spectrum.transform(&mix(&[white, sine(2000.0, 250.0)]));
+            single = detect_tone(fft, &strongest, PowerUnit::Power, &[]);
+            averaged = detect_tone(average.update(fft), &strongest, PowerUnit::Power, &[]);
+        }
+        let single = single.unwrap();
+        assert!((single - 2000.0).abs() > 50.0, "a single block found the tone at {} Hz", single);
+        let averaged = averaged.expect("averaging missed the tone");
+        assert!((averaged - 2000.0).abs() < 10.0, "{}", averaged);
+    }
+
+    // The generic profile tunes its thresholds at AT+VGR=3 with 2 dB a step, so at AT+VGR=8 the modem hands over the
+    // same tone 10 dB louder, which the compensation takes back out before the transform.
+    #[test]
+    fn receive_gain_compensation_keeps_levels_comparable() {
+        let peak = |vgr: &str, amplitude: f32| {
+            let config = test_config(&[("ALARM_MODEM_VGR", vgr)]).unwrap();
+            let mut spectrum = Spectrum::new(1, input_gain(&config), WindowFn::Hann).unwrap();
+            let levels = band_levels(spectrum.transform(&mix(&[sine(1665.0, amplitude)])), &BAND, PowerUnit::Db);
+            levels.into_iter().fold(f32::MIN, f32::max)
+        };
+        let (reference, louder) = (peak("3", AMPLITUDE), peak("8", AMPLITUDE * 10f32.powf(0.5)));
+        assert!((louder - reference).abs() < 0.1, "{} dB at AT+VGR=8 against {} dB at AT+VGR=3", louder, reference);
+        let uncompensated = peak("3", AMPLITUDE * 10f32.powf(0.5));
+        assert!((uncompensated - reference - 10.0).abs() < 0.1, "{} against {}", uncompensated, reference);
+    }
+
+    // A 5 Hz band holds no more than one of the unpadded transform's 7.8 Hz bins, so meeting three takes 4x padding.
+    #[test]
+    fn too_coarse_bins_for_the_band_increase_the_padding() {
+        let narrow = [("ALARM_TONE_MIN_FREQ", "1660"), ("ALARM_TONE_MAX_FREQ", "1665"), ("ALARM_MIN_BAND_BINS", "3")];
+        let config = |auto: &str| test_config(&[narrow.as_slice(), &[("ALARM_FFT_AUTO_PAD", auto)]].concat()).unwrap();
+        assert!(bins_in_band(&FrequencyBand { min: 1660.0, max: 1665.0 }, 1) <= 1);
+        assert_eq!(zero_pad(&config("0")), 1);
+        assert_eq!(zero_pad(&config("1")), 4);
+        assert_eq!(bins_in_band(&FrequencyBand { min: 1660.0, max: 1665.0 }, 4), 3);
+        // A band already wide enough is left alone.
+        assert_eq!(zero_pad(&test_config(&[("ALARM_MIN_BAND_BINS", "3"), ("ALARM_FFT_AUTO_PAD", "1")]).unwrap()), 1);
+    }
+
+    // Interleaved stereo with the alarm tone on the right only, so only selecting that channel finds it.
+    #[test]
+    fn capture_analyses_the_selected_channel() {
+        let left = mix(&[sine(1200.0, AMPLITUDE)]);
+        let right = mix(&[sine(1665.0, AMPLITUDE)]);
+        let interleaved: Vec<u8> = left.iter()
+            .zip(&right)
+            .flat_map(|(l, r)| [l.to_le_bytes(), r.to_le_bytes()])
+            .flatten()
+            .collect();
+        for (channel, expected) in [("0", None), ("1", Some(1665.0))] {
+            let config = test_config(&[("ALARM_CHANNELS", "2"), ("ALARM_CHANNEL", channel)]).unwrap();
+            let mut raw = interleaved.clone();
+            let captured = Capture::new(PcmFormat::SignedLe16, &config).take_samples(&mut raw);
+            assert_eq!(captured.len(), FFT_SAMPLE_SIZE);
+            let frequency = detect_tone(&spectrum(captured), &profile(), PowerUnit::Power, &[]);
+            assert_eq!(frequency.map(f32::round), expected, "channel {}", channel);
+        }
+    }
+
+    // However the audio is split into reads, the offset comes from the samples before the tone, not the clock.
+    #[test]
+    fn offset_counts_the_samples_before_the_tone() {
+        let start = 5000;
+        let mut samples = vec![0; start];
+        samples.extend(tone(1665.0, AMPLITUDE, FFT_SAMPLE_SIZE * 4));
+        let mut detector = test_detector(&[]);
+        let events: Vec<_> = samples.chunks(700).flat_map(|chunk| detector.process(chunk)).collect();
+        assert_eq!(labels(&events), ["trigger"]);
+        let offset = events[0].offset.unwrap().as_secs_f32();
+        let block = FFT_SAMPLE_SIZE as f32 / SAMPLE_RATE;
+        assert!((offset - start as f32 / SAMPLE_RATE).abs() <= block, "reported at {} s", offset);
+    }
+
+    #[test]
+    fn snr_is_in_db_and_always_finite() {
+        assert!((snr_db(1e6, 1e3) - 30.0).abs() < 1e-4);
+        assert!((snr_db(2e3, 1e3) - 3.0103).abs() < 1e-3);
+        assert!((snr_db(1e3, 1e4) + 10.0).abs() < 1e-4);
+        for (signal, noise) in [(1e6, 0.0), (1e6, -5.0), (1e6, f32::MIN_POSITIVE), (0.0, 0.0), (-1.0, 0.0)] {
+            let snr = snr_db(signal, noise);
+            assert!(snr.is_finite(), "{} dB for {} over {}", snr, signal, noise);
+        }
+        assert_eq!(snr_db(1e6, 0.0), 60.0, "a silent floor should count as one LSB squared");
+    }
+
+    // The real transform gives the same bins a full complex transform of the windowed block does, without the
+    // redundant mirrored half.
+    #[test]
+    fn real_fft_matches_the_complex_fft() {
+        let samples = mix(&[sine(1665.0, AMPLITUDE), noise(100.0)]);
+        let mut spectrum = Spectrum::new(1, 1.0, WindowFn::Hann).unwrap();
+        let real = spectrum.transform(&samples).to_vec();
+
+        let window = WindowFn::Hann.scaled(FFT_SAMPLE_SIZE, 1.0);
+        let mut complex: Vec<rustfft::num_complex::Complex<f32>> = samples.iter()
+            .zip(&window)
+            .map(|(&sample, coefficient)| rustfft::num_complex::Complex::new(sample as f32 * coefficient, 0.0))
+            .collect();
+        rustfft::FftPlanner::new().plan_fft_forward(FFT_SAMPLE_SIZE).process(&mut complex);
+
+        assert_eq!(real.len(), FFT_SAMPLE_SIZE / 2 + 1);
+        let peak = complex.iter().map(|bin| bin.norm()).fold(0.0, f32::max);
+        let magnitudes = real.iter().map(|bin| bin.norm()).zip(complex.iter().map(|bin| bin.norm()));
+        for (i, (real, complex)) in magnitudes.enumerate() {
+            assert!((real - complex).abs() <= peak * 1e-5, "bin {}: {} vs {}", i, real, complex);
+        }
+    }
+
+    // 8-bit audio read as 16-bit is a near full scale DC offset, so the capture switches to decoding it as 8-bit.
+    #[test]
+    fn capture_falls_back_to_the_format_the_audio_is_in() {
+        let bytes = PcmFormat::Unsigned8.encode(&tone(1665.0, 8000.0, 4096));
+        let mut capture = Capture::new(PcmFormat::SignedLe16, &test_config(&[]).unwrap());
+        let samples = capture.take_samples(&mut bytes.clone());
+        assert_eq!(capture.format, PcmFormat::Unsigned8);
+        assert_eq!(samples, PcmFormat::Unsigned8.decode(&bytes));
+    }
+
+    // Companded audio isn't held back for the format check, and a call ending before the check still has its
+    // linear audio heard.
+    #[test]
+    fn short_calls_are_not_lost_to_the_format_check() {
+        let mut capture = Capture::new(PcmFormat::MuLaw, &test_config(&[]).unwrap());
+        assert_eq!(capture.take_samples(&mut vec![0xff; 100]).len(), 100);
+
+        let mut call = voice_bytes(&tone(1665.0, AMPLITUDE, 1500));
+        assert!(call.len() < FORMAT_CHECK_BYTES);
+        call.extend([DLE, ETX]);
+        let (result, events) = listen_on(&mut call_port(vec![call]), detectable(&[]));
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(events, ["trigger"]);
+    }
+
+    #[test]
+    fn idle_backoff_grows_and_resets_on_activity() {
+        let ms = Duration::from_millis;
+        let mut backoff = IdleBackoff::new(ms(10), ms(70));
+        let delays: Vec<_> = (0..5).map(|_| backoff.idle()).collect();
+        assert_eq!(delays, [ms(10), ms(20), ms(40), ms(70), ms(70)]);
+        backoff.active();
+        assert_eq!((backoff.idle(), backoff.idle()), (ms(10), ms(20)));
+    }
+
+    // A clean tone at the alarm frequency is taken for an interferer once a harmonic is required, the same tone with
+    // its second harmonic passes. The band-pass would take the harmonic away, so it's heard through the high-pass.
+    #[test]
+    fn harmonic_confirmation_rejects_a_pure_interferer() {
+        let settings = [
+            ("ALARM_HARMONIC_TOLERANCE_HZ", "20"),
+            ("ALARM_HARMONIC_MIN_RATIO", "0.01"),
+            ("ALARM_FILTER", "highpass")
+        ];
+        let pure = tone(1665.0, AMPLITUDE, FFT_SAMPLE_SIZE * 4);
+        assert!(test_detector(&settings).process(&pure).is_empty(), "a fundamental alone was accepted");
+
+        let harmonic = tone(3330.0, AMPLITUDE / 4.0, pure.len());
+        let rich: Vec<i16> = pure.iter().zip(&harmonic).map(|(a, b)| a + b).collect();
+        assert_eq!(labels(&test_detector(&settings).process(&rich)), ["trigger"]);
+    }
+
+    // A threshold reloaded mid-call applies from the next block, the call and its detector carrying on throughout.
+    #[test]
+    fn reloaded_thresholds_apply_mid_call() {
+        let mut burst = tone(1665.0, AMPLITUDE, FFT_SAMPLE_SIZE * 4);
+        burst.extend(vec![0; FFT_SAMPLE_SIZE * 4]);
+        let mut detector = test_detector(&[("ALARM_TONE_MIN_POWER", "1e18")]);
+        assert!(detector.process(&burst).is_empty(), "detected above the threshold");
+
+        detector.reload(Arc::new(detectable(&[]))).unwrap();
+        assert_eq!(labels(&detector.process(&burst)), ["trigger"], "the lowered threshold wasn't applied");
+    }
+
+    // A sine peaking at a tenth of full scale is -20 dBFS at its peak, 3 dB lower as RMS.
+    #[test]
+    fn detections_report_the_rms_level() {
+        let samples = tone(1665.0, 3276.8, FFT_SAMPLE_SIZE * 4);
+        assert!((rms_dbfs(&samples, 32768.0) + 23.01).abs() < 0.05, "{}", rms_dbfs(&samples, 32768.0));
+        assert_eq!(rms_dbfs(&[0; 16], 32768.0), MIN_DBFS);
+
+        let events = test_detector(&[]).process(&samples);
+        let level = events[0].level_dbfs.expect("no level reported");
+        assert!((level + 23.01).abs() < 0.05, "{}", level);
+        let payload = serde_json::to_value(AlertPayload::from_event(&events[0], SAMPLE_RATE as u32)).unwrap();
+        assert_eq!(payload["level_dbfs"].as_f64().map(f64::round), Some(-23.0));
+    }
+}
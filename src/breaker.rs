@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant};
+use log::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Closed { failures: u32 }, // Delivering normally, counting consecutive failed attempts
+    Open { until: Instant },  // Failing fast until the cooldown ends
+    HalfOpen                  // One probe attempt is in flight, everything else still fails fast
+}
+
+// Stops hammering an endpoint that's persistently down. After `threshold` consecutive failed attempts the circuit
+// opens and attempts are refused for the cooldown, then a single probe is let through: success closes the circuit,
+// failure opens it for another cooldown.
+pub(crate) struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: State
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker { threshold, cooldown, state: State::Closed { failures: 0 } }
+    }
+
+    // Whether an attempt may be made now, moving an open circuit past its cooldown to half-open for the probe.
+    pub fn allow(&mut self) -> bool {
+        match self.state {
+            State::Closed { .. } => true,
+            State::Open { until } if Instant::now() >= until => {
+                info!("Webhook circuit half-open, probing the endpoint");
+                self.state = State::HalfOpen;
+                true
+            },
+            State::Open { .. } | State::HalfOpen => false
+        }
+    }
+
+    // Whether alerts are being held back, through the cooldown and while the probe is out.
+    pub fn is_open(&self) -> bool {
+        !matches!(self.state, State::Closed { .. })
+    }
+
+    pub fn record_success(&mut self) {
+        if self.state == State::HalfOpen {
+            info!("Webhook endpoint recovered, circuit closed");
+        }
+        self.state = State::Closed { failures: 0 };
+    }
+
+    // Returns whether the circuit is now open, so the caller can stop retrying straight away.
+    pub fn record_failure(&mut self) -> bool {
+        self.state = match self.state {
+            State::Closed { failures } if failures + 1 < self.threshold => State::Closed { failures: failures + 1 },
+            State::Closed { .. } => {
+                warn!("Webhook failed {} times in a row, opening the circuit for {:#?}", self.threshold, self.cooldown);
+                State::Open { until: Instant::now() + self.cooldown }
+            },
+            State::HalfOpen => {
+                warn!("Webhook probe failed, keeping the circuit open for {:#?}", self.cooldown);
+                State::Open { until: Instant::now() + self.cooldown }
+            },
+            open => open
+        };
+        matches!(self.state, State::Open { .. })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    #[test]
+    fn opens_on_failures_and_closes_on_a_successful_probe() {
+        let mut breaker = CircuitBreaker::new(2, Duration::from_millis(20));
+        assert!(breaker.allow() && !breaker.record_failure());
+        assert!(breaker.allow() && breaker.record_failure());
+        assert!(breaker.is_open() && !breaker.allow());
+
+        // Past the cooldown a single probe goes through, and failing it opens the circuit again.
+        sleep(Duration::from_millis(30));
+        assert!(breaker.allow() && !breaker.allow());
+        assert!(breaker.record_failure() && !breaker.allow());
+
+        sleep(Duration::from_millis(30));
+        assert!(breaker.allow());
+        breaker.record_success();
+        assert!(!breaker.is_open() && breaker.allow());
+        // Closed again, the failure count starts over.
+        assert!(!breaker.record_failure());
+    }
+}
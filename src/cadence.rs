@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+// Cadence limits, loose enough to cover the common national variants of each signal.
+const BUSY_MIN: Duration = Duration::from_millis(300);
+const BUSY_MAX: Duration = Duration::from_millis(700);
+const RING_ON_MIN: Duration = Duration::from_millis(800);
+const RING_ON_MAX: Duration = Duration::from_millis(2500);
+const RING_OFF_MIN: Duration = Duration::from_millis(2500);
+const RING_OFF_MAX: Duration = Duration::from_millis(5000);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallProgress {
+    Ringing,
+    Busy,
+    Connected
+}
+
+impl CallProgress {
+    pub fn label(&self) -> &'static str {
+        match self {
+            CallProgress::Ringing => "ringing",
+            CallProgress::Busy => "busy",
+            CallProgress::Connected => "connected"
+        }
+    }
+}
+
+// Classifies call progress from the on/off timing of the ~440 Hz progress tone, measured in stream time so
+// it doesn't depend on how quickly blocks are read. Ringing that stops for longer than a ring pause means
+// the call was answered.
+pub(crate) struct CadenceDetector {
+    tone_on: bool,
+    phase: Duration,
+    last_on: Option<Duration>,
+    state: Option<CallProgress>
+}
+
+impl CadenceDetector {
+    pub fn new() -> Self {
+        CadenceDetector { tone_on: false, phase: Duration::ZERO, last_on: None, state: None }
+    }
+
+    // Feed whether the tone was present over the last `block` of audio, returning the new state when it changes.
+    pub fn update(&mut self, present: bool, block: Duration) -> Option<CallProgress> {
+        let mut classified = None;
+        if present != self.tone_on {
+            if self.tone_on {
+                self.last_on = Some(self.phase);
+            } else if let Some(on) = self.last_on {
+                classified = classify(on, self.phase);
+            }
+            self.tone_on = present;
+            self.phase = Duration::ZERO;
+        }
+        self.phase += block;
+
+        if !self.tone_on && self.state == Some(CallProgress::Ringing) && self.phase > RING_OFF_MAX {
+            classified = Some(CallProgress::Connected);
+        }
+        match classified {
+            Some(state) if self.state != Some(state) => {
+                self.state = Some(state);
+                Some(state)
+            },
+            _ => None
+        }
+    }
+}
+
+fn classify(on: Duration, off: Duration) -> Option<CallProgress> {
+    let busy = |d: Duration| (BUSY_MIN..=BUSY_MAX).contains(&d);
+    if busy(on) && busy(off) {
+        Some(CallProgress::Busy)
+    } else if (RING_ON_MIN..=RING_ON_MAX).contains(&on) && (RING_OFF_MIN..=RING_OFF_MAX).contains(&off) {
+        Some(CallProgress::Ringing)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: Duration = Duration::from_millis(50);
+
+    // The states a detector reports over `cycles` of the cadence, fed a block at a time.
+    fn states(on: Duration, off: Duration, cycles: usize) -> Vec<CallProgress> {
+        let mut detector = CadenceDetector::new();
+        let mut states = Vec::new();
+        for _ in 0..cycles {
+            for (present, len) in [(true, on), (false, off)] {
+                for _ in 0..len.as_millis() / BLOCK.as_millis() {
+                    states.extend(detector.update(present, BLOCK));
+                }
+            }
+        }
+        states
+    }
+
+    #[test]
+    fn busy_cadence_is_busy() {
+        assert_eq!(states(Duration::from_millis(500), Duration::from_millis(500), 4), [CallProgress::Busy]);
+    }
+
+    #[test]
+    fn ringback_cadence_is_ringing() {
+        assert_eq!(states(Duration::from_secs(2), Duration::from_secs(4), 3), [CallProgress::Ringing]);
+    }
+
+    #[test]
+    fn ringing_that_stops_is_connected() {
+        let mut detector = CadenceDetector::new();
+        let mut states = Vec::new();
+        for (present, blocks) in [(true, 40), (false, 80), (true, 40), (false, 120)] {
+            for _ in 0..blocks {
+                states.extend(detector.update(present, BLOCK));
+            }
+        }
+        assert_eq!(states, [CallProgress::Ringing, CallProgress::Connected]);
+    }
+}
@@ -0,0 +1,136 @@
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use log::info;
+use serialport::SerialPort;
+use crate::audio::{Detector, PcmFormat};
+use crate::dle::DleDecoder;
+use crate::READ_TIMEOUT;
+
+// Distribution of per-block power readings.
+#[derive(Default)]
+pub(crate) struct PowerStats {
+    values: Vec<f32>
+}
+
+impl PowerStats {
+    pub fn push(&mut self, value: f32) {
+        self.values.push(value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn mean(&self) -> f32 {
+        if self.values.is_empty() {
+            return 0.0;
+        }
+        self.values.iter().sum::<f32>() / self.values.len() as f32
+    }
+
+    pub fn stddev(&self) -> f32 {
+        if self.values.is_empty() {
+            return 0.0;
+        }
+        let mean = self.mean();
+        (self.values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / self.values.len() as f32).sqrt()
+    }
+
+    // Nearest-rank percentile, `p` in 0..=100.
+    pub fn percentile(&self, p: f32) -> f32 {
+        if self.values.is_empty() {
+            return 0.0;
+        }
+        let mut sorted = self.values.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let rank = ((p / 100.0) * sorted.len() as f32).ceil() as usize;
+        sorted[rank.clamp(1, sorted.len()) - 1]
+    }
+}
+
+// Recommended (min, max) tone power, keeping clear of the in-band noise with the same 3x window as the defaults.
+pub(crate) fn recommend_thresholds(in_band: &PowerStats) -> (f32, f32) {
+    let min_power = (in_band.mean() + 4.0 * in_band.stddev()).max(in_band.percentile(99.9));
+    (min_power, min_power * 3.0)
+}
+
+// Listen to an idle line for the given duration and print threshold recommendations from the noise seen,
+// returning the recommended (min, max) tone level in the configured unit.
+pub(crate) fn calibrate_noise(port: &mut dyn SerialPort, format: PcmFormat, detector: &mut Detector, duration: Duration) -> Result<(f32, f32)> {
+    info!("Calibrating against idle line noise for {:#?}", duration);
+    let mut in_band = PowerStats::default();
+    let mut out_of_band = PowerStats::default();
+
+    let start = Instant::now();
+    let mut buffer = vec![0; 1024];
+    let mut dle = DleDecoder::new(detector.config().strip_xon_xoff);
+    let mut audio = Vec::new();
+    let mut capture = detector.capture(format);
+    while start.elapsed() < duration {
+        match port.read(&mut buffer) {
+            Ok(n) if n > 0 => {
+                dle.feed(&buffer[..n], &mut audio);
+                let samples = capture.take_samples(&mut audio);
+                for (peak, floor) in detector.band_powers(&samples) {
+                    in_band.push(peak);
+                    out_of_band.push(floor);
+                }
+            }
+            Ok(_) => {}
+            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => sleep(READ_TIMEOUT),
+            Err(e) => return Err(anyhow!(e))
+        }
+    }
+
+    if in_band.len() == 0 {
+        return Err(anyhow!("No audio received during calibration"));
+    }
+
+    let (min_power, max_power) = recommend_thresholds(&in_band);
+    println!("Blocks measured:       {}", in_band.len());
+    println!("In-band peak power:    mean {:.2}, stddev {:.2}, p50 {:.2}, p99 {:.2}, max {:.2}",
+        in_band.mean(), in_band.stddev(), in_band.percentile(50.0), in_band.percentile(99.0), in_band.percentile(100.0));
+    println!("Out-of-band power:     mean {:.2}, stddev {:.2}, p99 {:.2}",
+        out_of_band.mean(), out_of_band.stddev(), out_of_band.percentile(99.0));
+    // Thresholds are configured in the chosen unit, so recommend them in it too.
+    let unit = detector.config().power_unit;
+    println!("Recommended min power: {:.2} ({:?})", unit.level(min_power), unit);
+    println!("Recommended max power: {:.2} ({:?})", unit.level(max_power), unit);
+    Ok((unit.level(min_power), unit.level(max_power)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::audio::FFT_SAMPLE_SIZE;
+    use crate::config::test_config;
+    use crate::dle::DLE;
+    use crate::mock_port::MockPort;
+
+    #[test]
+    fn recommends_thresholds_above_the_noise() {
+        let mut state = 0x2545_f491_u32;
+        let noise: Vec<i16> = (0..FFT_SAMPLE_SIZE * 8)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 16) as i16 / 16
+            })
+            .collect();
+        let bytes: Vec<u8> = noise.iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .flat_map(|byte| if byte == DLE { vec![DLE, DLE] } else { vec![byte] })
+            .collect();
+        let mut detector = Detector::new(Arc::new(test_config(&[]).unwrap())).unwrap();
+        let loudest = detector.band_powers(&noise).iter().map(|&(peak, _)| peak).fold(0.0, f32::max);
+
+        let mut port = MockPort::with_reads([bytes]);
+        let mut detector = Detector::new(Arc::new(test_config(&[]).unwrap())).unwrap();
+        let (min_power, max_power) =
+            calibrate_noise(&mut port, PcmFormat::SignedLe16, &mut detector, Duration::from_millis(100)).unwrap();
+        assert!(loudest > 0.0);
+        assert!(min_power > loudest, "recommended {} within noise peaking at {}", min_power, loudest);
+        assert_eq!(max_power, min_power * 3.0);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::audio::PcmFormat;
+
+// A voice compression method reported by `AT+VSM=?`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct VoiceCoder {
+    pub id: u32,
+    pub name: String,
+    pub bits: Option<u8>,
+    rates: Vec<(u32, u32)>
+}
+
+impl VoiceCoder {
+    pub fn supports_rate(&self, rate: u32) -> bool {
+        // Some modems list no rates at all, in which case assume the standard 8 kHz is available.
+        self.rates.is_empty() || self.rates.iter().any(|&(min, max)| (min..=max).contains(&rate))
+    }
+
+    fn is_linear(&self) -> bool {
+        let name = self.name.to_ascii_uppercase();
+        (name.contains("LINEAR") || name.contains("PCM")) && !name.contains("LAW") && !name.contains("ADPCM")
+    }
+
+    // G.711 listed as e.g. `MU-LAW`, `ULAW`, `µ-LAW` or `A-LAW`, always 8 bit.
+    fn companding(&self) -> Option<PcmFormat> {
+        let name: String = self.name.to_ascii_uppercase().chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+        if name.contains("ADPCM") {
+            None
+        } else if name.contains("ALAW") {
+            Some(PcmFormat::ALaw)
+        } else if name.contains("ULAW") || name.contains("MULAW") || self.name.contains(['µ', 'μ']) {
+            Some(PcmFormat::MuLaw)
+        } else {
+            None
+        }
+    }
+
+    pub fn pcm_format(&self) -> Option<PcmFormat> {
+        if !self.is_linear() {
+            return self.companding();
+        }
+        match self.bits {
+            Some(8) => Some(PcmFormat::Unsigned8),
+            Some(16) => Some(PcmFormat::SignedLe16),
+            _ => None
+        }
+    }
+}
+
+// Parse a value list such as `0,1,(2-8)` into inclusive ranges.
+fn parse_ranges(list: &str) -> Vec<(u32, u32)> {
+    list.split(',')
+        .map(|item| item.trim().trim_matches(|c| c == '(' || c == ')'))
+        .filter_map(|item| match item.split_once('-') {
+            Some((min, max)) => Some((min.trim().parse().ok()?, max.trim().parse().ok()?)),
+            None => item.parse().ok().map(|v| (v, v))
+        })
+        .collect()
+}
+
+// Whether the `AT+FCLASS=?` response lists voice class 8.
+pub(crate) fn supports_voice(fclass_response: &str) -> bool {
+    fclass_response.lines()
+        .flat_map(parse_ranges)
+        .any(|(min, max)| (min..=max).contains(&8))
+}
+
+// Parse the devices listed by `AT+VLS=?`, either V.253 lines such as `4,"L",00000000,00000000,00000000` (one per
+// device, its value first) or a bare value list such as `0-2,4`.
+pub(crate) fn parse_vls(vls_response: &str) -> Vec<(u32, u32)> {
+    vls_response.lines().flat_map(|line| {
+        let line = line.trim();
+        if !line.contains('"') {
+            return parse_ranges(line);
+        }
+        line.split(',').next().and_then(|id| id.trim().parse().ok()).map(|id| vec![(id, id)]).unwrap_or_default()
+    }).collect()
+}
+
+// Parse `AT+VSM=?` lines such as `128,"8-BIT LINEAR",(7200,8000,11025)` or `1,"UNSIGNED PCM",8,0,(8000),(0),(0)`.
+pub(crate) fn parse_vsm(vsm_response: &str) -> Vec<VoiceCoder> {
+    vsm_response.lines().filter_map(|line| {
+        let (id, rest) = line.trim().split_once(',')?;
+        let id = id.trim().parse::<u32>().ok()?;
+
+        let rest = rest.trim().strip_prefix('"')?;
+        let (name, params) = rest.split_once('"')?;
+        let name = name.to_string();
+
+        // Bits come from the name when it says so, otherwise the first bare parameter (bits per sample).
+        let first_param = params.trim_start_matches(',').split(',').next()
+            .and_then(|p| p.trim().parse::<u8>().ok());
+        let bits = name.split(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+            .find_map(|word| word.to_ascii_uppercase().strip_suffix("-BIT").and_then(|b| b.parse::<u8>().ok()))
+            .or(first_param);
+
+        // Sample rates are anything plausibly audio-rate in the parameters.
+        let rates = parse_ranges(params).into_iter().filter(|&(_, max)| max >= 4000).collect();
+        Some(VoiceCoder { id, name, bits, rates })
+    }).collect()
+}
+
+// The sample rate from an `AT+VSM?` reply such as `128,8000,0,0` or `+VSM: 1,7200`, its second field.
+pub(crate) fn parse_vsm_rate(response: &str) -> Option<u32> {
+    let line = response.lines().map(str::trim).find(|line| !line.is_empty())?;
+    let setting = line.strip_prefix("+VSM:").unwrap_or(line);
+    setting.split(',').nth(1)?.trim().parse().ok().filter(|&rate| rate >= 4000)
+}
+
+// Prefer 16-bit linear PCM, falling back to 8-bit, then μ-law and A-law, at the requested sample rate. Linear 8-bit
+// comes ahead of the companded coders as the default power thresholds are set for its scale.
+pub(crate) fn select_voice_coder(coders: &[VoiceCoder], rate: u32) -> Option<(&VoiceCoder, PcmFormat)> {
+    let candidates = || coders.iter()
+        .filter(|c| c.supports_rate(rate))
+        .filter_map(|c| c.pcm_format().map(|format| (c, format)));
+
+    [PcmFormat::SignedLe16, PcmFormat::Unsigned8, PcmFormat::MuLaw, PcmFormat::ALaw]
+        .into_iter()
+        .find_map(|preferred| candidates().find(|(_, format)| *format == preferred))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_8_bit_without_16_bit() {
+        assert!(supports_voice("0,1,2,8"));
+        assert!(!supports_voice("0,1,2"));
+        let coders = parse_vsm("0,\"SIGNED PCM\",16,0,(11025)\n128,\"8-BIT LINEAR\",(7200,8000,11025)\n130,\"ADPCM\",4,0,(8000)");
+        let (coder, format) = select_voice_coder(&coders, 8000).unwrap();
+        assert_eq!((coder.id, format), (128, PcmFormat::Unsigned8));
+        assert_eq!(select_voice_coder(&coders, 11025).map(|(coder, _)| coder.id), Some(0));
+    }
+
+    #[test]
+    fn selects_companded_coder_without_linear() {
+        let coders = parse_vsm("130,\"ADPCM\",4,0,(8000)\n4,\"G.711 U-LAW\",8,0,(8000)\n5,\"G.711 A-LAW\",8,0,(8000)");
+        let (coder, format) = select_voice_coder(&coders, 8000).unwrap();
+        assert_eq!((coder.id, format), (4, PcmFormat::MuLaw));
+
+        let coders = parse_vsm("5,\"A-LAW\",8,0,(8000)\n1,\"UNSIGNED PCM\",8,0,(8000)");
+        assert_eq!(select_voice_coder(&coders, 8000).map(|(coder, _)| coder.id), Some(1));
+    }
+
+    #[test]
+    fn parses_listed_voice_devices() {
+        let v253 = "0,\"\",00000000,00000000,00000000\r\n1,\"T\",00000000,FFFFFFFF,FFFFFFFF\r\n\
+                    4,\"L\",00000000,00000000,00000000";
+        assert_eq!(parse_vls(v253), [(0, 0), (1, 1), (4, 4)]);
+        assert_eq!(parse_vls("0-2,4"), [(0, 2), (4, 4)]);
+        assert!(parse_vls("").is_empty());
+    }
+
+    #[test]
+    fn parses_negotiated_rate() {
+        assert_eq!(parse_vsm_rate("128,8000,0,0"), Some(8000));
+        assert_eq!(parse_vsm_rate("\n+VSM: 1,7200"), Some(7200));
+        assert_eq!(parse_vsm_rate("1"), None);
+    }
+}
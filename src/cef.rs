@@ -0,0 +1,138 @@
+use serde_json::Value;
+
+const VENDOR: &str = "morgverd";
+const PRODUCT: &str = "alarm-modem";
+const SEVERITY_HIGH: u8 = 10;
+const SEVERITY_NORMAL: u8 = 7;
+
+// SIEM-facing signature IDs and names, anything unlisted (e.g. call progress) uses its label for both.
+const SIGNATURES: [(&str, &str, &str); 8] = [
+    ("trigger", "tone_detected", "Alarm Tone Detected"),
+    ("panic", "panic_tone_detected", "Panic Tone Detected"),
+    ("tone_absent", "tone_absent", "Expected Tone Missing"),
+    ("answer_tone", "answer_tone_detected", "Answer Tone Detected"),
+    ("contact_id", "contact_id_received", "Contact ID Report Received"),
+    ("fsk_data", "fsk_data_received", "FSK Data Received"),
+    ("monitoring_stalled", "monitoring_stalled", "Monitoring Stalled"),
+    ("delivery_failed", "delivery_failed", "Webhook Delivery Failed")
+];
+
+// Payload fields with a standard CEF extension and the label it's given, the rest keep their own (camelCased) names.
+const EXTENSIONS: [(&str, &str, &str); 4] = [
+    ("event", "cs1", "label"),
+    ("frequency_hz", "cfp1", "frequencyHz"),
+    ("level_dbfs", "cfp2", "levelDbfs"),
+    ("offset_ms", "cn1", "offsetMs")
+];
+
+// Header fields escape the pipe and backslash, extensions the equals sign, backslash and line breaks.
+fn escape_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+fn escape_extension(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('=', "\\=").replace('\r', "\\r").replace('\n', "\\n")
+}
+
+fn camel_case(key: &str) -> String {
+    let mut result = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        if c == '_' || c == '.' {
+            upper = true;
+        } else if upper {
+            result.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+// Leaf fields as `(path, value)`, nested objects flattened to `parent.key`.
+fn leaves(prefix: Option<&str>, value: &Value, out: &mut Vec<(String, String)>) {
+    let Value::Object(fields) = value else {
+        return;
+    };
+    for (key, value) in fields {
+        let key = match prefix {
+            Some(prefix) => format!("{}.{}", prefix, key),
+            None => key.clone()
+        };
+        match value {
+            Value::Null => {}
+            Value::String(text) => out.push((key, text.clone())),
+            Value::Object(_) => leaves(Some(&key), value, out),
+            other => out.push((key, other.to_string()))
+        }
+    }
+}
+
+fn render_event(value: &Value) -> String {
+    let label = value["event"].as_str().unwrap_or("unknown");
+    let (signature, name) = SIGNATURES.iter()
+        .find(|(event, _, _)| *event == label)
+        .map_or((label, label), |(_, signature, name)| (*signature, *name));
+    let severity = if value["priority"].as_str() == Some("high") { SEVERITY_HIGH } else { SEVERITY_NORMAL };
+
+    let mut fields = Vec::new();
+    leaves(None, value, &mut fields);
+    let mut extensions = Vec::new();
+    for (key, text) in fields {
+        match key.as_str() {
+            // The severity carries the priority, and recorded audio is far too large for a log line.
+            "priority" | "audio_wav_base64" => {}
+            "timestamp" => extensions.push(format!("rt={}", text.parse::<u64>().unwrap_or_default() * 1000)),
+            _ => match EXTENSIONS.iter().find(|(field, _, _)| *field == key) {
+                Some((_, extension, label)) => {
+                    extensions.push(format!("{}={}", extension, escape_extension(&text)));
+                    extensions.push(format!("{}Label={}", extension, label));
+                },
+                None => extensions.push(format!("{}={}", camel_case(&key), escape_extension(&text)))
+            }
+        }
+    }
+
+    format!(
+        "CEF:0|{}|{}|{}|{}|{}|{}|{}",
+        VENDOR, PRODUCT, env!("CARGO_PKG_VERSION"), escape_header(signature), escape_header(name), severity, extensions.join(" ")
+    )
+}
+
+// Render a serialized payload as CEF, one line per detection so a batch becomes several lines.
+pub(crate) fn render(value: &Value) -> String {
+    match value["events"].as_array() {
+        Some(events) => events.iter().map(render_event).collect::<Vec<_>>().join("\n"),
+        None => render_event(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn renders_the_header_and_escaped_extensions() {
+        let payload = json!({
+            "event": "trigger", "priority": "high", "timestamp": 1700000000, "frequency_hz": 1665.5,
+            "level_dbfs": -23.0, "offset_ms": 640, "caller": {"number": "555=1234"}, "audio_wav_base64": "UklGRg=="
+        });
+        assert_eq!(render(&payload), format!(
+            "CEF:0|morgverd|alarm-modem|{}|tone_detected|Alarm Tone Detected|10|callerNumber=555\\=1234 cs1=trigger \
+             cs1Label=label cfp1=1665.5 cfp1Label=frequencyHz cfp2=-23.0 cfp2Label=levelDbfs cn1=640 \
+             cn1Label=offsetMs rt=1700000000000",
+            env!("CARGO_PKG_VERSION")
+        ));
+    }
+
+    #[test]
+    fn escapes_header_fields_and_splits_batches() {
+        let batch = json!({"event": "batch", "events": [{"event": "dial|tone"}, {"event": "panic"}]});
+        let lines: Vec<String> = render(&batch).lines().map(String::from).collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("|dial\\|tone|dial\\|tone|7|cs1=dial|tone "), "{}", lines[0]);
+        assert!(lines[1].contains("|panic_tone_detected|Panic Tone Detected|7|"), "{}", lines[1]);
+    }
+}
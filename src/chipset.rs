@@ -0,0 +1,109 @@
+use anyhow::{anyhow, Result};
+
+// One step of modem initialization. Optional commands tune the modem but aren't needed to capture audio,
+// so a modem rejecting them is logged and init carries on, while a critical failure aborts.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct InitCommand<'a> {
+    pub command: &'a str,
+    pub critical: bool
+}
+
+impl<'a> InitCommand<'a> {
+    pub const fn critical(command: &'a str) -> Self {
+        InitCommand { command, critical: true }
+    }
+
+    pub const fn optional(command: &'a str) -> Self {
+        InitCommand { command, critical: false }
+    }
+}
+
+// Voice mode setup for a modem chipset, sent before the voice coder is chosen. Every profile starts with
+// `AT+FCLASS=8`, the V.253 `+VSM` and `+VRX` commands used afterwards are common to all of them.
+pub(crate) struct ChipsetProfile {
+    pub name: &'static str,
+    pub commands: &'static [InitCommand<'static>],
+    pub vgr_db_per_step: f32 // Receive gain change per `AT+VGR` step, for compensating thresholds
+}
+
+pub(crate) const PROFILES: &[ChipsetProfile] = &[
+    ChipsetProfile {
+        name: "generic",
+        commands: &[
+            InitCommand::critical("AT+FCLASS=8"), // Voice mode
+            InitCommand::critical("AT+VLS=1"),    // Enable speaker
+            InitCommand::optional("AT+VGR=3")     // Gain
+        ],
+        vgr_db_per_step: 2.0
+    },
+    ChipsetProfile {
+        name: "conexant",
+        commands: &[
+            InitCommand::critical("AT+FCLASS=8"), // Voice mode
+            InitCommand::critical("AT+VLS=1"),    // Line to the host
+            InitCommand::optional("AT+VGR=128"),  // Nominal receive gain on the 0-255 scale
+            InitCommand::optional("AT+VSD=128,0") // Silence detection sensitivity, with no silence timeout
+        ],
+        vgr_db_per_step: 0.5
+    },
+    ChipsetProfile {
+        name: "rockwell",
+        commands: &[
+            InitCommand::critical("AT+FCLASS=8"), // Voice mode
+            InitCommand::critical("AT+VLS=1"),    // Line to the host
+            InitCommand::optional("AT+VGR=3"),    // Gain
+            InitCommand::optional("AT+VIT=0")     // Disable the DTE/DCE inactivity timer so long listens aren't cut off
+        ],
+        vgr_db_per_step: 2.0
+    },
+    ChipsetProfile {
+        name: "usr",
+        commands: &[
+            InitCommand::critical("AT+FCLASS=8"), // Voice mode
+            InitCommand::optional("AT+VNH=1"),    // Don't hang up automatically when leaving voice mode
+            InitCommand::critical("AT+VLS=1"),    // Line to the host
+            InitCommand::optional("AT+VGR=3")     // Gain
+        ],
+        vgr_db_per_step: 2.0
+    }
+];
+
+impl ChipsetProfile {
+    // The receive gain the profile's init sequence sets.
+    pub fn vgr(&self) -> Option<u32> {
+        self.commands.iter().find_map(|init| init.command.strip_prefix("AT+VGR=")?.parse().ok())
+    }
+}
+
+pub(crate) fn find_profile(name: &str) -> Result<&'static ChipsetProfile> {
+    PROFILES.iter()
+        .find(|profile| profile.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            let names: Vec<&str> = PROFILES.iter().map(|profile| profile.name).collect();
+            anyhow!("Unknown modem profile {}, expected one of {}", name, names.join(", "))
+        })
+}
+
+// Linear factor undoing the receive gain difference between the VGR the modem is set to and the one the power
+// thresholds were tuned at, so the same tone measures the same power at either.
+pub(crate) fn vgr_compensation(vgr: u32, reference: u32, db_per_step: f32) -> f32 {
+    let gain_db = (vgr as f32 - reference as f32) * db_per_step;
+    10f32.powf(-gain_db / 20.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_a_profile_by_name() {
+        let profile = find_profile("Conexant").unwrap();
+        let commands: Vec<&str> = profile.commands.iter().map(|init| init.command).collect();
+        assert_eq!(commands, ["AT+FCLASS=8", "AT+VLS=1", "AT+VGR=128", "AT+VSD=128,0"]);
+        assert_eq!(profile.vgr(), Some(128));
+
+        let error = find_profile("hayes").err().unwrap().to_string();
+        assert!(error.contains("Unknown modem profile hayes"), "{}", error);
+        assert!(error.contains("generic, conexant, rockwell, usr"), "{}", error);
+    }
+}
@@ -0,0 +1,112 @@
+use serde::Serialize;
+
+const MDMF: u8 = 0x80;
+const SDMF: u8 = 0x04;
+const MDMF_DATE_TIME: u8 = 0x01;
+const MDMF_NUMBER: u8 = 0x02;
+const MDMF_NAME: u8 = 0x07;
+
+// Who called, from the modem's caller ID report or a Type I FSK message. Date and time are as sent, `MMDD` and `HHMM`.
+#[derive(Clone, Debug, Serialize)]
+pub struct CallerId {
+    pub number: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>
+}
+
+// The value of a `KEY = value` line as reported by `AT+VCID=1` modems, once the line is terminated so a value split
+// across reads isn't taken early.
+fn cid_field<'a>(text: &'a str, key: &str) -> Option<&'a str> {
+    let start = text.find(key)? + key.len();
+    let rest = text[start..].trim_start_matches([' ', '=']);
+    let end = rest.find(['\r', '\n'])?;
+    let value = rest[..end].trim();
+    (!value.is_empty()).then_some(value)
+}
+
+// Caller ID as reported by `AT+VCID=1` modems, e.g. `NMBR = 5551234`, the number reduced to its digits. The `DATE`,
+// `TIME` and `NAME` lines are taken when they've arrived, the number alone decides whether there is a caller.
+pub(crate) fn parse_cid(text: &str) -> Option<CallerId> {
+    let number = normalize_number(cid_field(text, "NMBR")?);
+    (!number.is_empty()).then(|| CallerId {
+        number,
+        name: cid_field(text, "NAME").map(String::from),
+        date: cid_field(text, "DATE").map(String::from),
+        time: cid_field(text, "TIME").map(String::from)
+    })
+}
+
+// A caller ID message sent as Bell 202 or V.23 FSK between the rings, either single (SDMF) or multiple data message
+// format (MDMF). Any channel seizure and mark bytes before it are skipped, and the checksum must hold.
+pub(crate) fn parse_fsk_cid(data: &[u8]) -> Option<CallerId> {
+    (0..data.len()).find_map(|start| {
+        let message = &data[start..];
+        let len = *message.get(1)? as usize;
+        let message = message.get(..len + 3)?;
+        if !matches!(message[0], MDMF | SDMF) || message.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            return None;
+        }
+        let body = &message[2..len + 2];
+        let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).trim().to_string();
+
+        let (mut number, mut name, mut date_time) = (None, None, None);
+        if message[0] == SDMF {
+            date_time = body.get(..8).map(text);
+            number = body.get(8..).map(text);
+        } else {
+            let mut params = body;
+            while let [kind, len, rest @ ..] = params {
+                let value = rest.get(..*len as usize)?;
+                match *kind {
+                    MDMF_DATE_TIME => date_time = Some(text(value)),
+                    MDMF_NUMBER => number = Some(text(value)),
+                    MDMF_NAME => name = Some(text(value)),
+                    _ => {}
+                }
+                params = &rest[*len as usize..];
+            }
+        }
+
+        let number = normalize_number(&number?);
+        let date_time = date_time.filter(|date_time| date_time.len() == 8 && date_time.bytes().all(|b| b.is_ascii_digit()));
+        (!number.is_empty()).then(|| CallerId {
+            number,
+            name: name.filter(|name| !name.is_empty()),
+            date: date_time.as_ref().map(|date_time| date_time[..4].to_string()),
+            time: date_time.as_ref().map(|date_time| date_time[4..].to_string())
+        })
+    })
+}
+
+// Whether a call may proceed past caller ID screening: it must have a number, and one on the allowlist if there is one.
+pub(crate) fn caller_allowed(caller: Option<&str>, allowlist: &[String]) -> bool {
+    caller.is_some_and(|number| allowlist.is_empty() || allowlist.iter().any(|allowed| *allowed == number))
+}
+
+// Numbers are compared on their digits alone, so `555-1234` and `5551234` match.
+pub(crate) fn normalize_number(number: &str) -> String {
+    number.chars().filter(|c| c.is_ascii_digit()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // The number is taken as soon as its line is complete, the rest as they arrive, and the payload leaves out what
+    // hasn't.
+    #[test]
+    fn parses_the_modems_caller_id_report() {
+        assert!(parse_cid("\r\nDATE = 0312\r\nTIME = 1457\r\nNMBR = 555-12").is_none());
+        let caller = parse_cid("\r\nDATE = 0312\r\nTIME = 1457\r\nNMBR = 555-1234\r\n").unwrap();
+        assert_eq!(serde_json::to_value(&caller).unwrap(), json!({"number": "5551234", "date": "0312", "time": "1457"}));
+
+        let caller = parse_cid("NMBR=5551234\r\nNAME = ACME ALARMS\r\n").unwrap();
+        assert_eq!(caller.name.as_deref(), Some("ACME ALARMS"));
+        assert!(parse_cid("\r\nNMBR = O\r\n").is_none(), "withheld number taken as a caller");
+    }
+}
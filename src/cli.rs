@@ -0,0 +1,126 @@
+use std::env::set_var;
+use clap::Parser;
+
+// Command line arguments: one-off commands, and the most used config options for runs that don't warrant editing the
+// env. An option given here overrides its env var, which overrides the default.
+#[derive(Parser)]
+#[command(version, about = "Listens for alarm panel tones through a voice modem and reports them")]
+pub(crate) struct Cli {
+    #[arg(long, value_name = "FILE", help = "TOML config file, beneath the env vars (ALARM_CONFIG_FILE)")]
+    config: Option<String>,
+    #[arg(long, value_name = "PATH", help = "Modem device port (ALARM_MODEM_PORT)")]
+    port: Option<String>,
+    #[arg(long, help = "Modem baud rate (ALARM_MODEM_BAUD)")]
+    baud: Option<u32>,
+    #[arg(long, value_name = "URL", help = "Webhook to send detections to (ALARM_WEBHOOK_URL)")]
+    webhook_url: Option<String>,
+    #[arg(long, value_name = "KEY", help = "Key sent with each webhook (ALARM_WEBHOOK_KEY)")]
+    webhook_key: Option<String>,
+    #[arg(long, value_name = "HZ", help = "Lower edge of the target tone band (ALARM_TONE_MIN_FREQ)")]
+    tone_min_freq: Option<f32>,
+    #[arg(long, value_name = "HZ", help = "Upper edge of the target tone band (ALARM_TONE_MAX_FREQ)")]
+    tone_max_freq: Option<f32>,
+    #[arg(long, value_name = "LEVEL", allow_negative_numbers = true, help = "Minimum target tone level (ALARM_TONE_MIN_POWER)")]
+    tone_min_power: Option<f32>,
+    #[arg(long, value_name = "LEVEL", allow_negative_numbers = true, help = "Maximum target tone level (ALARM_TONE_MAX_POWER)")]
+    tone_max_power: Option<f32>,
+    #[arg(long, help = "Run detection but only log detections, notifying no webhook or other sink (ALARM_MODE=observe)")]
+    dry_run: bool,
+    #[arg(long, value_name = "SECS", help = "Stop listening after this long and exit cleanly (ALARM_RUN_SECONDS)")]
+    duration: Option<u64>,
+
+    #[arg(long, help = "List available serial ports with their type and USB VID:PID, then exit")]
+    pub list_ports: bool,
+    #[arg(long, value_name = "FILE", help = "Print a CSV row per block of a WAV recording")]
+    pub analyze: Option<String>,
+    #[arg(long, value_name = "SECS", help = "Measure idle line noise and print recommended tone thresholds")]
+    pub calibrate_noise: Option<u64>,
+    #[arg(long, value_name = "SECS", help = "Average the spectrum over a capture and print it as a histogram by band")]
+    pub spectrum: Option<u64>,
+    #[arg(long, value_name = "NAME", requires = "calibrate_noise", help = "Save the recommended thresholds as a named profile")]
+    pub save_profile: Option<String>,
+    #[arg(long, num_args = 2.., value_names = ["OUT", "WAV"], help = "Learn a tone template from recordings and save it")]
+    pub learn: Option<Vec<String>>,
+    #[arg(long, value_name = "COUNT", num_args = 0..=1, default_missing_value = "20",
+        help = "Print the most recent detections recorded in ALARM_DB_PATH, 20 by default")]
+    pub history: Option<usize>,
+    #[arg(long, help = "Check the modem's own tone is detected through its local loopback")]
+    pub loopback_test: bool,
+    #[arg(long, help = "Check the modem init, tone detection and webhook in turn, exiting non-zero if any fails")]
+    pub selftest: bool,
+    #[arg(long, value_name = "FILE", help = "Play a WAV recording through detection in real time")]
+    pub replay: Option<String>,
+    #[arg(long, value_name = "FILE", help = "Play an ALARM_CAPTURE_RAW capture through detection in real time")]
+    pub replay_raw: Option<String>,
+    #[arg(long, value_name = "DIGITS", help = "Play a DTMF sequence with AT+VTS and exit")]
+    pub send_dtmf: Option<String>
+}
+
+impl Cli {
+    // Set the overriding options as their env vars, before the process env is snapshotted, so they hold over .env and
+    // through a SIGHUP reload like the rest of the real environment.
+    pub fn apply_to_env(&self) {
+        let overrides = [
+            ("ALARM_CONFIG_FILE", self.config.clone()),
+            ("ALARM_MODEM_PORT", self.port.clone()),
+            ("ALARM_MODEM_BAUD", self.baud.map(|baud| baud.to_string())),
+            ("ALARM_WEBHOOK_URL", self.webhook_url.clone()),
+            ("ALARM_WEBHOOK_KEY", self.webhook_key.clone()),
+            ("ALARM_TONE_MIN_FREQ", self.tone_min_freq.map(|frequency| frequency.to_string())),
+            ("ALARM_TONE_MAX_FREQ", self.tone_max_freq.map(|frequency| frequency.to_string())),
+            ("ALARM_TONE_MIN_POWER", self.tone_min_power.map(|power| power.to_string())),
+            ("ALARM_TONE_MAX_POWER", self.tone_max_power.map(|power| power.to_string())),
+            ("ALARM_MODE", self.dry_run.then(|| "observe".to_string())),
+            ("ALARM_RUN_SECONDS", self.duration.map(|secs| secs.to_string()))
+        ];
+        for (key, value) in overrides {
+            if let Some(value) = value {
+                set_var(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::{remove_var, var};
+    use crate::config::lock_env;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(["alarm-modem"].iter().chain(args))
+    }
+
+    // Options given override the env, those not given leave it as it was.
+    #[test]
+    fn options_override_their_env_vars() {
+        let _env = lock_env();
+        set_var("ALARM_MODEM_PORT", "/dev/ttyACM0");
+        set_var("ALARM_WEBHOOK_KEY", "from-env");
+        parse(&["--port", "/dev/ttyUSB1", "--tone-min-power", "-30", "--dry-run"]).unwrap().apply_to_env();
+        let settings = [
+            "ALARM_MODEM_PORT",
+            "ALARM_WEBHOOK_KEY",
+            "ALARM_TONE_MIN_POWER",
+            "ALARM_MODE",
+            "ALARM_MODEM_BAUD"
+        ];
+        let values: Vec<_> = settings.iter().map(|key| var(key).ok()).collect();
+        settings.iter().for_each(|key| remove_var(key));
+        let expected = [Some("/dev/ttyUSB1"), Some("from-env"), Some("-30"), Some("observe"), None];
+        assert_eq!(values, expected.map(|value| value.map(String::from)));
+
+        assert!(parse(&["--save-profile", "panel"]).is_err(), "saved a profile without calibrating");
+        assert!(parse(&["--baud", "fast"]).is_err());
+    }
+
+    #[test]
+    fn duration_sets_run_seconds() {
+        let _env = lock_env();
+        parse(&["--duration", "600"]).unwrap().apply_to_env();
+        let value = var("ALARM_RUN_SECONDS").ok();
+        remove_var("ALARM_RUN_SECONDS");
+        assert_eq!(value.as_deref(), Some("600"));
+        assert!(parse(&["--duration", "soon"]).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use std::process::Command;
+use std::thread;
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use crate::sink::DetectionSink;
+use crate::webhook::AlertPayload;
+
+// Runs a local command for each detection (siren, GPIO, SMS tool...), describing it through `ALARM_EVENT_*`
+// environment variables. The command runs in the background and its exit status is logged when it finishes.
+pub(crate) struct CommandSink {
+    program: String,
+    args: Vec<String>
+}
+
+impl CommandSink {
+    pub fn new(command: &str) -> Result<Self> {
+        let mut parts = command.split_whitespace().map(String::from);
+        let program = parts.next().ok_or_else(|| anyhow!("Detection command is empty"))?;
+        Ok(CommandSink { program, args: parts.collect() })
+    }
+}
+
+fn payload_env(payload: &AlertPayload) -> Vec<(&'static str, String)> {
+    let mut env = vec![
+        ("ALARM_EVENT", payload.event.to_string()),
+        ("ALARM_EVENT_PRIORITY", payload.priority.to_string()),
+        ("ALARM_EVENT_TIMESTAMP", payload.timestamp.to_string())
+    ];
+    if let Some(contact_id) = &payload.contact_id {
+        env.push(("ALARM_EVENT_ACCOUNT", contact_id.account.clone()));
+        env.push(("ALARM_EVENT_CODE", contact_id.code.to_string()));
+        env.push(("ALARM_EVENT_DESCRIPTION", contact_id.description.to_string()));
+        env.push(("ALARM_EVENT_ZONE", contact_id.zone.to_string()));
+    }
+    if let Some(data) = &payload.fsk_data_hex {
+        env.push(("ALARM_EVENT_DATA", data.clone()));
+    }
+    env
+}
+
+impl DetectionSink for CommandSink {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+
+    fn on_detection(&mut self, payload: &AlertPayload) -> Result<()> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .envs(payload_env(payload))
+            .spawn()
+            .with_context(|| format!("Failed to run detection command {}", self.program))?;
+
+        let program = self.program.clone();
+        thread::spawn(move || match child.wait() {
+            Ok(status) if status.success() => info!("Detection command {} exited with {}", program, status),
+            Ok(status) => warn!("Detection command {} exited with {}", program, status),
+            Err(e) => warn!("Failed to wait for detection command {}: {}", program, e)
+        });
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::thread::sleep;
+    use std::time::{Duration, Instant, SystemTime};
+    use crate::audio::{Detection, DetectionEvent};
+    use crate::contact_id;
+
+    #[test]
+    fn runs_the_command_with_the_detection_in_its_environment() {
+        let path = std::env::temp_dir().join(format!("alarm-modem-{}-command-env", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut sink = CommandSink::new(&format!("sh -c env>{}", path.display())).unwrap();
+        let event = DetectionEvent {
+            detection: Detection::ContactId,
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(1000),
+            audio: None,
+            contact_id: Some(contact_id::parse("1234 18 1130 01 015 E").unwrap()),
+            fsk_data: None,
+            frequency: None,
+            offset: None,
+            level_dbfs: None,
+            caller: None
+        };
+        sink.on_detection(&AlertPayload::from_event(&event, 8000)).unwrap();
+
+        let start = Instant::now();
+        let mut env = String::new();
+        while !env.contains("ALARM_EVENT_ZONE") && start.elapsed() < Duration::from_secs(5) {
+            sleep(Duration::from_millis(10));
+            env = fs::read_to_string(&path).unwrap_or_default();
+        }
+        let mut vars: Vec<_> = env.lines().filter(|line| line.starts_with("ALARM_EVENT")).collect();
+        vars.sort();
+        assert_eq!(vars, [
+            "ALARM_EVENT=contact_id",
+            "ALARM_EVENT_ACCOUNT=1234",
+            "ALARM_EVENT_CODE=130",
+            "ALARM_EVENT_DESCRIPTION=Burglary",
+            "ALARM_EVENT_PRIORITY=normal",
+            "ALARM_EVENT_TIMESTAMP=1000",
+            "ALARM_EVENT_ZONE=15"
+        ]);
+        fs::remove_file(&path).unwrap();
+    }
+}
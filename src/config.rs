@@ -1,24 +1,1183 @@
 use std::env::var;
-use anyhow::{Context, Result};
+use std::str::FromStr;
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use serialport::{DataBits, FlowControl, Parity, StopBits};
+use crate::audio::{PcmFormat, WindowFn, CALL_PROGRESS_FREQ, FFT_SAMPLE_SIZE, SAMPLE_RATE};
+use crate::chipset::{find_profile, ChipsetProfile};
+use crate::cid::normalize_number;
+use crate::modem::dtmf_command;
+use crate::payload_template::PayloadTemplate;
+use crate::ports::detect_modem_port;
 
-pub(crate) struct Config {
-    pub modem_port: String,
-    pub modem_baud: u32,
-    pub webhook_url: String,
-    pub webhook_key: String
+// Rates a modem's serial side is commonly built for, anything else is likely a typo.
+const STANDARD_BAUD_RATES: [u32; 10] = [300, 1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400];
+const BAND_PASS_MARGIN: f32 = 200.0; // Hz left either side of the detected bands by the default band-pass
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ControlLine {
+    Dsr,
+    Cts,
+    Ri,
+    Cd
+}
+
+impl FromStr for ControlLine {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "dsr" => Ok(ControlLine::Dsr),
+            "cts" => Ok(ControlLine::Cts),
+            "ri" => Ok(ControlLine::Ri),
+            "cd" | "dcd" => Ok(ControlLine::Cd),
+            _ => Err(anyhow!("Unknown control line {}, expected one of dsr, cts, ri, cd", s))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DetectMode {
+    Presence, // Alert when the tone appears
+    Absence   // Alert when an expected tone stops
+}
+
+impl FromStr for DetectMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "presence" => Ok(DetectMode::Presence),
+            "absence" => Ok(DetectMode::Absence),
+            _ => Err(anyhow!("Unknown detect mode {}, expected presence or absence", s))
+        }
+    }
+}
+
+// How the target tone's level is measured in each block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ToneDetector {
+    Fft,     // The full spectrum, which the template, whitening, harmonic and SNR checks also work from
+    Goertzel // Only the frequencies in the band, far cheaper when nothing else needs the spectrum
+}
+
+impl FromStr for ToneDetector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "fft" => Ok(ToneDetector::Fft),
+            "goertzel" => Ok(ToneDetector::Goertzel),
+            _ => Err(anyhow!("Unknown tone detector {}, expected fft or goertzel", s))
+        }
+    }
+}
+
+// The filter samples pass through before detection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum InputFilter {
+    BandPass, // A biquad around the bands being detected
+    HighPass, // The original single pole filter, kept for comparison
+    None
+}
+
+impl FromStr for InputFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "bandpass" => Ok(InputFilter::BandPass),
+            "highpass" => Ok(InputFilter::HighPass),
+            "none" => Ok(InputFilter::None),
+            _ => Err(anyhow!("Unknown filter {}, expected bandpass, highpass or none", s))
+        }
+    }
+}
+
+// How the webhook body and syslog message render a detection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PayloadFormat {
+    Json, // Per the webhook content type, JSON or form fields
+    Cef   // ArcSight Common Event Format, for SIEM ingestion
+}
+
+impl FromStr for PayloadFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(PayloadFormat::Json),
+            "cef" => Ok(PayloadFormat::Cef),
+            _ => Err(anyhow!("Unknown payload format {}, expected json or cef", s))
+        }
+    }
+}
+
+// FSK modulation a panel reports data with: mark (1) and space (0) tones, both at 1200 baud.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FskStandard {
+    Bell202, // 1200 Hz mark, 2200 Hz space
+    V23      // 1300 Hz mark, 2100 Hz space
+}
+
+impl FskStandard {
+    pub fn mark(&self) -> f32 {
+        match self {
+            FskStandard::Bell202 => 1200.0,
+            FskStandard::V23 => 1300.0
+        }
+    }
+
+    pub fn space(&self) -> f32 {
+        match self {
+            FskStandard::Bell202 => 2200.0,
+            FskStandard::V23 => 2100.0
+        }
+    }
+
+    pub fn baud(&self) -> f32 {
+        1200.0
+    }
+}
+
+impl FromStr for FskStandard {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "bell202" => Ok(FskStandard::Bell202),
+            "v23" => Ok(FskStandard::V23),
+            _ => Err(anyhow!("Unknown FSK standard {}, expected bell202 or v23", s))
+        }
+    }
+}
+
+// Line format for detections mirrored to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StdoutFormat {
+    Json,
+    Tsv
+}
+
+impl FromStr for StdoutFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(StdoutFormat::Json),
+            "tsv" => Ok(StdoutFormat::Tsv),
+            _ => Err(anyhow!("Unknown stdout event format {}, expected json or tsv", s))
+        }
+    }
+}
+
+// Unit tone thresholds are written in: bin power (re² + im²), magnitude (its square root) or decibels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerUnit {
+    Power,
+    Magnitude,
+    Db
+}
+
+impl PowerUnit {
+    // As written in ALARM_POWER_UNIT.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PowerUnit::Power => "power",
+            PowerUnit::Magnitude => "magnitude",
+            PowerUnit::Db => "db"
+        }
+    }
+
+    // Express a bin power in this unit.
+    pub fn level(&self, power: f32) -> f32 {
+        match self {
+            PowerUnit::Power => power,
+            PowerUnit::Magnitude => power.sqrt(),
+            PowerUnit::Db => 10.0 * power.max(f32::MIN_POSITIVE).log10()
+        }
+    }
+}
+
+impl FromStr for PowerUnit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "power" => Ok(PowerUnit::Power),
+            "magnitude" => Ok(PowerUnit::Magnitude),
+            "db" => Ok(PowerUnit::Db),
+            _ => Err(anyhow!("Unknown power unit {}, expected power, magnitude or db", s))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MetricsBackend {
+    None,
+    Statsd,
+    Prometheus
+}
+
+impl FromStr for MetricsBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(MetricsBackend::None),
+            "statsd" => Ok(MetricsBackend::Statsd),
+            "prometheus" => Ok(MetricsBackend::Prometheus),
+            _ => Err(anyhow!("Unknown metrics backend {}, expected none, statsd or prometheus", s))
+        }
+    }
+}
+
+// Whether detections reach the sinks, observe mode only logs and counts them for tuning against live traffic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RunMode {
+    Active,
+    Observe
+}
+
+impl FromStr for RunMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "active" => Ok(RunMode::Active),
+            "observe" => Ok(RunMode::Observe),
+            _ => Err(anyhow!("Unknown mode {}, expected active or observe", s))
+        }
+    }
+}
+
+// Inclusive frequency range in Hz, written as `min-max`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrequencyBand {
+    pub min: f32,
+    pub max: f32
+}
+
+impl FrequencyBand {
+    pub fn contains(&self, frequency: f32) -> bool {
+        (self.min..=self.max).contains(&frequency)
+    }
+}
+
+impl FromStr for FrequencyBand {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (min, max) = s.split_once('-').with_context(|| format!("Expected a band as min-max, got {}", s))?;
+        let band = FrequencyBand {
+            min: min.trim().parse().with_context(|| format!("Invalid band minimum in {}", s))?,
+            max: max.trim().parse().with_context(|| format!("Invalid band maximum in {}", s))?
+        };
+        if band.min > band.max {
+            return Err(anyhow!("Band {} has its minimum above its maximum", s));
+        }
+        Ok(band)
+    }
+}
+
+// Expected tone band and the range its bin level must fall within, in the configured power unit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneProfile {
+    pub band: FrequencyBand,
+    pub min_power: f32,
+    pub max_power: f32
+}
+
+// Tone profile for a panel identified by caller ID, written as `number=min-max` or `number=min-max@min_power-max_power`.
+#[derive(Debug, Clone)]
+pub(crate) struct CallerProfile {
+    pub number: String,
+    pub band: FrequencyBand,
+    pub power: Option<(f32, f32)>
+}
+
+impl FromStr for CallerProfile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (number, spec) = s.split_once('=').with_context(|| format!("Expected a profile as number=min-max, got {}", s))?;
+        let number = normalize_number(number);
+        if number.is_empty() {
+            return Err(anyhow!("Profile {} has no caller number", s));
+        }
+
+        let ToneSpec { band, power } = spec.parse()?;
+        Ok(CallerProfile { number, band, power })
+    }
+}
+
+// Tone band with an optional power range, written as `min-max` or `min-max@min_power-max_power`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ToneSpec {
+    pub band: FrequencyBand,
+    pub power: Option<(f32, f32)>
+}
+
+impl FromStr for ToneSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (band, power) = match s.split_once('@') {
+            Some((band, power)) => {
+                let power: FrequencyBand = power.parse().with_context(|| format!("Invalid power range in {}", s))?;
+                (band, Some((power.min, power.max)))
+            },
+            None => (s, None)
+        };
+        Ok(ToneSpec { band: band.parse()?, power })
+    }
+}
+
+// Software AGC: the level in dBFS the input is steered toward, how quickly the gain falls when the level rises (attack)
+// and recovers when it drops (release), each one time constant, and the most gain it will ever apply.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct AgcSettings {
+    pub target_dbfs: f32,
+    pub attack: Duration,
+    pub release: Duration,
+    pub max_gain_db: f32
+}
+
+// One step of a tone sequence, written as `min-max:on_ms` or `min-max:on_ms:gap_ms`: a tone within the band held for
+// at least `on_ms`, then at most `gap_ms` (default 0) before the next step's tone starts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct SequenceStep {
+    pub band: FrequencyBand,
+    pub min_on: Duration,
+    pub max_gap: Duration
+}
+
+impl FromStr for SequenceStep {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split(':');
+        let band = parts.next().unwrap_or_default().parse()?;
+        let millis = |part: Option<&str>, name: &str| -> Result<Option<Duration>> {
+            part.map(|ms| ms.trim().parse().map(Duration::from_millis).with_context(|| format!("Invalid {} in {}", name, s)))
+                .transpose()
+        };
+        let min_on = millis(parts.next(), "tone duration")?
+            .with_context(|| format!("Expected a step as min-max:on_ms or min-max:on_ms:gap_ms, got {}", s))?;
+        let max_gap = millis(parts.next(), "gap")?.unwrap_or_default();
+        if min_on.is_zero() || parts.next().is_some() {
+            return Err(anyhow!("Expected a step as min-max:on_ms or min-max:on_ms:gap_ms with on_ms above 0, got {}", s));
+        }
+        Ok(SequenceStep { band, min_on, max_gap })
+    }
+}
+
+// Tones that must be heard in order, all within `window`, for the trigger to fire.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ToneSequence {
+    pub steps: Vec<SequenceStep>,
+    pub window: Duration
+}
+
+// Built-in sequences: `handshake` is the Contact ID handshake of 1400 Hz then 2300 Hz, `pulsed` the target tone heard
+// three times over with short breaks between.
+fn builtin_sequence(name: &str, tone: FrequencyBand) -> Option<Vec<SequenceStep>> {
+    let step = |min, max, on, gap| SequenceStep {
+        band: FrequencyBand { min, max },
+        min_on: Duration::from_millis(on),
+        max_gap: Duration::from_millis(gap)
+    };
+    match name {
+        "handshake" => Some(vec![step(1360.0, 1440.0, 50, 300), step(2260.0, 2340.0, 50, 0)]),
+        "pulsed" => Some(vec![step(tone.min, tone.max, 250, 1000); 3]),
+        _ => None
+    }
+}
+
+// A built-in sequence by name or steps of its own separated by commas.
+fn parse_sequence(s: &str, tone: FrequencyBand) -> Result<Vec<SequenceStep>> {
+    if let Some(steps) = builtin_sequence(s.trim(), tone) {
+        return Ok(steps);
+    }
+    if !s.contains(':') {
+        return Err(anyhow!("Unknown tone sequence {}, expected handshake, pulsed or a list of min-max:on_ms:gap_ms steps", s));
+    }
+    s.split(',').filter(|step| !step.trim().is_empty()).map(|step| step.trim().parse()).collect()
+}
+
+// A further webhook endpoint, written as `url` or `url|key` when it takes a key other than `ALARM_WEBHOOK_KEY`, and
+// `url|key|template_file` (the key left empty to keep the default) for a body in its own shape.
+#[derive(Debug, Clone)]
+pub(crate) struct WebhookTarget {
+    pub url: String,
+    pub key: Option<String>,
+    pub template: Option<PayloadTemplate>
+}
+
+impl FromStr for WebhookTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split('|').map(str::trim);
+        let url = parts.next().unwrap_or_default();
+        let key = parts.next().filter(|key| !key.is_empty()).map(String::from);
+        let template = parts.next().map(PayloadTemplate::load).transpose()?;
+        if url.is_empty() {
+            return Err(anyhow!("Webhook endpoint {} has no URL", s));
+        }
+        Ok(WebhookTarget { url: url.to_string(), key, template })
+    }
+}
+
+// Serial control line that signals the line is active, optionally inverted for active-low hardware.
+pub(crate) struct LineGate {
+    pub line: ControlLine,
+    pub active_low: bool
+}
+
+pub struct Config {
+    pub(crate) modem_port: String,
+    pub(crate) modem_baud: u32,
+    pub(crate) modem_auto_baud: bool,
+    pub(crate) modem_lock_dir: Option<String>,
+    pub(crate) modem_profile: &'static ChipsetProfile,
+    pub(crate) modem_init: Vec<String>,
+    pub(crate) modem_vls: Option<u32>,
+    pub(crate) modem_vgr: Option<u32>,
+    pub(crate) vgr_reference: Option<u32>,
+    pub(crate) vgr_db_per_step: f32,
+    pub(crate) modem_data_bits: DataBits,
+    pub(crate) modem_parity: Parity,
+    pub(crate) modem_stop_bits: StopBits,
+    pub(crate) modem_flow_control: FlowControl,
+    pub(crate) strip_xon_xoff: bool,
+    pub(crate) webhook_url: String,
+    pub(crate) webhook_key: String,
+    pub(crate) webhook_urls: Vec<WebhookTarget>, // Delivered to alongside `webhook_url`
+    pub(crate) webhook_dlq_url: Option<String>,
+    pub(crate) webhook_max_retries: u32,
+    pub(crate) webhook_retry_base: Duration,
+    pub(crate) webhook_retry_max: Duration,
+    pub(crate) webhook_breaker_failures: Option<u32>,
+    pub(crate) webhook_breaker_cooldown: Duration,
+    pub(crate) webhook_spool: Option<String>, // Where alerts wait while the circuit is open
+    pub(crate) fft_zero_pad: usize,
+    pub(crate) fft_window: WindowFn,
+    pub(crate) fft_average_blocks: Option<usize>,
+    pub(crate) min_band_bins: Option<usize>,
+    pub(crate) fft_auto_pad: bool,
+    pub(crate) frame_hop: usize,
+    pub(crate) max_frame_latency: Option<Duration>,
+    pub(crate) max_events_per_min: u32,
+    pub(crate) answer_tone_min_freq: f32,
+    pub(crate) answer_tone_max_freq: f32,
+    pub(crate) answer_tone_min_power: f32,
+    pub(crate) tone_min_freq: f32,
+    pub(crate) tone_max_freq: f32,
+    pub(crate) tone_min_power: Option<f32>,
+    pub(crate) tone_max_power: Option<f32>,
+    pub(crate) power_unit: PowerUnit,
+    pub(crate) state_file: Option<String>,
+    pub(crate) line_gate: Option<LineGate>,
+    pub(crate) vad_threshold: Option<f32>,
+    pub(crate) vad_hangover: Duration,
+    pub(crate) webhook_attach_audio: bool,
+    pub(crate) record_pre_ms: u64,
+    pub(crate) record_post_ms: u64,
+    pub(crate) record_dir: Option<String>,
+    pub(crate) freq_drift_hz: Option<f32>,
+    pub(crate) freq_drift_blocks: usize,
+    pub(crate) vote: Option<(usize, usize)>, // (required, window) blocks
+    pub(crate) detection_min_frames: usize,
+    pub(crate) detection_release_frames: usize,
+    pub(crate) whiten_min_ratio: Option<f32>,
+    pub(crate) tone_template: Option<String>,
+    pub(crate) template_min_correlation: f32,
+    pub(crate) whiten_blocks: usize,
+    pub(crate) min_snr_db: Option<f32>,
+    pub(crate) noise_floor_blocks: usize,
+    pub(crate) power_gate: bool,
+    pub(crate) harmonic: Option<(f32, f32)>, // Frequency tolerance in Hz and minimum power relative to the fundamental
+    pub(crate) tone_exclude_bands: Vec<FrequencyBand>,
+    pub(crate) reconnect_max_attempts: u32,
+    pub(crate) reconnect_max_delay: Duration,
+    pub(crate) run_duration: Option<Duration>,
+    pub(crate) filter: InputFilter,
+    pub(crate) high_pass_cutoff: f32,
+    pub(crate) band_pass: FrequencyBand,
+    pub(crate) pre_emphasis: Option<f32>,
+    pub(crate) stats_file: Option<String>,
+    pub(crate) modem_probe: bool,
+    pub(crate) trace_serial: bool,
+    pub(crate) write_retries: u32,
+    pub(crate) detect_mode: DetectMode,
+    pub(crate) tone_sequence: Option<ToneSequence>,
+    pub(crate) tone_detector: ToneDetector,
+    pub(crate) absence_timeout: Duration,
+    pub(crate) webhook_compress: bool,
+    pub(crate) webhook_compress_min_bytes: usize,
+    pub(crate) webhook_pool_size: usize,
+    pub(crate) batch_interval: Option<Duration>,
+    pub(crate) webhook_coalesce: Option<Duration>,
+    pub(crate) webhook_min_interval: Option<Duration>,
+    pub(crate) dedup_window: Option<Duration>,
+    pub(crate) webhook_content_type: String,
+    pub(crate) payload_format: PayloadFormat,
+    pub(crate) webhook_template: Option<PayloadTemplate>,
+    pub(crate) min_gap: Option<Duration>,
+    pub(crate) detection_interval: Duration,
+    pub(crate) silence: Option<Duration>,
+    pub(crate) stall_timeout: Option<Duration>,
+    pub(crate) read_timeout: Duration,
+    pub(crate) agc: Option<AgcSettings>,
+    pub(crate) idle_backoff_min: Duration,
+    pub(crate) idle_backoff_max: Duration,
+    pub(crate) level_report: Option<Duration>,
+    pub(crate) realtime_priority: Option<i32>,
+    pub(crate) answer_after_rings: u32, // 0 listens straight away, for a line that is already up
+    pub(crate) call_progress: bool,
+    pub(crate) fsk: Option<FskStandard>,
+    pub(crate) dtmf: bool,
+    pub(crate) dtmf_gap: Duration,
+    pub(crate) metrics_backend: MetricsBackend,
+    pub(crate) mode: RunMode,
+    pub(crate) latch: bool,
+    pub(crate) statsd_addr: Option<String>,
+    pub(crate) metrics_addr: Option<String>,
+    pub(crate) spectrum_addr: Option<String>,
+    pub(crate) spectrum_bins: usize,
+    pub(crate) spectrum_peaks: usize,
+    pub(crate) answer_pin: Option<String>,
+    pub(crate) caller_id: bool,
+    pub(crate) require_caller_id: bool,
+    pub(crate) caller_allowlist: Vec<String>,
+    pub(crate) answer_pin_attempts: u32,
+    pub(crate) answer_pin_timeout: Duration,
+    pub(crate) pcm_pipe_command: Option<String>,
+    pub(crate) capture_raw: Option<String>,
+    pub(crate) on_detect_command: Option<String>,
+    pub(crate) event_fifo: Option<String>,
+    pub(crate) stdout_events: Option<StdoutFormat>,
+    pub(crate) ha_url: Option<String>,
+    pub(crate) ha_token: Option<String>,
+    pub(crate) ha_entity: String,
+    pub(crate) ha_event: Option<String>,
+    pub(crate) mqtt_broker: Option<String>,
+    pub(crate) syslog: bool,
+    pub(crate) grpc_url: Option<String>,
+    pub(crate) db_path: Option<String>,
+    pub(crate) websocket_url: Option<String>,
+    #[cfg_attr(not(feature = "websocket"), allow(dead_code))]
+    pub(crate) websocket_format: PcmFormat,
+    #[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+    pub(crate) mqtt_topic: String,
+    #[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+    pub(crate) mqtt_qos: u8,
+    #[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+    pub(crate) mqtt_retain: bool,
+    #[cfg_attr(not(all(unix, feature = "syslog")), allow(dead_code))]
+    pub(crate) syslog_addr: Option<String>,
+    #[cfg_attr(not(all(unix, feature = "syslog")), allow(dead_code))]
+    pub(crate) syslog_facility: String,
+    #[cfg_attr(not(all(unix, feature = "syslog")), allow(dead_code))]
+    pub(crate) syslog_severity: String,
+    pub(crate) decimation: usize,
+    pub(crate) channels: usize,
+    pub(crate) channel: usize,
+    pub(crate) tone_profiles: Vec<CallerProfile>,
+    pub(crate) panic_tone: Option<ToneSpec>,
+    pub(crate) panic_webhook_url: Option<String>,
+    pub(crate) heartbeat_interval: Option<Duration>,
+    pub(crate) heartbeat_url: Option<String>
+}
+
+impl Config {
+    // The same settings for another modem, e.g. to run several from one set of env vars.
+    pub fn with_modem_port(mut self, port: impl Into<String>) -> Self {
+        self.modem_port = port.into();
+        self
+    }
 }
 
 fn get_env_var(key: &'static str) -> Result<String> {
     var(key).with_context(|| format!("Missing environment variable {}", key))
 }
 
-pub(crate) fn from_env() -> Result<Config> {
+fn type_name<T>() -> &'static str {
+    std::any::type_name::<T>().rsplit("::").next().unwrap_or_default()
+}
+
+fn get_env_or<T>(key: &'static str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Into<anyhow::Error>
+{
+    match var(key) {
+        Ok(v) => v.parse::<T>().map_err(Into::into)
+            .with_context(|| format!("Failed to parse {} as {}, got {}", key, type_name::<T>(), v)),
+        Err(_) => Ok(default)
+    }
+}
+
+fn get_env_opt<T>(key: &'static str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Into<anyhow::Error>
+{
+    var(key).ok()
+        .map(|v| v.parse::<T>().map_err(Into::into)
+            .with_context(|| format!("Failed to parse {} as {}, got {}", key, type_name::<T>(), v)))
+        .transpose()
+}
+
+fn get_env_list<T>(key: &'static str) -> Result<Vec<T>>
+where
+    T: FromStr,
+    T::Err: Into<anyhow::Error>
+{
+    match var(key) {
+        Ok(v) => v.split(',')
+            .filter(|item| !item.trim().is_empty())
+            .map(|item| item.trim().parse::<T>().map_err(Into::into)
+                .with_context(|| format!("Failed to parse {} entry {}", key, item.trim())))
+            .collect(),
+        Err(_) => Ok(Vec::new())
+    }
+}
+
+// For settings whose type is defined elsewhere and so can't implement FromStr here.
+fn get_env_with<T>(key: &'static str, default: T, parse: fn(&str) -> Result<T>) -> Result<T> {
+    match var(key) {
+        Ok(v) => parse(&v).with_context(|| format!("Failed to parse {}", key)),
+        Err(_) => Ok(default)
+    }
+}
+
+fn parse_data_bits(s: &str) -> Result<DataBits> {
+    match s {
+        "5" => Ok(DataBits::Five),
+        "6" => Ok(DataBits::Six),
+        "7" => Ok(DataBits::Seven),
+        "8" => Ok(DataBits::Eight),
+        _ => Err(anyhow!("Unknown data bits {}, expected 5, 6, 7 or 8", s))
+    }
+}
+
+fn parse_parity(s: &str) -> Result<Parity> {
+    match s.to_ascii_lowercase().as_str() {
+        "none" => Ok(Parity::None),
+        "odd" => Ok(Parity::Odd),
+        "even" => Ok(Parity::Even),
+        _ => Err(anyhow!("Unknown parity {}, expected none, odd or even", s))
+    }
+}
+
+fn parse_stop_bits(s: &str) -> Result<StopBits> {
+    match s {
+        "1" => Ok(StopBits::One),
+        "2" => Ok(StopBits::Two),
+        _ => Err(anyhow!("Unknown stop bits {}, expected 1 or 2", s))
+    }
+}
+
+fn parse_flow_control(s: &str) -> Result<FlowControl> {
+    match s.to_ascii_lowercase().as_str() {
+        "none" => Ok(FlowControl::None),
+        "software" | "xonxoff" => Ok(FlowControl::Software),
+        "hardware" | "rtscts" => Ok(FlowControl::Hardware),
+        _ => Err(anyhow!("Unknown flow control {}, expected none, software or hardware", s))
+    }
+}
+
+fn get_env_flag(key: &'static str, default: bool) -> Result<bool> {
+    match var(key).map(|v| v.to_ascii_lowercase()) {
+        Ok(v) if matches!(v.as_str(), "1" | "true" | "yes" | "on") => Ok(true),
+        Ok(v) if matches!(v.as_str(), "0" | "false" | "no" | "off" | "") => Ok(false),
+        Ok(v) => Err(anyhow!("Failed to parse {} as a flag, got {}", key, v)),
+        Err(_) => Ok(default)
+    }
+}
+
+pub fn from_env() -> Result<Config> {
+    let fft_zero_pad = get_env_or("ALARM_FFT_ZERO_PAD", 1usize)?;
+    if fft_zero_pad == 0 {
+        return Err(anyhow!("ALARM_FFT_ZERO_PAD must be at least 1"));
+    }
+    let fft_average_blocks = get_env_opt("ALARM_FFT_AVERAGE_BLOCKS")?;
+    if fft_average_blocks == Some(0) {
+        return Err(anyhow!("ALARM_FFT_AVERAGE_BLOCKS must be at least 1"));
+    }
+    let frame_hop = get_env_or("ALARM_FRAME_HOP", FFT_SAMPLE_SIZE)?;
+    if !(1..=FFT_SAMPLE_SIZE).contains(&frame_hop) {
+        return Err(anyhow!("ALARM_FRAME_HOP must be between 1 and {}", FFT_SAMPLE_SIZE));
+    }
+    let freq_drift_blocks = get_env_or("ALARM_FREQ_DRIFT_BLOCKS", 3usize)?;
+    if freq_drift_blocks == 0 {
+        return Err(anyhow!("ALARM_FREQ_DRIFT_BLOCKS must be at least 1"));
+    }
+    let whiten_blocks = get_env_or("ALARM_WHITEN_BLOCKS", 32usize)?;
+    if whiten_blocks == 0 {
+        return Err(anyhow!("ALARM_WHITEN_BLOCKS must be at least 1"));
+    }
+    let vote = match get_env_opt::<usize>("ALARM_VOTE_WINDOW")? {
+        Some(window) => {
+            let required = get_env_or("ALARM_VOTE_REQUIRED", window / 2 + 1)?;
+            if required == 0 || required > window {
+                return Err(anyhow!("ALARM_VOTE_REQUIRED must be between 1 and ALARM_VOTE_WINDOW ({})", window));
+            }
+            Some((required, window))
+        },
+        None => None
+    };
+    let detection_min_frames = get_env_or("ALARM_DETECTION_MIN_FRAMES", 1usize)?;
+    let detection_release_frames = get_env_or("ALARM_DETECTION_RELEASE_FRAMES", detection_min_frames)?;
+    if detection_min_frames == 0 || detection_release_frames == 0 {
+        return Err(anyhow!("ALARM_DETECTION_MIN_FRAMES and ALARM_DETECTION_RELEASE_FRAMES must be at least 1"));
+    }
+    let answer_pin = var("ALARM_ANSWER_PIN").ok().map(|pin| pin.to_ascii_uppercase());
+    if let Some(pin) = &answer_pin {
+        if pin.is_empty() || dtmf_command(pin).is_err() {
+            return Err(anyhow!("ALARM_ANSWER_PIN must be a sequence of DTMF digits"));
+        }
+    }
+    // Callers are screened between rings, so there must be rings to wait for.
+    let caller_allowlist: Vec<String> = get_env_list::<String>("ALARM_CALLER_ALLOWLIST")?.iter().map(|n| normalize_number(n)).collect();
+    let require_caller_id = get_env_flag("ALARM_REQUIRE_CALLER_ID", false)? || !caller_allowlist.is_empty();
+    // ALARM_AUTO_ANSWER predates the ring count, and answers on the first ring.
+    let auto_answer = get_env_flag("ALARM_AUTO_ANSWER", false)?;
+    let answer_after_rings = get_env_or("ALARM_ANSWER_AFTER_RINGS", if auto_answer { 1 } else { 0 })?;
+    if require_caller_id && answer_after_rings == 0 {
+        return Err(anyhow!("ALARM_REQUIRE_CALLER_ID and ALARM_CALLER_ALLOWLIST need ALARM_ANSWER_AFTER_RINGS above 0"));
+    }
+    let caller_id = get_env_flag("ALARM_CALLER_ID", false)? || require_caller_id;
+    let power_unit = get_env_or("ALARM_POWER_UNIT", PowerUnit::Power)?;
+    let decimation = get_env_or("ALARM_DECIMATION", 1usize)?;
+    if decimation == 0 {
+        return Err(anyhow!("ALARM_DECIMATION must be at least 1"));
+    }
+    let channels = get_env_or("ALARM_CHANNELS", 1usize)?;
+    let channel = get_env_or("ALARM_CHANNEL", 0usize)?;
+    if channels == 0 || channel >= channels {
+        return Err(anyhow!("ALARM_CHANNEL must be below ALARM_CHANNELS ({}), counting from 0", channels));
+    }
+    let harmonic = match get_env_opt::<f32>("ALARM_HARMONIC_TOLERANCE_HZ")? {
+        Some(tolerance) => Some((tolerance, get_env_or("ALARM_HARMONIC_MIN_RATIO", 0.01)?)),
+        None => None
+    };
+    let read_timeout = Duration::from_millis(get_env_or("ALARM_READ_TIMEOUT_MS", 250)?);
+    if read_timeout.is_zero() {
+        return Err(anyhow!("ALARM_READ_TIMEOUT_MS must be at least 1"));
+    }
+    let idle_backoff_min = Duration::from_millis(get_env_or("ALARM_IDLE_BACKOFF_MIN_MS", 10)?);
+    let idle_backoff_max = Duration::from_millis(get_env_or("ALARM_IDLE_BACKOFF_MAX_MS", 250)?);
+    if idle_backoff_min.is_zero() || idle_backoff_min > idle_backoff_max {
+        return Err(anyhow!("ALARM_IDLE_BACKOFF_MIN_MS must be at least 1 and no more than ALARM_IDLE_BACKOFF_MAX_MS"));
+    }
+    let level_report = get_env_opt("ALARM_LEVEL_REPORT_SECS")?.map(Duration::from_secs);
+    if level_report.is_some_and(|interval| interval.is_zero()) {
+        return Err(anyhow!("ALARM_LEVEL_REPORT_SECS must be at least 1"));
+    }
+    let webhook_max_retries = get_env_or("ALARM_WEBHOOK_MAX_RETRIES", 1440)?;
+    if webhook_max_retries == 0 {
+        return Err(anyhow!("ALARM_WEBHOOK_MAX_RETRIES must be at least 1"));
+    }
+    let webhook_retry_base = Duration::from_millis(get_env_or("ALARM_WEBHOOK_RETRY_BASE_MS", 1000)?);
+    let webhook_retry_max = Duration::from_millis(get_env_or("ALARM_WEBHOOK_RETRY_MAX_MS", 20000)?);
+    if webhook_retry_base > webhook_retry_max {
+        return Err(anyhow!("ALARM_WEBHOOK_RETRY_BASE_MS must not exceed ALARM_WEBHOOK_RETRY_MAX_MS"));
+    }
+    let webhook_breaker_failures = get_env_opt("ALARM_WEBHOOK_BREAKER_FAILURES")?;
+    if webhook_breaker_failures == Some(0) {
+        return Err(anyhow!("ALARM_WEBHOOK_BREAKER_FAILURES must be at least 1"));
+    }
+    let webhook_spool = var("ALARM_WEBHOOK_SPOOL").ok();
+    if webhook_spool.is_some() && webhook_breaker_failures.is_none() {
+        return Err(anyhow!("ALARM_WEBHOOK_SPOOL needs ALARM_WEBHOOK_BREAKER_FAILURES"));
+    }
+    let modem_lock_dir = get_env_flag("ALARM_MODEM_LOCK", false)?
+        .then(|| var("ALARM_MODEM_LOCK_DIR").unwrap_or_else(|_| "/var/lock".to_string()));
+    let tone_min_freq = get_env_or("ALARM_TONE_MIN_FREQ", 1640.0)?;
+    let tone_max_freq = get_env_or("ALARM_TONE_MAX_FREQ", 1720.0)?;
+    if tone_min_freq >= tone_max_freq {
+        return Err(anyhow!("ALARM_TONE_MIN_FREQ must be below ALARM_TONE_MAX_FREQ"));
+    }
+    let high_pass_cutoff = get_env_or("ALARM_HIGH_PASS_CUTOFF", 3000.0)?;
+    if high_pass_cutoff <= 0.0 {
+        return Err(anyhow!("ALARM_HIGH_PASS_CUTOFF must be above 0"));
+    }
+    // ALARM_HIGH_PASS predates the band-pass, so setting it still chooses between the high-pass and no filter.
+    let filter = match get_env_opt("ALARM_FILTER")? {
+        Some(filter) => filter,
+        None if var("ALARM_HIGH_PASS").is_ok() => {
+            if get_env_flag("ALARM_HIGH_PASS", true)? { InputFilter::HighPass } else { InputFilter::None }
+        },
+        None => InputFilter::BandPass
+    };
+    let answer_tone_min_freq = get_env_or("ALARM_ANSWER_TONE_MIN_FREQ", 2080.0)?;
+    let answer_tone_max_freq = get_env_or("ALARM_ANSWER_TONE_MAX_FREQ", 2120.0)?;
+    let tone_profiles: Vec<CallerProfile> = get_env_list("ALARM_TONE_PROFILES")?;
+    let panic_tone: Option<ToneSpec> = get_env_opt("ALARM_PANIC_TONE")?;
+    let call_progress = get_env_flag("ALARM_CALL_PROGRESS", false)?;
+    let tone_sequence = match var("ALARM_TONE_SEQUENCE") {
+        Ok(spec) => Some(ToneSequence {
+            steps: parse_sequence(&spec, FrequencyBand { min: tone_min_freq, max: tone_max_freq })
+                .context("Failed to parse ALARM_TONE_SEQUENCE")?,
+            window: Duration::from_millis(get_env_or("ALARM_TONE_SEQUENCE_WINDOW_MS", 10000)?)
+        }),
+        Err(_) => None
+    };
+    if tone_sequence.as_ref().is_some_and(|sequence| sequence.steps.is_empty()) {
+        return Err(anyhow!("ALARM_TONE_SEQUENCE must have at least one step"));
+    }
+    // Unless given, the band-pass spans every band detection looks at, so it only ever takes away what none of them hear.
+    let bands = [
+        FrequencyBand { min: tone_min_freq, max: tone_max_freq },
+        FrequencyBand { min: answer_tone_min_freq, max: answer_tone_max_freq }
+    ].into_iter()
+        .chain(tone_profiles.iter().map(|profile| profile.band))
+        .chain(panic_tone.map(|spec| spec.band))
+        .chain(call_progress.then_some(FrequencyBand { min: CALL_PROGRESS_FREQ, max: CALL_PROGRESS_FREQ }))
+        .chain(tone_sequence.iter().flat_map(|sequence| sequence.steps.iter().map(|step| step.band)));
+    let (lowest, highest) = bands.fold((f32::MAX, f32::MIN), |(min, max), band| (min.min(band.min), max.max(band.max)));
+    let band_pass = FrequencyBand {
+        min: get_env_or("ALARM_BAND_PASS_MIN_FREQ", (lowest - BAND_PASS_MARGIN).max(1.0))?,
+        max: get_env_or("ALARM_BAND_PASS_MAX_FREQ", (highest + BAND_PASS_MARGIN).min(SAMPLE_RATE / 2.0 - 1.0))?
+    };
+    if band_pass.min <= 0.0 || band_pass.min >= band_pass.max || band_pass.max >= SAMPLE_RATE / 2.0 {
+        return Err(anyhow!("ALARM_BAND_PASS_MIN_FREQ and ALARM_BAND_PASS_MAX_FREQ must be a band between 0 and 4000 Hz"));
+    }
+    let agc = if get_env_flag("ALARM_AGC", false)? {
+        let agc = AgcSettings {
+            target_dbfs: get_env_or("ALARM_AGC_TARGET_DBFS", -20.0)?,
+            attack: Duration::from_millis(get_env_or("ALARM_AGC_ATTACK_MS", 10)?),
+            release: Duration::from_millis(get_env_or("ALARM_AGC_RELEASE_MS", 500)?),
+            max_gain_db: get_env_or("ALARM_AGC_MAX_GAIN_DB", 20.0)?
+        };
+        if agc.target_dbfs >= 0.0 {
+            return Err(anyhow!("ALARM_AGC_TARGET_DBFS must be below 0"));
+        }
+        if agc.attack.is_zero() || agc.release.is_zero() {
+            return Err(anyhow!("ALARM_AGC_ATTACK_MS and ALARM_AGC_RELEASE_MS must be at least 1"));
+        }
+        if agc.max_gain_db < 0.0 {
+            return Err(anyhow!("ALARM_AGC_MAX_GAIN_DB must not be negative"));
+        }
+        Some(agc)
+    } else {
+        None
+    };
+    let modem_baud = get_env_or("ALARM_MODEM_BAUD", 9600)?;
+    if modem_baud == 0 {
+        return Err(anyhow!("ALARM_MODEM_BAUD must be more than 0"));
+    }
+    if !STANDARD_BAUD_RATES.contains(&modem_baud) {
+        warn!("ALARM_MODEM_BAUD {} is not a standard rate, modems usually only take one of {:?}", modem_baud, STANDARD_BAUD_RATES);
+    }
+    let modem_flow_control = get_env_with("ALARM_MODEM_FLOW_CONTROL", FlowControl::None, parse_flow_control)?;
+    let modem_profile = find_profile(&var("ALARM_MODEM_PROFILE").unwrap_or_else(|_| "generic".to_string()))?;
+    let vgr_db_per_step = get_env_or("ALARM_VGR_DB_PER_STEP", modem_profile.vgr_db_per_step)?;
+    // Semicolon separated, the commands themselves being full of commas.
+    let modem_init: Vec<String> = var("ALARM_MODEM_INIT").unwrap_or_default().split(';')
+        .map(|command| command.trim().to_string())
+        .filter(|command| !command.is_empty())
+        .collect();
+    if let Some(command) = modem_init.iter().find(|command| !command.to_ascii_uppercase().starts_with("AT")) {
+        return Err(anyhow!("ALARM_MODEM_INIT must be AT commands separated by semicolons, got {}", command));
+    }
+    let vgr_reference = get_env_opt("ALARM_VGR_REFERENCE")?.or(modem_profile.vgr());
+    let min_snr_db = get_env_opt("ALARM_MIN_SNR_DB")?;
+    let noise_floor_blocks = get_env_or("ALARM_NOISE_FLOOR_BLOCKS", 32usize)?;
+    if noise_floor_blocks == 0 {
+        return Err(anyhow!("ALARM_NOISE_FLOOR_BLOCKS must be at least 1"));
+    }
+    let power_gate = get_env_flag("ALARM_POWER_GATE", true)?;
+    if !power_gate && min_snr_db.is_none() {
+        return Err(anyhow!("ALARM_POWER_GATE can only be turned off with ALARM_MIN_SNR_DB set"));
+    }
+    let detect_mode = get_env_or("ALARM_DETECT_MODE", DetectMode::Presence)?;
+    if tone_sequence.is_some() && detect_mode == DetectMode::Absence {
+        return Err(anyhow!("ALARM_TONE_SEQUENCE only applies to ALARM_DETECT_MODE=presence"));
+    }
+    let spectrum_bins = get_env_or("ALARM_SPECTRUM_BINS", 32usize)?;
+    if spectrum_bins == 0 {
+        return Err(anyhow!("ALARM_SPECTRUM_BINS must be at least 1"));
+    }
+    let heartbeat_interval = get_env_opt("ALARM_HEARTBEAT_SECS")?.filter(|&secs| secs > 0).map(Duration::from_secs);
+    let heartbeat_url = var("ALARM_HEARTBEAT_URL").ok();
+    if heartbeat_url.is_some() && heartbeat_interval.is_none() {
+        return Err(anyhow!("ALARM_HEARTBEAT_URL needs ALARM_HEARTBEAT_SECS"));
+    }
+    let webhook_urls: Vec<WebhookTarget> = get_env_list("ALARM_WEBHOOK_URLS")?;
+    let webhook_template: Option<PayloadTemplate> = get_env_opt("ALARM_WEBHOOK_TEMPLATE")?;
+    let payload_format = get_env_or("ALARM_PAYLOAD_FORMAT", PayloadFormat::Json)?;
+    let templated = webhook_template.is_some() || webhook_urls.iter().any(|target| target.template.is_some());
+    if templated && payload_format == PayloadFormat::Cef {
+        return Err(anyhow!("Webhook templates can't be used with ALARM_PAYLOAD_FORMAT=cef"));
+    }
+    let batch_interval = get_env_opt("ALARM_BATCH_INTERVAL_SECS")?.map(Duration::from_secs);
+    let webhook_coalesce = get_env_opt("ALARM_WEBHOOK_COALESCE_MS")?.filter(|&ms| ms > 0).map(Duration::from_millis);
+    let webhook_min_interval = get_env_opt("ALARM_WEBHOOK_MIN_INTERVAL_MS")?.filter(|&ms| ms > 0).map(Duration::from_millis);
+    if batch_interval.is_some() && (webhook_coalesce.is_some() || webhook_min_interval.is_some()) {
+        let key = if webhook_coalesce.is_some() { "ALARM_WEBHOOK_COALESCE_MS" } else { "ALARM_WEBHOOK_MIN_INTERVAL_MS" };
+        return Err(anyhow!("{} can't be combined with ALARM_BATCH_INTERVAL_SECS", key));
+    }
+
     Ok(Config {
-        modem_port: get_env_var("ALARM_MODEM_PORT")?,
-        modem_baud: get_env_var("ALARM_MODEM_BAUD")
-            .map(|v| v.parse::<u32>().context("Failed to parse ALARM_MODEM_BAUD as u32"))
-            .unwrap_or_else(|_| Ok(9600))?,
+        modem_port: match var("ALARM_MODEM_PORT") {
+            Ok(port) => port,
+            Err(_) => detect_modem_port()?
+        },
+        modem_baud,
+        modem_auto_baud: get_env_flag("ALARM_MODEM_AUTO_BAUD", false)?,
+        modem_lock_dir,
+        modem_profile,
+        modem_init,
+        modem_vls: get_env_opt("ALARM_MODEM_VLS")?,
+        modem_vgr: get_env_opt("ALARM_MODEM_VGR")?,
+        vgr_reference,
+        vgr_db_per_step,
+        modem_data_bits: get_env_with("ALARM_MODEM_DATA_BITS", DataBits::Eight, parse_data_bits)?,
+        modem_parity: get_env_with("ALARM_MODEM_PARITY", Parity::None, parse_parity)?,
+        modem_stop_bits: get_env_with("ALARM_MODEM_STOP_BITS", StopBits::One, parse_stop_bits)?,
+        modem_flow_control,
+        strip_xon_xoff: get_env_flag("ALARM_STRIP_XON_XOFF", modem_flow_control == FlowControl::Software)?,
         webhook_url: get_env_var("ALARM_WEBHOOK_URL")?,
-        webhook_key: get_env_var("ALARM_WEBHOOK_KEY")?
+        webhook_key: get_env_var("ALARM_WEBHOOK_KEY")?,
+        webhook_urls,
+        webhook_dlq_url: var("ALARM_WEBHOOK_DLQ_URL").ok(),
+        webhook_max_retries,
+        webhook_retry_base,
+        webhook_retry_max,
+        webhook_breaker_failures,
+        webhook_breaker_cooldown: Duration::from_secs(get_env_or("ALARM_WEBHOOK_BREAKER_SECS", 60)?),
+        webhook_spool,
+        fft_zero_pad,
+        fft_window: get_env_or("ALARM_FFT_WINDOW", WindowFn::Hann)?,
+        fft_average_blocks,
+        min_band_bins: get_env_opt("ALARM_MIN_BAND_BINS")?,
+        fft_auto_pad: get_env_flag("ALARM_FFT_AUTO_PAD", false)?,
+        frame_hop,
+        max_frame_latency: get_env_opt("ALARM_MAX_FRAME_LATENCY_MS")?.map(Duration::from_millis),
+        max_events_per_min: get_env_or("ALARM_MAX_EVENTS_PER_MIN", 0)?,
+        answer_tone_min_freq,
+        answer_tone_max_freq,
+        answer_tone_min_power: get_env_opt("ALARM_ANSWER_TONE_MIN_POWER")?.unwrap_or(power_unit.level(100.0)),
+        tone_min_freq,
+        tone_max_freq,
+        tone_min_power: get_env_opt("ALARM_TONE_MIN_POWER")?,
+        tone_max_power: get_env_opt("ALARM_TONE_MAX_POWER")?,
+        power_unit,
+        state_file: var("ALARM_STATE_FILE").ok(),
+        line_gate: var("ALARM_LINE_SIGNAL").ok()
+            .map(|v| -> Result<LineGate> {
+                Ok(LineGate {
+                    line: v.parse()?,
+                    active_low: get_env_flag("ALARM_LINE_SIGNAL_ACTIVE_LOW", false)?
+                })
+            })
+            .transpose()?,
+        vad_threshold: get_env_opt("ALARM_VAD_THRESHOLD")?,
+        vad_hangover: Duration::from_millis(get_env_or("ALARM_VAD_HANGOVER_MS", 500)?),
+        webhook_attach_audio: get_env_flag("ALARM_WEBHOOK_ATTACH_AUDIO", false)?,
+        // ALARM_WEBHOOK_ATTACH_AUDIO_MS predates the separate windows and still sets the pre-trigger length.
+        record_pre_ms: match get_env_opt("ALARM_RECORD_PRE_MS")? {
+            Some(ms) => ms,
+            None => get_env_or("ALARM_WEBHOOK_ATTACH_AUDIO_MS", 2000)?
+        },
+        record_post_ms: get_env_or("ALARM_RECORD_POST_MS", 0)?,
+        record_dir: var("ALARM_RECORD_DIR").ok(),
+        freq_drift_hz: get_env_opt("ALARM_FREQ_DRIFT_HZ")?,
+        freq_drift_blocks,
+        vote,
+        detection_min_frames,
+        detection_release_frames,
+        whiten_min_ratio: get_env_opt("ALARM_WHITEN_MIN_RATIO")?,
+        tone_template: var("ALARM_TONE_TEMPLATE").ok(),
+        template_min_correlation: get_env_or("ALARM_TEMPLATE_MIN_CORRELATION", 0.8)?,
+        whiten_blocks,
+        min_snr_db,
+        noise_floor_blocks,
+        power_gate,
+        harmonic,
+        tone_exclude_bands: get_env_list("ALARM_TONE_EXCLUDE_BANDS")?,
+        reconnect_max_attempts: get_env_or("ALARM_RECONNECT_MAX_ATTEMPTS", 0)?,
+        reconnect_max_delay: Duration::from_secs(get_env_or("ALARM_RECONNECT_MAX_DELAY_SECS", 60)?),
+        run_duration: get_env_opt("ALARM_RUN_SECONDS")?.filter(|&secs| secs > 0).map(Duration::from_secs),
+        filter,
+        high_pass_cutoff,
+        band_pass,
+        pre_emphasis: get_env_opt("ALARM_PRE_EMPHASIS")?,
+        stats_file: var("ALARM_STATS_FILE").ok(),
+        modem_probe: get_env_flag("ALARM_MODEM_PROBE", false)?,
+        trace_serial: get_env_flag("ALARM_TRACE_SERIAL", false)?,
+        write_retries: get_env_or("ALARM_WRITE_RETRIES", 2)?,
+        detect_mode,
+        tone_sequence,
+        tone_detector: get_env_or("ALARM_TONE_DETECTOR", ToneDetector::Fft)?,
+        absence_timeout: Duration::from_secs(get_env_or("ALARM_ABSENCE_TIMEOUT_SECS", 30)?),
+        webhook_compress: get_env_flag("ALARM_WEBHOOK_COMPRESS", false)?,
+        webhook_compress_min_bytes: get_env_or("ALARM_WEBHOOK_COMPRESS_MIN_BYTES", 1024)?,
+        batch_interval,
+        webhook_coalesce,
+        webhook_min_interval,
+        dedup_window: get_env_opt("ALARM_DEDUP_WINDOW_SECS")?.filter(|&secs| secs > 0).map(Duration::from_secs),
+        webhook_pool_size: get_env_or("ALARM_WEBHOOK_POOL_SIZE", 1)?,
+        webhook_content_type: var("ALARM_WEBHOOK_CONTENT_TYPE").unwrap_or_else(|_| "application/json".to_string()),
+        payload_format,
+        webhook_template,
+        min_gap: get_env_opt("ALARM_MIN_GAP_MS")?.map(Duration::from_millis),
+        detection_interval: Duration::from_secs(get_env_or("ALARM_DETECTION_INTERVAL_SECS", 5)?),
+        silence: get_env_opt("ALARM_SILENCE_MS")?.map(Duration::from_millis),
+        stall_timeout: get_env_opt("ALARM_STALL_TIMEOUT_SECS")?.map(Duration::from_secs),
+        read_timeout,
+        agc,
+        idle_backoff_min,
+        idle_backoff_max,
+        level_report,
+        realtime_priority: get_env_opt("ALARM_REALTIME_PRIORITY")?,
+        answer_after_rings,
+        call_progress,
+        fsk: get_env_opt("ALARM_FSK")?,
+        dtmf: get_env_flag("ALARM_DTMF", false)?,
+        dtmf_gap: Duration::from_millis(get_env_or("ALARM_DTMF_GAP_MS", 1000)?),
+        metrics_backend: get_env_or("ALARM_METRICS_BACKEND", MetricsBackend::None)?,
+        mode: get_env_or("ALARM_MODE", RunMode::Active)?,
+        latch: get_env_flag("ALARM_LATCH", false)?,
+        statsd_addr: var("ALARM_STATSD_ADDR").ok(),
+        metrics_addr: var("ALARM_METRICS_ADDR").ok(),
+        spectrum_addr: var("ALARM_SPECTRUM_ADDR").ok(),
+        spectrum_bins,
+        spectrum_peaks: get_env_or("ALARM_SPECTRUM_PEAKS", 0usize)?,
+        answer_pin,
+        caller_id,
+        require_caller_id,
+        caller_allowlist,
+        answer_pin_attempts: get_env_or("ALARM_ANSWER_PIN_ATTEMPTS", 3)?,
+        answer_pin_timeout: Duration::from_secs(get_env_or("ALARM_ANSWER_PIN_TIMEOUT_SECS", 15)?),
+        pcm_pipe_command: var("ALARM_PCM_PIPE_CMD").ok(),
+        capture_raw: var("ALARM_CAPTURE_RAW").ok(),
+        on_detect_command: var("ALARM_ON_DETECT_CMD").ok(),
+        event_fifo: var("ALARM_EVENT_FIFO").ok(),
+        stdout_events: get_env_opt("ALARM_STDOUT_EVENTS")?,
+        ha_url: var("ALARM_HA_URL").ok(),
+        ha_token: var("ALARM_HA_TOKEN").ok(),
+        ha_entity: get_env_or("ALARM_HA_ENTITY", "binary_sensor.alarm_modem".to_string())?,
+        ha_event: var("ALARM_HA_EVENT").ok(),
+        mqtt_broker: var("ALARM_MQTT_BROKER").ok(),
+        syslog: get_env_flag("ALARM_SYSLOG", false)?,
+        grpc_url: var("ALARM_GRPC_URL").ok(),
+        db_path: var("ALARM_DB_PATH").ok(),
+        websocket_url: var("ALARM_WS_URL").ok(),
+        websocket_format: get_env_or("ALARM_WS_FORMAT", PcmFormat::SignedLe16)?,
+        mqtt_topic: get_env_or("ALARM_MQTT_TOPIC", "alarm-modem/events".to_string())?,
+        mqtt_qos: get_env_or("ALARM_MQTT_QOS", 1)?,
+        mqtt_retain: get_env_flag("ALARM_MQTT_RETAIN", false)?,
+        syslog_addr: var("ALARM_SYSLOG_ADDR").ok(),
+        syslog_facility: get_env_or("ALARM_SYSLOG_FACILITY", "daemon".to_string())?,
+        syslog_severity: get_env_or("ALARM_SYSLOG_SEVERITY", "alert".to_string())?,
+        decimation,
+        channels,
+        channel,
+        tone_profiles,
+        panic_tone,
+        panic_webhook_url: var("ALARM_PANIC_WEBHOOK_URL").ok(),
+        heartbeat_interval,
+        heartbeat_url
     })
-}
\ No newline at end of file
+}
+
+// The process env is shared by every test, so those setting it take turns.
+#[cfg(test)]
+static ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[cfg(test)]
+pub(crate) fn lock_env() -> std::sync::MutexGuard<'static, ()> {
+    ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// A config of the defaults and the given settings alone, for tests. The port and webhook are set so it loads without a
+// modem attached, the webhook on a port nothing listens on.
+#[cfg(test)]
+pub(crate) fn test_config(settings: &[(&str, &str)]) -> Result<Config> {
+    let _env = lock_env();
+    let clear = || std::env::vars().filter(|(key, _)| key.starts_with("ALARM_")).for_each(|(key, _)| std::env::remove_var(key));
+    clear();
+    let required = [("ALARM_MODEM_PORT", "/dev/null"), ("ALARM_WEBHOOK_URL", "http://127.0.0.1:9/"), ("ALARM_WEBHOOK_KEY", "test")];
+    for (key, value) in required.iter().chain(settings) {
+        std::env::set_var(key, value);
+    }
+    let config = from_env();
+    clear();
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_serial_framing_to_port_settings() {
+        let config = test_config(&[]).unwrap();
+        assert_eq!(
+            (config.modem_data_bits, config.modem_parity, config.modem_stop_bits, config.modem_flow_control),
+            (DataBits::Eight, Parity::None, StopBits::One, FlowControl::None)
+        );
+
+        let config = test_config(&[
+            ("ALARM_MODEM_DATA_BITS", "7"),
+            ("ALARM_MODEM_PARITY", "Even"),
+            ("ALARM_MODEM_STOP_BITS", "2"),
+            ("ALARM_MODEM_FLOW_CONTROL", "rtscts")
+        ]).unwrap();
+        assert_eq!(
+            (config.modem_data_bits, config.modem_parity, config.modem_stop_bits, config.modem_flow_control),
+            (DataBits::Seven, Parity::Even, StopBits::Two, FlowControl::Hardware)
+        );
+        assert!(!config.strip_xon_xoff);
+        assert!(test_config(&[("ALARM_MODEM_FLOW_CONTROL", "xonxoff")]).unwrap().strip_xon_xoff);
+
+        for (key, value) in [("ALARM_MODEM_DATA_BITS", "9"), ("ALARM_MODEM_PARITY", "mark"), ("ALARM_MODEM_STOP_BITS", "1.5")] {
+            assert!(test_config(&[(key, value)]).is_err(), "{}={} was accepted", key, value);
+        }
+    }
+
+    #[test]
+    fn detection_settings_default_to_the_original_constants() {
+        let config = test_config(&[]).unwrap();
+        assert_eq!((config.tone_min_freq, config.tone_max_freq, config.high_pass_cutoff), (1640.0, 1720.0, 3000.0));
+        assert_eq!(config.detection_interval, Duration::from_secs(5));
+
+        let config = test_config(&[
+            ("ALARM_TONE_MIN_FREQ", "1400"),
+            ("ALARM_TONE_MAX_FREQ", "1450"),
+            ("ALARM_HIGH_PASS_CUTOFF", "2500"),
+            ("ALARM_DETECTION_INTERVAL_SECS", "30")
+        ]).unwrap();
+        assert_eq!((config.tone_min_freq, config.tone_max_freq, config.high_pass_cutoff), (1400.0, 1450.0, 2500.0));
+        assert_eq!(config.detection_interval, Duration::from_secs(30));
+
+        let inverted = test_config(&[("ALARM_TONE_MIN_FREQ", "1800")]).err().unwrap();
+        assert_eq!(inverted.to_string(), "ALARM_TONE_MIN_FREQ must be below ALARM_TONE_MAX_FREQ");
+        assert!(test_config(&[("ALARM_HIGH_PASS_CUTOFF", "0")]).is_err());
+    }
+
+    #[test]
+    fn modem_init_is_split_on_semicolons() {
+        assert!(test_config(&[]).unwrap().modem_init.is_empty());
+        let config = test_config(&[("ALARM_MODEM_INIT", "AT+FCLASS=8; AT+VLS=1 ;;at+vsm=128,8000;")]).unwrap();
+        assert_eq!(config.modem_init, ["AT+FCLASS=8", "AT+VLS=1", "at+vsm=128,8000"]);
+
+        let error = test_config(&[("ALARM_MODEM_INIT", "AT+FCLASS=8;VLS=1")]).err().unwrap();
+        assert_eq!(error.to_string(), "ALARM_MODEM_INIT must be AT commands separated by semicolons, got VLS=1");
+    }
+
+    // Unset or 0 runs until stopped.
+    #[test]
+    fn run_seconds_limits_how_long_to_listen() {
+        assert_eq!(test_config(&[]).unwrap().run_duration, None);
+        assert_eq!(test_config(&[("ALARM_RUN_SECONDS", "0")]).unwrap().run_duration, None);
+        assert_eq!(test_config(&[("ALARM_RUN_SECONDS", "90")]).unwrap().run_duration, Some(Duration::from_secs(90)));
+        assert!(test_config(&[("ALARM_RUN_SECONDS", "-1")]).is_err());
+    }
+}
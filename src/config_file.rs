@@ -0,0 +1,149 @@
+use std::env::{set_var, var, var_os};
+use std::fs;
+use std::sync::Mutex;
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use toml::{Table, Value};
+use crate::config::{from_env, Config};
+
+// Env vars set from the config file, with the key that set each, so an error in one can point back at the file.
+static FILE_SETTINGS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+// A setting's value as an env var would hold it, lists comma separated like the env list settings.
+fn env_value(key: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(n) => Ok(n.to_string()),
+        Value::Float(n) => Ok(n.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        Value::Datetime(datetime) => Ok(datetime.to_string()),
+        Value::Array(items) => items.iter()
+            .map(|item| match item {
+                Value::Array(_) | Value::Table(_) => Err(anyhow!("{} must be a list of strings, numbers or booleans", key)),
+                item => env_value(key, item)
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(|items| items.join(",")),
+        Value::Table(_) => Err(anyhow!("{} nests tables too deep, only one level of [section] is supported", key))
+    }
+}
+
+// Each key is an env var without its `ALARM_` prefix, e.g. `modem_port`, or under a table, so `url` in `[webhook]`
+// is `ALARM_WEBHOOK_URL`.
+fn collect(table: &Table, prefix: Option<&str>, settings: &mut Vec<(String, String, String)>) -> Result<()> {
+    for (key, value) in table {
+        let key = match prefix {
+            Some(prefix) => format!("{}.{}", prefix, key),
+            None => key.clone()
+        };
+        match value {
+            Value::Table(table) if prefix.is_none() => collect(table, Some(&key), settings)?,
+            value => {
+                let name = format!("ALARM_{}", key.replace(['.', '-'], "_").to_ascii_uppercase());
+                settings.push((name, env_value(&key, value)?, key));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Load the TOML file named by ALARM_CONFIG_FILE (or `--config`), if any, as the layer beneath the environment, .env and
+// the profile: only variables none of them set are taken from it.
+pub(crate) fn load_config_file() -> Result<()> {
+    set_file_settings(Vec::new());
+    let Ok(path) = var("ALARM_CONFIG_FILE") else {
+        return Ok(());
+    };
+    let text = fs::read_to_string(&path).with_context(|| format!("Failed to read config file {}", path))?;
+    let table: Table = toml::from_str(&text).with_context(|| format!("Failed to parse config file {}", path))?;
+
+    let mut settings = Vec::new();
+    collect(&table, None, &mut settings).with_context(|| format!("Invalid setting in config file {}", path))?;
+    let mut applied = Vec::new();
+    for (name, value, key) in settings {
+        if var_os(&name).is_none() {
+            set_var(&name, value);
+            applied.push((name, format!("{} in {}", key, path)));
+        }
+    }
+    info!("Loaded {} settings from config file {}", applied.len(), path);
+    set_file_settings(applied);
+    Ok(())
+}
+
+fn set_file_settings(applied: Vec<(String, String)>) {
+    if let Ok(mut settings) = FILE_SETTINGS.lock() {
+        *settings = applied;
+    }
+}
+
+// Whether the error message names the env var on its own, rather than as the start of a longer name.
+fn mentions(message: &str, name: &str) -> bool {
+    message.match_indices(name)
+        .any(|(at, _)| !message[at + name.len()..].starts_with(|c: char| c == '_' || c.is_ascii_alphanumeric()))
+}
+
+// The config from the environment, an error in a setting that came from the config file naming the key that set it.
+pub(crate) fn load_config() -> Result<Config> {
+    from_env().map_err(|e| {
+        let message = format!("{:#}", e);
+        let settings = FILE_SETTINGS.lock().map(|settings| settings.clone()).unwrap_or_default();
+        match settings.iter().find(|(name, _)| mentions(&message, name)) {
+            Some((_, source)) => e.context(format!("Invalid setting {}", source)),
+            None => e
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::remove_var;
+    use crate::config::lock_env;
+
+    #[test]
+    fn keys_and_sections_name_their_env_vars() {
+        let table: Table = toml::from_str(r#"
+            modem_port = "/dev/ttyACM0"
+            tone-min-freq = 1640
+            [webhook]
+            url = "http://alarms.local/"
+            urls = ["http://a.local/", "http://b.local/"]
+            compress = true
+        "#).unwrap();
+        let mut settings = Vec::new();
+        collect(&table, None, &mut settings).unwrap();
+        let mut env: Vec<_> = settings.iter().map(|(name, value, _)| (name.as_str(), value.as_str())).collect();
+        env.sort();
+        assert_eq!(env, [
+            ("ALARM_MODEM_PORT", "/dev/ttyACM0"),
+            ("ALARM_TONE_MIN_FREQ", "1640"),
+            ("ALARM_WEBHOOK_COMPRESS", "true"),
+            ("ALARM_WEBHOOK_URL", "http://alarms.local/"),
+            ("ALARM_WEBHOOK_URLS", "http://a.local/,http://b.local/")
+        ]);
+
+        let nested: Table = toml::from_str("[webhook.retry]\nmax = 3").unwrap();
+        assert!(collect(&nested, None, &mut Vec::new()).is_err());
+        assert!(mentions("ALARM_WEBHOOK_URL is not a URL", "ALARM_WEBHOOK_URL"));
+        assert!(!mentions("ALARM_WEBHOOK_URLS has an empty entry", "ALARM_WEBHOOK_URL"));
+    }
+
+    // The file only fills in what the environment leaves unset.
+    #[test]
+    fn the_environment_wins_over_the_file() {
+        let _env = lock_env();
+        let path = std::env::temp_dir().join(format!("alarm-modem-{}-config.toml", std::process::id()));
+        fs::write(&path, "modem_port = \"/dev/ttyUSB0\"\nmodem_baud = 9600\n").unwrap();
+        set_var("ALARM_CONFIG_FILE", &path);
+        set_var("ALARM_MODEM_PORT", "/dev/ttyACM0");
+        let loaded = load_config_file();
+        let values = [var("ALARM_MODEM_PORT").ok(), var("ALARM_MODEM_BAUD").ok()];
+        for key in ["ALARM_CONFIG_FILE", "ALARM_MODEM_PORT", "ALARM_MODEM_BAUD"] {
+            remove_var(key);
+        }
+        fs::remove_file(&path).unwrap();
+        loaded.unwrap();
+        assert_eq!(values, [Some("/dev/ttyACM0".to_string()), Some("9600".to_string())]);
+    }
+}
@@ -0,0 +1,211 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+const MESSAGE_DIGITS: usize = 16; // ACCT MT Q EEE GG CCC S
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Qualifier {
+    New,       // New event or opening
+    Restore,   // Restore or closing
+    Repeat     // Previously reported condition still present
+}
+
+#[derive(Debug, Clone, PartialEq, Hash, Serialize)]
+pub struct ContactIdEvent {
+    pub account: String,
+    pub qualifier: Qualifier,
+    pub code: u16,
+    pub description: &'static str,
+    pub partition: u8,
+    pub zone: u16
+}
+
+// Standard Ademco Contact ID event codes.
+pub(crate) fn describe(code: u16) -> &'static str {
+    match code {
+        100 => "Medical",
+        101 => "Personal emergency",
+        102 => "Fail to report in",
+        110 => "Fire",
+        111 => "Smoke",
+        112 => "Combustion",
+        113 => "Water flow",
+        114 => "Heat",
+        115 => "Pull station",
+        116 => "Duct",
+        117 => "Flame",
+        118 => "Near alarm",
+        120 => "Panic",
+        121 => "Duress",
+        122 => "Silent panic",
+        123 => "Audible panic",
+        130 => "Burglary",
+        131 => "Perimeter",
+        132 => "Interior",
+        133 => "24 hour burglary",
+        134 => "Entry/exit",
+        135 => "Day/night",
+        136 => "Outdoor",
+        137 => "Tamper",
+        138 => "Near alarm",
+        139 => "Intrusion verifier",
+        140 => "General alarm",
+        141 => "Polling loop open",
+        142 => "Polling loop short",
+        143 => "Expansion module failure",
+        144 => "Sensor tamper",
+        145 => "Expansion module tamper",
+        146 => "Silent burglary",
+        150 => "24 hour non-burglary",
+        151 => "Gas detected",
+        152 => "Refrigeration",
+        153 => "Loss of heat",
+        154 => "Water leakage",
+        155 => "Foil break",
+        156 => "Day trouble",
+        157 => "Low bottled gas level",
+        158 => "High temperature",
+        159 => "Low temperature",
+        161 => "Loss of air flow",
+        162 => "Carbon monoxide detected",
+        300 => "System trouble",
+        301 => "AC loss",
+        302 => "Low system battery",
+        303 => "RAM checksum bad",
+        304 => "ROM checksum bad",
+        305 => "System reset",
+        306 => "Panel programming changed",
+        307 => "Self-test failure",
+        308 => "System shutdown",
+        309 => "Battery test failure",
+        310 => "Ground fault",
+        311 => "Battery missing",
+        312 => "Power supply overcurrent",
+        313 => "Engineer reset",
+        320 => "Sounder/relay trouble",
+        321 => "Bell 1",
+        322 => "Bell 2",
+        330 => "System peripheral trouble",
+        333 => "Expansion module failure",
+        344 => "RF receiver jam",
+        350 => "Communication trouble",
+        351 => "Telco 1 fault",
+        352 => "Telco 2 fault",
+        353 => "Long range radio transmitter fault",
+        354 => "Failure to communicate event",
+        355 => "Loss of radio supervision",
+        356 => "Loss of central polling",
+        370 => "Protection loop",
+        371 => "Protection loop open",
+        372 => "Protection loop short",
+        373 => "Fire trouble",
+        374 => "Exit error",
+        380 => "Sensor trouble",
+        381 => "Loss of supervision (RF)",
+        382 => "Loss of supervision (RPM)",
+        383 => "Sensor tamper",
+        384 => "RF low battery",
+        400 => "Open/close",
+        401 => "Open/close by user",
+        402 => "Group open/close",
+        403 => "Automatic open/close",
+        406 => "Cancel",
+        407 => "Remote arm/disarm",
+        408 => "Quick arm",
+        409 => "Keyswitch open/close",
+        441 => "Armed stay",
+        442 => "Keyswitch armed stay",
+        570 => "Zone bypass",
+        571 => "Fire bypass",
+        572 => "24 hour zone bypass",
+        573 => "Burglary bypass",
+        574 => "Group bypass",
+        601 => "Manual trigger test",
+        602 => "Periodic test report",
+        603 => "Periodic RF transmission",
+        604 => "Fire test",
+        607 => "Walk test mode",
+        627 => "Program mode entry",
+        628 => "Program mode exit",
+        _ => "Unknown event"
+    }
+}
+
+// Contact ID sends 0 as DTMF `0` but counts it as 10, and uses B-F for the hex digits.
+fn digit_value(c: char) -> Option<u32> {
+    match c {
+        '0' => Some(10),
+        '1'..='9' => c.to_digit(10),
+        'B'..='F' => c.to_digit(16),
+        _ => None
+    }
+}
+
+fn number(digits: &str) -> Result<u16> {
+    digits.parse().map_err(|_| anyhow!("Contact ID field {} is not decimal", digits))
+}
+
+// Parse a full 16 digit message such as `1234 18 1130 01 015 1`, whitespace ignored, checking its checksum.
+pub(crate) fn parse(message: &str) -> Result<ContactIdEvent> {
+    let digits: String = message.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_ascii_uppercase();
+    if digits.len() != MESSAGE_DIGITS {
+        return Err(anyhow!("Contact ID message {} has {} digits, expected {}", message, digits.len(), MESSAGE_DIGITS));
+    }
+
+    let mut sum = 0;
+    for c in digits.chars() {
+        sum += digit_value(c).ok_or_else(|| anyhow!("Invalid Contact ID digit {:?} in {}", c, message))?;
+    }
+    if sum % 15 != 0 {
+        return Err(anyhow!("Contact ID message {} failed its checksum", message));
+    }
+
+    let message_type = &digits[4..6];
+    if message_type != "18" && message_type != "98" {
+        return Err(anyhow!("Unsupported Contact ID message type {}", message_type));
+    }
+    let qualifier = match &digits[6..7] {
+        "1" => Qualifier::New,
+        "3" => Qualifier::Restore,
+        "6" => Qualifier::Repeat,
+        other => return Err(anyhow!("Unknown Contact ID qualifier {}", other))
+    };
+
+    let code = number(&digits[7..10])?;
+    Ok(ContactIdEvent {
+        account: digits[..4].to_string(),
+        qualifier,
+        code,
+        description: describe(code),
+        partition: number(&digits[10..12])? as u8,
+        zone: number(&digits[12..15])?
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_message_into_a_described_event() {
+        let event = parse("1234 18 1130 01 015 E").unwrap();
+        assert_eq!(event, ContactIdEvent {
+            account: "1234".to_string(),
+            qualifier: Qualifier::New,
+            code: 130,
+            description: "Burglary",
+            partition: 1,
+            zone: 15
+        });
+        let restore = parse("123418313001015C").unwrap();
+        assert_eq!((restore.qualifier, restore.description), (Qualifier::Restore, "Burglary"));
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        assert!(parse("1234 18 1130 01 015 D").is_err(), "bad checksum");
+        assert!(parse("1234 18 1130 01 015").is_err(), "too short");
+        assert!(parse("1234 18 2130 01 015 D").is_err(), "unknown qualifier");
+    }
+}
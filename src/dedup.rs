@@ -0,0 +1,49 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+use log::info;
+use crate::webhook::AlertPayload;
+
+// Remembers decoded messages by a hash of their content, so a panel retransmitting the same Contact ID or FSK
+// message within the window is only reported once. Tones carry no content and are left to the cooldowns.
+pub(crate) struct DedupCache {
+    window: Duration,
+    seen: HashMap<u64, Instant>
+}
+
+// A detection's event and decoded content hashed together, equal only for the same event carrying the same message.
+pub(crate) fn content_key(payload: &AlertPayload) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (&payload.event, &payload.contact_id, &payload.fsk_data_hex).hash(&mut hasher);
+    hasher.finish()
+}
+
+fn content_hash(payload: &AlertPayload) -> Option<u64> {
+    if payload.contact_id.is_none() && payload.fsk_data_hex.is_none() {
+        return None;
+    }
+    Some(content_key(payload))
+}
+
+impl DedupCache {
+    pub fn new(window: Duration) -> Self {
+        DedupCache { window, seen: HashMap::new() }
+    }
+
+    // Whether the payload repeats a message already reported within the window. A repeat doesn't extend the window,
+    // so a message that keeps being sent is still reported once per window.
+    pub fn is_duplicate(&mut self, payload: &AlertPayload) -> bool {
+        let Some(hash) = content_hash(payload) else {
+            return false;
+        };
+        let now = Instant::now();
+        self.seen.retain(|_, first| now.duration_since(*first) < self.window);
+        if self.seen.contains_key(&hash) {
+            info!("Dropping repeated {} message, already reported within {:#?}", payload.event, self.window);
+            return true;
+        }
+        self.seen.insert(hash, now);
+        false
+    }
+}
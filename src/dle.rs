@@ -0,0 +1,113 @@
+pub(crate) const DLE: u8 = 0x10;
+pub(crate) const ETX: u8 = 0x03;
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+// In-band codes a voice modem shields behind DLE while streaming audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DleEvent {
+    Dtmf(char),  // DTMF digit heard on the line
+    EndOfStream, // DLE ETX, the modem has left voice receive
+    Other(u8)    // Anything else, e.g. `b` busy or `s` silence on modems that report them
+}
+
+// Double any DLE in outgoing audio so the modem doesn't take it as the start of a code.
+pub(crate) fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &byte in data {
+        escaped.push(byte);
+        if byte == DLE {
+            escaped.push(DLE);
+        }
+    }
+    escaped
+}
+
+// Splits a DLE shielded voice stream into plain audio bytes and the events between them.
+// A DLE at the very end of one read is carried over to pair with the first byte of the next.
+// Under software flow control, bare XON and XOFF bytes the modem injects can be dropped rather than taken as audio.
+pub(crate) struct DleDecoder {
+    pending: bool,
+    strip_xon_xoff: bool
+}
+
+impl DleDecoder {
+    pub fn new(strip_xon_xoff: bool) -> Self {
+        DleDecoder { pending: false, strip_xon_xoff }
+    }
+
+    // Append the unescaped audio from `raw` to `data`, returning the events found.
+    pub fn feed(&mut self, raw: &[u8], data: &mut Vec<u8>) -> Vec<DleEvent> {
+        let mut events = Vec::new();
+        for &byte in raw {
+            if !std::mem::take(&mut self.pending) {
+                if byte == DLE {
+                    self.pending = true;
+                } else if !(self.strip_xon_xoff && (byte == XON || byte == XOFF)) {
+                    data.push(byte);
+                }
+                continue;
+            }
+
+            match byte {
+                DLE => data.push(DLE), // Escaped data byte
+                ETX => events.push(DleEvent::EndOfStream),
+                b'0'..=b'9' | b'*' | b'#' | b'A'..=b'D' => events.push(DleEvent::Dtmf(byte as char)),
+                other => events.push(DleEvent::Other(other))
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // Decode a stream read in chunks of the given sizes, the last chunk taking whatever is left.
+    fn decode_chunked(raw: &[u8], sizes: &[usize], strip_xon_xoff: bool) -> (Vec<u8>, Vec<DleEvent>) {
+        let mut decoder = DleDecoder::new(strip_xon_xoff);
+        let mut data = Vec::new();
+        let mut events = Vec::new();
+        let mut rest = raw;
+        for &size in sizes {
+            let (chunk, tail) = rest.split_at(size.min(rest.len()));
+            events.extend(decoder.feed(chunk, &mut data));
+            rest = tail;
+        }
+        events.extend(decoder.feed(rest, &mut data));
+        (data, events)
+    }
+
+    // Flow control bytes injected between samples are dropped, while the same values escaped with DLE are events.
+    #[test]
+    fn strips_injected_xon_and_xoff() {
+        let raw = [&[XOFF, 0x80, 0x7f][..], &[XON, 0x00, DLE, DLE, XOFF], &[XON, 0xff, 0x42, DLE, XON]].concat();
+        let samples = vec![0x80, 0x7f, 0x00, DLE, 0xff, 0x42];
+        assert_eq!(decode_chunked(&raw, &[3, 4], true), (samples.clone(), vec![DleEvent::Other(XON)]));
+        // Left alone without software flow control, all four reach the audio.
+        assert_eq!(decode_chunked(&raw, &[3, 4], false).0.len(), samples.len() + 4);
+    }
+
+    proptest! {
+        #[test]
+        fn decodes_any_chunking_like_one_read(
+            raw in proptest::collection::vec(any::<u8>(), 0..512),
+            sizes in proptest::collection::vec(0usize..64, 0..16),
+            strip_xon_xoff: bool
+        ) {
+            prop_assert_eq!(decode_chunked(&raw, &sizes, strip_xon_xoff), decode_chunked(&raw, &[], strip_xon_xoff));
+        }
+
+        #[test]
+        fn unescapes_what_was_escaped(
+            data in proptest::collection::vec(any::<u8>(), 0..512),
+            sizes in proptest::collection::vec(0usize..64, 0..16)
+        ) {
+            let (decoded, events) = decode_chunked(&escape(&data), &sizes, false);
+            prop_assert_eq!(decoded, data);
+            prop_assert!(events.is_empty());
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::f32::consts::TAU;
+use std::time::Duration;
+use log::debug;
+use crate::audio::SAMPLE_RATE;
+
+const LOW_GROUP: [f32; 4] = [697.0, 770.0, 852.0, 941.0];
+const HIGH_GROUP: [f32; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+const KEYS: [[char; 4]; 4] = [
+    ['1', '2', '3', 'A'],
+    ['4', '5', '6', 'B'],
+    ['7', '8', '9', 'C'],
+    ['*', '0', '#', 'D']
+];
+const BLOCK_LEN: usize = 205;          // The usual DTMF block at 8 kHz, fine enough to tell neighbouring tones apart
+const BLOCK_STEP: usize = BLOCK_LEN / 2; // Overlapping blocks, so even a 40 ms tone fills two of them whole
+const MIN_MEAN_SQUARE: f32 = 1.0e4;    // Around -40 dBFS for 16 bit audio, quieter blocks are taken as silence
+const MIN_TONE_SHARE: f32 = 0.6;       // Share of the block's energy the two tones must hold together
+const MIN_GROUP_RATIO: f32 = 4.0;      // Each tone over the next strongest in its group, ruling out speech
+const MAX_TWIST: f32 = 6.3;            // Power ratio either way between the two tones, 8 dB
+const MIN_BLOCKS: usize = 2;           // Consecutive blocks agreeing on a digit before it counts
+
+// Power of a single frequency over the block using the Goertzel algorithm.
+fn goertzel(samples: &[f32], frequency: f32) -> f32 {
+    let coeff = 2.0 * (TAU * frequency / SAMPLE_RATE).cos();
+    let (mut s1, mut s2) = (0.0f32, 0.0f32);
+    for &sample in samples {
+        let s0 = sample + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+    }
+    s1.powi(2) + s2.powi(2) - coeff * s1 * s2
+}
+
+// Strongest tone of a group and its power, provided it stands clear of the others.
+fn strongest(powers: &[f32]) -> Option<(usize, f32)> {
+    let (index, &best) = powers.iter().enumerate().max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+    let next = powers.iter().enumerate().filter(|&(i, _)| i != index).map(|(_, &power)| power).fold(0.0, f32::max);
+    (best >= next * MIN_GROUP_RATIO).then_some((index, best))
+}
+
+// The DTMF digit held through a block of samples, if its low and high group tones are both present and make up most
+// of the block's energy.
+pub(crate) fn decode_dtmf(samples: &[i16]) -> Option<char> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mean = samples.iter().map(|&sample| sample as f32).sum::<f32>() / samples.len() as f32;
+    let samples: Vec<f32> = samples.iter().map(|&sample| sample as f32 - mean).collect();
+    let energy: f32 = samples.iter().map(|sample| sample * sample).sum();
+    if energy / (samples.len() as f32) < MIN_MEAN_SQUARE {
+        return None;
+    }
+
+    let low: Vec<f32> = LOW_GROUP.iter().map(|&frequency| goertzel(&samples, frequency)).collect();
+    let high: Vec<f32> = HIGH_GROUP.iter().map(|&frequency| goertzel(&samples, frequency)).collect();
+    let (row, low_power) = strongest(&low)?;
+    let (column, high_power) = strongest(&high)?;
+    // A whole tone of amplitude A gives (A N / 2)^2, against N A^2 / 2 of energy, so this is its share of the block.
+    let share = 2.0 * (low_power + high_power) / (samples.len() as f32 * energy);
+    let twist = high_power / low_power.max(f32::MIN_POSITIVE);
+    if share < MIN_TONE_SHARE || !(1.0 / MAX_TWIST..=MAX_TWIST).contains(&twist) {
+        return None;
+    }
+    Some(KEYS[row][column])
+}
+
+// Collects the digits decoded from a stream of samples into sequences, one ending once no digit has been heard for
+// the configured gap. A digit is taken once per press, when it has held for MIN_BLOCKS blocks.
+pub(crate) struct DtmfDecoder {
+    pending: Vec<i16>,
+    gap: usize, // In samples
+    digit: Option<char>,
+    held: usize,
+    since_digit: usize,
+    digits: String
+}
+
+impl DtmfDecoder {
+    pub fn new(gap: Duration) -> Self {
+        DtmfDecoder {
+            pending: Vec::with_capacity(BLOCK_LEN * 2),
+            gap: (gap.as_secs_f32() * SAMPLE_RATE) as usize,
+            digit: None,
+            held: 0,
+            since_digit: 0,
+            digits: String::new()
+        }
+    }
+
+    // Start afresh for a new call, dropping any partial sequence.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.digit = None;
+        self.held = 0;
+        self.since_digit = 0;
+        self.digits.clear();
+    }
+
+    // Feed decoded samples, returning each sequence the gap after its last digit completed.
+    pub fn feed(&mut self, samples: &[i16]) -> Vec<String> {
+        let mut sequences = Vec::new();
+        self.pending.extend_from_slice(samples);
+        let mut start = 0;
+        while start + BLOCK_LEN <= self.pending.len() {
+            let digit = decode_dtmf(&self.pending[start..start + BLOCK_LEN]);
+            start += BLOCK_STEP;
+
+            if digit.is_some() && digit == self.digit {
+                self.held += 1;
+            } else {
+                self.digit = digit;
+                self.held = 1;
+            }
+            if let Some(digit) = digit.filter(|_| self.held == MIN_BLOCKS) {
+                debug!("DTMF digit {}", digit);
+                self.digits.push(digit);
+            }
+
+            if digit.is_some() {
+                self.since_digit = 0;
+            } else {
+                self.since_digit += BLOCK_STEP;
+                if self.since_digit >= self.gap && !self.digits.is_empty() {
+                    sequences.push(std::mem::take(&mut self.digits));
+                }
+            }
+        }
+        self.pending.drain(..start);
+        sequences
+    }
+
+    // Whatever was received of a sequence still in progress, e.g. when the call ends.
+    pub fn finish(&mut self) -> Option<String> {
+        self.digit = None;
+        self.held = 0;
+        (!self.digits.is_empty()).then(|| std::mem::take(&mut self.digits))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tones(frequencies: &[f32], ms: usize) -> Vec<i16> {
+        (0..ms * SAMPLE_RATE as usize / 1000)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE;
+                frequencies.iter().map(|frequency| 4000.0 * (TAU * frequency * t).sin()).sum::<f32>() as i16
+            })
+            .collect()
+    }
+
+    fn key(digit: char, ms: usize) -> Vec<i16> {
+        let row = KEYS.iter().position(|row| row.contains(&digit)).unwrap();
+        let column = KEYS[row].iter().position(|&key| key == digit).unwrap();
+        tones(&[LOW_GROUP[row], HIGH_GROUP[column]], ms)
+    }
+
+    fn silence(ms: usize) -> Vec<i16> {
+        vec![0; ms * SAMPLE_RATE as usize / 1000]
+    }
+
+    #[test]
+    fn decodes_every_key() {
+        for digit in KEYS.iter().flatten().copied() {
+            assert_eq!(decode_dtmf(&key(digit, 50)[..BLOCK_LEN]), Some(digit));
+        }
+        assert_eq!(decode_dtmf(&silence(50)), None);
+        // A single tone isn't a key, nor is a pair of one group's tones.
+        assert_eq!(decode_dtmf(&tones(&[697.0], 50)), None);
+        assert_eq!(decode_dtmf(&tones(&[697.0, 852.0], 50)), None);
+    }
+
+    // Each press counts once however long it's held, and a sequence ends once the gap has passed without a digit.
+    #[test]
+    fn collects_presses_into_sequences() {
+        let mut decoder = DtmfDecoder::new(Duration::from_millis(500));
+        let audio = [key('1', 200), silence(60), key('1', 60), silence(60), key('#', 60), silence(600), key('5', 60)]
+            .concat();
+        let sequences: Vec<String> = audio.chunks(160).flat_map(|chunk| decoder.feed(chunk)).collect();
+        assert_eq!(sequences, ["11#"]);
+        assert_eq!(decoder.finish().as_deref(), Some("5"));
+        assert_eq!(decoder.finish(), None);
+    }
+}
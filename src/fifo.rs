@@ -0,0 +1,127 @@
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use crate::sink::DetectionSink;
+use crate::webhook::AlertPayload;
+
+// Writes each detection as a line of JSON to a named pipe for a local reader. The pipe is opened non-blocking
+// so a missing or stalled reader never holds up detection, events with nobody to read them are dropped.
+pub(crate) struct FifoSink {
+    path: String,
+    writer: Option<File>
+}
+
+impl FifoSink {
+    pub fn new(path: &str) -> Self {
+        FifoSink { path: path.to_string(), writer: None }
+    }
+
+    // Open the write end if a reader is attached, opening a FIFO for writing with no reader fails with ENXIO.
+    fn connect(&mut self) -> Result<Option<&mut File>> {
+        if self.writer.is_none() {
+            match OpenOptions::new().write(true).custom_flags(libc::O_NONBLOCK).open(&self.path) {
+                Ok(file) => {
+                    info!("Event FIFO {} reader attached", self.path);
+                    self.writer = Some(file);
+                },
+                Err(e) if e.raw_os_error() == Some(libc::ENXIO) => return Ok(None),
+                Err(e) => return Err(e).with_context(|| format!("Failed to open event FIFO {}", self.path))
+            }
+        }
+        Ok(self.writer.as_mut())
+    }
+}
+
+impl DetectionSink for FifoSink {
+    fn name(&self) -> &'static str {
+        "fifo"
+    }
+
+    fn on_detection(&mut self, payload: &AlertPayload) -> Result<()> {
+        let mut line = serde_json::to_vec(payload)?;
+        line.push(b'\n');
+
+        let path = self.path.clone();
+        let Some(writer) = self.connect()? else {
+            debug!("No reader on event FIFO {}, dropping {} event", path, payload.event);
+            return Ok(());
+        };
+
+        // A single write so a line is never interleaved, a short write leaves a torn line so the reader is
+        // disconnected instead of being sent the rest out of step.
+        match writer.write(&line) {
+            Ok(n) if n == line.len() => Ok(()),
+            Ok(_) => {
+                warn!("Event FIFO {} is full, dropping {} event and disconnecting the reader", path, payload.event);
+                self.writer = None;
+                Ok(())
+            },
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                warn!("Event FIFO {} is full, dropping {} event", path, payload.event);
+                Ok(())
+            },
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => {
+                info!("Event FIFO {} reader went away, dropping {} event", path, payload.event);
+                self.writer = None;
+                Ok(())
+            },
+            Err(e) => {
+                self.writer = None;
+                Err(e).with_context(|| format!("Failed to write to event FIFO {}", path))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::ffi::OsStrExt;
+    use std::time::SystemTime;
+    use crate::audio::{Detection, DetectionEvent};
+
+    fn payload() -> AlertPayload {
+        let event = DetectionEvent {
+            detection: Detection::Trigger,
+            timestamp: SystemTime::now(),
+            audio: None,
+            contact_id: None,
+            fsk_data: None,
+            frequency: Some(1665.0),
+            offset: None,
+            level_dbfs: None,
+            caller: None
+        };
+        AlertPayload::from_event(&event, 8000)
+    }
+
+    #[test]
+    fn reader_gets_a_line_per_detection() {
+        let path = std::env::temp_dir().join(format!("alarm-modem-{}-events.fifo", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        let mut sink = FifoSink::new(path.to_str().unwrap());
+
+        // Nobody reading yet, so the event is dropped rather than blocking.
+        sink.on_detection(&payload()).unwrap();
+        assert!(sink.writer.is_none());
+
+        let reader = OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(&path).unwrap();
+        sink.on_detection(&payload()).unwrap();
+        let mut line = String::new();
+        BufReader::new(reader).read_line(&mut line).unwrap();
+        let event: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!((event["event"].as_str(), event["frequency_hz"].as_f64()), (Some("trigger"), Some(1665.0)));
+
+        // The reader going away drops the event and the write end with it.
+        sink.on_detection(&payload()).unwrap();
+        assert!(sink.writer.is_none());
+        fs::remove_file(&path).unwrap();
+    }
+}
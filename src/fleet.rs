@@ -0,0 +1,40 @@
+use std::sync::{Arc, Mutex};
+use anyhow::{anyhow, Result};
+use log::info;
+use tokio::task::JoinSet;
+use crate::audio::Detector;
+use crate::config::Config;
+use crate::metrics::{self, Metrics, SharedMetrics};
+use crate::stats::SessionStats;
+use crate::watchdog::Watchdog;
+use crate::webhook::{build_agent, WebhookSink};
+use crate::{build_sinks, monitor, report_stats};
+
+// Monitor every modem from one process, each as a task running its own read loop, detection and delivery, while all
+// of them share one HTTP agent and one metrics backend (both set up from the first config). Reads stay blocking and
+// the detection per frame synchronous, so each task runs on tokio's blocking pool. A task ends with an error once its
+// modem's reconnect attempts run out or its config can't be set up, the rest keep going until `shutdown`. Must be
+// called within a tokio runtime.
+pub fn spawn_modems(configs: Vec<Config>) -> Result<JoinSet<Result<()>>> {
+    let first = configs.first().ok_or_else(|| anyhow!("No modems to monitor"))?;
+    let agent = build_agent(first.webhook_pool_size);
+    let metrics = metrics::from_config(first)?.map(|metrics| SharedMetrics(Arc::new(Mutex::new(metrics))));
+
+    let mut tasks = JoinSet::new();
+    for config in configs {
+        let config = Arc::new(config);
+        let agent = agent.clone();
+        let metrics = metrics.clone().map(|metrics| Box::new(metrics) as Box<dyn Metrics + Send>);
+        // Sinks needn't be Send, so each task builds its own.
+        tasks.spawn_blocking(move || {
+            info!("Monitoring the modem on {}", config.modem_port);
+            let mut detector = Detector::new(config.clone())?;
+            let mut sinks = build_sinks(&config, WebhookSink::with_agent(&config, agent))?;
+            let mut stats = SessionStats::new(metrics);
+            let result = monitor(&config, &mut detector, &mut sinks, &mut stats, &mut Watchdog::new());
+            report_stats(&stats, config.stats_file.as_deref());
+            result
+        });
+    }
+    Ok(tasks)
+}
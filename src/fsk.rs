@@ -0,0 +1,214 @@
+use std::collections::VecDeque;
+use std::f32::consts::TAU;
+use log::debug;
+use crate::audio::SAMPLE_RATE;
+use crate::config::FskStandard;
+
+const DC_TRACKING: f32 = 0.001; // Per sample, slow enough to leave the tones alone
+const MIN_CARRIER_RATIO: f32 = 0.5; // Share of the window's energy the two tones must hold to count as a carrier
+const END_OF_MESSAGE_BITS: f32 = 10.0; // Carrier gone for this long ends the message
+const DATA_BITS: u8 = 8;
+
+// Correlates the last window of samples with one tone, a single bin DFT that slides a sample at a time.
+struct Correlator {
+    step: f32,
+    phase: f32,
+    products: VecDeque<(f32, f32)>,
+    len: usize
+}
+
+impl Correlator {
+    fn new(frequency: f32, len: usize) -> Self {
+        Correlator { step: TAU * frequency / SAMPLE_RATE, phase: 0.0, products: VecDeque::with_capacity(len + 1), len }
+    }
+
+    // Energy of the tone over the window, summed afresh each sample so it can't drift.
+    fn update(&mut self, sample: f32) -> f32 {
+        self.products.push_back((sample * self.phase.cos(), -sample * self.phase.sin()));
+        if self.products.len() > self.len {
+            self.products.pop_front();
+        }
+        self.phase = (self.phase + self.step) % TAU;
+        let (re, im) = self.products.iter().fold((0.0, 0.0), |(re, im), (r, i)| (re + r, im + i));
+        re * re + im * im
+    }
+}
+
+// Where the UART is in a character, timed in samples since the start bit's leading edge.
+enum Framing {
+    Idle,
+    Start,
+    Data { bit: u8, byte: u8 },
+    Stop { byte: u8 }
+}
+
+// Demodulates a V.23 or Bell 202 FSK stream into asynchronous 8N1 characters, mark (idle) being a 1 bit. Bytes are
+// collected into a message until the carrier drops.
+pub(crate) struct FskDemodulator {
+    mark: Correlator,
+    space: Correlator,
+    window: VecDeque<f32>,
+    samples_per_bit: f32,
+    dc: Option<f32>,
+    framing: Framing,
+    clock: f32,
+    quiet: f32,
+    message: Vec<u8>
+}
+
+impl FskDemodulator {
+    pub fn new(standard: FskStandard) -> Self {
+        let samples_per_bit = SAMPLE_RATE / standard.baud();
+        let len = samples_per_bit.round() as usize;
+        FskDemodulator {
+            mark: Correlator::new(standard.mark(), len),
+            space: Correlator::new(standard.space(), len),
+            window: VecDeque::with_capacity(len + 1),
+            samples_per_bit,
+            dc: None,
+            framing: Framing::Idle,
+            clock: 0.0,
+            quiet: 0.0,
+            message: Vec::new()
+        }
+    }
+
+    // Start afresh for a new call, dropping any partial message.
+    pub fn reset(&mut self) {
+        self.dc = None;
+        self.framing = Framing::Idle;
+        self.quiet = 0.0;
+        self.message.clear();
+    }
+
+    // Feed decoded samples, returning each message the carrier dropping completed.
+    pub fn feed(&mut self, samples: &[i16]) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+        for &sample in samples {
+            // u8 audio isn't centred, and an offset would read as energy in both tones.
+            let dc = self.dc.get_or_insert(sample as f32);
+            *dc += (sample as f32 - *dc) * DC_TRACKING;
+            let sample = sample as f32 - *dc;
+
+            let mark = self.mark.update(sample);
+            let space = self.space.update(sample);
+            self.window.push_back(sample * sample);
+            if self.window.len() > self.mark.len {
+                self.window.pop_front();
+            }
+            let energy: f32 = self.window.iter().sum();
+            let carrier = energy > 0.0 && 2.0 * (mark + space) / (self.mark.len as f32 * energy) >= MIN_CARRIER_RATIO;
+
+            if carrier {
+                self.quiet = 0.0;
+            } else {
+                self.quiet += 1.0;
+                if self.quiet >= END_OF_MESSAGE_BITS * self.samples_per_bit && !self.message.is_empty() {
+                    messages.push(std::mem::take(&mut self.message));
+                }
+                // The window straddling a change of tone holds neither for long, so only a gap of a bit or more is
+                // the carrier dropping. Shorter ones are still clocked, keeping the character's timing.
+                if self.quiet >= self.samples_per_bit {
+                    self.framing = Framing::Idle;
+                    continue;
+                }
+            }
+            self.clock_bit(mark > space);
+        }
+        messages
+    }
+
+    // Whatever was received of a message still in progress, e.g. when the call ends.
+    pub fn finish(&mut self) -> Option<Vec<u8>> {
+        self.framing = Framing::Idle;
+        (!self.message.is_empty()).then(|| std::mem::take(&mut self.message))
+    }
+
+    // Sample each bit at its centre, counted from the start bit's leading edge.
+    fn clock_bit(&mut self, mark: bool) {
+        let bit_index = match self.framing {
+            Framing::Idle => {
+                if !mark {
+                    self.framing = Framing::Start;
+                    self.clock = 0.0;
+                }
+                return;
+            },
+            Framing::Start => 0,
+            Framing::Data { bit, .. } => bit + 1,
+            Framing::Stop { .. } => DATA_BITS + 1
+        };
+        self.clock += 1.0;
+        if self.clock < (bit_index as f32 + 0.5) * self.samples_per_bit {
+            return;
+        }
+
+        self.framing = match self.framing {
+            // A start bit that doesn't last to its centre was noise.
+            Framing::Start if mark => Framing::Idle,
+            Framing::Start => Framing::Data { bit: 0, byte: 0 },
+            Framing::Data { bit, byte } => {
+                let byte = byte | (mark as u8) << bit;
+                if bit + 1 == DATA_BITS {
+                    Framing::Stop { byte }
+                } else {
+                    Framing::Data { bit: bit + 1, byte }
+                }
+            },
+            Framing::Stop { byte } => {
+                if mark {
+                    self.message.push(byte);
+                } else {
+                    debug!("FSK framing error, dropped character {:#04x}", byte);
+                }
+                Framing::Idle
+            },
+            Framing::Idle => Framing::Idle
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Phase continuous FSK of each byte framed 8N1 after a mark leader, then silence to drop the carrier.
+    fn modulate(standard: FskStandard, bytes: &[u8]) -> Vec<i16> {
+        let mut bits = vec![true; 30];
+        for &byte in bytes {
+            bits.push(false);
+            bits.extend((0..DATA_BITS).map(|bit| byte >> bit & 1 == 1));
+            bits.push(true);
+        }
+        bits.extend([true; 5]);
+
+        let samples_per_bit = SAMPLE_RATE / standard.baud();
+        let len = (bits.len() as f32 * samples_per_bit) as usize;
+        let mut phase = 0.0f32;
+        let mut samples: Vec<i16> = (0..len)
+            .map(|i| {
+                let mark = bits[(i as f32 / samples_per_bit) as usize];
+                let frequency = if mark { standard.mark() } else { standard.space() };
+                phase = (phase + TAU * frequency / SAMPLE_RATE) % TAU;
+                (8000.0 * phase.sin()) as i16
+            })
+            .collect();
+        samples.extend(vec![0; (20.0 * samples_per_bit) as usize]);
+        samples
+    }
+
+    #[test]
+    fn decodes_a_synthesized_message() {
+        let message = b"\x04\x1218 1131 01 015\xa5";
+        for standard in [FskStandard::Bell202, FskStandard::V23] {
+            let mut demodulator = FskDemodulator::new(standard);
+            let samples = modulate(standard, message);
+            // Split mid-character, as reads would.
+            let (first, rest) = samples.split_at(samples.len() / 2);
+            let mut messages = demodulator.feed(first);
+            messages.extend(demodulator.feed(rest));
+            assert_eq!(messages, [message.to_vec()]);
+            assert!(demodulator.finish().is_none());
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use log::{error, info, warn};
+use tokio::runtime::{Builder, Runtime};
+use tokio::time::sleep;
+use tonic::client::Grpc;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::{Channel, Endpoint};
+use tonic::Request;
+use tonic_prost::ProstCodec;
+use crate::sink::DetectionSink;
+use crate::webhook::AlertPayload;
+
+const REPORT_PATH: &str = "/alarm_modem.AlertService/Report";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_ATTEMPTS: u32 = 720; // An hour of retries before an alert is dropped.
+const QUEUE_CAPACITY: usize = 64;
+
+// Messages from proto/alert.proto, written out by hand so the build doesn't need protoc.
+#[derive(Clone, PartialEq, prost::Message)]
+struct ContactId {
+    #[prost(string, tag = "1")]
+    account: String,
+    #[prost(string, tag = "2")]
+    qualifier: String,
+    #[prost(uint32, tag = "3")]
+    code: u32,
+    #[prost(string, tag = "4")]
+    description: String,
+    #[prost(uint32, tag = "5")]
+    partition: u32,
+    #[prost(uint32, tag = "6")]
+    zone: u32
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Caller {
+    #[prost(string, tag = "1")]
+    number: String,
+    #[prost(string, optional, tag = "2")]
+    name: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    date: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    time: Option<String>
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Alert {
+    #[prost(string, tag = "1")]
+    event: String,
+    #[prost(string, tag = "2")]
+    priority: String,
+    #[prost(uint64, tag = "3")]
+    timestamp: u64,
+    #[prost(uint64, optional, tag = "4")]
+    offset_ms: Option<u64>,
+    #[prost(double, optional, tag = "5")]
+    frequency_hz: Option<f64>,
+    #[prost(double, optional, tag = "6")]
+    level_dbfs: Option<f64>,
+    #[prost(string, optional, tag = "7")]
+    audio_wav_base64: Option<String>,
+    #[prost(message, optional, tag = "8")]
+    contact_id: Option<ContactId>,
+    #[prost(string, optional, tag = "9")]
+    fsk_data_hex: Option<String>,
+    #[prost(message, optional, tag = "10")]
+    caller: Option<Caller>
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Ack {}
+
+impl From<&AlertPayload> for Alert {
+    fn from(payload: &AlertPayload) -> Self {
+        Alert {
+            event: payload.event.to_string(),
+            priority: payload.priority.to_string(),
+            timestamp: payload.timestamp,
+            offset_ms: payload.offset_ms,
+            frequency_hz: payload.frequency_hz,
+            level_dbfs: payload.level_dbfs,
+            audio_wav_base64: payload.audio_wav_base64.clone(),
+            contact_id: payload.contact_id.as_ref().map(|contact_id| ContactId {
+                account: contact_id.account.clone(),
+                // Named as in the JSON payload.
+                qualifier: serde_json::to_value(contact_id.qualifier).ok()
+                    .and_then(|qualifier| qualifier.as_str().map(String::from))
+                    .unwrap_or_default(),
+                code: contact_id.code as u32,
+                description: contact_id.description.to_string(),
+                partition: contact_id.partition as u32,
+                zone: contact_id.zone as u32
+            }),
+            fsk_data_hex: payload.fsk_data_hex.clone(),
+            caller: payload.caller.as_ref().map(|caller| Caller {
+                number: caller.number.clone(),
+                name: caller.name.clone(),
+                date: caller.date.clone(),
+                time: caller.time.clone()
+            })
+        }
+    }
+}
+
+// Reports each detection to an `AlertService` with a unary call. The calls are made on a thread of their own, so
+// detection only ever queues, and the channel reconnects by itself when the server comes back.
+pub(crate) struct GrpcSink {
+    queue: SyncSender<Alert>
+}
+
+async fn report(client: &mut Grpc<Channel>, alert: Alert) -> Result<()> {
+    client.ready().await.context("gRPC channel not ready")?;
+    client.unary::<_, Ack, _>(Request::new(alert), PathAndQuery::from_static(REPORT_PATH), ProstCodec::default()).await
+        .map_err(|status| anyhow!("{} ({})", status.message(), status.code()))?;
+    Ok(())
+}
+
+async fn deliver(client: &mut Grpc<Channel>, url: &str, alert: Alert) {
+    for attempt in 1..=MAX_ATTEMPTS {
+        match report(client, alert.clone()).await {
+            Ok(()) => return,
+            Err(e) => warn!("gRPC report of {} to {} failed (attempt {}), retrying in {:#?}: {:#}", alert.event, url, attempt, RETRY_DELAY, e)
+        }
+        sleep(RETRY_DELAY).await;
+    }
+    error!("Giving up on gRPC report of {} to {} after {} attempts", alert.event, url, MAX_ATTEMPTS);
+}
+
+fn run(runtime: Runtime, channel: Channel, url: String, queue: Receiver<Alert>) {
+    let mut client = Grpc::new(channel);
+    for alert in queue {
+        runtime.block_on(deliver(&mut client, &url, alert));
+    }
+}
+
+impl GrpcSink {
+    pub fn new(url: &str) -> Result<Self> {
+        let runtime = Builder::new_current_thread().enable_all().build().context("Failed to start the gRPC runtime")?;
+        let endpoint = Endpoint::from_shared(url.to_string())
+            .with_context(|| format!("Invalid gRPC URL {}", url))?
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT);
+        // Built inside the runtime, whose reactor the lazily opened connection will run on.
+        let channel = runtime.block_on(async { endpoint.connect_lazy() });
+
+        let (queue, receiver) = sync_channel(QUEUE_CAPACITY);
+        let url = url.to_string();
+        info!("Reporting detections over gRPC to {}", url);
+        thread::spawn(move || run(runtime, channel, url, receiver));
+        Ok(GrpcSink { queue })
+    }
+}
+
+impl DetectionSink for GrpcSink {
+    fn name(&self) -> &'static str {
+        "grpc"
+    }
+
+    fn on_detection(&mut self, payload: &AlertPayload) -> Result<()> {
+        match self.queue.try_send(Alert::from(payload)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(anyhow!("gRPC queue is full, dropped the detection")),
+            Err(TrySendError::Disconnected(_)) => Err(anyhow!("gRPC delivery thread has stopped"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::future::{ready, Future, Ready};
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+    use std::time::Instant;
+    use tonic::body::Body;
+    use tonic::codegen::http;
+    use tonic::codegen::tokio_stream::wrappers::TcpListenerStream;
+    use tonic::codegen::Service;
+    use tonic::transport::Server;
+    use tonic::{Response, Status};
+    use crate::cid::CallerId;
+
+    // The Report method, keeping each alert it's sent.
+    struct Report(Arc<Mutex<Vec<Alert>>>);
+
+    impl Service<Request<Alert>> for Report {
+        type Response = Response<Ack>;
+        type Error = Status;
+        type Future = Ready<Result<Response<Ack>, Status>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Status>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Alert>) -> Self::Future {
+            self.0.lock().unwrap().push(request.into_inner());
+            ready(Ok(Response::new(Ack {})))
+        }
+    }
+
+    // An `AlertService` as protoc would generate it, routing its one path to Report.
+    #[derive(Clone)]
+    struct AlertService(Arc<Mutex<Vec<Alert>>>);
+
+    impl Service<http::Request<Body>> for AlertService {
+        type Response = http::Response<Body>;
+        type Error = Infallible;
+        type Future = Pin<Box<dyn Future<Output = Result<http::Response<Body>, Infallible>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+            let received = self.0.clone();
+            Box::pin(async move {
+                if request.uri().path() != REPORT_PATH {
+                    return Ok(Status::unimplemented(request.uri().path().to_string()).into_http());
+                }
+                let mut grpc = tonic::server::Grpc::new(ProstCodec::<Ack, Alert>::default());
+                Ok(grpc.unary(Report(received), request).await)
+            })
+        }
+    }
+
+    #[test]
+    fn reports_a_decodable_alert() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        listener.set_nonblocking(true).unwrap();
+        let service = AlertService(received.clone());
+        thread::spawn(move || {
+            let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(async {
+                let incoming = TcpListenerStream::new(tokio::net::TcpListener::from_std(listener).unwrap());
+                Server::builder().serve_with_incoming(service, incoming).await
+            })
+        });
+
+        let payload = AlertPayload {
+            event: "trigger",
+            priority: "high",
+            timestamp: 1_700_000_000,
+            offset_ms: Some(640),
+            frequency_hz: Some(1665.5),
+            level_dbfs: Some(-23.0),
+            audio_wav_base64: None,
+            contact_id: None,
+            fsk_data_hex: Some("04a5".to_string()),
+            caller: Some(CallerId { number: "5551234".to_string(), name: None, date: None, time: None })
+        };
+        let mut sink = GrpcSink::new(&url).unwrap();
+        sink.on_detection(&payload).unwrap();
+
+        let started = Instant::now();
+        while received.lock().unwrap().is_empty() && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(*received.lock().unwrap(), [Alert::from(&payload)]);
+        let alert = received.lock().unwrap()[0].clone();
+        assert_eq!((alert.event.as_str(), alert.priority.as_str(), alert.offset_ms), ("trigger", "high", Some(640)));
+        assert_eq!(alert.caller.map(|caller| caller.number).as_deref(), Some("5551234"));
+    }
+}
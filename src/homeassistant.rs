@@ -0,0 +1,154 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, sleep};
+use std::time::Duration;
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::json;
+use ureq::{Agent, AgentBuilder};
+use crate::config::Config;
+use crate::sink::DetectionSink;
+use crate::webhook::AlertPayload;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const FRIENDLY_NAME: &str = "Alarm modem";
+
+// Home Assistant's REST API endpoint and long-lived access token, shared with the thread that clears the state.
+#[derive(Clone)]
+struct HomeAssistant {
+    agent: Agent,
+    url: String,
+    token: String
+}
+
+impl HomeAssistant {
+    fn post(&self, path: &str, body: &impl Serialize) -> Result<()> {
+        self.agent.post(&format!("{}/api/{}", self.url, path))
+            .set("Authorization", &format!("Bearer {}", self.token))
+            .set("Content-Type", "application/json")
+            .send_string(&serde_json::to_string(body)?)
+            .with_context(|| format!("Home Assistant request to /api/{} failed", path))?;
+        Ok(())
+    }
+}
+
+// Turns a binary_sensor on for each detection and back off once the cooldown passes without another,
+// optionally also firing a Home Assistant event carrying the full payload.
+pub(crate) struct HomeAssistantSink {
+    api: HomeAssistant,
+    entity: String,
+    event: Option<String>,
+    cooldown: Duration,
+    generation: Arc<AtomicU64> // Bumped per detection so only the latest one's timer clears the state
+}
+
+impl HomeAssistantSink {
+    pub fn new(url: &str, config: &Config) -> Result<Self> {
+        let token = config.ha_token.clone().context("ALARM_HA_TOKEN is required to send detections to Home Assistant")?;
+        info!("Sending detections to Home Assistant at {} as {}", url, config.ha_entity);
+        Ok(HomeAssistantSink {
+            api: HomeAssistant {
+                agent: AgentBuilder::new().timeout(REQUEST_TIMEOUT).build(),
+                url: url.trim_end_matches('/').to_string(),
+                token
+            },
+            entity: config.ha_entity.clone(),
+            event: config.ha_event.clone(),
+            cooldown: config.min_gap.unwrap_or(config.detection_interval),
+            generation: Arc::new(AtomicU64::new(0))
+        })
+    }
+}
+
+fn state_body(state: &str, payload: Option<&AlertPayload>) -> serde_json::Value {
+    let mut attributes = json!({ "friendly_name": FRIENDLY_NAME, "device_class": "safety" });
+    if let Some(payload) = payload {
+        attributes["event"] = json!(payload.event);
+        attributes["priority"] = json!(payload.priority);
+        attributes["timestamp"] = json!(payload.timestamp);
+    }
+    json!({ "state": state, "attributes": attributes })
+}
+
+impl DetectionSink for HomeAssistantSink {
+    fn name(&self) -> &'static str {
+        "homeassistant"
+    }
+
+    fn on_detection(&mut self, payload: &AlertPayload) -> Result<()> {
+        let path = format!("states/{}", self.entity);
+        self.api.post(&path, &state_body("on", Some(payload)))?;
+        if let Some(event) = &self.event {
+            self.api.post(&format!("events/{}", event), payload)?;
+        }
+
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let (api, latest, cooldown) = (self.api.clone(), self.generation.clone(), self.cooldown);
+        thread::spawn(move || {
+            sleep(cooldown);
+            if latest.load(Ordering::SeqCst) != generation {
+                return;
+            }
+            if let Err(e) = api.post(&path, &state_body("off", None)) {
+                warn!("Failed to clear Home Assistant state: {:#}", e);
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+    use crate::audio::{Detection, DetectionEvent};
+    use crate::config::test_config;
+    use crate::mock_http::MockServer;
+
+    // The sensor is turned on with the detection's attributes and the token as a bearer, then cleared after the gap.
+    #[test]
+    fn sets_and_clears_the_sensor_state() {
+        let server = MockServer::start(200);
+        let config = test_config(&[
+            ("ALARM_HA_TOKEN", "long-lived-token"),
+            ("ALARM_HA_ENTITY", "binary_sensor.panel"),
+            ("ALARM_MIN_GAP_MS", "100")
+        ]).unwrap();
+        let mut sink = HomeAssistantSink::new(&server.url, &config).unwrap();
+        let event = DetectionEvent {
+            detection: Detection::Trigger,
+            timestamp: SystemTime::now(),
+            audio: None,
+            contact_id: None,
+            fsk_data: None,
+            frequency: Some(1665.0),
+            offset: None,
+            level_dbfs: None,
+            caller: None
+        };
+        let payload = AlertPayload::from_event(&event, 8000);
+        sink.on_detection(&payload).unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/api/states/binary_sensor.panel");
+        assert_eq!(requests[0].header("authorization"), Some("Bearer long-lived-token"));
+        assert_eq!(requests[0].json(), json!({
+            "state": "on",
+            "attributes": {
+                "friendly_name": "Alarm modem",
+                "device_class": "safety",
+                "event": "trigger",
+                "priority": "normal",
+                "timestamp": payload.timestamp
+            }
+        }));
+
+        sleep(Duration::from_millis(400));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].path, "/api/states/binary_sensor.panel");
+        assert_eq!(requests[1].json()["state"], "off");
+    }
+}
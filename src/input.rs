@@ -0,0 +1,51 @@
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use anyhow::Result;
+use log::info;
+use crate::audio::{deliver, Detector, PcmFormat, SAMPLE_RATE};
+use crate::reload;
+use crate::sink::DetectionSink;
+use crate::stats::SessionStats;
+use crate::SHUTDOWN;
+
+// Audio from somewhere other than the modem's serial stream, already decoded to samples at the processing rate.
+pub(crate) trait AudioSource {
+    fn name(&self) -> &'static str;
+
+    // The next chunk of samples (possibly empty while waiting), or None once the source has ended.
+    fn next_samples(&mut self) -> Result<Option<Vec<i16>>>;
+
+    // The PCM format the samples were decoded from, which sets the full scale levels are measured against.
+    fn format(&self) -> PcmFormat {
+        PcmFormat::SignedLe16
+    }
+}
+
+// Run a source through the same detection and sinks as a call, until it ends or a shutdown is requested.
+pub(crate) fn run_source(
+    source: &mut dyn AudioSource,
+    detector: &mut Detector,
+    sinks: &mut [Box<dyn DetectionSink>],
+    stats: &mut SessionStats
+) -> Result<()> {
+    info!("Listening to {} input", source.name());
+    detector.set_caller(None);
+    detector.start_call();
+    while !SHUTDOWN.load(Ordering::Relaxed) {
+        reload::apply_pending(detector, sinks);
+        sinks.iter_mut().for_each(|sink| sink.tick(stats));
+        match source.next_samples()? {
+            Some(samples) if samples.is_empty() => continue,
+            Some(samples) => {
+                let started = Instant::now();
+                detector.set_format(source.format());
+                let events = detector.process(&samples);
+                stats.record_processing(started.elapsed(), Duration::from_secs_f32(samples.len() as f32 / SAMPLE_RATE));
+                deliver(events, sinks, stats);
+            },
+            None => break
+        }
+    }
+    deliver(detector.flush(), sinks, stats);
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use crate::config::Config;
+use crate::sink::{dispatch, DetectionSink};
+use crate::stats::SessionStats;
+use crate::webhook::AlertPayload;
+
+// Set on SIGUSR1, taken by the latch on the next detection so the reset happens outside the handler.
+static RESET: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_sigusr1(_: libc::c_int) {
+    RESET.store(true, Ordering::Relaxed);
+}
+
+#[cfg(unix)]
+pub(crate) fn install() -> Result<()> {
+    // Safety: the handler only stores to an atomic, which is async-signal-safe.
+    let previous = unsafe { libc::signal(libc::SIGUSR1, on_sigusr1 as extern "C" fn(libc::c_int) as libc::sighandler_t) };
+    if previous == libc::SIG_ERR {
+        return Err(anyhow!("Failed to install SIGUSR1 handler: {}", std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn install() -> Result<()> {
+    Err(anyhow!("ALARM_LATCH is reset with SIGUSR1, so is only supported on Unix"))
+}
+
+// Passes the first detection on to the sinks it wraps, then holds every later one back until reset with SIGUSR1, for
+// setups where one alarm should stand until someone acknowledges it. Detections are still logged and counted.
+pub(crate) struct LatchSink {
+    sinks: Vec<Box<dyn DetectionSink>>,
+    latched: bool,
+    suppressed: u64
+}
+
+impl LatchSink {
+    pub fn new(sinks: Vec<Box<dyn DetectionSink>>) -> Self {
+        LatchSink { sinks, latched: false, suppressed: 0 }
+    }
+}
+
+impl DetectionSink for LatchSink {
+    fn name(&self) -> &'static str {
+        "latch"
+    }
+
+    fn on_detection(&mut self, payload: &AlertPayload) -> Result<()> {
+        if RESET.swap(false, Ordering::Relaxed) && self.latched {
+            info!("Latch reset, {} detections were held back while it was set", self.suppressed);
+            self.latched = false;
+            self.suppressed = 0;
+        }
+        if self.latched {
+            self.suppressed += 1;
+            info!("Latched, holding back {} until reset with SIGUSR1", payload.event);
+            return Ok(());
+        }
+
+        // Latched even when a sink fails, the rest have still been told.
+        self.latched = true;
+        warn!("Latching on {}, further detections are held back until reset with SIGUSR1", payload.event);
+        let failed = dispatch(&mut self.sinks, payload);
+        if !failed.is_empty() {
+            return Err(anyhow!("{} failed to handle the latching detection", failed.join(", ")));
+        }
+        Ok(())
+    }
+
+    fn reload(&mut self, config: &Config) {
+        self.sinks.iter_mut().for_each(|sink| sink.reload(config));
+    }
+
+    // Heartbeats aren't detections, so keep going out while latched.
+    fn tick(&mut self, stats: &SessionStats) {
+        self.sinks.iter_mut().for_each(|sink| sink.tick(stats));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use crate::sink::CallbackSink;
+
+    fn payload(event: &'static str) -> AlertPayload {
+        AlertPayload {
+            event,
+            priority: "normal",
+            timestamp: 1_700_000_000,
+            offset_ms: None,
+            frequency_hz: None,
+            level_dbfs: None,
+            audio_wav_base64: None,
+            contact_id: None,
+            fsk_data_hex: None,
+            caller: None
+        }
+    }
+
+    #[test]
+    fn holds_detections_back_until_reset() {
+        let passed = Arc::new(Mutex::new(Vec::new()));
+        let noting = passed.clone();
+        let mut latch = LatchSink::new(vec![Box::new(CallbackSink(move |payload: &AlertPayload| {
+            noting.lock().unwrap().push(payload.event);
+        }))]);
+        for event in ["trigger", "panic", "trigger"] {
+            latch.on_detection(&payload(event)).unwrap();
+        }
+        assert_eq!(*passed.lock().unwrap(), ["trigger"]);
+        assert_eq!(latch.suppressed, 2);
+
+        // As the SIGUSR1 handler does, the next detection then goes through and latches again.
+        RESET.store(true, Ordering::Relaxed);
+        latch.on_detection(&payload("panic")).unwrap();
+        latch.on_detection(&payload("trigger")).unwrap();
+        assert_eq!(*passed.lock().unwrap(), ["trigger", "panic"]);
+        assert_eq!(latch.suppressed, 1);
+    }
+}
@@ -0,0 +1,648 @@
+mod analyze;
+mod answer;
+mod audio;
+mod breaker;
+mod cadence;
+mod calibrate;
+mod capabilities;
+mod cef;
+mod chipset;
+mod cid;
+mod cli;
+mod command;
+mod config;
+mod config_file;
+mod contact_id;
+mod dedup;
+mod dle;
+mod dtmf;
+#[cfg(unix)]
+mod fifo;
+#[cfg(feature = "tokio")]
+mod fleet;
+mod fsk;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod homeassistant;
+mod input;
+mod latch;
+mod limiter;
+mod lock;
+mod loopback;
+mod metrics;
+#[cfg(test)]
+mod mock_http;
+#[cfg(test)]
+mod mock_port;
+mod modem;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod payload_template;
+mod pipe;
+mod ports;
+mod profile;
+mod raw;
+#[cfg(feature = "realtime")]
+mod realtime;
+mod reload;
+mod replay;
+mod response;
+mod sequence;
+mod sink;
+mod snapshot;
+mod spectrum;
+mod spool;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod state;
+mod stats;
+mod stdout;
+#[cfg(all(unix, feature = "syslog"))]
+mod syslog;
+mod telemetry;
+mod template;
+mod trace;
+mod vad;
+mod watchdog;
+mod wav;
+mod webhook;
+#[cfg(feature = "websocket")]
+mod websocket;
+
+use std::fs;
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, sleep};
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Env;
+use log::{error, info, warn};
+use serialport::SerialPort;
+use crate::analyze::analyze;
+use crate::answer::authenticate;
+use crate::audio::{deliver, line_fault, listen};
+use crate::calibrate::calibrate_noise;
+use crate::cid::caller_allowed;
+use crate::cli::Cli;
+use crate::command::CommandSink;
+use crate::config::RunMode;
+use crate::config_file::{load_config, load_config_file};
+#[cfg(unix)]
+use crate::fifo::FifoSink;
+#[cfg(feature = "grpc")]
+use crate::grpc::GrpcSink;
+use crate::homeassistant::HomeAssistantSink;
+use crate::input::run_source;
+use crate::latch::LatchSink;
+use crate::loopback::{generated_tone_test, loopback_test};
+use crate::modem::{answer_call, hang_up, open_modem, reject_call, release_modem, send_command, send_commands, send_dtmf, wait_for_call, ModemPort};
+#[cfg(feature = "mqtt")]
+use crate::mqtt::MqttSink;
+use crate::ports::list_ports;
+use crate::profile::{load_profile, save_profile};
+use crate::raw::RawSource;
+use crate::replay::WavSource;
+use crate::sink::DetectionSink;
+use crate::snapshot::SpectrumSnapshot;
+use crate::spectrum::capture_spectrum;
+#[cfg(feature = "sqlite")]
+use crate::sqlite::{print_history, SqliteSink};
+use crate::stats::SessionStats;
+use crate::stdout::StdoutSink;
+#[cfg(all(unix, feature = "syslog"))]
+use crate::syslog::SyslogSink;
+use crate::telemetry::Span;
+use crate::template::learn;
+use crate::watchdog::Watchdog;
+use crate::webhook::{send_test, WebhookSink};
+#[cfg(feature = "websocket")]
+use crate::websocket::WebSocketSource;
+
+// The public API for embedding detection in another program, the binary itself being `run` on top of the same parts.
+pub use crate::audio::{detect_tone, high_pass_filter, Detection, DetectionEvent, Detector, PcmFormat};
+pub use crate::cadence::CallProgress;
+pub use crate::cid::CallerId;
+pub use crate::config::{from_env, Config, FrequencyBand, PowerUnit, ToneProfile};
+pub use crate::contact_id::{ContactIdEvent, Qualifier};
+#[cfg(feature = "tokio")]
+pub use crate::fleet::spawn_modems;
+pub use crate::modem::Modem;
+pub use crate::response::LineStatus;
+pub use crate::webhook::AlertPayload;
+
+const READ_TIMEOUT: Duration = Duration::from_millis(250);
+#[cfg(not(test))]
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+#[cfg(test)]
+const RECONNECT_DELAY: Duration = Duration::from_millis(1); // So the reconnect paths run through in a moment
+const EXIT_RECONNECT_EXHAUSTED: i32 = 3;
+const EXIT_LOOPBACK_FAILED: i32 = 4;
+const EXIT_SELFTEST_FAILED: i32 = 5;
+
+// Set on Ctrl-C, polled by the read loops so they can wind down cleanly.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+// Only returns on a modem error or shutdown, each call ends with the modem dropping back to command mode so re-enter VRX for the next one.
+fn run_session(
+    port: &mut dyn SerialPort,
+    format: PcmFormat,
+    detector: &mut Detector,
+    sinks: &mut [Box<dyn DetectionSink>],
+    stats: &mut SessionStats,
+    watchdog: &mut Watchdog
+) -> Result<()> {
+    while !SHUTDOWN.load(Ordering::Relaxed) {
+        // Fetched per call so a SIGHUP reload applies from the next one.
+        let config = detector.config();
+        // On a line shared with voice calls, wait to be rung rather than listening continuously.
+        let (mut caller, mut rings) = (None, 0);
+        if config.answer_after_rings > 0 {
+            info!("Waiting for a call");
+            let Some(incoming) = wait_for_call(port, config.answer_after_rings, config.caller_id)? else {
+                break;
+            };
+            rings = incoming.rings;
+
+            if config.caller_id {
+                caller = incoming.caller;
+                let number = caller.as_ref().map(|caller| caller.number.as_str());
+                // Screen the caller before answering, so spam and wrong numbers never reach detection.
+                if config.require_caller_id && !caller_allowed(number, &config.caller_allowlist) {
+                    info!("Caller {} is not allowed", number.unwrap_or("(no caller ID)"));
+                    reject_call(port)?;
+                    continue;
+                }
+                info!("Caller ID {}", number.unwrap_or("not received"));
+            }
+        }
+
+        // Traced from the ring (or entering VRX) until the call ends.
+        let call = Span::start("call");
+        call.set_str("answer", if config.answer_after_rings > 0 { "ring" } else { "listen" });
+        if config.answer_after_rings > 0 {
+            call.set_f64("rings", rings as f64);
+            answer_call(port)?;
+        }
+
+        info!("Connecting to VRX");
+        let response = send_commands(port, &["AT+VRX"])?.remove(0);
+        if response.result.as_deref() != Some("CONNECT") {
+            // The line is reported as the fault it is, rather than only as a failed session to retry.
+            let status = LineStatus::from_result(response.result.as_deref().unwrap_or_default());
+            if status != LineStatus::Healthy {
+                deliver(vec![line_fault(status, detector)], sinks, stats);
+            }
+            return Err(anyhow!("Failed to connect to VRX, got: {}", response.text()));
+        }
+
+        // Callers that aren't the panel won't know the PIN, so hang up on them before any detection.
+        if let Some(pin) = &config.answer_pin {
+            if !authenticate(port, pin, config.answer_pin_attempts, config.answer_pin_timeout)? {
+                hang_up(port)?;
+                continue;
+            }
+        }
+
+        info!("Listening...");
+        let _listen = Span::start("listen");
+        listen(port, format, detector, sinks, stats, watchdog, caller)?;
+    }
+    Ok(())
+}
+
+// Initialize the modem, hear the target tone through its loopback (or a generated one, if it can't loop back) and have
+// every webhook URL accept a test event.
+fn selftest(config: &Config, detector: &mut Detector) -> Result<()> {
+    let (mut port, format) = open_modem(config).context("Modem initialization failed")?;
+    detector.set_input_rate(port.rate());
+    selftest_initialized(config, &mut *port, format, detector)
+}
+
+// The self-test checks that follow a successful init.
+fn selftest_initialized(
+    config: &Config,
+    port: &mut dyn SerialPort,
+    format: PcmFormat,
+    detector: &mut Detector
+) -> Result<()> {
+    match loopback_test(port, format, detector) {
+        Ok(true) => info!("Tone detected through the modem's loopback"),
+        Ok(false) => return Err(anyhow!("The tone played through the modem's loopback was not detected")),
+        Err(e) => {
+            warn!("Modem loopback is unavailable ({:#}), checking detection with a generated tone", e);
+            match generated_tone_test(detector) {
+                Some(dbfs) => info!("Generated tone detected at {} dBFS", dbfs),
+                None => return Err(anyhow!("The generated {} Hz tone was never detected", detector.tone_frequency()))
+            }
+        }
+    }
+    let failed = send_test(config);
+    if !failed.is_empty() {
+        return Err(anyhow!("Webhook {} did not accept the test event", failed.join(", ")));
+    }
+    Ok(())
+}
+
+// Ask every read loop to wind down, as Ctrl-C does for the binary, e.g. to stop the modems of a fleet.
+pub fn shutdown() {
+    SHUTDOWN.store(true, Ordering::Relaxed);
+}
+
+pub(crate) fn report_stats(stats: &SessionStats, path: Option<&str>) {
+    let summary = stats.summary();
+    info!("{}", summary.trim_end());
+    if let Some(path) = path {
+        if let Err(e) = fs::write(path, &summary) {
+            error!("Failed to write session summary to {}: {}", path, e);
+        }
+    }
+}
+
+// Every sink the config enables around the given webhook sink, which is always first.
+pub(crate) fn build_sinks(config: &Config, webhook: WebhookSink) -> Result<Vec<Box<dyn DetectionSink>>> {
+    let mut sinks: Vec<Box<dyn DetectionSink>> = vec![Box::new(webhook)];
+    if let Some(command) = &config.on_detect_command {
+        sinks.push(Box::new(CommandSink::new(command)?));
+    }
+    if let Some(path) = &config.event_fifo {
+        #[cfg(unix)]
+        sinks.push(Box::new(FifoSink::new(path)));
+        #[cfg(not(unix))]
+        return Err(anyhow!("ALARM_EVENT_FIFO {} is only supported on Unix", path));
+    }
+    if let Some(format) = config.stdout_events {
+        sinks.push(Box::new(StdoutSink::new(format)));
+    }
+    if let Some(url) = &config.ha_url {
+        sinks.push(Box::new(HomeAssistantSink::new(url, config)?));
+    }
+    if let Some(broker) = &config.mqtt_broker {
+        #[cfg(feature = "mqtt")]
+        sinks.push(Box::new(MqttSink::new(broker, config)?));
+        #[cfg(not(feature = "mqtt"))]
+        return Err(anyhow!("ALARM_MQTT_BROKER {} needs a build with the mqtt feature", broker));
+    }
+    if config.syslog {
+        #[cfg(all(unix, feature = "syslog"))]
+        sinks.push(Box::new(SyslogSink::new(config)?));
+        #[cfg(not(all(unix, feature = "syslog")))]
+        return Err(anyhow!("ALARM_SYSLOG needs a build with the syslog feature, on Unix"));
+    }
+    if let Some(url) = &config.grpc_url {
+        #[cfg(feature = "grpc")]
+        sinks.push(Box::new(GrpcSink::new(url)?));
+        #[cfg(not(feature = "grpc"))]
+        return Err(anyhow!("ALARM_GRPC_URL {} needs a build with the grpc feature", url));
+    }
+    if let Some(path) = &config.db_path {
+        #[cfg(feature = "sqlite")]
+        sinks.push(Box::new(SqliteSink::new(path)?));
+        #[cfg(not(feature = "sqlite"))]
+        return Err(anyhow!("ALARM_DB_PATH {} needs a build with the sqlite feature", path));
+    }
+    // Sinks are still built so their config is checked, they just never see a detection.
+    if config.mode == RunMode::Observe {
+        info!("Observe mode, detections will be logged and counted without notifying any sinks");
+        sinks.clear();
+    }
+    if config.latch {
+        latch::install()?;
+        info!("Latch mode, only the first detection is delivered until reset with SIGUSR1");
+        sinks = vec![Box::new(LatchSink::new(sinks))];
+    }
+    Ok(sinks)
+}
+
+// Open the modem and run sessions on it until shutdown, reopening and reinitializing it whenever one fails. Returns an
+// error once the configured reconnect attempts have all failed.
+pub(crate) fn monitor(
+    config: &Config,
+    detector: &mut Detector,
+    sinks: &mut [Box<dyn DetectionSink>],
+    stats: &mut SessionStats,
+    watchdog: &mut Watchdog
+) -> Result<()> {
+    monitor_with(config, open_modem, detector, sinks, stats, watchdog)
+}
+
+// Doubling, so a modem that's been unplugged isn't reopened every few seconds for as long as it's gone.
+fn reconnect_delay(attempts: u32, max: Duration) -> Duration {
+    RECONNECT_DELAY.saturating_mul(1 << attempts.saturating_sub(1).min(16)).min(max.max(RECONNECT_DELAY))
+}
+
+fn monitor_with(
+    config: &Config,
+    mut open: impl FnMut(&Config) -> Result<(ModemPort, PcmFormat)>,
+    detector: &mut Detector,
+    sinks: &mut [Box<dyn DetectionSink>],
+    stats: &mut SessionStats,
+    watchdog: &mut Watchdog
+) -> Result<()> {
+    let mut attempts = 0;
+    while !SHUTDOWN.load(Ordering::Relaxed) {
+        let result = open(config).and_then(|(mut port, format)| {
+            attempts = 0;
+            watchdog.ready();
+            detector.set_input_rate(port.rate());
+            run_session(&mut *port, format, detector, sinks, stats, watchdog)?;
+            release_modem(&mut *port);
+            Ok(())
+        });
+
+        if let Err(e) = result {
+            attempts += 1;
+            error!("Modem session on {} failed: {:#}", config.modem_port, e);
+            if config.reconnect_max_attempts > 0 && attempts >= config.reconnect_max_attempts {
+                return Err(anyhow!("Giving up on {} after {} reconnect attempts", config.modem_port, attempts));
+            }
+
+            let delay = reconnect_delay(attempts, config.reconnect_max_delay);
+            info!("Reconnecting in {:#?} (attempt {})", delay, attempts);
+            stats.record_reconnect();
+            sleep(delay);
+        }
+    }
+    Ok(())
+}
+
+// Everything the binary does, from loading the config to the last session ending.
+pub fn run() -> Result<()> {
+    let cli = Cli::parse();
+    env_logger::init_from_env(Env::new().default_filter_or("info"));
+
+    // Listing ports is for setting up the config, so it must not require one.
+    if cli.list_ports {
+        return list_ports();
+    }
+
+    info!("Loading config");
+    cli.apply_to_env();
+    reload::snapshot_env();
+    dotenv().ok();
+    load_profile()?;
+    load_config_file()?;
+    let config = Arc::new(load_config()?);
+
+    // Reading back recorded detections needs nothing but the database.
+    if let Some(count) = cli.history {
+        let path = config.db_path.as_deref().ok_or_else(|| anyhow!("--history needs ALARM_DB_PATH"))?;
+        #[cfg(feature = "sqlite")]
+        return print_history(path, count);
+        #[cfg(not(feature = "sqlite"))]
+        return Err(anyhow!("Printing the last {} detections in {} needs a build with the sqlite feature", count, path));
+    }
+    telemetry::init()?;
+    reload::install()?;
+
+    ctrlc::set_handler(|| {
+        info!("Shutting down");
+        shutdown();
+    }).context("Failed to install shutdown handler")?;
+
+    let mut detector = Detector::new(config.clone())?;
+    if let Some(addr) = &config.spectrum_addr {
+        let snapshot = SpectrumSnapshot::new(config.spectrum_bins);
+        snapshot::serve(addr, snapshot.clone())?;
+        detector.publish_spectrum(snapshot);
+    }
+
+    // Play a DTMF sequence down the line, useful for checking the modem can transmit.
+    if let Some(digits) = &cli.send_dtmf {
+        let (mut port, _) = open_modem(&config)?;
+        return send_dtmf(&mut *port, digits);
+    }
+
+    // One-off calibration pass against the idle line, printing recommended thresholds.
+    if let Some(secs) = cli.calibrate_noise {
+        let (mut port, format) = open_modem(&config)?;
+        detector.set_input_rate(port.rate());
+        if send_command(&mut *port, "AT+VRX")? != "CONNECT" {
+            return Err(anyhow!("Failed to connect to VRX"));
+        }
+        let (min_power, max_power) = calibrate_noise(&mut *port, format, &mut detector, Duration::from_secs(secs))?;
+        if let Some(name) = &cli.save_profile {
+            save_profile(name, &[
+                ("ALARM_POWER_UNIT", config.power_unit.name().to_string()),
+                ("ALARM_TONE_MIN_POWER", min_power.to_string()),
+                ("ALARM_TONE_MAX_POWER", max_power.to_string())
+            ])?;
+        }
+        return Ok(());
+    }
+
+    // One-off look at where the line's energy is, for when the tone isn't being detected.
+    if let Some(secs) = cli.spectrum {
+        let (mut port, format) = open_modem(&config)?;
+        detector.set_input_rate(port.rate());
+        if send_command(&mut *port, "AT+VRX")? != "CONNECT" {
+            return Err(anyhow!("Failed to connect to VRX"));
+        }
+        return capture_spectrum(&mut *port, format, &mut detector, Duration::from_secs(secs));
+    }
+
+    // Learn a tone template from recordings of the panel's genuine transmissions.
+    if let Some([output, recordings @ ..]) = cli.learn.as_deref() {
+        return learn(output, recordings, &mut detector);
+    }
+
+    // Per-block CSV of how a recording would be judged, for tuning thresholds against a whole captured call.
+    if let Some(path) = &cli.analyze {
+        return analyze(path, &mut detector);
+    }
+
+    // Self-test the whole receive path by looping the modem's own transmitted tone back into the detector.
+    if cli.loopback_test {
+        let (mut port, format) = open_modem(&config)?;
+        detector.set_input_rate(port.rate());
+        if !loopback_test(&mut *port, format, &mut detector)? {
+            println!("Loopback test FAILED: the tone was not detected");
+            exit(EXIT_LOOPBACK_FAILED);
+        }
+        println!("Loopback test passed");
+        return Ok(());
+    }
+
+    // The whole pipeline end to end, for gating the service on with an ExecStartPre.
+    if cli.selftest {
+        if let Err(e) = selftest(&config, &mut detector) {
+            println!("Self-test FAILED: {:#}", e);
+            exit(EXIT_SELFTEST_FAILED);
+        }
+        println!("Self-test passed");
+        return Ok(());
+    }
+
+    // A bounded run, for CI and scheduled captures, winds down as Ctrl-C would once its time is up.
+    if let Some(duration) = config.run_duration {
+        thread::spawn(move || {
+            sleep(duration);
+            info!("Run duration of {} reached, shutting down", humantime::format_duration(duration));
+            shutdown();
+        });
+    }
+
+    let mut sinks = build_sinks(&config, WebhookSink::new(&config))?;
+
+    // Raised before any read loop starts, they all run on this thread.
+    if let Some(priority) = config.realtime_priority {
+        #[cfg(feature = "realtime")]
+        realtime::raise(priority);
+        #[cfg(not(feature = "realtime"))]
+        return Err(anyhow!("ALARM_REALTIME_PRIORITY {} needs a build with the realtime feature", priority));
+    }
+
+    // Reopen and reinitialize the modem whenever the session fails, giving up after the configured attempts.
+    let mut stats = SessionStats::new(metrics::from_config(&config)?);
+
+    // Replay a recording through detection and the sinks in place of a call, in real time so timing behaves as live.
+    if let Some(path) = &cli.replay {
+        let mut source = WavSource::open(path, true)?;
+        run_source(&mut source, &mut detector, &mut sinks, &mut stats)?;
+        report_stats(&stats, config.stats_file.as_deref());
+        telemetry::shutdown();
+        return Ok(());
+    }
+
+    // Replay the modem's byte stream as captured with ALARM_CAPTURE_RAW, DLE codes and all, to reproduce a call.
+    if let Some(path) = &cli.replay_raw {
+        let mut source = RawSource::open(path, config.clone(), true)?;
+        run_source(&mut source, &mut detector, &mut sinks, &mut stats)?;
+        report_stats(&stats, config.stats_file.as_deref());
+        telemetry::shutdown();
+        return Ok(());
+    }
+
+    // A remote modem behind an audio WebSocket bridge replaces the serial port entirely.
+    if let Some(url) = &config.websocket_url {
+        #[cfg(feature = "websocket")]
+        {
+            let mut source = WebSocketSource::new(url, audio::Capture::new(config.websocket_format, &config));
+            run_source(&mut source, &mut detector, &mut sinks, &mut stats)?;
+            report_stats(&stats, config.stats_file.as_deref());
+            telemetry::shutdown();
+            return Ok(());
+        }
+        #[cfg(not(feature = "websocket"))]
+        return Err(anyhow!("ALARM_WS_URL {} needs a build with the websocket feature", url));
+    }
+
+    let mut watchdog = Watchdog::new();
+    if let Err(e) = monitor(&config, &mut detector, &mut sinks, &mut stats, &mut watchdog) {
+        error!("{:#}", e);
+        report_stats(&stats, config.stats_file.as_deref());
+        telemetry::shutdown();
+        exit(EXIT_RECONNECT_EXHAUSTED);
+    }
+    report_stats(&stats, config.stats_file.as_deref());
+    telemetry::shutdown();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::mock_http::MockServer;
+    use crate::mock_port::MockPort;
+
+    // A modem that can't loop back has detection checked with a generated tone instead, and the self-test still
+    // fails when the webhook doesn't accept its event.
+    #[test]
+    fn selftest_falls_back_to_a_generated_tone_and_checks_the_webhook() {
+        let server = MockServer::start(200);
+        for (url, accepted) in [(server.url.as_str(), true), ("http://127.0.0.1:9/", false)] {
+            let config = Arc::new(test_config(&[("ALARM_WEBHOOK_URL", url)]).unwrap());
+            let mut detector = Detector::new(config.clone()).unwrap();
+            let mut port = MockPort::answering(|_| vec!["\r\nERROR\r\n"]);
+            let result = selftest_initialized(&config, &mut port, PcmFormat::SignedLe16, &mut detector);
+            assert_eq!(port.written_text(), "AT&T1\r", "went on past the failed loopback");
+            match result {
+                Ok(()) => assert!(accepted, "passed with {} refusing the test event", url),
+                Err(e) => {
+                    assert!(!accepted, "{:#}", e);
+                    assert_eq!(e.to_string(), format!("Webhook {} did not accept the test event", url));
+                }
+            }
+        }
+        assert_eq!(server.requests()[0].json()["event"], "self_test");
+    }
+
+    // A modem that never opens is given up on once the configured attempts have all failed.
+    #[test]
+    fn gives_up_after_the_reconnect_attempts() {
+        let config = test_config(&[("ALARM_RECONNECT_MAX_ATTEMPTS", "3")]).unwrap();
+        let mut detector = Detector::new(Arc::new(test_config(&[]).unwrap())).unwrap();
+        let mut stats = SessionStats::new(None);
+        let mut opened = 0;
+        let open = |_: &Config| {
+            opened += 1;
+            Err(anyhow!("No such device"))
+        };
+        let result = monitor_with(&config, open, &mut detector, &mut [], &mut stats, &mut Watchdog::new());
+        assert_eq!(result.unwrap_err().to_string(), "Giving up on /dev/null after 3 reconnect attempts");
+        assert_eq!(opened, 3);
+    }
+
+    #[test]
+    fn reconnect_delays_double_up_to_the_limit() {
+        let max = RECONNECT_DELAY * 5;
+        let delays: Vec<_> = [1, 2, 3, 4, 40].into_iter().map(|attempts| reconnect_delay(attempts, max)).collect();
+        assert_eq!(delays, [1, 2, 4, 5, 5].map(|n| RECONNECT_DELAY * n));
+        // A limit below the first delay still waits that long.
+        assert_eq!(reconnect_delay(3, Duration::ZERO), RECONNECT_DELAY);
+    }
+
+    // Observe mode detects and counts as usual, but nothing hears of it: no webhook, recording or PCM pipe.
+    #[test]
+    fn observe_mode_counts_detections_without_side_effects() {
+        let server = MockServer::start(200);
+        let dir = std::env::temp_dir().join(format!("alarm-modem-{}-observed", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let config = Arc::new(test_config(&[
+            ("ALARM_MODE", "observe"),
+            ("ALARM_WEBHOOK_URL", &server.url),
+            ("ALARM_RECORD_DIR", dir.to_str().unwrap()),
+            ("ALARM_PCM_PIPE_CMD", "cat"),
+            ("ALARM_TONE_MIN_POWER", "1e6"),
+            ("ALARM_TONE_MAX_POWER", "1e20"),
+            ("ALARM_ANSWER_TONE_MIN_POWER", "1e10"),
+            ("ALARM_MIN_GAP_MS", "100")
+        ]).unwrap());
+        let mut sinks = build_sinks(&config, WebhookSink::new(&config)).unwrap();
+        let mut detector = Detector::new(config).unwrap();
+        let mut stats = SessionStats::new(None);
+        let samples: Vec<i16> = (0..4096)
+            .map(|i| (1000.0 * (2.0 * std::f32::consts::PI * 1665.0 * i as f32 / 8000.0).sin()) as i16)
+            .collect();
+        deliver(detector.process(&samples), &mut sinks, &mut stats);
+
+        assert!(stats.summary().contains("    trigger: 1"), "{}", stats.summary());
+        assert!(server.requests().is_empty(), "the webhook was sent");
+        assert!(!dir.exists(), "the recording was saved");
+    }
+
+    // A caller off the allowlist is turned away before answering, and the next call, from the panel, is answered and
+    // goes on to VRX (which this modem refuses, ending the session).
+    #[test]
+    fn screens_callers_before_answering() {
+        let settings = [("ALARM_ANSWER_AFTER_RINGS", "1"), ("ALARM_CALLER_ALLOWLIST", "555-1234")];
+        let config = Arc::new(test_config(&settings).unwrap());
+        let mut port = MockPort::answering(|line| match line {
+            "ATH1" => vec!["\r\nOK\r\n"],
+            "ATH0" => vec!["\r\nOK\r\n", "RING\r\nNMBR = 5551234\r\nNAME = PANEL\r\n"],
+            "ATA" => vec!["\r\nVCON\r\n"],
+            _ => vec!["\r\nERROR\r\n"]
+        });
+        port.reads.push_back(Ok(b"RING\r\nNMBR = 5559999\r\nNAME = SPAM\r\n".to_vec()));
+        let mut detector = Detector::new(config).unwrap();
+        let result = run_session(
+            &mut port, PcmFormat::SignedLe16, &mut detector, &mut [], &mut SessionStats::new(None), &mut Watchdog::new()
+        );
+        assert!(result.unwrap_err().to_string().starts_with("Failed to connect to VRX"));
+        assert_eq!(port.written_text(), "ATH1\rATH0\rATA\rAT+VRX\r");
+        assert!(!caller_allowed(None, &["5551234".to_string()]), "a call without caller ID was allowed");
+    }
+}
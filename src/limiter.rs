@@ -0,0 +1,63 @@
+use std::time::{Duration, Instant};
+use log::{info, warn};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+// Token bucket holding up to `capacity` deliveries, refilled continuously over a minute.
+pub(crate) struct RateLimiter {
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+    dropped: u64,
+    dropped_total: u64
+}
+
+impl RateLimiter {
+    pub fn per_minute(max_events: u32) -> Self {
+        RateLimiter {
+            capacity: max_events as f64,
+            tokens: max_events as f64,
+            last_refill: Instant::now(),
+            dropped: 0,
+            dropped_total: 0
+        }
+    }
+
+    fn refill(&mut self) {
+        let elapsed = self.last_refill.elapsed().as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.capacity / WINDOW.as_secs_f64()).min(self.capacity);
+        self.last_refill = Instant::now();
+    }
+
+    // Take a token for an event, returning false (and counting the drop) if the bucket is empty.
+    pub fn try_acquire(&mut self) -> bool {
+        self.refill();
+        if self.tokens < 1.0 {
+            self.dropped += 1;
+            self.dropped_total += 1;
+            warn!("Event rate limit reached, dropping event ({} dropped in total)", self.dropped_total);
+            return false;
+        }
+
+        if self.dropped > 0 {
+            info!("Rate limit recovered, {} events were dropped since the last delivery", self.dropped);
+            self.dropped = 0;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_and_counts_events_past_the_limit() {
+        let mut limiter = RateLimiter::per_minute(3);
+        assert!((0..3).all(|_| limiter.try_acquire()));
+        assert!(!limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+        assert_eq!((limiter.dropped, limiter.dropped_total), (2, 2));
+    }
+}
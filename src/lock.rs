@@ -0,0 +1,94 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+
+// A UUCP style `LCK..<device>` lock file holding the owner's PID, the convention minicom, ModemManager and the
+// getty family honour. Removed again when dropped.
+pub(crate) struct PortLock {
+    path: PathBuf
+}
+
+#[cfg(unix)]
+fn process_alive(pid: i32) -> bool {
+    // A PID of 0 or below would address a process group rather than the owner.
+    if pid <= 0 {
+        return false;
+    }
+    // Safety: signal 0 sends nothing, it only checks the process exists. EPERM means it does but isn't ours.
+    let signalled = unsafe { libc::kill(pid, 0) } == 0;
+    signalled || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_alive(_pid: i32) -> bool {
+    true
+}
+
+impl PortLock {
+    pub fn acquire(port: &str, dir: &str) -> Result<Self> {
+        let device = Path::new(port).file_name().and_then(|name| name.to_str()).unwrap_or(port);
+        let path = Path::new(dir).join(format!("LCK..{}", device));
+        // A second attempt only happens after clearing a stale lock, a live one fails the first.
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    // HDB UUCP format, the PID as ten ASCII digits and a newline.
+                    writeln!(file, "{:>10}", process::id()).with_context(|| format!("Failed to write lock file {}", path.display()))?;
+                    info!("Locked {} with {}", port, path.display());
+                    return Ok(PortLock { path });
+                },
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    let owner = fs::read_to_string(&path).ok().and_then(|contents| contents.trim().parse::<i32>().ok());
+                    match owner {
+                        Some(pid) if process_alive(pid) => {
+                            return Err(anyhow!("Serial port {} is in use by process {} (lock file {})", port, pid, path.display()));
+                        },
+                        _ => {
+                            warn!("Removing stale lock file {}", path.display());
+                            fs::remove_file(&path).with_context(|| format!("Failed to remove stale lock file {}", path.display()))?;
+                        }
+                    }
+                },
+                Err(e) => return Err(anyhow!(e).context(format!("Failed to create lock file {}", path.display())))
+            }
+        }
+        Err(anyhow!("Serial port {} is in use, lock file {} keeps reappearing", port, path.display()))
+    }
+}
+
+impl Drop for PortLock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove lock file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_second_lock_on_the_port_fails_until_released() {
+        let dir = std::env::temp_dir().join(format!("alarm-modem-{}-locks", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_str().unwrap();
+        let lock = PortLock::acquire("/dev/ttyACM0", dir).unwrap();
+        let contents = fs::read_to_string(Path::new(dir).join("LCK..ttyACM0")).unwrap();
+        assert_eq!(contents, format!("{:>10}\n", process::id()));
+
+        let error = PortLock::acquire("/dev/ttyACM0", dir).err().unwrap().to_string();
+        assert!(error.contains("Serial port /dev/ttyACM0 is in use by process"), "{}", error);
+        drop(lock);
+        drop(PortLock::acquire("/dev/ttyACM0", dir).unwrap());
+
+        // A lock left by a process that's gone is taken over.
+        fs::write(Path::new(dir).join("LCK..ttyACM0"), "         0\n").unwrap();
+        drop(PortLock::acquire("/dev/ttyACM0", dir).unwrap());
+        assert!(!Path::new(dir).join("LCK..ttyACM0").exists());
+        fs::remove_dir(dir).unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+use std::f32::consts::TAU;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serialport::SerialPort;
+use crate::audio::{Detector, PcmFormat, FFT_SAMPLE_SIZE, SAMPLE_RATE};
+use crate::dle::{escape, DleDecoder, DLE, ETX};
+use crate::modem::send_command;
+
+const TONE_AMPLITUDE: f32 = 8000.0;
+#[cfg(not(test))]
+const LOOPBACK_TIMEOUT: Duration = Duration::from_secs(5);
+#[cfg(test)]
+const LOOPBACK_TIMEOUT: Duration = Duration::from_millis(200); // So a failing test gives up in a moment
+const GENERATED_RANGE_DB: u32 = 90; // How far below full scale the generated tone is tried, near a 16 bit floor
+const REQUIRED_BLOCKS: usize = 3; // Blocks the tone must be heard in, so one noisy block can't pass the test
+
+// Generates the tone one block at a time, carrying the phase between blocks so there are no clicks.
+struct ToneGenerator {
+    step: f32,
+    phase: f32,
+    amplitude: f32
+}
+
+impl ToneGenerator {
+    fn new(frequency: f32, rate: f32, amplitude: f32) -> Self {
+        ToneGenerator { step: TAU * frequency / rate, phase: 0.0, amplitude }
+    }
+
+    fn next_block(&mut self, len: usize) -> Vec<i16> {
+        (0..len).map(|_| {
+            let sample = (self.phase.sin() * self.amplitude) as i16;
+            self.phase = (self.phase + self.step) % TAU;
+            sample
+        }).collect()
+    }
+}
+
+// Loop the modem's transmit path back into its receive path with AT&T1, play the target tone and check the detector hears it.
+// Returns whether the tone was detected within the timeout.
+pub(crate) fn loopback_test(port: &mut dyn SerialPort, format: PcmFormat, detector: &mut Detector) -> Result<bool> {
+    // The tone is generated at the rate the modem negotiated, for transmit as for receive.
+    let rate = detector.input_rate();
+    let frequency = detector.tone_frequency();
+    info!("Running loopback self-test with a {} Hz tone", frequency);
+
+    let response = send_command(port, "AT&T1")?;
+    if response != "OK" {
+        return Err(anyhow!("Command AT&T1 expected OK, instead got: {response}"));
+    }
+    // Full duplex voice, so the tone we transmit comes straight back in on the receive side.
+    let response = send_command(port, "AT+VTR")?;
+    if response != "CONNECT" {
+        return Err(anyhow!("Command AT+VTR expected CONNECT, instead got: {response}"));
+    }
+
+    let mut generator = ToneGenerator::new(frequency, rate as f32, TONE_AMPLITUDE);
+    let block = FFT_SAMPLE_SIZE * rate as usize / SAMPLE_RATE as usize;
+    let mut dle = DleDecoder::new(detector.config().strip_xon_xoff);
+    let mut audio = Vec::new();
+    let mut capture = detector.capture(format);
+    let mut buffer = vec![0; 1024];
+    let mut found = 0;
+    let start = Instant::now();
+    while found < REQUIRED_BLOCKS && start.elapsed() < LOOPBACK_TIMEOUT {
+        port.write_all(&escape(&format.encode(&generator.next_block(block))))?;
+
+        match port.read(&mut buffer) {
+            Ok(n) => {
+                dle.feed(&buffer[..n], &mut audio);
+                let samples = capture.take_samples(&mut audio);
+                found += detector.tone_blocks(&samples);
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e.into())
+        }
+    }
+
+    // Leave full duplex and take the modem back out of loopback, the result stands either way.
+    port.write_all(&[DLE, ETX])?;
+    if let Err(e) = send_command(port, "AT&T0") {
+        warn!("Failed to leave loopback mode: {:#}", e);
+    }
+    Ok(found >= REQUIRED_BLOCKS)
+}
+
+// For modems that can't loop back: generate the target tone in memory and check the detector hears it at some level,
+// stepping down from full scale a dB at a time as the thresholds only pass a window of levels. This proves the band,
+// thresholds and filters can pass the tone, though not the modem's audio path. Returns the level in dBFS it was heard at.
+pub(crate) fn generated_tone_test(detector: &mut Detector) -> Option<f32> {
+    let decimation = detector.config().decimation;
+    let frequency = detector.tone_frequency();
+    info!("Running self-test with a generated {} Hz tone", frequency);
+    (0..=GENERATED_RANGE_DB).map(|db| -(db as f32)).find(|&dbfs| {
+        let amplitude = i16::MAX as f32 * 10f32.powf(dbfs / 20.0);
+        let mut generator = ToneGenerator::new(frequency, SAMPLE_RATE * decimation as f32, amplitude);
+        // A block over for the filters to settle, from the previous level or from silence.
+        detector.tone_blocks(&generator.next_block((REQUIRED_BLOCKS + 1) * FFT_SAMPLE_SIZE * decimation)) >= REQUIRED_BLOCKS
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::config::test_config;
+    use crate::mock_port::MockPort;
+
+    // A modem that answers the loopback commands and sends back what was transmitted, or only silence when the loop
+    // is broken.
+    fn looped_modem(looped: bool) -> MockPort {
+        let mut port = MockPort::new();
+        port.responder = Some(Box::new(move |written: &[u8]| match written {
+            b"AT+VTR\r" => vec![b"\r\nCONNECT\r\n".to_vec()],
+            command if command.starts_with(b"AT") => vec![b"\r\nOK\r\n".to_vec()],
+            audio if looped => vec![audio.to_vec()],
+            audio => vec![vec![0; audio.len()]]
+        }));
+        port
+    }
+
+    #[test]
+    fn passes_only_when_the_tone_comes_back() {
+        let settings = [("ALARM_TONE_MIN_POWER", "1e6"), ("ALARM_TONE_MAX_POWER", "1e20")];
+        for looped in [true, false] {
+            let mut detector = Detector::new(Arc::new(test_config(&settings).unwrap())).unwrap();
+            let mut port = looped_modem(looped);
+            assert_eq!(loopback_test(&mut port, PcmFormat::SignedLe16, &mut detector).unwrap(), looped);
+            assert!(port.written_text().ends_with("AT&T0\r"), "left the modem in loopback");
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write as _};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info};
+use crate::config::{Config, MetricsBackend};
+
+const METRIC_PREFIX: &str = "alarm_modem";
+const PROMETHEUS_PREFIX: &str = "alarm";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Where session counters are exported as they're recorded, in addition to the shutdown summary.
+pub(crate) trait Metrics {
+    fn count(&mut self, name: &str, value: u64);
+
+    fn timing(&mut self, name: &str, duration: Duration);
+
+    fn gauge(&mut self, name: &str, value: f64);
+}
+
+// Sends each metric as a StatsD line over UDP, e.g. `alarm_modem.detections.trigger:1|c`. Delivery is best effort.
+pub(crate) struct StatsdMetrics {
+    socket: UdpSocket
+}
+
+impl StatsdMetrics {
+    pub fn connect(addr: &str) -> Result<Self> {
+        let target = addr.to_socket_addrs()
+            .with_context(|| format!("Failed to resolve StatsD address {}", addr))?
+            .next()
+            .with_context(|| format!("StatsD address {} did not resolve", addr))?;
+        let bind = if target.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+        let socket = UdpSocket::bind(bind).context("Failed to bind StatsD socket")?;
+        socket.connect(target).with_context(|| format!("Failed to connect StatsD socket to {}", target))?;
+
+        info!("Sending metrics to StatsD at {}", target);
+        Ok(StatsdMetrics { socket })
+    }
+}
+
+fn statsd_line(name: &str, value: impl Display, kind: &str) -> String {
+    format!("{}.{}:{}|{}", METRIC_PREFIX, name, value, kind)
+}
+
+impl StatsdMetrics {
+    fn send(&self, name: &str, line: String) {
+        if let Err(e) = self.socket.send(line.as_bytes()) {
+            debug!("Failed to send StatsD metric {}: {}", name, e);
+        }
+    }
+}
+
+impl Metrics for StatsdMetrics {
+    fn count(&mut self, name: &str, value: u64) {
+        self.send(name, statsd_line(name, value, "c"));
+    }
+
+    // Timers in milliseconds, which StatsD aggregates into mean, max and percentiles.
+    fn timing(&mut self, name: &str, duration: Duration) {
+        self.send(name, statsd_line(name, format!("{:.3}", duration.as_secs_f64() * 1000.0), "ms"));
+    }
+
+    fn gauge(&mut self, name: &str, value: f64) {
+        self.send(name, statsd_line(name, value, "g"));
+    }
+}
+
+// One metric's type and the value of each of its series, keyed by the series as exposed, labels included.
+struct Family {
+    kind: &'static str,
+    series: BTreeMap<String, f64>
+}
+
+// Totals kept for Prometheus to scrape, shared between the session and the HTTP thread serving them.
+#[derive(Clone, Default)]
+pub(crate) struct PrometheusMetrics {
+    families: Arc<Mutex<BTreeMap<String, Family>>>
+}
+
+// The Prometheus metric and series for a counter, e.g. `detections.trigger` counts into
+// `alarm_tones_detected_total{event="trigger"}`.
+fn prometheus_counter(name: &str) -> (String, String) {
+    let (metric, label) = match name.split_once('.') {
+        Some(("detections", label)) => ("tones_detected", Some(("event", label))),
+        Some(("sink_failures", "webhook")) => ("webhook_failures", None),
+        Some(("sink_failures", sink)) => ("sink_failures", Some(("sink", sink))),
+        _ if name == "reconnects" => ("serial_reconnects", None),
+        _ => (name, None)
+    };
+    let metric = format!("{}_{}_total", PROMETHEUS_PREFIX, metric.replace('.', "_"));
+    let series = match label {
+        Some((key, value)) => format!("{}{{{}=\"{}\"}}", metric, key, value),
+        None => metric.clone()
+    };
+    (metric, series)
+}
+
+impl PrometheusMetrics {
+    fn update(&self, metric: String, kind: &'static str, series: String, update: impl FnOnce(&mut f64)) {
+        if let Ok(mut families) = self.families.lock() {
+            let family = families.entry(metric).or_insert_with(|| Family { kind, series: BTreeMap::new() });
+            update(family.series.entry(series).or_default());
+        }
+    }
+
+    // The text exposition format, a `# TYPE` line heading each metric's series.
+    fn render(&self) -> String {
+        let mut text = String::new();
+        if let Ok(families) = self.families.lock() {
+            for (metric, family) in families.iter() {
+                let _ = writeln!(text, "# TYPE {} {}", metric, family.kind);
+                for (series, value) in &family.series {
+                    let _ = writeln!(text, "{} {}", series, value);
+                }
+            }
+        }
+        text
+    }
+}
+
+impl Metrics for PrometheusMetrics {
+    fn count(&mut self, name: &str, value: u64) {
+        let (metric, series) = prometheus_counter(name);
+        self.update(metric, "counter", series, |total| *total += value as f64);
+    }
+
+    // A summary of the count and total seconds, from which Prometheus rates give the mean.
+    fn timing(&mut self, name: &str, duration: Duration) {
+        let metric = format!("{}_{}_seconds", PROMETHEUS_PREFIX, name);
+        self.update(metric.clone(), "summary", format!("{}_sum", metric), |sum| *sum += duration.as_secs_f64());
+        self.update(metric.clone(), "summary", format!("{}_count", metric), |count| *count += 1.0);
+    }
+
+    fn gauge(&mut self, name: &str, value: f64) {
+        let metric = format!("{}_{}", PROMETHEUS_PREFIX, name);
+        self.update(metric.clone(), "gauge", metric, |gauge| *gauge = value);
+    }
+}
+
+fn respond(mut stream: TcpStream, metrics: &PrometheusMetrics) -> Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+
+    let (status, body) = match path {
+        "/metrics" => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", "Not found\n".to_string())
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body
+    )?;
+    Ok(())
+}
+
+// Serve the metrics at `/metrics` on a background thread, one short-lived connection per scrape.
+fn serve(addr: &str, metrics: PrometheusMetrics) -> Result<()> {
+    let listener = TcpListener::bind(addr).with_context(|| format!("Failed to bind metrics server to {}", addr))?;
+    info!("Serving Prometheus metrics at http://{}/metrics", listener.local_addr()?);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(stream, &metrics) {
+                debug!("Failed to answer metrics request: {:#}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+// One backend fed by several sessions, e.g. every modem of a fleet, so their counts add up in the same registry.
+#[cfg(feature = "tokio")]
+#[derive(Clone)]
+pub(crate) struct SharedMetrics(pub Arc<Mutex<Box<dyn Metrics + Send>>>);
+
+#[cfg(feature = "tokio")]
+impl Metrics for SharedMetrics {
+    fn count(&mut self, name: &str, value: u64) {
+        if let Ok(mut metrics) = self.0.lock() {
+            metrics.count(name, value);
+        }
+    }
+
+    fn timing(&mut self, name: &str, duration: Duration) {
+        if let Ok(mut metrics) = self.0.lock() {
+            metrics.timing(name, duration);
+        }
+    }
+
+    fn gauge(&mut self, name: &str, value: f64) {
+        if let Ok(mut metrics) = self.0.lock() {
+            metrics.gauge(name, value);
+        }
+    }
+}
+
+pub(crate) fn from_config(config: &Config) -> Result<Option<Box<dyn Metrics + Send>>> {
+    match config.metrics_backend {
+        MetricsBackend::None => Ok(None),
+        MetricsBackend::Statsd => {
+            let addr = config.statsd_addr.as_deref().ok_or_else(|| anyhow!("ALARM_STATSD_ADDR is required for the statsd metrics backend"))?;
+            Ok(Some(Box::new(StatsdMetrics::connect(addr)?)))
+        },
+        MetricsBackend::Prometheus => {
+            let addr = config.metrics_addr.as_deref()
+                .ok_or_else(|| anyhow!("ALARM_METRICS_ADDR is required for the prometheus metrics backend"))?;
+            let mut metrics = PrometheusMetrics::default();
+            // Exposed from the start, so an alert on their increase has a series to work with before the first one.
+            metrics.count("reconnects", 0);
+            metrics.count("sink_failures.webhook", 0);
+            serve(addr, metrics.clone())?;
+            Ok(Some(Box::new(metrics)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sends_a_statsd_packet_per_metric() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let mut metrics = StatsdMetrics::connect(&receiver.local_addr().unwrap().to_string()).unwrap();
+        metrics.count("detections.trigger", 1);
+        metrics.timing("block_processing", Duration::from_micros(1500));
+
+        let mut packet = [0; 512];
+        let mut receive = || {
+            let n = receiver.recv(&mut packet).unwrap();
+            String::from_utf8_lossy(&packet[..n]).into_owned()
+        };
+        assert_eq!(receive(), "alarm_modem.detections.trigger:1|c");
+        assert_eq!(receive(), "alarm_modem.block_processing:1.500|ms");
+    }
+
+    #[test]
+    fn renders_exposition_format() {
+        let mut metrics = PrometheusMetrics::default();
+        metrics.count("detections.trigger", 1);
+        metrics.count("detections.trigger", 2);
+        metrics.count("sink_failures.webhook", 1);
+        metrics.count("sink_failures.mqtt", 1);
+        metrics.count("reconnects", 0);
+        metrics.timing("block_processing", Duration::from_millis(250));
+        metrics.gauge("last_tone_frequency_hz", 1665.5);
+        assert_eq!(metrics.render(), "\
+# TYPE alarm_block_processing_seconds summary
+alarm_block_processing_seconds_count 1
+alarm_block_processing_seconds_sum 0.25
+# TYPE alarm_last_tone_frequency_hz gauge
+alarm_last_tone_frequency_hz 1665.5
+# TYPE alarm_serial_reconnects_total counter
+alarm_serial_reconnects_total 0
+# TYPE alarm_sink_failures_total counter
+alarm_sink_failures_total{sink=\"mqtt\"} 1
+# TYPE alarm_tones_detected_total counter
+alarm_tones_detected_total{event=\"trigger\"} 3
+# TYPE alarm_webhook_failures_total counter
+alarm_webhook_failures_total 1
+");
+    }
+}
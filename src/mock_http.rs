@@ -0,0 +1,100 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// A request as the mock server received it, headers lowercased.
+#[derive(Debug, Clone)]
+pub(crate) struct Request {
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).expect("request body is not JSON")
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    requests: Mutex<Vec<Request>>,
+    status: AtomicU16,
+    connections: AtomicUsize
+}
+
+// An HTTP server for tests on a port of its own, recording each request and answering them all with one status.
+pub(crate) struct MockServer {
+    pub url: String,
+    shared: Arc<Shared>
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> Option<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok().filter(|&n| n > 0)?;
+    let path = line.split_whitespace().nth(1).unwrap_or_default().to_string();
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).ok().filter(|&n| n > 0)?;
+        let Some((key, value)) = line.trim_end().split_once(':') else {
+            break;
+        };
+        headers.push((key.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+    let len = headers.iter().find(|(key, _)| key == "content-length").and_then(|(_, value)| value.parse().ok());
+    let mut body = vec![0; len.unwrap_or(0)];
+    reader.read_exact(&mut body).ok()?;
+    Some(Request { path, headers, body })
+}
+
+// Serves each connection's requests in turn, as a client keeping the connection alive sends them.
+fn serve(stream: TcpStream, shared: Arc<Shared>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    let mut reader = BufReader::new(stream);
+    while let Some(request) = read_request(&mut reader) {
+        shared.requests.lock().unwrap().push(request);
+        let status = shared.status.load(Ordering::Relaxed);
+        if write!(writer, "HTTP/1.1 {} Mock\r\nContent-Length: 0\r\n\r\n", status).is_err() {
+            return;
+        }
+    }
+}
+
+impl MockServer {
+    pub fn start(status: u16) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let shared = Arc::new(Shared { status: AtomicU16::new(status), ..Shared::default() });
+        let accepting = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                accepting.connections.fetch_add(1, Ordering::Relaxed);
+                let shared = accepting.clone();
+                thread::spawn(move || serve(stream, shared));
+            }
+        });
+        MockServer { url, shared }
+    }
+
+    // Answer from now on with another status, e.g. to have an endpoint that was down recover.
+    pub fn set_status(&self, status: u16) {
+        self.shared.status.store(status, Ordering::Relaxed);
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.shared.requests.lock().unwrap().clone()
+    }
+
+    pub fn connections(&self) -> usize {
+        self.shared.connections.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::thread::sleep;
+use std::time::Duration;
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+
+type Responder = Box<dyn FnMut(&[u8]) -> Vec<Vec<u8>> + Send>;
+
+// A serial port for tests. Reads hand back the queued chunks in turn and then time out like a quiet line, and writes
+// are kept to be checked, with a responder standing in for the modem answering each command.
+pub(crate) struct MockPort {
+    pub reads: VecDeque<io::Result<Vec<u8>>>,
+    pub written: Vec<u8>,
+    pub write_errors: VecDeque<ErrorKind>, // Failures the next writes get, before any succeed
+    pub responder: Option<Responder>,
+    pub wait_out_timeouts: bool, // Timed out reads take the port's timeout to return, as a real port's do
+    pub baud_rate: u32,
+    pub timeout: Duration,
+    pub cts: bool,
+    pub dsr: bool,
+    pub ri: bool,
+    pub cd: bool
+}
+
+impl MockPort {
+    pub fn new() -> Self {
+        MockPort {
+            reads: VecDeque::new(),
+            written: Vec::new(),
+            write_errors: VecDeque::new(),
+            responder: None,
+            wait_out_timeouts: false,
+            baud_rate: 115200,
+            timeout: Duration::from_millis(10),
+            cts: true,
+            dsr: true,
+            ri: false,
+            cd: true
+        }
+    }
+
+    // A port whose reads return each chunk in turn.
+    pub fn with_reads<I: IntoIterator<Item = Vec<u8>>>(chunks: I) -> Self {
+        let mut port = MockPort::new();
+        port.reads.extend(chunks.into_iter().map(Ok));
+        port
+    }
+
+    // A port answering each command line with the reply the closure gives it, each part arriving as a read of its own.
+    pub fn answering(mut respond: impl FnMut(&str) -> Vec<&'static str> + Send + 'static) -> Self {
+        let mut port = MockPort::new();
+        port.responder = Some(Box::new(move |line| {
+            respond(String::from_utf8_lossy(line).trim_end()).into_iter().map(|part| part.as_bytes().to_vec()).collect()
+        }));
+        port
+    }
+
+    pub fn written_text(&self) -> String {
+        String::from_utf8_lossy(&self.written).into_owned()
+    }
+}
+
+impl Read for MockPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.reads.pop_front() {
+            Some(Ok(mut chunk)) => {
+                let n = chunk.len().min(buf.len());
+                buf[..n].copy_from_slice(&chunk[..n]);
+                if n < chunk.len() {
+                    self.reads.push_front(Ok(chunk.split_off(n)));
+                }
+                Ok(n)
+            },
+            Some(Err(e)) => {
+                if self.wait_out_timeouts && e.kind() == ErrorKind::TimedOut {
+                    sleep(self.timeout);
+                }
+                Err(e)
+            },
+            None => {
+                if self.wait_out_timeouts {
+                    sleep(self.timeout);
+                }
+                Err(io::Error::new(ErrorKind::TimedOut, "Operation timed out"))
+            }
+        }
+    }
+}
+
+impl Write for MockPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(kind) = self.write_errors.pop_front() {
+            return Err(io::Error::new(kind, "Mock write failure"));
+        }
+        self.written.extend_from_slice(buf);
+        if let Some(respond) = self.responder.as_mut() {
+            self.reads.extend(respond(buf).into_iter().map(Ok));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SerialPort for MockPort {
+    fn name(&self) -> Option<String> {
+        Some("mock".to_string())
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.baud_rate)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(DataBits::Eight)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(FlowControl::None)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(Parity::None)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(StopBits::One)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.baud_rate = baud_rate;
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, _data_bits: DataBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, _flow_control: FlowControl) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_parity(&mut self, _parity: Parity) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, _stop_bits: StopBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, _level: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn write_data_terminal_ready(&mut self, _level: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(self.cts)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(self.dsr)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(self.ri)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(self.cd)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(match self.reads.front() {
+            Some(Ok(chunk)) => chunk.len() as u32,
+            _ => 0
+        })
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, _buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Err(serialport::Error::new(serialport::ErrorKind::Unknown, "Mock ports can't be cloned"))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+}
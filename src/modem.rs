@@ -0,0 +1,774 @@
+use std::fmt;
+use std::io::ErrorKind;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info, warn};
+use serialport::{ClearBuffer, SerialPort};
+use crate::audio::{listen, Detector, PcmFormat, SAMPLE_RATE};
+use crate::capabilities::{parse_vls, parse_vsm, parse_vsm_rate, select_voice_coder, supports_voice};
+use crate::chipset::InitCommand;
+use crate::cid::{parse_cid, CallerId};
+use crate::config::Config;
+use crate::lock::PortLock;
+use crate::response::{CommandResponse, CommandStatus, ResponseParser};
+use crate::sink::{CallbackSink, DetectionSink};
+use crate::stats::SessionStats;
+use crate::dle::{DLE, ETX};
+use crate::telemetry::Span;
+use crate::trace::{trace_serial, TRACE_SERIAL};
+use crate::watchdog::Watchdog;
+use crate::webhook::AlertPayload;
+use crate::{READ_TIMEOUT, SHUTDOWN};
+
+const IO_TIMEOUT: Duration = Duration::from_secs(2);
+const READ_EMPTY: Duration = Duration::from_millis(100);
+const DTMF_DIGITS: &str = "0123456789*#ABCD";
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(200);
+const AUTO_BAUD_RATES: [u32; 5] = [115200, 57600, 38400, 19200, 9600];
+const CALLER_ID_WAIT: Duration = Duration::from_secs(6); // Comfortably past the gap before the second ring
+const CALLER_NAME_WAIT: Duration = Duration::from_secs(1); // After the number, for a name following it
+const RING_GAP: Duration = Duration::from_secs(10); // Longer without a ring and the caller has given up
+const ESCAPE_GUARD: Duration = Duration::from_millis(1100); // Just over the default S12 guard time of one second
+
+// Retries allowed for transient write errors, set from the config when the modem is opened.
+static WRITE_RETRIES: AtomicU32 = AtomicU32::new(0);
+
+// A command that couldn't be written to the modem, either because the port went away or transient errors persisted.
+#[derive(Debug)]
+pub(crate) struct CommandFailed {
+    pub command: String,
+    pub disconnected: bool,
+    pub source: std::io::Error
+}
+
+impl fmt::Display for CommandFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = if self.disconnected { "modem disconnected" } else { "write kept failing" };
+        write!(f, "Failed to write command {} ({}): {}", self.command, reason, self.source)
+    }
+}
+
+impl std::error::Error for CommandFailed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+// Errors worth retrying, anything else means the port is gone.
+fn is_transient(error: &std::io::Error) -> bool {
+    matches!(error.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted | ErrorKind::WouldBlock)
+}
+
+fn write_command(port: &mut dyn SerialPort, cmd: &str) -> Result<()> {
+    let retries = WRITE_RETRIES.load(Ordering::Relaxed);
+    let mut attempt = 0;
+    let line = format!("{}\r", cmd);
+    loop {
+        match port.write_all(line.as_bytes()) {
+            Ok(()) => {
+                trace_serial("Wrote", line.as_bytes());
+                return Ok(());
+            },
+            Err(e) if is_transient(&e) && attempt < retries => {
+                attempt += 1;
+                warn!("Write of {} failed ({}), retrying {}/{}", cmd, e, attempt, retries);
+                sleep(WRITE_RETRY_DELAY);
+            },
+            Err(e) => return Err(CommandFailed { command: cmd.to_string(), disconnected: !is_transient(&e), source: e }.into())
+        }
+    }
+}
+
+// Discard anything left over from earlier commands or unsolicited results, so it can't be taken as this response.
+fn drain_stale(port: &mut dyn SerialPort) -> Result<()> {
+    let stale = port.bytes_to_read()? as usize;
+    if stale > 0 {
+        let mut discard = vec![0; stale];
+        let n = port.read(&mut discard)?;
+        trace_serial("Discarded", &discard[..n]);
+        debug!("Discarded stale bytes: {:?}", String::from_utf8_lossy(&discard[..n]));
+    }
+    Ok(())
+}
+
+// Write a command and collect its response up to the final result code, or whatever arrived before the timeout, None
+// if nothing did.
+fn exchange(port: &mut dyn SerialPort, cmd: &str) -> Result<Option<CommandResponse>> {
+    let span = Span::start("modem.command");
+    span.set_str("command", cmd);
+    drain_stale(port)?;
+    debug!("Sending command: {}", cmd);
+    write_command(port, cmd)?;
+
+    let mut parser = ResponseParser::new(cmd);
+    let mut received = false;
+    let start_time = Instant::now();
+
+    while !parser.is_complete() {
+        if start_time.elapsed() > IO_TIMEOUT {
+            debug!("Timeout waiting for response to command: {}", cmd);
+            break;
+        }
+
+        let bytes_to_read = port.bytes_to_read()? as usize;
+        if bytes_to_read > 0 {
+            let mut temp_buffer = vec![0; bytes_to_read];
+            match port.read(&mut temp_buffer) {
+                Ok(n) if n > 0 => {
+                    trace_serial("Read", &temp_buffer[..n]);
+                    received = true;
+                    parser.feed(&temp_buffer[..n]);
+                }
+                Ok(_) => {}
+                Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => sleep(READ_TIMEOUT),
+                Err(e) => return Err(e.into())
+            }
+        } else {
+            sleep(READ_EMPTY);
+        }
+    }
+
+    if !received {
+        return Ok(None);
+    }
+    let response = parser.finish();
+    debug!("Command response: {:?}", response);
+    span.set_str("result", response.result.as_deref().unwrap_or_default());
+    Ok(Some(response))
+}
+
+fn execute(port: &mut dyn SerialPort, cmd: &str) -> Result<CommandResponse> {
+    exchange(port, cmd)?.ok_or_else(|| anyhow!("Failed to send command"))
+}
+
+// Send each command in turn, returning every response for the caller to branch on. A command the modem didn't answer
+// at all is a timeout like one left without a result code, only failing to write or read the port is an error.
+pub(crate) fn send_commands<S: AsRef<str>>(port: &mut dyn SerialPort, commands: &[S]) -> Result<Vec<CommandResponse>> {
+    commands.iter()
+        .map(|cmd| {
+            let cmd = cmd.as_ref();
+            Ok(exchange(port, cmd)?.unwrap_or_else(|| ResponseParser::new(cmd).finish()))
+        })
+        .collect()
+}
+
+// Send a command, returning its result code (or the last line received if none arrived).
+pub(crate) fn send_command(port: &mut dyn SerialPort, cmd: &str) -> Result<String> {
+    let response = execute(port, cmd)?;
+    let result = response.result.or_else(|| response.lines.last().cloned()).unwrap_or_default();
+    Ok(result.chars().filter(|c| c.is_alphanumeric()).collect())
+}
+
+// Send a query such as `AT+VSM=?`, returning the information lines of the response without the final result code.
+pub(crate) fn send_query(port: &mut dyn SerialPort, cmd: &str) -> Result<String> {
+    let response = execute(port, cmd)?;
+    match response.status() {
+        CommandStatus::Ok => Ok(response.lines.join("\n")),
+        CommandStatus::Error => Err(anyhow!("Command {cmd} returned {}", response.text())),
+        CommandStatus::Timeout => Err(anyhow!("Command {cmd} did not complete"))
+    }
+}
+
+// Query the modem's voice support and choose the best PCM coder it offers at the input rate.
+fn probe_voice_format(port: &mut dyn SerialPort, rate: u32) -> Result<(String, PcmFormat)> {
+    if !supports_voice(&send_query(port, "AT+FCLASS=?")?) {
+        return Err(anyhow!("Modem does not support voice mode (+FCLASS=8)"));
+    }
+
+    // Coders are only listed once in voice mode.
+    send_command(port, "AT+FCLASS=8")?;
+    let coders = parse_vsm(&send_query(port, "AT+VSM=?")?);
+    let (coder, format) = select_voice_coder(&coders, rate)
+        .with_context(|| format!("Modem offers no linear or G.711 PCM coder at {} Hz, found: {:?}", rate, coders))?;
+
+    info!("Selected voice coder {} ({}) at {} Hz", coder.id, coder.name, rate);
+    Ok((format!("AT+VSM={},{}", coder.id, rate), format))
+}
+
+// The rate the modem will stream at as `AT+VSM?` reports it, or the requested one if it can't say. Some accept
+// `AT+VSM` and quietly settle on a rate of their own, which would throw every frequency off by the ratio.
+fn negotiated_rate(port: &mut dyn SerialPort, requested: u32) -> u32 {
+    let rate = match send_query(port, "AT+VSM?") {
+        Ok(response) => parse_vsm_rate(&response),
+        Err(e) => {
+            debug!("Modem did not report its voice sample rate: {:#}", e);
+            None
+        }
+    };
+    match rate {
+        Some(rate) if rate != requested => {
+            warn!("Modem is streaming voice at {} Hz, not the {} Hz requested, resampling it to {} Hz for detection",
+                rate, requested, SAMPLE_RATE);
+            rate
+        },
+        _ => requested
+    }
+}
+
+// Warn when a configured voice device isn't one the modem lists. Modems that can't list them are given the benefit
+// of the doubt, the AT+VLS command itself still fails init if the value is wrong.
+fn check_vls(port: &mut dyn SerialPort, vls: u32) -> Result<()> {
+    // Devices are only listed once in voice mode.
+    send_command(port, "AT+FCLASS=8")?;
+    let supported = match send_query(port, "AT+VLS=?") {
+        Ok(response) => parse_vls(&response),
+        Err(e) => {
+            debug!("Modem did not list its voice devices: {:#}", e);
+            return Ok(());
+        }
+    };
+    if supported.is_empty() {
+        debug!("Modem listed no voice devices for AT+VLS=?");
+    } else if !supported.iter().any(|&(min, max)| (min..=max).contains(&vls)) {
+        warn!("AT+VLS={} is not among the modem's voice devices {:?}, it may hear nothing", vls, supported);
+    } else {
+        info!("Using voice device AT+VLS={} (supported: {:?})", vls, supported);
+    }
+    Ok(())
+}
+
+fn open_port(config: &Config, baud: u32) -> Result<Box<dyn SerialPort>> {
+    debug!("Creating serial port: {} @ {} baud ({} data bits, {} parity, {} stop bits, {} flow control)",
+        &config.modem_port, baud, config.modem_data_bits, config.modem_parity, config.modem_stop_bits, config.modem_flow_control);
+    serialport::new(&config.modem_port, baud)
+        .data_bits(config.modem_data_bits)
+        .parity(config.modem_parity)
+        .stop_bits(config.modem_stop_bits)
+        .flow_control(config.modem_flow_control)
+        .timeout(IO_TIMEOUT)
+        .open()
+        .context("Failed to open serial port")
+}
+
+// First rate for which the probe reports the modem answering, a failed probe just moves on to the next rate.
+fn select_baud(rates: &[u32], mut probe: impl FnMut(u32) -> Result<bool>) -> Option<u32> {
+    rates.iter().copied().find(|&rate| match probe(rate) {
+        Ok(answered) => answered,
+        Err(e) => {
+            debug!("No response at {} baud: {:#}", rate, e);
+            false
+        }
+    })
+}
+
+// Try common rates from fastest to slowest, keeping the port open at the first one where `AT` gets an `OK`.
+fn open_auto_baud(config: &Config) -> Result<Box<dyn SerialPort>> {
+    let mut found = None;
+    let rate = select_baud(&AUTO_BAUD_RATES, |rate| {
+        let mut port = open_port(config, rate)?;
+        let answered = send_command(&mut *port, "AT")? == "OK";
+        if answered {
+            found = Some(port);
+        }
+        Ok(answered)
+    });
+    match (rate, found) {
+        (Some(rate), Some(port)) => {
+            info!("Modem answered at {} baud", rate);
+            Ok(port)
+        },
+        _ => Err(anyhow!("Modem did not answer AT at any of {:?} baud", AUTO_BAUD_RATES))
+    }
+}
+
+// Send each command expecting OK, aborting on a critical command's failure and logging the rest.
+fn run_init<'a>(port: &mut dyn SerialPort, commands: impl IntoIterator<Item = InitCommand<'a>>) -> Result<()> {
+    for InitCommand { command, critical } in commands {
+        let response = match send_command(port, command) {
+            Ok(response) if response == "OK" => continue,
+            Ok(response) => format!("got: {response}"),
+            Err(e) if critical => return Err(e),
+            Err(e) => format!("failed: {e:#}")
+        };
+        if critical {
+            return Err(anyhow!("Command {command} expected OK, instead {response}"));
+        }
+        warn!("Optional command {command} expected OK, instead {response}, continuing");
+    }
+    Ok(())
+}
+
+// The open serial port, along with the lock file claiming it when locking is enabled, released together.
+pub(crate) struct ModemPort {
+    port: Box<dyn SerialPort>,
+    _lock: Option<PortLock>,
+    rate: u32 // The voice sample rate the modem negotiated
+}
+
+impl ModemPort {
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+}
+
+impl Deref for ModemPort {
+    type Target = dyn SerialPort;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.port
+    }
+}
+
+impl DerefMut for ModemPort {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut *self.port
+    }
+}
+
+// An opened and initialized modem, for a program embedding detection to drive calls itself. Dropping it returns the
+// modem to command mode.
+pub struct Modem {
+    port: ModemPort,
+    format: PcmFormat
+}
+
+impl Modem {
+    pub fn open(config: &Config) -> Result<Self> {
+        let (port, format) = open_modem(config)?;
+        Ok(Modem { port, format })
+    }
+
+    pub fn format(&self) -> PcmFormat {
+        self.format
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.port.rate
+    }
+
+    // Enter voice receive and listen until the call ends, handing each detection to `on_detection`.
+    pub fn listen(&mut self, detector: &mut Detector, on_detection: impl FnMut(&AlertPayload) + 'static) -> Result<()> {
+        if send_command(&mut *self.port, "AT+VRX")? != "CONNECT" {
+            return Err(anyhow!("Failed to connect to VRX"));
+        }
+        let mut sinks: Vec<Box<dyn DetectionSink>> = vec![Box::new(CallbackSink(on_detection))];
+        let mut stats = SessionStats::new(None);
+        detector.set_input_rate(self.port.rate);
+        listen(&mut *self.port, self.format, detector, &mut sinks, &mut stats, &mut Watchdog::new(), None)
+    }
+}
+
+impl Drop for Modem {
+    fn drop(&mut self) {
+        release_modem(&mut *self.port);
+    }
+}
+
+pub(crate) fn open_modem(config: &Config) -> Result<(ModemPort, PcmFormat)> {
+    WRITE_RETRIES.store(config.write_retries, Ordering::Relaxed);
+    TRACE_SERIAL.store(config.trace_serial, Ordering::Relaxed);
+    let _span = Span::start("modem.init");
+    // Claimed before the port is opened, so a second instance can't get far enough to send it garbage.
+    let lock = config.modem_lock_dir.as_deref().map(|dir| PortLock::acquire(&config.modem_port, dir)).transpose()?;
+    let port = if config.modem_auto_baud {
+        open_auto_baud(config)?
+    } else {
+        open_port(config, config.modem_baud)?
+    };
+    // Capture at a multiple of the processing rate when decimating down to it.
+    let rate = SAMPLE_RATE as u32 * config.decimation as u32;
+    let mut port = ModemPort { port, _lock: lock, rate };
+
+    info!("Initializing modem");
+    run_init(&mut *port, [
+        InitCommand::optional("ATZ"),  // Reset
+        InitCommand::critical("ATV1"), // Verbose result codes, numeric ones can't be told from a query's answer
+        InitCommand::optional("ATE0")  // Disable echo
+    ])?;
+
+    let (voice_format, format) = if config.modem_probe {
+        probe_voice_format(&mut *port, rate)?
+    } else {
+        (format!("AT+VSM=1,{}", rate), PcmFormat::Unsigned8)
+    };
+    // The chipset's voice setup, with the voice device and receive gain swapped for the configured ones, then PCM
+    // at the input rate and a final connection test.
+    let vls_command = config.modem_vls.map(|vls| format!("AT+VLS={}", vls));
+    if let Some(vls) = config.modem_vls {
+        check_vls(&mut *port, vls)?;
+    }
+    let vgr_command = config.modem_vgr.map(|vgr| format!("AT+VGR={}", vgr));
+    // A custom sequence stands in for the profile's, every command of it critical so a bad one fails init straight away.
+    // One choosing its own voice coder replaces the default `AT+VSM` too.
+    let custom: Vec<InitCommand> = config.modem_init.iter().map(|command| InitCommand::critical(command)).collect();
+    let setup = if custom.is_empty() { config.modem_profile.commands } else { &custom };
+    let sets_voice_format = setup.iter().any(|init| init.command.to_ascii_uppercase().starts_with("AT+VSM="));
+    if !custom.is_empty() {
+        info!("Using the init sequence from ALARM_MODEM_INIT: {}", config.modem_init.join("; "));
+    }
+    let initialization_commands = setup.iter()
+        .map(|init| match (&vls_command, &vgr_command) {
+            (Some(command), _) if init.command.starts_with("AT+VLS=") => InitCommand { command, ..*init },
+            (_, Some(command)) if init.command.starts_with("AT+VGR=") => InitCommand { command, ..*init },
+            _ => *init
+        })
+        .chain((!sets_voice_format).then(|| InitCommand::critical(&voice_format)))
+        .chain([InitCommand::critical("AT")]);
+    run_init(&mut *port, initialization_commands)?;
+    port.rate = negotiated_rate(&mut *port, rate);
+
+    // Caller ID is only needed to report or screen callers or pick tone profiles, and not every modem supports it.
+    if config.caller_id || !config.tone_profiles.is_empty() {
+        let response = send_command(&mut *port, "AT+VCID=1")?;
+        if response != "OK" {
+            if config.require_caller_id {
+                return Err(anyhow!("Modem did not enable caller ID ({}), which ALARM_REQUIRE_CALLER_ID needs", response));
+            }
+            warn!("Modem did not enable caller ID ({}), callers will not be identified", response);
+        }
+    }
+    Ok((port, format))
+}
+
+// Build the `AT+VTS` command playing each DTMF digit in turn, e.g. `AT+VTS=1,2,3`.
+pub(crate) fn dtmf_command(digits: &str) -> Result<String> {
+    if digits.is_empty() {
+        return Err(anyhow!("DTMF sequence is empty"));
+    }
+
+    let digits = digits.to_ascii_uppercase();
+    if let Some(invalid) = digits.chars().find(|c| !DTMF_DIGITS.contains(*c)) {
+        return Err(anyhow!("Invalid DTMF digit {:?}, expected one of {}", invalid, DTMF_DIGITS));
+    }
+    Ok(format!("AT+VTS={}", digits.chars().map(String::from).collect::<Vec<_>>().join(",")))
+}
+
+pub(crate) fn send_dtmf(port: &mut dyn SerialPort, digits: &str) -> Result<()> {
+    let cmd = dtmf_command(digits)?;
+    let response = send_command(port, &cmd)?;
+    if response != "OK" {
+        return Err(anyhow!("Command {cmd} expected OK, instead got: {response}"));
+    }
+    Ok(())
+}
+
+// A call that has rung enough times to answer, with the caller ID sent between its rings.
+pub(crate) struct IncomingCall {
+    pub rings: u32,
+    pub caller: Option<CallerId>
+}
+
+// Count `RING`s until there have been `rings` of them, picking out the caller ID lines arriving between rings when
+// `caller_id` is set. The caller ID follows the first ring, so even answering on the first waits for it a while, and
+// the name usually follows the number so it's given a moment to arrive too. Returns None on shutdown.
+pub(crate) fn wait_for_call(port: &mut dyn SerialPort, rings: u32, caller_id: bool) -> Result<Option<IncomingCall>> {
+    let mut line: Vec<u8> = Vec::new();
+    let mut cid_text = String::new();
+    let mut count = 0;
+    let mut first_ring: Option<Instant> = None;
+    let mut last_ring = Instant::now();
+    let mut caller: Option<(CallerId, Instant)> = None;
+    while !SHUTDOWN.load(Ordering::Relaxed) {
+        if count > 0 && last_ring.elapsed() > RING_GAP {
+            info!("Stopped ringing after {} of {} rings", count, rings);
+            (count, first_ring, caller) = (0, None, None);
+            cid_text.clear();
+        }
+        if count >= rings {
+            let cid_settled = !caller_id || match &caller {
+                Some((caller, found)) => caller.name.is_some() || found.elapsed() >= CALLER_NAME_WAIT,
+                None => first_ring.is_some_and(|at| at.elapsed() >= CALLER_ID_WAIT)
+            };
+            if cid_settled {
+                return Ok(Some(IncomingCall { rings: count, caller: caller.map(|(caller, _)| caller) }));
+            }
+        }
+
+        let mut buffer = vec![0; 256];
+        match port.read(&mut buffer) {
+            Ok(n) => {
+                trace_serial("Read", &buffer[..n]);
+                // Whole lines only, so a RING is told apart from caller ID lines and a value split across reads
+                // isn't taken early.
+                for &byte in &buffer[..n] {
+                    if !matches!(byte, b'\r' | b'\n') {
+                        line.push(byte);
+                        continue;
+                    }
+                    let text = String::from_utf8_lossy(&line).trim().to_string();
+                    line.clear();
+                    if text == "RING" {
+                        count += 1;
+                        last_ring = Instant::now();
+                        first_ring.get_or_insert(last_ring);
+                        if count <= rings {
+                            info!("Ring {} of {}", count, rings);
+                        } else {
+                            debug!("Ring {} while waiting for the caller ID", count);
+                        }
+                    } else if caller_id && !text.is_empty() {
+                        cid_text.push_str(&text);
+                        cid_text.push('\n');
+                        let found = caller.as_ref().map_or_else(Instant::now, |(_, found)| *found);
+                        caller = parse_cid(&cid_text).map(|caller| (caller, found));
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e.into())
+        }
+    }
+    Ok(None)
+}
+
+// Turn away a call that hasn't been answered by briefly going off-hook, which ends the ringing for the caller.
+pub(crate) fn reject_call(port: &mut dyn SerialPort) -> Result<()> {
+    info!("Rejecting call");
+    for cmd in ["ATH1", "ATH0"] {
+        let response = send_command(port, cmd)?;
+        if response != "OK" {
+            return Err(anyhow!("Command {cmd} expected OK, instead got: {response}"));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn answer_call(port: &mut dyn SerialPort) -> Result<()> {
+    info!("Answering call");
+    let _span = Span::start("answer");
+    let response = send_command(port, "ATA")?;
+    if !matches!(response.as_str(), "VCON" | "OK" | "CONNECT") {
+        return Err(anyhow!("Command ATA failed to answer, got: {response}"));
+    }
+    Ok(())
+}
+
+// Abort voice receive with the DLE shielded `!`, then go on-hook.
+pub(crate) fn hang_up(port: &mut dyn SerialPort) -> Result<()> {
+    info!("Hanging up");
+    port.write_all(&[DLE, b'!'])?;
+    trace_serial("Wrote", &[DLE, b'!']);
+    sleep(READ_EMPTY);
+    let response = send_command(port, "ATH")?;
+    if response != "OK" {
+        return Err(anyhow!("Command ATH expected OK, instead got: {response}"));
+    }
+    Ok(())
+}
+
+// Best effort teardown on shutdown so the next run finds the modem in command mode rather than needing a power cycle:
+// end voice receive, escape any online state with `+++` between guard silences, go on-hook and leave voice mode.
+pub(crate) fn release_modem(port: &mut dyn SerialPort) {
+    info!("Returning modem to data mode");
+    let mut step = |name: &str, bytes: &[u8]| {
+        debug!("Teardown: {}", name);
+        if let Err(e) = port.write_all(bytes).and_then(|_| port.flush()) {
+            warn!("Failed to send {} during teardown: {}", name, e);
+        }
+        trace_serial("Wrote", bytes);
+    };
+    step("DLE ETX", &[DLE, ETX]);
+    sleep(ESCAPE_GUARD);
+    step("+++", b"+++");
+    sleep(ESCAPE_GUARD);
+    let _ = port.clear(ClearBuffer::Input);
+
+    let responses = match send_commands(port, &["ATH", "AT+FCLASS=0"]) {
+        Ok(responses) => responses,
+        Err(e) => {
+            warn!("Commands failed during teardown: {:#}", e);
+            return;
+        }
+    };
+    for response in responses {
+        match response.status() {
+            CommandStatus::Ok => {},
+            CommandStatus::Error => warn!("Command {} failed during teardown, got: {}", response.command, response.text()),
+            CommandStatus::Timeout => warn!("Command {} timed out during teardown", response.command)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use crate::mock_port::MockPort;
+
+    #[test]
+    fn plays_dtmf_digits_with_vts() {
+        assert_eq!(dtmf_command("12*#a").unwrap(), "AT+VTS=1,2,*,#,A");
+        assert!(dtmf_command("").is_err());
+        assert!(dtmf_command("12E").is_err());
+
+        let mut port = MockPort::answering(|_| vec!["\r\nOK\r\n"]);
+        send_dtmf(&mut port, "123").unwrap();
+        assert_eq!(port.written_text(), "AT+VTS=1,2,3\r");
+        assert!(send_dtmf(&mut port, "1x").is_err());
+        assert_eq!(port.written_text(), "AT+VTS=1,2,3\r", "invalid digits were sent");
+    }
+
+    // An echo, information line and result code each in a read of their own still pair up with their command, and
+    // nothing left over from before it is taken as its response.
+    #[test]
+    fn pairs_responses_split_across_reads() {
+        let mut port = MockPort::answering(|line| match line {
+            "AT+VSM=?" => vec!["AT+VSM=?\r", "\r\n128,\"8-BIT LINEAR\",(8000)\r\n", "\r\nOK\r\n"],
+            "AT+FCLASS=8" => vec!["AT+FCLASS=8\r\r\n", "ERR", "OR\r\n"],
+            _ => vec![]
+        });
+        port.reads.push_back(Ok(b"\r\nRING\r\n".to_vec()));
+        let responses = send_commands(&mut port, &["AT+VSM=?", "AT+FCLASS=8"]).unwrap();
+        assert_eq!(responses[0].command, "AT+VSM=?");
+        assert_eq!(responses[0].lines, ["128,\"8-BIT LINEAR\",(8000)"]);
+        assert_eq!(responses[0].status(), CommandStatus::Ok);
+        assert_eq!(responses[1].command, "AT+FCLASS=8");
+        assert!(responses[1].lines.is_empty());
+        assert_eq!(responses[1].status(), CommandStatus::Error);
+    }
+
+    // A query comes back as its information lines once the modem says OK, and as an error naming it when the modem
+    // says ERROR, rather than whatever arrived first.
+    #[test]
+    fn queries_wait_for_the_result_code() {
+        let mut port = MockPort::answering(|line| match line {
+            "AT+FCLASS=?" => vec!["\r\n0,1,8", ",10\r\n", "\r\nOK\r\n"],
+            _ => vec!["\r\nERROR\r\n"]
+        });
+        assert_eq!(send_query(&mut port, "AT+FCLASS=?").unwrap(), "0,1,8,10");
+        let error = send_query(&mut port, "AT+VSM=?").unwrap_err();
+        assert_eq!(error.to_string(), "Command AT+VSM=? returned ERROR");
+    }
+
+    // Rings are counted from whole lines, the caller ID lines between them parsed even when split across reads.
+    #[test]
+    fn counts_rings_and_picks_up_the_caller_id_between_them() {
+        let port = |reads: &[&str]| MockPort::with_reads(reads.iter().map(|read| read.as_bytes().to_vec()));
+        let mut ringing = port(&[
+            "\r\nRING\r\n",
+            "\r\nDATE = 0312\r\nNMBR = 555",
+            "-1234\r\nNAME = ACME\r\n",
+            "\r\nRI",
+            "NG\r\n\r\nRING\r\n"
+        ]);
+        let call = wait_for_call(&mut ringing, 3, true).unwrap().expect("no call");
+        let caller = call.caller.expect("no caller ID");
+        assert_eq!((call.rings, caller.number.as_str(), caller.name.as_deref()), (3, "5551234", Some("ACME")));
+
+        let mut ringing = port(&["\r\nRING\r\n", "NMBR = 5551234\r\n", "\r\nRING\r\n"]);
+        let call = wait_for_call(&mut ringing, 2, false).unwrap().expect("no call");
+        assert_eq!(call.rings, 2);
+        assert!(call.caller.is_none(), "parsed a caller ID that wasn't asked for");
+    }
+
+    // A transient failure is retried and the command goes through, a lost port fails at once naming the command.
+    #[test]
+    fn retries_a_transient_write_failure() {
+        WRITE_RETRIES.store(1, Ordering::Relaxed);
+        let mut port = MockPort::new();
+        port.write_errors.push_back(ErrorKind::TimedOut);
+        write_command(&mut port, "ATZ").unwrap();
+        assert_eq!(port.written_text(), "ATZ\r");
+
+        port.write_errors.extend([ErrorKind::TimedOut, ErrorKind::TimedOut]);
+        let error = write_command(&mut port, "AT+FCLASS=8").unwrap_err();
+        assert!(!error.downcast_ref::<CommandFailed>().unwrap().disconnected);
+
+        port.write_errors.push_back(ErrorKind::BrokenPipe);
+        let error = write_command(&mut port, "ATH").unwrap_err();
+        let failed = error.downcast_ref::<CommandFailed>().unwrap();
+        assert_eq!((failed.command.as_str(), failed.disconnected), ("ATH", true));
+        assert_eq!(port.written_text(), "ATZ\r", "wrote after a failure");
+    }
+
+    // The first rate the modem answers at is chosen, those it can't be opened at or that give garbage are passed over.
+    #[test]
+    fn selects_the_first_rate_the_modem_answers_at() {
+        let mut tried = Vec::new();
+        let rate = select_baud(&AUTO_BAUD_RATES, |rate| {
+            tried.push(rate);
+            let mut port = match rate {
+                115200 => return Err(anyhow!("Failed to open port")),
+                38400 | 9600 => MockPort::answering(|_| vec!["AT\r\r\nOK\r\n"]),
+                _ => MockPort::answering(|_| vec!["\u{7f}~\r\nERROR\r\n"])
+            };
+            Ok(send_command(&mut port, "AT")? == "OK")
+        });
+        assert_eq!(rate, Some(38400));
+        assert_eq!(tried, [115200, 57600, 38400]);
+        assert_eq!(select_baud(&AUTO_BAUD_RATES, |_| Ok(false)), None);
+    }
+
+    // A modem rejecting an optional command still initializes, one rejecting a critical command doesn't.
+    #[test]
+    fn init_tolerates_only_optional_failures() {
+        let mut port = MockPort::answering(|line| match line {
+            "AT+VGR=3" | "AT+VIT=0" => vec!["\r\nERROR\r\n"],
+            _ => vec!["\r\nOK\r\n"]
+        });
+        let optional = [InitCommand::optional("ATE0"), InitCommand::optional("AT+VGR=3")];
+        run_init(&mut port, optional.into_iter().chain([InitCommand::critical("AT+FCLASS=8")])).unwrap();
+        assert_eq!(port.written_text(), "ATE0\rAT+VGR=3\rAT+FCLASS=8\r", "init stopped at the optional failure");
+
+        let error = run_init(&mut port, [InitCommand::critical("AT+VIT=0"), InitCommand::critical("AT")]).unwrap_err();
+        assert!(error.to_string().contains("Command AT+VIT=0 expected OK"), "{}", error);
+        assert!(!port.written_text().ends_with("AT\r"), "init carried on past the critical failure");
+    }
+
+    // The library's modem enters voice receive, hands each detection of the call to the callback until the modem ends
+    // it, and returns the modem to command mode once dropped.
+    #[test]
+    fn a_modem_listens_through_a_call_and_is_released_on_drop() {
+        let tone: Vec<u8> = (0..8192)
+            .map(|i| (1000.0 * (2.0 * std::f32::consts::PI * 1665.0 * i as f32 / SAMPLE_RATE).sin()) as i16)
+            .flat_map(i16::to_le_bytes)
+            .flat_map(|byte| if byte == DLE { vec![DLE, DLE] } else { vec![byte] })
+            .collect();
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let recorded = written.clone();
+        let mut port = MockPort::new();
+        port.responder = Some(Box::new(move |bytes| {
+            recorded.lock().unwrap().extend_from_slice(bytes);
+            match bytes {
+                b"AT+VRX\r" => vec![b"\r\nCONNECT\r\n".to_vec(), tone.clone(), vec![DLE, ETX]],
+                b"ATH\r" | b"AT+FCLASS=0\r" => vec![b"\r\nOK\r\n".to_vec()],
+                _ => vec![]
+            }
+        }));
+        let config = crate::config::test_config(&[
+            ("ALARM_TONE_MIN_POWER", "1e6"),
+            ("ALARM_TONE_MAX_POWER", "1e20"),
+            ("ALARM_ANSWER_TONE_MIN_POWER", "1e10"),
+            ("ALARM_MIN_GAP_MS", "100")
+        ]);
+        let mut detector = Detector::new(Arc::new(config.unwrap())).unwrap();
+        let port = ModemPort { port: Box::new(port), _lock: None, rate: 8000 };
+        let mut modem = Modem { port, format: PcmFormat::SignedLe16 };
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = events.clone();
+        modem.listen(&mut detector, move |payload| seen.lock().unwrap().push(payload.event)).unwrap();
+        assert_eq!(*events.lock().unwrap(), ["trigger"]);
+        drop(modem);
+        let written = String::from_utf8_lossy(&written.lock().unwrap()).into_owned();
+        assert!(written.starts_with("AT+VRX\r"), "{:?}", written);
+        assert!(written.ends_with("+++ATH\rAT+FCLASS=0\r"), "{:?}", written);
+    }
+
+    // Teardown leaves voice receive, escapes with a guard silence either side of `+++`, then hangs up and leaves
+    // voice mode, carrying on past a command the modem rejects.
+    #[test]
+    fn releases_the_modem_in_order_with_guard_times() {
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let mut port = MockPort::new();
+        let recorded = writes.clone();
+        port.responder = Some(Box::new(move |bytes| {
+            recorded.lock().unwrap().push((Instant::now(), bytes.to_vec()));
+            match bytes {
+                b"ATH\r" => vec![b"\r\nERROR\r\n".to_vec()],
+                b"AT+FCLASS=0\r" => vec![b"\r\nOK\r\n".to_vec()],
+                _ => vec![]
+            }
+        }));
+        release_modem(&mut port);
+
+        let writes = writes.lock().unwrap();
+        let sent: Vec<&[u8]> = writes.iter().map(|(_, bytes)| bytes.as_slice()).collect();
+        assert_eq!(sent, [&[DLE, ETX][..], b"+++", b"ATH\r", b"AT+FCLASS=0\r"]);
+        assert!(writes[1].0 - writes[0].0 >= ESCAPE_GUARD, "no guard time before the escape");
+        assert!(writes[2].0 - writes[1].0 >= ESCAPE_GUARD, "no guard time after the escape");
+    }
+}
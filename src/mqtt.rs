@@ -0,0 +1,144 @@
+use std::thread;
+use std::thread::sleep;
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+use crate::config::Config;
+use crate::sink::DetectionSink;
+use crate::webhook::AlertPayload;
+
+const CLIENT_ID: &str = "alarm-modem";
+const DEFAULT_PORT: u16 = 1883;
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const QUEUE_CAPACITY: usize = 64;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+// Publishes each detection as JSON to a topic. The connection is driven on its own thread, which keeps
+// reconnecting after broker loss, while publishing only queues so a slow or absent broker never stalls detection.
+pub(crate) struct MqttSink {
+    client: Client,
+    topic: String,
+    qos: QoS,
+    retain: bool
+}
+
+// Broker written as `host` or `host:port`.
+fn parse_broker(broker: &str) -> Result<(String, u16)> {
+    match broker.rsplit_once(':') {
+        Some((host, port)) => Ok((host.to_string(), port.parse().with_context(|| format!("Invalid MQTT broker port in {}", broker))?)),
+        None => Ok((broker.to_string(), DEFAULT_PORT))
+    }
+}
+
+impl MqttSink {
+    pub fn new(broker: &str, config: &Config) -> Result<Self> {
+        let (host, port) = parse_broker(broker)?;
+        let qos = match config.mqtt_qos {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            2 => QoS::ExactlyOnce,
+            other => return Err(anyhow!("Invalid MQTT QoS {}, expected 0, 1 or 2", other))
+        };
+
+        let mut options = MqttOptions::new(CLIENT_ID, host, port);
+        options.set_keep_alive(KEEP_ALIVE);
+        let (client, mut connection) = Client::new(options, QUEUE_CAPACITY);
+
+        let broker = broker.to_string();
+        thread::spawn(move || {
+            for notification in connection.iter() {
+                match notification {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => info!("Connected to MQTT broker {}", broker),
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("MQTT connection to {} failed, retrying in {:#?}: {}", broker, RECONNECT_DELAY, e);
+                        sleep(RECONNECT_DELAY);
+                    }
+                }
+            }
+        });
+
+        Ok(MqttSink { client, topic: config.mqtt_topic.clone(), qos, retain: config.mqtt_retain })
+    }
+}
+
+impl DetectionSink for MqttSink {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn on_detection(&mut self, payload: &AlertPayload) -> Result<()> {
+        let body = serde_json::to_vec(payload)?;
+        self.client.try_publish(self.topic.as_str(), self.qos, self.retain, body)
+            .with_context(|| format!("Failed to queue MQTT publish to {}", self.topic))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::SystemTime;
+    use crate::audio::{Detection, DetectionEvent};
+    use crate::config::test_config;
+
+    // Reads one MQTT control packet, as its first header byte and the rest after the remaining length.
+    fn read_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut byte = [0; 1];
+        stream.read_exact(&mut byte).unwrap();
+        let header = byte[0];
+        let (mut len, mut shift) = (0, 0);
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            len |= ((byte[0] & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).unwrap();
+        (header, body)
+    }
+
+    #[test]
+    fn publishes_each_detection_to_the_topic() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let broker = listener.local_addr().unwrap().to_string();
+        let config = test_config(&[
+            ("ALARM_MQTT_TOPIC", "site/panel"),
+            ("ALARM_MQTT_QOS", "1"),
+            ("ALARM_MQTT_RETAIN", "true")
+        ]).unwrap();
+        let mut sink = MqttSink::new(&broker, &config).unwrap();
+
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(read_packet(&mut stream).0 >> 4, 1, "expected CONNECT");
+        stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+
+        let event = DetectionEvent {
+            detection: Detection::Trigger,
+            timestamp: SystemTime::now(),
+            audio: None,
+            contact_id: None,
+            fsk_data: None,
+            frequency: Some(1665.0),
+            offset: None,
+            level_dbfs: None,
+            caller: None
+        };
+        sink.on_detection(&AlertPayload::from_event(&event, 8000)).unwrap();
+
+        let (header, body) = read_packet(&mut stream);
+        assert_eq!(header >> 4, 3, "expected PUBLISH");
+        assert_eq!(((header >> 1) & 3, header & 1), (1, 1), "QoS and retain flags");
+        let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+        assert_eq!(&body[2..2 + topic_len], b"site/panel");
+        // The QoS 1 packet identifier comes before the payload.
+        let published: serde_json::Value = serde_json::from_slice(&body[4 + topic_len..]).unwrap();
+        assert_eq!((published["event"].as_str(), published["frequency_hz"].as_f64()), (Some("trigger"), Some(1665.0)));
+    }
+}
@@ -0,0 +1,160 @@
+use std::fs;
+use std::str::FromStr;
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+
+// The placeholders a template can use, with the payload field each is filled from.
+const PLACEHOLDERS: [(&str, &str); 8] = [
+    ("event", "/event"),
+    ("priority", "/priority"),
+    ("timestamp", "/timestamp"),
+    ("offset_ms", "/offset_ms"),
+    ("frequency", "/frequency_hz"),
+    ("power", "/level_dbfs"),
+    ("caller_id", "/caller/number"),
+    ("count", "/count")
+];
+
+// A webhook body in the shape a receiver expects, its strings holding `{name}` placeholders filled in from each
+// payload. A string that is only a placeholder takes the field's own value, so `"{frequency}"` sends a number (or null
+// when the detection has none), while one within other text is substituted as text.
+#[derive(Debug, Clone)]
+pub(crate) struct PayloadTemplate(Value);
+
+// The `{name}` placeholders in a string, as (start, end, name).
+fn placeholders(text: &str) -> Vec<(usize, usize, &str)> {
+    let mut found = Vec::new();
+    let mut from = 0;
+    while let Some(open) = text[from..].find('{').map(|at| from + at) {
+        let Some(close) = text[open..].find('}').map(|at| open + at) else {
+            break;
+        };
+        let name = &text[open + 1..close];
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+            found.push((open, close + 1, name));
+            from = close + 1;
+        } else {
+            from = open + 1;
+        }
+    }
+    found
+}
+
+// Every placeholder in the template is one we can fill, so a typo fails at startup rather than sending nulls.
+fn check(template: &Value) -> Result<()> {
+    match template {
+        Value::String(text) => {
+            for (_, _, name) in placeholders(text) {
+                if !PLACEHOLDERS.iter().any(|(placeholder, _)| *placeholder == name) {
+                    let known: Vec<_> = PLACEHOLDERS.iter().map(|(placeholder, _)| format!("{{{}}}", placeholder)).collect();
+                    return Err(anyhow!("Unknown webhook template placeholder {{{}}}, expected {}", name, known.join(", ")));
+                }
+            }
+            Ok(())
+        },
+        Value::Array(items) => items.iter().try_for_each(check),
+        Value::Object(fields) => fields.values().try_for_each(check),
+        _ => Ok(())
+    }
+}
+
+fn field(payload: &Value, name: &str) -> Value {
+    PLACEHOLDERS.iter()
+        .find(|(placeholder, _)| *placeholder == name)
+        .and_then(|(_, path)| payload.pointer(path))
+        .cloned()
+        .unwrap_or(Value::Null)
+}
+
+fn fill(template: &Value, payload: &Value) -> Value {
+    match template {
+        Value::String(text) => {
+            let found = placeholders(text);
+            if let [(0, end, name)] = found.as_slice() {
+                if *end == text.len() {
+                    return field(payload, name);
+                }
+            }
+            let mut filled = String::new();
+            let mut last = 0;
+            for (start, end, name) in found {
+                filled += &text[last..start];
+                match field(payload, name) {
+                    Value::Null => {}
+                    Value::String(value) => filled += &value,
+                    value => filled += &value.to_string()
+                }
+                last = end;
+            }
+            filled += &text[last..];
+            Value::String(filled)
+        },
+        Value::Array(items) => Value::Array(items.iter().map(|item| fill(item, payload)).collect()),
+        Value::Object(fields) => Value::Object(fields.iter().map(|(key, value)| (key.clone(), fill(value, payload))).collect()),
+        other => other.clone()
+    }
+}
+
+impl PayloadTemplate {
+    // For the fan-out endpoints, whose templates can't sit within their comma separated list.
+    pub(crate) fn load(path: &str) -> Result<Self> {
+        fs::read_to_string(path)
+            .with_context(|| format!("Failed to read webhook template {}", path))?
+            .parse()
+            .with_context(|| format!("Invalid webhook template in {}", path))
+    }
+
+    // Fill the template from a serialized payload, each detection of a batch in turn within the batch's own envelope.
+    pub(crate) fn render(&self, payload: &Value) -> Value {
+        match payload["events"].as_array() {
+            Some(events) => {
+                let mut batch = payload.clone();
+                batch["events"] = Value::Array(events.iter().map(|event| fill(&self.0, event)).collect());
+                batch
+            },
+            None => fill(&self.0, payload)
+        }
+    }
+}
+
+impl FromStr for PayloadTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let template: Value = serde_json::from_str(s).context("Webhook template is not valid JSON")?;
+        check(&template)?;
+        Ok(PayloadTemplate(template))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn fills_typed_and_embedded_placeholders() {
+        let template: PayloadTemplate = r#"{"alert": {"source": "panel {event} at {frequency} Hz", "hz": "{frequency}",
+            "caller": "{caller_id}", "fixed": 1}}"#.parse().unwrap();
+        let payload = json!({"event": "trigger", "timestamp": 10, "frequency_hz": 1665.5});
+        assert_eq!(template.render(&payload), json!({
+            "alert": {"source": "panel trigger at 1665.5 Hz", "hz": 1665.5, "caller": null, "fixed": 1}
+        }));
+    }
+
+    #[test]
+    fn renders_each_batched_event() {
+        let template: PayloadTemplate = r#"{"type": "{event}"}"#.parse().unwrap();
+        let batch = json!({"event": "batch", "timestamp": 10, "events": [{"event": "trigger"}, {"event": "panic"}]});
+        assert_eq!(template.render(&batch), json!({
+            "event": "batch", "timestamp": 10, "events": [{"type": "trigger"}, {"type": "panic"}]
+        }));
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert!("{\"event\": ".parse::<PayloadTemplate>().is_err());
+        assert!(r#"{"event": "{frequnecy}"}"#.parse::<PayloadTemplate>().is_err());
+        assert!(r#"{"note": "{} and {Not a placeholder}"}"#.parse::<PayloadTemplate>().is_ok());
+    }
+}
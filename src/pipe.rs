@@ -0,0 +1,93 @@
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::thread;
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+
+const PIPE_QUEUE_BLOCKS: usize = 64; // Blocks buffered for a slow reader before dropping
+
+// Tees decoded PCM (16-bit little endian) into a child process's stdin. Writes happen on a separate
+// thread behind a bounded queue, so a slow or stuck child drops audio rather than stalling detection.
+pub(crate) struct PcmPipe {
+    child: Child,
+    sender: Option<SyncSender<Vec<u8>>>,
+    dropped: u64
+}
+
+impl PcmPipe {
+    pub fn spawn(command: &str) -> Result<Self> {
+        let mut parts = command.split_whitespace();
+        let program = parts.next().ok_or_else(|| anyhow!("PCM pipe command is empty"))?;
+        let mut child = Command::new(program)
+            .args(parts)
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to spawn PCM pipe command {}", command))?;
+
+        let mut stdin = child.stdin.take().context("PCM pipe child has no stdin")?;
+        let (sender, receiver) = sync_channel::<Vec<u8>>(PIPE_QUEUE_BLOCKS);
+        thread::spawn(move || {
+            for chunk in receiver {
+                if let Err(e) = stdin.write_all(&chunk) {
+                    warn!("PCM pipe closed: {}", e);
+                    break;
+                }
+            }
+        });
+
+        info!("Forwarding PCM to {}", command);
+        Ok(PcmPipe { child, sender: Some(sender), dropped: 0 })
+    }
+
+    pub fn forward(&mut self, samples: &[i16]) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+
+        let bytes = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        match sender.try_send(bytes) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                warn!("PCM pipe is not keeping up, dropped {} blocks", self.dropped);
+            },
+            Err(TrySendError::Disconnected(_)) => {
+                warn!("PCM pipe reader has exited, no longer forwarding");
+                self.sender = None;
+            }
+        }
+    }
+}
+
+impl Drop for PcmPipe {
+    fn drop(&mut self) {
+        // Closing the queue ends the writer thread and the child's stdin.
+        self.sender = None;
+        match self.child.wait() {
+            Ok(status) => info!("PCM pipe command exited with {}", status),
+            Err(e) => warn!("Failed to wait for PCM pipe command: {}", e)
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn forwards_every_byte_to_the_child() {
+        let path = std::env::temp_dir().join(format!("alarm-modem-{}-pipe", std::process::id()));
+        let mut pipe = PcmPipe::spawn(&format!("dd of={} status=none", path.display())).unwrap();
+        let samples: Vec<i16> = (0..1000).collect();
+        for _ in 0..10 {
+            pipe.forward(&samples);
+        }
+        drop(pipe);
+        let forwarded = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(forwarded.len(), 10 * samples.len() * 2);
+        assert_eq!(&forwarded[..4], &[0, 0, 1, 0]);
+    }
+}
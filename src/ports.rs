@@ -0,0 +1,165 @@
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use serialport::{available_ports, SerialPortInfo, SerialPortType};
+
+// USB vendors whose devices here are almost always voice modems: Conexant (the CX93010 in most USB 56K modems),
+// Zoom Telephonics and U.S. Robotics.
+const MODEM_VENDORS: [u16; 3] = [0x0572, 0x0803, 0x0baf];
+
+// Where the modem usually is when the ports can't be enumerated at all, e.g. without sysfs in a container.
+#[cfg(windows)]
+const FALLBACK_PORT: &str = "COM3";
+#[cfg(not(windows))]
+const FALLBACK_PORT: &str = "/dev/ttyACM0";
+
+fn describe(port_type: &SerialPortType) -> (&'static str, String) {
+    match port_type {
+        SerialPortType::UsbPort(usb) => {
+            let mut details = format!("{:04x}:{:04x}", usb.vid, usb.pid);
+            for part in [&usb.manufacturer, &usb.product, &usb.serial_number].into_iter().flatten() {
+                details.push(' ');
+                details.push_str(part);
+            }
+            ("USB", details)
+        },
+        SerialPortType::PciPort => ("PCI", String::new()),
+        SerialPortType::BluetoothPort => ("Bluetooth", String::new()),
+        SerialPortType::Unknown => ("Unknown", String::new())
+    }
+}
+
+// Render ports as an aligned table of name, type and USB details (VID:PID, manufacturer, product, serial).
+pub(crate) fn format_ports(ports: &[SerialPortInfo]) -> String {
+    if ports.is_empty() {
+        return "No serial ports found\n".to_string();
+    }
+
+    let rows: Vec<(&str, &str, String)> = ports.iter()
+        .map(|port| {
+            let (kind, details) = describe(&port.port_type);
+            (port.port_name.as_str(), kind, details)
+        })
+        .collect();
+    let name_width = rows.iter().map(|(name, _, _)| name.len()).max().unwrap_or(0).max("PORT".len());
+    let kind_width = rows.iter().map(|(_, kind, _)| kind.len()).max().unwrap_or(0).max("TYPE".len());
+
+    let mut table = format!("{:name_width$}  {:kind_width$}  DETAILS\n", "PORT", "TYPE");
+    for (name, kind, details) in rows {
+        table.push_str(format!("{:name_width$}  {:kind_width$}  {}", name, kind, details).trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+pub(crate) fn list_ports() -> Result<()> {
+    let ports = available_ports().context("Failed to enumerate serial ports")?;
+    print!("{}", format_ports(&ports));
+    Ok(())
+}
+
+// How likely a port is to be the modem, lower being likelier, or None for ports that aren't USB, like the built-in
+// UARTs at `/dev/ttyS*` that are listed whether or not anything is attached.
+fn modem_rank(port: &SerialPortInfo) -> Option<u8> {
+    let SerialPortType::UsbPort(usb) = &port.port_type else {
+        return None;
+    };
+    let named_modem = [&usb.manufacturer, &usb.product].into_iter().flatten()
+        .any(|name| name.to_ascii_lowercase().contains("modem"));
+    if named_modem || MODEM_VENDORS.contains(&usb.vid) {
+        return Some(0);
+    }
+    // USB modems are CDC ACM devices, where USB serial adapters are `ttyUSB` (or `usbserial` on macOS).
+    if port.port_name.contains("ACM") || port.port_name.contains("usbmodem") {
+        return Some(1);
+    }
+    Some(2)
+}
+
+// The likeliest modem among the ports, provided no other is as likely.
+pub(crate) fn choose_modem_port(ports: &[SerialPortInfo]) -> Option<&SerialPortInfo> {
+    let best = ports.iter().filter_map(modem_rank).min()?;
+    let mut likeliest = ports.iter().filter(|port| modem_rank(port) == Some(best));
+    let port = likeliest.next()?;
+    likeliest.next().is_none().then_some(port)
+}
+
+// The modem port for when ALARM_MODEM_PORT isn't set, chosen from the serial ports present. Without one to choose the
+// error lists them all, so the right one can be set.
+pub(crate) fn detect_modem_port() -> Result<String> {
+    let ports = match available_ports() {
+        Ok(ports) => ports,
+        Err(e) => {
+            warn!("Failed to enumerate serial ports ({}), using {}", e, FALLBACK_PORT);
+            return Ok(FALLBACK_PORT.to_string());
+        }
+    };
+    match choose_modem_port(&ports) {
+        Some(port) => {
+            info!("ALARM_MODEM_PORT is not set, using {} ({})", port.port_name, describe(&port.port_type).1);
+            Ok(port.port_name.clone())
+        },
+        None => Err(anyhow!("ALARM_MODEM_PORT is not set and no single port looks like the modem, set it to one of:\n{}",
+            format_ports(&ports).trim_end()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialport::UsbPortInfo;
+
+    fn usb(name: &str, vid: u16, product: Option<&str>) -> SerialPortInfo {
+        SerialPortInfo {
+            port_name: name.to_string(),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid,
+                pid: 0x1329,
+                serial_number: None,
+                manufacturer: None,
+                product: product.map(String::from)
+            })
+        }
+    }
+
+    fn builtin(name: &str) -> SerialPortInfo {
+        SerialPortInfo { port_name: name.to_string(), port_type: SerialPortType::Unknown }
+    }
+
+    #[test]
+    fn prefers_a_known_modem_vendor() {
+        let ports = [builtin("/dev/ttyS0"), usb("/dev/ttyUSB0", 0x0403, None), usb("/dev/ttyACM0", 0x0572, None)];
+        assert_eq!(choose_modem_port(&ports).unwrap().port_name, "/dev/ttyACM0");
+    }
+
+    #[test]
+    fn prefers_a_port_named_as_a_modem() {
+        let ports = [usb("/dev/ttyACM0", 0x1234, None), usb("/dev/ttyACM1", 0x1234, Some("USB 56K Modem"))];
+        assert_eq!(choose_modem_port(&ports).unwrap().port_name, "/dev/ttyACM1");
+    }
+
+    #[test]
+    fn prefers_cdc_acm_over_a_serial_adapter() {
+        let ports = [usb("/dev/ttyUSB0", 0x0403, None), usb("/dev/ttyACM0", 0x1234, None)];
+        assert_eq!(choose_modem_port(&ports).unwrap().port_name, "/dev/ttyACM0");
+    }
+
+    #[test]
+    fn chooses_nothing_when_ambiguous_or_absent() {
+        assert!(choose_modem_port(&[usb("/dev/ttyUSB0", 0x0403, None), usb("/dev/ttyUSB1", 0x0403, None)]).is_none());
+        assert!(choose_modem_port(&[builtin("/dev/ttyS0"), builtin("/dev/ttyS1")]).is_none());
+        assert!(choose_modem_port(&[]).is_none());
+    }
+
+    #[test]
+    fn formats_an_aligned_table() {
+        let mut modem = usb("/dev/ttyACM0", 0x0572, Some("USB Modem"));
+        if let SerialPortType::UsbPort(info) = &mut modem.port_type {
+            info.manufacturer = Some("Conexant".to_string());
+        }
+        let table = format_ports(&[modem, builtin("/dev/ttyS0")]);
+        assert_eq!(table, "PORT          TYPE     DETAILS\n\
+            /dev/ttyACM0  USB      0572:1329 Conexant USB Modem\n\
+            /dev/ttyS0    Unknown\n");
+        assert_eq!(format_ports(&[]), "No serial ports found\n");
+    }
+}
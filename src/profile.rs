@@ -0,0 +1,96 @@
+use std::env::var;
+use std::fs;
+use std::path::PathBuf;
+use anyhow::{anyhow, Context, Result};
+use log::info;
+
+const DEFAULT_PROFILE_DIR: &str = "profiles";
+
+// Profiles are saved as env files of the settings they hold, so loading one is just another layer of config.
+fn profile_path(name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(anyhow!("Invalid profile name {:?}", name));
+    }
+    let dir = var("ALARM_PROFILE_DIR").unwrap_or_else(|_| DEFAULT_PROFILE_DIR.to_string());
+    Ok(PathBuf::from(dir).join(format!("{}.env", name)))
+}
+
+pub(crate) fn save_profile(name: &str, settings: &[(&str, String)]) -> Result<()> {
+    let path = profile_path(name)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create profile directory {}", dir.display()))?;
+    }
+    let contents: String = settings.iter().map(|(key, value)| format!("{}={}\n", key, value)).collect();
+    fs::write(&path, contents).with_context(|| format!("Failed to write profile {}", path.display()))?;
+    info!("Saved profile {} to {}", name, path.display());
+    Ok(())
+}
+
+// Load the profile named by ALARM_PROFILE, if any. Variables already set, by the environment or .env, take precedence.
+pub(crate) fn load_profile() -> Result<()> {
+    let Ok(name) = var("ALARM_PROFILE") else {
+        return Ok(());
+    };
+    let path = profile_path(&name)?;
+    dotenv::from_path(&path).with_context(|| format!("Failed to load profile {}", path.display()))?;
+    info!("Loaded profile {} from {}", name, path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{lock_env, test_config, Config};
+
+    const SETTINGS: [&str; 3] = ["ALARM_POWER_UNIT", "ALARM_TONE_MIN_POWER", "ALARM_TONE_MAX_POWER"];
+
+    // Save a profile and load it back as startup would, returning the settings it left in the environment.
+    fn round_trip(saved: &[(&str, String)], already_set: &[(&str, &str)]) -> Vec<(&'static str, String)> {
+        let _env = lock_env();
+        let dir = std::env::temp_dir().join(format!("alarm-modem-{}-profiles", std::process::id()));
+        std::env::set_var("ALARM_PROFILE_DIR", &dir);
+        save_profile("site", saved).unwrap();
+        for (key, value) in already_set {
+            std::env::set_var(key, value);
+        }
+        std::env::set_var("ALARM_PROFILE", "site");
+        load_profile().unwrap();
+        let loaded = SETTINGS.iter().filter_map(|&key| Some((key, var(key).ok()?))).collect();
+        for key in SETTINGS.iter().chain(&["ALARM_PROFILE", "ALARM_PROFILE_DIR"]) {
+            std::env::remove_var(key);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+        loaded
+    }
+
+    fn config(settings: &[(&str, String)]) -> Config {
+        let settings: Vec<_> = settings.iter().map(|(key, value)| (*key, value.as_str())).collect();
+        test_config(&settings).unwrap()
+    }
+
+    #[test]
+    fn loaded_profile_gives_the_saved_thresholds() {
+        let saved = [
+            ("ALARM_POWER_UNIT", "db".to_string()),
+            ("ALARM_TONE_MIN_POWER", 61.23456f32.to_string()),
+            ("ALARM_TONE_MAX_POWER", 118.0f32.to_string())
+        ];
+        let loaded = config(&round_trip(&saved, &[]));
+        let expected = config(&saved);
+        assert_eq!(
+            (loaded.power_unit, loaded.tone_min_power, loaded.tone_max_power),
+            (expected.power_unit, expected.tone_min_power, expected.tone_max_power)
+        );
+
+        // The environment overrides what the profile holds.
+        let overridden = config(&round_trip(&saved, &[("ALARM_TONE_MAX_POWER", "100")]));
+        assert_eq!((overridden.tone_min_power, overridden.tone_max_power), (expected.tone_min_power, Some(100.0)));
+    }
+
+    #[test]
+    fn rejects_names_outside_the_profile_directory() {
+        for name in ["", "../etc", "a/b", ".hidden"] {
+            assert!(profile_path(name).is_err(), "accepted {:?}", name);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use log::{debug, error, info};
+use crate::audio::{Capture, PcmFormat, NO_CARRIER, SAMPLE_RATE};
+use crate::config::Config;
+use crate::dle::{DleDecoder, DleEvent};
+use crate::input::AudioSource;
+use crate::response::ResultCodeFilter;
+
+// A raw capture is a sequence of records, each a tag byte, a little-endian u32 length and that many bytes.
+// Every call starts with a call record naming the PCM format, followed by one read record per serial read
+// so the replay sees the same read boundaries as the live session did.
+const TAG_CALL: u8 = b'C';
+const TAG_READ: u8 = b'R';
+const RECORD_HEADER: usize = 5;
+
+// Tees every byte read from the modem during a call, before any decoding, to a file for replay with --replay-raw.
+// Calls are appended, so the capture covers every call since the file was last removed.
+pub(crate) struct RawCapture {
+    path: String,
+    writer: Option<BufWriter<File>>
+}
+
+impl RawCapture {
+    pub fn start(path: &str, format: PcmFormat) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .with_context(|| format!("Failed to open raw capture {}", path))?;
+        let mut capture = RawCapture { path: path.to_string(), writer: Some(BufWriter::new(file)) };
+        capture.record(TAG_CALL, format.name().as_bytes());
+        Ok(capture)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        self.record(TAG_READ, bytes);
+    }
+
+    // A failed write stops the capture rather than the call, the detection matters more than the recording of it.
+    fn record(&mut self, tag: u8, bytes: &[u8]) {
+        let Some(writer) = self.writer.as_mut() else {
+            return;
+        };
+        let result = writer.write_all(&[tag])
+            .and_then(|_| writer.write_all(&(bytes.len() as u32).to_le_bytes()))
+            .and_then(|_| writer.write_all(bytes))
+            .and_then(|_| writer.flush());
+        if let Err(e) = result {
+            error!("Failed to write raw capture {}, stopping it: {}", self.path, e);
+            self.writer = None;
+        }
+    }
+}
+
+enum Record {
+    Call(PcmFormat),
+    Read(Vec<u8>)
+}
+
+fn parse_records(bytes: &[u8]) -> Result<Vec<Record>> {
+    let mut records = Vec::new();
+    let mut position = 0;
+    while position < bytes.len() {
+        let header = bytes.get(position..position + RECORD_HEADER)
+            .ok_or_else(|| anyhow!("Truncated record header at byte {}", position))?;
+        let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        let start = position + RECORD_HEADER;
+        let body = bytes.get(start..start + len)
+            .ok_or_else(|| anyhow!("Truncated record at byte {}, expected {} bytes", position, len))?;
+        records.push(match header[0] {
+            TAG_CALL => Record::Call(String::from_utf8_lossy(body).parse()?),
+            TAG_READ => Record::Read(body.to_vec()),
+            tag => return Err(anyhow!("Unknown record tag {:#04x} at byte {}", tag, position))
+        });
+        position = start + len;
+    }
+    Ok(records)
+}
+
+// Replays a raw capture through the same DLE unescaping and PCM decoding as a live call, one serial read at a time.
+// Reads after a call's end (DLE ETX or NO CARRIER) are skipped, as the live session would have stopped listening.
+pub(crate) struct RawSource {
+    records: std::vec::IntoIter<Record>,
+    config: Arc<Config>,
+    call: Option<(DleDecoder, ResultCodeFilter, Capture)>,
+    audio: Vec<u8>,
+    tail: Vec<u8>,
+    paced: bool
+}
+
+impl RawSource {
+    pub fn open(path: &str, config: Arc<Config>, paced: bool) -> Result<Self> {
+        let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+        let records = parse_records(&bytes).with_context(|| format!("Failed to parse raw capture {}", path))?;
+        if !matches!(records.first(), Some(Record::Call(_))) {
+            return Err(anyhow!("Raw capture {} doesn't start with a call", path));
+        }
+        info!("Replaying raw capture {} ({} bytes)", path, bytes.len());
+        Ok(RawSource {
+            records: records.into_iter(),
+            config,
+            call: None,
+            audio: Vec::new(),
+            tail: Vec::with_capacity(NO_CARRIER.len()),
+            paced
+        })
+    }
+}
+
+impl AudioSource for RawSource {
+    fn name(&self) -> &'static str {
+        "raw replay"
+    }
+
+    fn format(&self) -> PcmFormat {
+        self.call.as_ref().map_or(PcmFormat::SignedLe16, |(_, _, capture)| capture.format)
+    }
+
+    fn next_samples(&mut self) -> Result<Option<Vec<i16>>> {
+        let bytes = match self.records.next() {
+            Some(Record::Call(format)) => {
+                debug!("Replaying a call in {:?} PCM", format);
+                self.call = Some((DleDecoder::new(self.config.strip_xon_xoff), ResultCodeFilter::new(), Capture::new(format, &self.config)));
+                self.audio.clear();
+                self.tail.clear();
+                return Ok(Some(Vec::new()));
+            },
+            Some(Record::Read(bytes)) => bytes,
+            None => return Ok(None)
+        };
+        let Some((dle, results, capture)) = self.call.as_mut() else {
+            return Ok(Some(Vec::new()));
+        };
+
+        let mut unescaped = Vec::with_capacity(bytes.len());
+        let events = dle.feed(&bytes, &mut unescaped);
+        for code in results.feed(&unescaped, &mut self.audio) {
+            info!("Modem sent {} within the audio stream, dropped it from the audio", code);
+        }
+        self.tail.extend_from_slice(&bytes);
+        if events.contains(&DleEvent::EndOfStream) || self.tail.windows(NO_CARRIER.len()).any(|w| w == NO_CARRIER) {
+            debug!("Replayed call ended");
+            let samples = capture.finish(&mut self.audio);
+            self.call = None;
+            return Ok(Some(samples));
+        }
+        self.tail.drain(..self.tail.len().saturating_sub(NO_CARRIER.len() - 1));
+
+        let samples = capture.take_samples(&mut self.audio);
+        if self.paced {
+            sleep(Duration::from_secs_f32(samples.len() as f32 / SAMPLE_RATE));
+        }
+        Ok(Some(samples))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use crate::audio::{listen, Detector};
+    use crate::config::test_config;
+    use crate::dle::{DLE, ETX};
+    use crate::input::run_source;
+    use crate::mock_port::MockPort;
+    use crate::sink::{CallbackSink, DetectionSink};
+    use crate::stats::SessionStats;
+    use crate::watchdog::Watchdog;
+    use crate::webhook::AlertPayload;
+
+    // Sinks noting each detection they hear of in `events`.
+    fn noting(events: &Arc<Mutex<Vec<&'static str>>>) -> Vec<Box<dyn DetectionSink>> {
+        let seen = events.clone();
+        vec![Box::new(CallbackSink(move |payload: &AlertPayload| seen.lock().unwrap().push(payload.event)))]
+    }
+
+    // A live call, DLE shielded and split into odd reads, detects the same when its capture is replayed.
+    #[test]
+    fn replaying_a_capture_reproduces_the_detections() {
+        let path = std::env::temp_dir().join(format!("alarm-modem-{}-capture.raw", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let config = Arc::new(test_config(&[
+            ("ALARM_CAPTURE_RAW", path.to_str().unwrap()),
+            ("ALARM_TONE_MIN_POWER", "1e6"),
+            ("ALARM_TONE_MAX_POWER", "1e20"),
+            ("ALARM_ANSWER_TONE_MIN_POWER", "1e10"),
+            ("ALARM_MIN_GAP_MS", "100")
+        ]).unwrap());
+        let mut stream: Vec<u8> = (0..8192)
+            .map(|i| (1000.0 * (2.0 * std::f32::consts::PI * 1665.0 * i as f32 / 8000.0).sin()) as i16)
+            .flat_map(|sample| sample.to_le_bytes())
+            .flat_map(|byte| if byte == DLE { vec![DLE, DLE] } else { vec![byte] })
+            .collect();
+        stream.extend([DLE, ETX]);
+        let mut port = MockPort::with_reads(stream.chunks(333).map(<[u8]>::to_vec));
+
+        let (live, replayed) = (Arc::new(Mutex::new(Vec::new())), Arc::new(Mutex::new(Vec::new())));
+        let mut detector = Detector::new(config.clone()).unwrap();
+        let mut stats = SessionStats::new(None);
+        let format = PcmFormat::SignedLe16;
+        listen(&mut port, format, &mut detector, &mut noting(&live), &mut stats, &mut Watchdog::new(), None).unwrap();
+
+        let mut source = RawSource::open(path.to_str().unwrap(), config.clone(), false).unwrap();
+        run_source(&mut source, &mut Detector::new(config).unwrap(), &mut noting(&replayed), &mut stats).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(*live.lock().unwrap(), ["trigger"]);
+        assert_eq!(*replayed.lock().unwrap(), *live.lock().unwrap());
+    }
+}
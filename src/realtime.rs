@@ -0,0 +1,58 @@
+use log::{info, warn};
+
+// Ask for real-time scheduling of the calling thread, which runs the serial read loop, so a loaded system doesn't
+// starve it and drop PCM. Best effort: running without the privilege for it only gets a warning.
+pub(crate) fn raise(priority: i32) {
+    match set_priority(priority) {
+        Ok(()) => info!("Read loop running with real-time priority {}", priority),
+        Err(reason) => warn!("Failed to raise the read loop to real-time priority {}, continuing without: {}", priority, reason)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_priority(priority: i32) -> Result<(), String> {
+    // Safety: plain queries of the valid SCHED_FIFO range.
+    let (min, max) = unsafe { (libc::sched_get_priority_min(libc::SCHED_FIFO), libc::sched_get_priority_max(libc::SCHED_FIFO)) };
+    if !(min..=max).contains(&priority) {
+        return Err(format!("SCHED_FIFO priorities range from {} to {}", min, max));
+    }
+    let param = libc::sched_param { sched_priority: priority };
+    // Safety: the parameter outlives the call, which only applies to the current thread.
+    match unsafe { libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) } {
+        0 => Ok(()),
+        libc::EPERM => Err("not permitted, it needs CAP_SYS_NICE or an RLIMIT_RTPRIO allowance".to_string()),
+        error => Err(std::io::Error::from_raw_os_error(error).to_string())
+    }
+}
+
+// Windows has no numbered real-time priorities, any priority asks for the highest thread priority.
+#[cfg(windows)]
+fn set_priority(_priority: i32) -> Result<(), String> {
+    use windows_sys::Win32::System::Threading::{GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_TIME_CRITICAL};
+    // Safety: the pseudo handle always refers to the current thread and needs no closing.
+    match unsafe { SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_TIME_CRITICAL) } {
+        0 => Err(std::io::Error::last_os_error().to_string()),
+        _ => Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn set_priority(_priority: i32) -> Result<(), String> {
+    Err("real-time scheduling is only supported on Linux and Windows".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    // A priority that can't be set is refused with the reason, and raising to it only warns so the loop carries on.
+    #[test]
+    fn failing_to_raise_the_priority_continues() {
+        let reason = set_priority(1000).unwrap_err();
+        assert!(!reason.is_empty());
+        #[cfg(target_os = "linux")]
+        assert!(reason.starts_with("SCHED_FIFO priorities range from"), "{}", reason);
+        thread::spawn(|| raise(1000)).join().expect("raising the priority panicked");
+    }
+}
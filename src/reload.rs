@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::env::{remove_var, set_var, vars_os};
+use std::ffi::OsString;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use anyhow::Result;
+use log::{error, info, warn};
+use crate::audio::Detector;
+use crate::config::Config;
+use crate::config_file::{load_config, load_config_file};
+use crate::profile::load_profile;
+use crate::sink::DetectionSink;
+
+// Set on SIGHUP, polled by the read loops so a reload happens between reads rather than inside the handler.
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+// The process environment from before .env, the profile and the config file were layered on, restored before they're
+// re-read.
+static BASE_ENV: OnceLock<HashMap<OsString, OsString>> = OnceLock::new();
+
+// Must run before .env is loaded, so a reload can tell what it set apart from the real environment.
+pub(crate) fn snapshot_env() {
+    BASE_ENV.get_or_init(|| vars_os().collect());
+}
+
+#[cfg(unix)]
+extern "C" fn on_sighup(_: libc::c_int) {
+    RELOAD.store(true, Ordering::Relaxed);
+}
+
+#[cfg(unix)]
+pub(crate) fn install() -> Result<()> {
+    // Safety: the handler only stores to an atomic, which is async-signal-safe.
+    let previous = unsafe { libc::signal(libc::SIGHUP, on_sighup as extern "C" fn(libc::c_int) as libc::sighandler_t) };
+    if previous == libc::SIG_ERR {
+        return Err(anyhow::anyhow!("Failed to install SIGHUP handler: {}", std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn install() -> Result<()> {
+    Ok(())
+}
+
+// Layer .env, the profile and the config file over the original environment again, so edited and removed settings both take effect,
+// and rebuild the config. The serial session stays open across a reload, so settings that only apply when the port
+// is opened keep their current values.
+fn reread(current: &Config) -> Result<Config> {
+    if let Some(base) = BASE_ENV.get() {
+        for (key, _) in vars_os().filter(|(key, _)| !base.contains_key(key)) {
+            remove_var(key);
+        }
+        for (key, value) in base {
+            set_var(key, value);
+        }
+    }
+    dotenv::dotenv().ok();
+    load_profile()?;
+    load_config_file()?;
+
+    let mut config = load_config()?;
+    if config.modem_port != current.modem_port {
+        warn!("ALARM_MODEM_PORT can't change without a restart, keeping {}", current.modem_port);
+        config.modem_port = current.modem_port.clone();
+    }
+    if config.modem_baud != current.modem_baud {
+        warn!("ALARM_MODEM_BAUD can't change without a restart, keeping {}", current.modem_baud);
+        config.modem_baud = current.modem_baud;
+    }
+    if config.modem_vgr != current.modem_vgr {
+        warn!("ALARM_MODEM_VGR can't change without a restart, keeping the current receive gain");
+        config.modem_vgr = current.modem_vgr;
+    }
+    Ok(config)
+}
+
+// Apply a pending SIGHUP reload to the detector and sinks. A config that fails to load or apply is logged and
+// the running one kept.
+pub(crate) fn apply_pending(detector: &mut Detector, sinks: &mut [Box<dyn DetectionSink>]) {
+    if !RELOAD.swap(false, Ordering::Relaxed) {
+        return;
+    }
+
+    info!("Reloading config");
+    let config = match reread(&detector.config()) {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            error!("Failed to reload config, keeping the current one: {:#}", e);
+            return;
+        }
+    };
+    if let Err(e) = detector.reload(config.clone()) {
+        error!("Failed to apply reloaded config, keeping the current one: {:#}", e);
+        return;
+    }
+    for sink in sinks.iter_mut() {
+        sink.reload(&config);
+    }
+    info!("Config reloaded");
+}
@@ -0,0 +1,80 @@
+use std::fs;
+use std::thread::sleep;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use log::info;
+use crate::audio::{resample, FFT_SAMPLE_SIZE, SAMPLE_RATE};
+use crate::input::AudioSource;
+use crate::wav::decode_wav;
+
+// A WAV recording played through detection, resampled to the processing rate. When paced it's fed in real time,
+// so cooldowns and other wall-clock timing behave as they would on a live call.
+pub(crate) struct WavSource {
+    samples: Vec<i16>,
+    position: usize,
+    paced: bool
+}
+
+impl WavSource {
+    pub fn open(path: &str, paced: bool) -> Result<Self> {
+        let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+        let wav = decode_wav(&bytes).with_context(|| format!("Failed to decode {}", path))?;
+        info!("Replaying {} ({} samples at {} Hz)", path, wav.samples.len(), wav.sample_rate);
+        Ok(WavSource { samples: resample(&wav.samples, wav.sample_rate, SAMPLE_RATE as u32), position: 0, paced })
+    }
+}
+
+impl AudioSource for WavSource {
+    fn name(&self) -> &'static str {
+        "WAV replay"
+    }
+
+    fn next_samples(&mut self) -> Result<Option<Vec<i16>>> {
+        if self.position >= self.samples.len() {
+            return Ok(None);
+        }
+        let end = (self.position + FFT_SAMPLE_SIZE).min(self.samples.len());
+        let chunk = self.samples[self.position..end].to_vec();
+        self.position = end;
+        if self.paced {
+            sleep(Duration::from_secs_f32(chunk.len() as f32 / SAMPLE_RATE));
+        }
+        Ok(Some(chunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::audio::Detector;
+    use crate::config::test_config;
+    use crate::wav::encode_wav;
+
+    #[test]
+    fn replays_a_48_khz_recording_at_the_processing_rate() {
+        let path = std::env::temp_dir().join(format!("alarm-modem-{}-48k.wav", std::process::id()));
+        let recording: Vec<i16> = (0..48000)
+            .map(|i| (1000.0 * (2.0 * std::f32::consts::PI * 1665.0 * i as f32 / 48000.0).sin()) as i16)
+            .collect();
+        fs::write(&path, encode_wav(&recording, 48000)).unwrap();
+        let mut source = WavSource::open(path.to_str().unwrap(), false).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut samples = Vec::new();
+        while let Some(chunk) = source.next_samples().unwrap() {
+            samples.extend(chunk);
+        }
+        assert_eq!(samples.len(), 8000);
+
+        let config = test_config(&[
+            ("ALARM_TONE_MIN_POWER", "1e6"),
+            ("ALARM_TONE_MAX_POWER", "1e20"),
+            ("ALARM_ANSWER_TONE_MIN_POWER", "1e10"),
+            ("ALARM_MIN_GAP_MS", "100")
+        ]).unwrap();
+        let events = Detector::new(Arc::new(config)).unwrap().process(&samples);
+        let frequency = events.first().and_then(|event| event.frequency).expect("no detection in the replay");
+        assert!((frequency - 1665.0).abs() < 4.0, "detected at {} Hz", frequency);
+    }
+}
@@ -0,0 +1,292 @@
+const MAX_LINE_LEN: usize = 256; // Longer lines are noise, e.g. audio still arriving after leaving voice mode
+
+// Result codes that end a command's response.
+const FINAL_RESULTS: [&str; 8] = ["OK", "CONNECT", "ERROR", "NO CARRIER", "BUSY", "NO DIALTONE", "NO DIAL TONE", "NO ANSWER"];
+
+fn is_final_result(line: &str) -> bool {
+    FINAL_RESULTS.iter().any(|code| line == *code || (*code == "CONNECT" && line.starts_with("CONNECT ")))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CommandResponse {
+    pub command: String,
+    pub lines: Vec<String>,     // Information lines between the echo and the result code
+    pub result: Option<String>  // Final result code, if one arrived
+}
+
+// How a command ended: a successful result code, any other result code, or none before the timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CommandStatus {
+    Ok,
+    Error,
+    Timeout
+}
+
+impl CommandResponse {
+    pub fn status(&self) -> CommandStatus {
+        match self.result.as_deref() {
+            Some("OK") => CommandStatus::Ok,
+            Some(result) if result.starts_with("CONNECT") => CommandStatus::Ok,
+            Some(_) => CommandStatus::Error,
+            None => CommandStatus::Timeout
+        }
+    }
+
+    // The response as the modem sent it, less the echo, one line per line.
+    pub fn text(&self) -> String {
+        self.lines.iter().chain(&self.result).cloned().collect::<Vec<_>>().join("\n")
+    }
+}
+
+// What a result code says of the phone line, so a dead line is reported rather than monitored as though all is well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineStatus {
+    Healthy,
+    Busy,
+    NoDialtone,
+    NoCarrier,
+    NoAnswer,
+    Error
+}
+
+impl LineStatus {
+    pub(crate) fn from_result(code: &str) -> Self {
+        match code {
+            "BUSY" => LineStatus::Busy,
+            "NO DIALTONE" | "NO DIAL TONE" => LineStatus::NoDialtone,
+            "NO CARRIER" => LineStatus::NoCarrier,
+            "NO ANSWER" => LineStatus::NoAnswer,
+            "ERROR" => LineStatus::Error,
+            _ => LineStatus::Healthy
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            LineStatus::Healthy => "line_healthy",
+            LineStatus::Busy => "line_busy",
+            LineStatus::NoDialtone => "line_no_dialtone",
+            LineStatus::NoCarrier => "line_no_carrier",
+            LineStatus::NoAnswer => "line_no_answer",
+            LineStatus::Error => "line_error"
+        }
+    }
+}
+
+// Length of a UTF-8 sequence cut short at the end of `bytes`, to be held until the rest of it arrives.
+fn incomplete_tail(bytes: &[u8]) -> usize {
+    for back in 1..=bytes.len().min(3) {
+        let byte = bytes[bytes.len() - back];
+        if byte & 0xc0 == 0x80 {
+            continue; // Continuation byte, the sequence starts further back
+        }
+        let len = match byte {
+            0xf0.. => 4,
+            0xe0.. => 3,
+            0xc0.. => 2,
+            _ => 1
+        };
+        return if len > back { back } else { 0 };
+    }
+    0
+}
+
+// Incrementally assembles the response to a single command from however the bytes happen to be chunked,
+// skipping the command's own echo and stopping at the final result code.
+pub(crate) struct ResponseParser {
+    command: String,
+    partial: String,
+    undecoded: Vec<u8>, // Start of a character split across reads
+    lines: Vec<String>,
+    result: Option<String>
+}
+
+impl ResponseParser {
+    pub fn new(command: &str) -> Self {
+        ResponseParser {
+            command: command.trim().to_string(),
+            partial: String::new(),
+            undecoded: Vec::new(),
+            lines: Vec::new(),
+            result: None
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.result.is_some()
+    }
+
+    // Feed the next chunk read from the port, returning true once the final result code has been seen.
+    pub fn feed(&mut self, bytes: &[u8]) -> bool {
+        let mut pending = std::mem::take(&mut self.undecoded);
+        pending.extend_from_slice(bytes);
+        let complete = pending.len() - incomplete_tail(&pending);
+        self.undecoded = pending.split_off(complete);
+        self.push_text(&String::from_utf8_lossy(&pending));
+        self.is_complete()
+    }
+
+    fn push_text(&mut self, text: &str) {
+        for c in text.chars() {
+            if self.is_complete() {
+                break;
+            }
+            if c == '\r' || c == '\n' {
+                let line = std::mem::take(&mut self.partial);
+                self.push_line(line.trim());
+            } else if !c.is_control() && self.partial.len() < MAX_LINE_LEN {
+                self.partial.push(c);
+            }
+        }
+    }
+
+    fn push_line(&mut self, line: &str) {
+        if line.is_empty() {
+            return;
+        }
+        if self.lines.is_empty() && line.eq_ignore_ascii_case(&self.command) {
+            return; // Echo of the command itself
+        }
+        if is_final_result(line) {
+            self.result = Some(line.to_string());
+        } else {
+            self.lines.push(line.to_string());
+        }
+    }
+
+    // Finish parsing, treating any unterminated trailing text as a final line.
+    pub fn finish(mut self) -> CommandResponse {
+        if !self.is_complete() {
+            let undecoded = std::mem::take(&mut self.undecoded);
+            self.push_text(&String::from_utf8_lossy(&undecoded));
+            let line = std::mem::take(&mut self.partial);
+            self.push_line(line.trim());
+        }
+        CommandResponse { command: self.command, lines: self.lines, result: self.result }
+    }
+}
+
+// Result codes a modem can send mid-stream, e.g. leaving voice receive, which would otherwise be read as PCM.
+const STREAM_RESULTS: [&str; 9] = ["OK", "CONNECT", "ERROR", "NO CARRIER", "BUSY", "NO DIALTONE", "NO DIAL TONE", "NO ANSWER", "RING"];
+
+// Splits result codes framed as `\r\n<code>\r\n` out of unescaped audio. A tail that could still be the start of one
+// is held back until the next read settles it, so a code split across reads is caught too.
+pub(crate) struct ResultCodeFilter {
+    held: Vec<u8>
+}
+
+impl ResultCodeFilter {
+    pub fn new() -> Self {
+        ResultCodeFilter { held: Vec::new() }
+    }
+
+    // Append `data` to `audio` minus any embedded result codes, returning the codes found.
+    pub fn feed(&mut self, data: &[u8], audio: &mut Vec<u8>) -> Vec<&'static str> {
+        let mut buffer = std::mem::take(&mut self.held);
+        buffer.extend_from_slice(data);
+
+        let mut codes = Vec::new();
+        let mut position = 0;
+        'scan: while position < buffer.len() {
+            if buffer[position] == b'\r' {
+                let rest = &buffer[position..];
+                for code in STREAM_RESULTS {
+                    let framed = [b"\r\n", code.as_bytes(), b"\r\n"].concat();
+                    if rest.starts_with(&framed) {
+                        codes.push(code);
+                        position += framed.len();
+                        continue 'scan;
+                    }
+                    if framed.starts_with(rest) {
+                        self.held = rest.to_vec();
+                        break 'scan;
+                    }
+                }
+            }
+            audio.push(buffer[position]);
+            position += 1;
+        }
+        codes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn parse(command: &str, bytes: &[u8]) -> CommandResponse {
+        let mut parser = ResponseParser::new(command);
+        parser.feed(bytes);
+        parser.finish()
+    }
+
+    #[test]
+    fn status_follows_result_code() {
+        assert_eq!(parse("AT", b"AT\r\r\nOK\r\n").status(), CommandStatus::Ok);
+        assert_eq!(parse("AT+VRX", b"\r\nCONNECT\r\n").status(), CommandStatus::Ok);
+        assert_eq!(parse("AT+VCID=1", b"\r\nERROR\r\n").status(), CommandStatus::Error);
+        assert_eq!(parse("AT", b"").status(), CommandStatus::Timeout);
+    }
+
+    #[test]
+    fn line_status_from_result_codes() {
+        assert_eq!(LineStatus::from_result("NO DIAL TONE"), LineStatus::NoDialtone);
+        assert_eq!(LineStatus::from_result("BUSY").label(), "line_busy");
+        assert_eq!(LineStatus::from_result("CONNECT"), LineStatus::Healthy);
+        assert_eq!(LineStatus::from_result("RING"), LineStatus::Healthy);
+    }
+
+    #[test]
+    fn text_keeps_lines_and_result() {
+        let response = parse("AT+VSM=?", b"AT+VSM=?\r\n1,\"UNSIGNED PCM\",8,0,8000,0,0\r\n\r\nOK\r\n");
+        assert_eq!(response.text(), "1,\"UNSIGNED PCM\",8,0,8000,0,0\nOK");
+        assert_eq!(parse("AT", b"").text(), "");
+    }
+
+    // A result code between samples comes out as itself, split across reads or not, leaving the samples either side.
+    #[test]
+    fn separates_a_result_code_from_the_audio() {
+        let (before, after) = ([0x10, 0x7f, b'\r', 0x80], [b'\n', 0x01, 0x02]);
+        let stream = [&before[..], b"\r\nNO CARRIER\r\n", &after].concat();
+        for split in [stream.len(), 9, 14] {
+            let mut filter = ResultCodeFilter::new();
+            let mut audio = Vec::new();
+            let mut codes = filter.feed(&stream[..split], &mut audio);
+            codes.extend(filter.feed(&stream[split..], &mut audio));
+            assert_eq!(codes, ["NO CARRIER"], "split at {}", split);
+            assert_eq!(audio, [&before[..], &after].concat(), "split at {}", split);
+        }
+    }
+
+    proptest! {
+        // However the bytes are chunked the parser settles on the same response, and a line never outgrows its cap.
+        #[test]
+        fn parses_any_stream_the_same_however_chunked(
+            raw in proptest::collection::vec(any::<u8>(), 0..1024),
+            sizes in proptest::collection::vec(1usize..64, 0..32)
+        ) {
+            let mut chunked = ResponseParser::new("AT");
+            let mut rest = raw.as_slice();
+            for size in sizes {
+                let (chunk, tail) = rest.split_at(size.min(rest.len()));
+                chunked.feed(chunk);
+                prop_assert!(chunked.partial.len() < MAX_LINE_LEN + 4);
+                rest = tail;
+            }
+            chunked.feed(rest);
+            prop_assert_eq!(chunked.finish(), parse("AT", &raw));
+        }
+
+        #[test]
+        fn filters_any_stream_without_losing_audio(raw in proptest::collection::vec(
+            prop_oneof![Just(b'\r'), Just(b'\n'), Just(b'O'), Just(b'K'), any::<u8>()], 0..512
+        )) {
+            let mut filter = ResultCodeFilter::new();
+            let mut audio = Vec::new();
+            let codes = filter.feed(&raw, &mut audio);
+            let removed: usize = codes.iter().map(|code| code.len() + 4).sum();
+            prop_assert_eq!(audio.len() + filter.held.len() + removed, raw.len());
+        }
+    }
+}
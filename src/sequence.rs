@@ -0,0 +1,120 @@
+use std::time::Duration;
+use crate::config::{FrequencyBand, ToneSequence};
+
+// Follows a tone sequence through the stream a block at a time, measured in stream time like the cadence detector. A
+// step is heard once its tone has held for its minimum, after which the next step's tone must start within the gap and
+// the whole sequence must finish within the window, or matching starts over.
+pub(crate) struct SequenceMatcher {
+    sequence: ToneSequence,
+    step: usize,
+    held: Duration,    // How long the current step's tone has been heard
+    gap: Duration,     // Since the previous step's tone ended
+    elapsed: Duration  // Since the first step's tone started
+}
+
+impl SequenceMatcher {
+    pub fn new(sequence: ToneSequence) -> Self {
+        SequenceMatcher { sequence, step: 0, held: Duration::ZERO, gap: Duration::ZERO, elapsed: Duration::ZERO }
+    }
+
+    pub fn sequence(&self) -> &ToneSequence {
+        &self.sequence
+    }
+
+    // Start over from the first step, e.g. for a new call.
+    pub fn reset(&mut self) {
+        self.step = 0;
+        self.held = Duration::ZERO;
+        self.gap = Duration::ZERO;
+        self.elapsed = Duration::ZERO;
+    }
+
+    // Feed the next `block` of audio, `detect` giving the peak frequency of a tone within a band if there is one.
+    // Returns the frequency the last step was heard at once the whole sequence has been.
+    pub fn update(&mut self, detect: impl Fn(&FrequencyBand) -> Option<f32>, block: Duration) -> Option<f32> {
+        if self.step > 0 || !self.held.is_zero() {
+            self.elapsed += block;
+            if self.elapsed > self.sequence.window {
+                self.reset();
+            }
+        }
+
+        let step = self.sequence.steps[self.step];
+        let heard = detect(&step.band);
+        if heard.is_none() && !self.held.is_zero() && self.held >= step.min_on {
+            // The step is complete, the block counting towards the next step's tone or its gap.
+            self.step += 1;
+            self.held = Duration::ZERO;
+            self.gap = Duration::ZERO;
+            return self.advance(detect(&self.sequence.steps[self.step].band), block);
+        }
+        self.advance(heard, block)
+    }
+
+    fn advance(&mut self, heard: Option<f32>, block: Duration) -> Option<f32> {
+        match heard {
+            Some(frequency) => {
+                self.held += block;
+                self.gap = Duration::ZERO;
+                if self.step + 1 == self.sequence.steps.len() && self.held >= self.sequence.steps[self.step].min_on {
+                    self.reset();
+                    return Some(frequency);
+                }
+            },
+            // A tone that stops short of its minimum isn't the step.
+            None if !self.held.is_zero() => self.reset(),
+            None if self.step > 0 => {
+                self.gap += block;
+                if self.gap > self.sequence.steps[self.step - 1].max_gap {
+                    self.reset();
+                }
+            },
+            None => {}
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SequenceStep;
+
+    const BLOCK: Duration = Duration::from_millis(10);
+
+    fn handshake() -> SequenceMatcher {
+        let step = |min, max, gap| SequenceStep {
+            band: FrequencyBand { min, max },
+            min_on: Duration::from_millis(50),
+            max_gap: Duration::from_millis(gap)
+        };
+        let steps = vec![step(1360.0, 1440.0, 300), step(2260.0, 2340.0, 0)];
+        SequenceMatcher::new(ToneSequence { steps, window: Duration::from_secs(1) })
+    }
+
+    // Runs of (frequency heard, or 0 for none, and for how many ms), returning what each block matched.
+    fn feed(matcher: &mut SequenceMatcher, timeline: &[(f32, u64)]) -> Vec<f32> {
+        let blocks = timeline.iter().flat_map(|&(frequency, ms)| std::iter::repeat_n(frequency, (ms / 10) as usize));
+        blocks
+            .filter_map(|frequency| matcher.update(|band| band.contains(frequency).then_some(frequency), BLOCK))
+            .collect()
+    }
+
+    #[test]
+    fn matches_the_steps_in_order_within_their_limits() {
+        let mut matcher = handshake();
+        assert_eq!(feed(&mut matcher, &[(1400.0, 60), (0.0, 100), (2300.0, 60), (0.0, 100)]), [2300.0]);
+        // Straight from one tone to the next counts too.
+        assert_eq!(feed(&mut matcher, &[(1400.0, 60), (2300.0, 60)]), [2300.0]);
+
+        for timeline in [
+            [(2300.0, 60), (0.0, 100), (1400.0, 60)], // Out of order
+            [(1400.0, 30), (0.0, 100), (2300.0, 60)], // First tone too short
+            [(1400.0, 60), (0.0, 400), (2300.0, 60)], // Gap too long
+            [(1400.0, 900), (0.0, 100), (2300.0, 60)] // Past the window
+        ] {
+            let mut matcher = handshake();
+            assert!(feed(&mut matcher, &timeline).is_empty(), "matched {:?}", timeline);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use anyhow::Result;
+use log::error;
+use crate::config::Config;
+use crate::stats::SessionStats;
+use crate::webhook::AlertPayload;
+
+pub(crate) trait DetectionSink {
+    fn name(&self) -> &'static str;
+    fn on_detection(&mut self, payload: &AlertPayload) -> Result<()>;
+
+    // Pick up a config reloaded on SIGHUP, for sinks whose settings can change at runtime.
+    fn reload(&mut self, _config: &Config) {}
+
+    // Told which sinks failed the detection just dispatched, for sinks that record how it was delivered.
+    fn on_delivered(&mut self, _failed: &[&'static str]) {}
+
+    // Called on every pass of the read loop, for sinks with periodic work of their own.
+    fn tick(&mut self, _stats: &SessionStats) {}
+}
+
+// Deliver to every sink, isolating each so an error or panic in one doesn't stop the rest.
+// Returns the names of the sinks that failed, which each sink is also told once they've all had it.
+pub(crate) fn dispatch(sinks: &mut [Box<dyn DetectionSink>], payload: &AlertPayload) -> Vec<&'static str> {
+    let mut failed = Vec::new();
+    for sink in sinks.iter_mut() {
+        match catch_unwind(AssertUnwindSafe(|| sink.on_detection(payload))) {
+            Ok(Ok(())) => continue,
+            Ok(Err(e)) => error!("Sink {} failed to handle detection: {:#}", sink.name(), e),
+            Err(_) => error!("Sink {} panicked while handling detection", sink.name())
+        }
+        failed.push(sink.name());
+    }
+    sinks.iter_mut().for_each(|sink| sink.on_delivered(&failed));
+    failed
+}
+
+// Hands each detection to a closure, for a program embedding detection that handles delivery itself.
+pub(crate) struct CallbackSink<F>(pub F);
+
+impl<F: FnMut(&AlertPayload)> DetectionSink for CallbackSink<F> {
+    fn name(&self) -> &'static str {
+        "callback"
+    }
+
+    fn on_detection(&mut self, payload: &AlertPayload) -> Result<()> {
+        (self.0)(payload);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use anyhow::anyhow;
+
+    struct Broken {
+        panics: bool
+    }
+
+    impl DetectionSink for Broken {
+        fn name(&self) -> &'static str {
+            if self.panics { "panicking" } else { "failing" }
+        }
+
+        fn on_detection(&mut self, _payload: &AlertPayload) -> Result<()> {
+            if self.panics {
+                panic!("sink panicked");
+            }
+            Err(anyhow!("sink failed"))
+        }
+    }
+
+    #[test]
+    fn a_failing_sink_does_not_stop_the_others() {
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let seen = received.clone();
+        let mut sinks: Vec<Box<dyn DetectionSink>> = vec![
+            Box::new(Broken { panics: true }),
+            Box::new(Broken { panics: false }),
+            Box::new(CallbackSink(move |payload: &AlertPayload| seen.borrow_mut().push(payload.event)))
+        ];
+        let payload = AlertPayload {
+            event: "trigger",
+            priority: "normal",
+            timestamp: 0,
+            offset_ms: None,
+            frequency_hz: None,
+            level_dbfs: None,
+            audio_wav_base64: None,
+            contact_id: None,
+            fsk_data_hex: None,
+            caller: None
+        };
+        assert_eq!(dispatch(&mut sinks, &payload), ["panicking", "failing"]);
+        assert_eq!(*received.borrow(), ["trigger"]);
+    }
+}
@@ -0,0 +1,121 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use log::{debug, info};
+use serde::Serialize;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The most recent in-band bin levels, downsampled for a live view to poll.
+#[derive(Serialize, Default, Clone)]
+struct Snapshot {
+    min_hz: f32,
+    max_hz: f32,
+    unit: &'static str,
+    bins: Vec<f32>
+}
+
+// Shared between the detector, which replaces it each block, and the HTTP thread serving it.
+#[derive(Clone)]
+pub(crate) struct SpectrumSnapshot {
+    inner: Arc<Mutex<Snapshot>>,
+    bins: usize
+}
+
+impl SpectrumSnapshot {
+    pub fn new(bins: usize) -> Self {
+        SpectrumSnapshot { inner: Arc::new(Mutex::new(Snapshot::default())), bins }
+    }
+
+    pub fn update(&self, min_hz: f32, max_hz: f32, unit: &'static str, levels: &[f32]) {
+        let snapshot = Snapshot { min_hz, max_hz, unit, bins: downsample(levels, self.bins) };
+        if let Ok(mut inner) = self.inner.lock() {
+            *inner = snapshot;
+        }
+    }
+
+    fn to_json(&self) -> Result<String> {
+        let snapshot = self.inner.lock().map(|inner| inner.clone()).unwrap_or_default();
+        Ok(serde_json::to_string(&snapshot)?)
+    }
+}
+
+// Reduce to at most `bins` values, each the loudest of the bins it covers so a narrow tone isn't averaged away.
+fn downsample(levels: &[f32], bins: usize) -> Vec<f32> {
+    if levels.len() <= bins {
+        return levels.to_vec();
+    }
+    (0..bins).map(|i| {
+        let start = i * levels.len() / bins;
+        let end = (i + 1) * levels.len() / bins;
+        levels[start..end].iter().copied().fold(f32::MIN, f32::max)
+    }).collect()
+}
+
+fn respond(mut stream: TcpStream, snapshot: &SpectrumSnapshot) -> Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+
+    let (status, body) = match path {
+        "/spectrum" => ("200 OK", snapshot.to_json()?),
+        _ => ("404 Not Found", "{\"error\":\"not found\"}".to_string())
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body
+    )?;
+    Ok(())
+}
+
+// Serve the snapshot as JSON at `/spectrum` on a background thread, one short-lived connection per poll.
+pub(crate) fn serve(addr: &str, snapshot: SpectrumSnapshot) -> Result<()> {
+    let listener = TcpListener::bind(addr).with_context(|| format!("Failed to bind spectrum server to {}", addr))?;
+    info!("Serving the live spectrum at http://{}/spectrum", listener.local_addr()?);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(stream, &snapshot) {
+                debug!("Failed to answer spectrum request: {:#}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::audio::Detector;
+    use crate::config::test_config;
+
+    #[test]
+    fn serves_the_last_blocks_band_levels() {
+        let config = test_config(&[("ALARM_TONE_MIN_POWER", "1e6"), ("ALARM_TONE_MAX_POWER", "1e20")]).unwrap();
+        let mut detector = Detector::new(Arc::new(config)).unwrap();
+        let snapshot = SpectrumSnapshot::new(32);
+        detector.publish_spectrum(snapshot.clone());
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        serve(&addr, snapshot).unwrap();
+
+        let samples: Vec<i16> = (0..1024)
+            .map(|i| (1000.0 * (2.0 * std::f32::consts::PI * 1665.0 * i as f32 / 8000.0).sin()) as i16)
+            .collect();
+        detector.process(&samples);
+
+        let response = ureq::get(&format!("http://{}/spectrum", addr)).call().unwrap();
+        let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!((body["min_hz"].as_f64(), body["max_hz"].as_f64()), (Some(1640.0), Some(1720.0)));
+        // The band's bins are 1640.6 Hz to 1718.75 Hz, 7.8 Hz apart, with the tone's peak at 1664.1 Hz.
+        let bins: Vec<f64> = body["bins"].as_array().unwrap().iter().map(|bin| bin.as_f64().unwrap()).collect();
+        assert_eq!(bins.len(), 11);
+        let peak = (0..bins.len()).max_by(|&a, &b| bins[a].total_cmp(&bins[b])).unwrap();
+        assert_eq!(peak, 3, "{:?}", bins);
+        assert!(ureq::get(&format!("http://{}/other", addr)).call().is_err());
+    }
+}
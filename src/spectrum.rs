@@ -0,0 +1,132 @@
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use log::info;
+use realfft::num_complex::Complex;
+use serialport::SerialPort;
+use crate::audio::{bin_width, Detector, PcmFormat, SAMPLE_RATE};
+use crate::config::{FrequencyBand, PowerUnit};
+use crate::dle::DleDecoder;
+use crate::READ_TIMEOUT;
+
+const HISTOGRAM_BAND: f32 = 100.0; // Hz covered by each histogram row
+const HISTOGRAM_WIDTH: usize = 50;
+const HISTOGRAM_RANGE_DB: f32 = 60.0; // Rows this far below the loudest get no bar
+
+// The `count` loudest local maxima of the spectrum as (frequency, bin power), loudest first, so a tone that misses the
+// band shows up alongside whatever else the line is carrying.
+pub(crate) fn top_peaks(fft_output: &[Complex<f32>], count: usize) -> Vec<(f32, f32)> {
+    let bin_width = bin_width(fft_output);
+    let power = |i: usize| fft_output.get(i).map_or(0.0, |bin| bin.norm_sqr());
+    let mut peaks: Vec<_> = (1..fft_output.len())
+        .filter(|&i| power(i) > power(i - 1) && power(i) >= power(i + 1))
+        .map(|i| (i as f32 * bin_width, power(i)))
+        .collect();
+    peaks.sort_by(|a, b| b.1.total_cmp(&a.1));
+    peaks.truncate(count);
+    peaks
+}
+
+// The peaks as logged for each frame, levels in the unit the thresholds are set in.
+pub(crate) fn format_peaks(peaks: &[(f32, f32)], unit: PowerUnit) -> String {
+    peaks.iter()
+        .map(|(frequency, power)| format!("{:.0} Hz {:.2}", frequency, unit.level(*power)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Mean bin power over every block, summed into HISTOGRAM_BAND wide rows up to Nyquist.
+fn band_powers(spectra: &[Vec<f32>]) -> Vec<f32> {
+    let bins = spectra[0].len();
+    let bin_width = SAMPLE_RATE / ((bins - 1) * 2) as f32;
+    let mut rows = vec![0.0; (SAMPLE_RATE / 2.0 / HISTOGRAM_BAND).ceil() as usize];
+    for spectrum in spectra {
+        for (i, magnitude) in spectrum.iter().enumerate() {
+            let row = ((i as f32 * bin_width / HISTOGRAM_BAND) as usize).min(rows.len() - 1);
+            rows[row] += magnitude.powi(2) / spectra.len() as f32;
+        }
+    }
+    rows
+}
+
+// One row per band with its level and a bar on a dB scale relative to the loudest, the tone band's rows marked.
+fn histogram(rows: &[f32], unit: PowerUnit, band: &FrequencyBand) -> String {
+    let loudest = rows.iter().copied().fold(f32::MIN_POSITIVE, f32::max);
+    let mut out = String::new();
+    for (i, power) in rows.iter().enumerate() {
+        let (min, max) = (i as f32 * HISTOGRAM_BAND, (i + 1) as f32 * HISTOGRAM_BAND);
+        let below = 10.0 * (loudest / power.max(f32::MIN_POSITIVE)).log10();
+        let bar = ((1.0 - below / HISTOGRAM_RANGE_DB).max(0.0) * HISTOGRAM_WIDTH as f32).round() as usize;
+        let marker = if band.min < max && band.max > min { '*' } else { ' ' };
+        out += &format!("{:>5}-{:<5} Hz {} {:>12.2} {}\n", min, max, marker, unit.level(*power), "#".repeat(bar));
+    }
+    out
+}
+
+// Listen for the given duration and print the average spectrum as a histogram by frequency band, showing where the
+// line's energy actually is when a tone isn't being detected.
+pub(crate) fn capture_spectrum(port: &mut dyn SerialPort, format: PcmFormat, detector: &mut Detector, duration: Duration) -> Result<()> {
+    info!("Capturing the spectrum for {:#?}", duration);
+    let start = Instant::now();
+    let mut buffer = vec![0; 1024];
+    let mut dle = DleDecoder::new(detector.config().strip_xon_xoff);
+    let mut audio = Vec::new();
+    let mut capture = detector.capture(format);
+    let mut spectra = Vec::new();
+    while start.elapsed() < duration {
+        match port.read(&mut buffer) {
+            Ok(n) if n > 0 => {
+                dle.feed(&buffer[..n], &mut audio);
+                let samples = capture.take_samples(&mut audio);
+                spectra.extend(detector.block_spectra(&samples));
+            }
+            Ok(_) => {}
+            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => sleep(READ_TIMEOUT),
+            Err(e) => return Err(anyhow!(e))
+        }
+    }
+
+    if spectra.is_empty() {
+        return Err(anyhow!("No audio received during the spectrum capture"));
+    }
+    let config = detector.config();
+    let band = FrequencyBand { min: config.tone_min_freq, max: config.tone_max_freq };
+    println!("Blocks averaged: {}, levels in {} (* marks the tone band)", spectra.len(), config.power_unit.name());
+    print!("{}", histogram(&band_powers(&spectra), config.power_unit, &band));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spectrum(peaks: &[(usize, f32)]) -> Vec<Complex<f32>> {
+        let mut output = vec![Complex::new(0.1, 0.0); 257];
+        for &(bin, magnitude) in peaks {
+            output[bin] = Complex::new(magnitude, 0.0);
+        }
+        output
+    }
+
+    #[test]
+    fn top_peaks_are_loudest_first() {
+        let output = spectrum(&[(10, 2.0), (50, 5.0), (51, 4.0), (100, 3.0)]);
+        let peaks = top_peaks(&output, 2);
+        assert_eq!(peaks.len(), 2);
+        assert_eq!(peaks[0], (50.0 * bin_width(&output), 25.0));
+        assert_eq!(peaks[1], (100.0 * bin_width(&output), 9.0));
+    }
+
+    #[test]
+    fn histogram_marks_the_tone_band() {
+        let mut spectrum = vec![0.001; 257];
+        spectrum[106] = 100.0; // 1656 Hz
+        let rows = band_powers(&[spectrum]);
+        assert_eq!(rows.len(), 40);
+        let histogram = histogram(&rows, PowerUnit::Power, &FrequencyBand { min: 1600.0, max: 1700.0 });
+        let loudest = histogram.lines().nth(16).unwrap();
+        assert!(loudest.starts_with(" 1600-1700  Hz *"), "{}", loudest);
+        assert!(loudest.ends_with(&"#".repeat(HISTOGRAM_WIDTH)));
+        assert!(!histogram.lines().nth(15).unwrap().contains('*'));
+    }
+}
@@ -0,0 +1,95 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::sync::Mutex;
+use anyhow::{Context, Result};
+use log::warn;
+use serde_json::Value;
+
+// Alerts held on disk while the webhook circuit is open, one JSON payload per line, so they outlast a restart and go
+// out in the order they arrived once the endpoint is back.
+pub(crate) struct Spool {
+    path: String,
+    lock: Mutex<()> // Held across a whole drain, so a payload pushed meanwhile isn't lost to the rewrite
+}
+
+impl Spool {
+    pub fn new(path: &str) -> Self {
+        Spool { path: path.to_string(), lock: Mutex::new(()) }
+    }
+
+    pub fn push(&self, payload: &Value) -> Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open webhook spool {}", self.path))?;
+        writeln!(file, "{}", payload).with_context(|| format!("Failed to write to webhook spool {}", self.path))
+    }
+
+    // Hand each spooled payload to `send` in turn, stopping at the first it fails to deliver, which stays spooled along
+    // with everything after it. Returns how many were delivered.
+    pub fn drain(&self, mut send: impl FnMut(&Value) -> bool) -> Result<usize> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read webhook spool {}", self.path))
+        };
+
+        let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+        let mut sent = 0;
+        let mut remaining = Vec::new();
+        for line in lines.by_ref() {
+            match serde_json::from_str::<Value>(line) {
+                Ok(payload) if send(&payload) => sent += 1,
+                Ok(_) => {
+                    remaining.push(line);
+                    break;
+                },
+                Err(e) => warn!("Dropping an unreadable payload from webhook spool {}: {}", self.path, e)
+            }
+        }
+        remaining.extend(lines);
+
+        if remaining.is_empty() {
+            fs::remove_file(&self.path).with_context(|| format!("Failed to remove webhook spool {}", self.path))?;
+        } else {
+            fs::write(&self.path, remaining.join("\n") + "\n")
+                .with_context(|| format!("Failed to rewrite webhook spool {}", self.path))?;
+        }
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn drains_in_order_and_keeps_what_failed() {
+        let path = std::env::temp_dir().join(format!("alarm-modem-{}-spool.jsonl", std::process::id()));
+        let spool = Spool::new(path.to_str().unwrap());
+        assert_eq!(spool.drain(|_| true).unwrap(), 0);
+        for n in 1..=3 {
+            spool.push(&json!({"event": "trigger", "count": n})).unwrap();
+        }
+
+        // The second fails, so it and the third wait for the next drain.
+        let mut seen = Vec::new();
+        let sent = spool.drain(|payload| {
+            seen.push(payload["count"].as_u64().unwrap());
+            payload["count"] != 2
+        });
+        assert_eq!((sent.unwrap(), seen), (1, vec![1, 2]));
+
+        let mut seen = Vec::new();
+        let sent = spool.drain(|payload| {
+            seen.push(payload["count"].as_u64().unwrap());
+            true
+        });
+        assert_eq!((sent.unwrap(), seen), (2, vec![2, 3]));
+        assert!(!path.exists());
+    }
+}
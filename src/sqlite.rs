@@ -0,0 +1,202 @@
+use std::time::{Duration, UNIX_EPOCH};
+use anyhow::{Context, Result};
+use humantime::format_rfc3339_seconds;
+use log::{error, info};
+use rusqlite::{params, Connection};
+use crate::sink::DetectionSink;
+use crate::webhook::AlertPayload;
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS detections (
+    id INTEGER PRIMARY KEY,
+    timestamp INTEGER NOT NULL,
+    event TEXT NOT NULL,
+    priority TEXT NOT NULL,
+    offset_ms INTEGER,
+    frequency_hz REAL,
+    level_dbfs REAL,
+    contact_id_account TEXT,
+    contact_id_code INTEGER,
+    contact_id_zone INTEGER,
+    fsk_data_hex TEXT,
+    failed_sinks TEXT
+);
+CREATE INDEX IF NOT EXISTS detections_timestamp ON detections (timestamp);";
+
+// Databases created before the delivery outcome was recorded get its column, NULL for the rows already there.
+const ADD_FAILED_SINKS: &str = "ALTER TABLE detections ADD COLUMN failed_sinks TEXT";
+
+const SET_FAILED_SINKS: &str = "UPDATE detections SET failed_sinks = ?1 WHERE id = ?2";
+
+const HISTORY: &str = "SELECT timestamp, event, frequency_hz, level_dbfs, failed_sinks FROM detections
+    ORDER BY timestamp DESC, id DESC LIMIT ?1";
+
+const INSERT: &str = "INSERT INTO detections (
+    timestamp, event, priority, offset_ms, frequency_hz, level_dbfs, contact_id_account, contact_id_code, contact_id_zone, fsk_data_hex
+) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)";
+
+// Appends each detection as a row of a local SQLite database, for querying the history later. The timestamp is in
+// Unix seconds, as in the webhook payload. Once every sink has had the detection its row is updated with the ones
+// that failed it, comma separated, so an empty `failed_sinks` means it was delivered everywhere.
+pub(crate) struct SqliteSink {
+    connection: Connection,
+    last_row: Option<i64>
+}
+
+impl SqliteSink {
+    pub fn new(path: &str) -> Result<Self> {
+        let connection = open(path)?;
+        info!("Recording detections to {}", path);
+        Ok(SqliteSink { connection, last_row: None })
+    }
+}
+
+fn open(path: &str) -> Result<Connection> {
+    let connection = Connection::open(path).with_context(|| format!("Failed to open detection database {}", path))?;
+    connection.execute_batch(SCHEMA).with_context(|| format!("Failed to create the schema in {}", path))?;
+    let has_failed_sinks = connection.prepare("SELECT failed_sinks FROM detections LIMIT 0").is_ok();
+    if !has_failed_sinks {
+        connection.execute_batch(ADD_FAILED_SINKS).with_context(|| format!("Failed to upgrade the schema in {}", path))?;
+    }
+    Ok(connection)
+}
+
+// Print the most recent detections in the database, newest first, with whether each was delivered.
+pub(crate) fn print_history(path: &str, count: usize) -> Result<()> {
+    let connection = open(path)?;
+    let mut statement = connection.prepare(HISTORY)?;
+    let mut rows = statement.query(params![count as i64])?;
+    while let Some(row) = rows.next()? {
+        let timestamp = UNIX_EPOCH + Duration::from_secs(row.get::<_, i64>(0)?.max(0) as u64);
+        let frequency: Option<f64> = row.get(2)?;
+        let level: Option<f64> = row.get(3)?;
+        let failed: Option<String> = row.get(4)?;
+        println!("{}  {:<12} {:>10} {:>11}  {}",
+            format_rfc3339_seconds(timestamp),
+            row.get::<_, String>(1)?,
+            frequency.map(|frequency| format!("{:.2} Hz", frequency)).unwrap_or_default(),
+            level.map(|level| format!("{:.1} dBFS", level)).unwrap_or_default(),
+            match failed.as_deref() {
+                Some("") => "delivered".to_string(),
+                Some(failed) => format!("failed: {}", failed),
+                None => "not recorded".to_string()
+            });
+    }
+    Ok(())
+}
+
+impl DetectionSink for SqliteSink {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn on_detection(&mut self, payload: &AlertPayload) -> Result<()> {
+        let contact_id = payload.contact_id.as_ref();
+        // SQLite integers are signed 64 bit, which a timestamp or call offset won't outgrow.
+        self.connection.prepare_cached(INSERT)?.execute(params![
+            payload.timestamp as i64,
+            payload.event,
+            payload.priority,
+            payload.offset_ms.map(|offset| offset as i64),
+            payload.frequency_hz,
+            payload.level_dbfs,
+            contact_id.map(|contact_id| &contact_id.account),
+            contact_id.map(|contact_id| contact_id.code),
+            contact_id.map(|contact_id| contact_id.zone),
+            payload.fsk_data_hex
+        ]).context("Failed to insert detection")?;
+        self.last_row = Some(self.connection.last_insert_rowid());
+        Ok(())
+    }
+
+    fn on_delivered(&mut self, failed: &[&'static str]) {
+        let Some(row) = self.last_row.take() else {
+            return;
+        };
+        let result = self.connection.prepare_cached(SET_FAILED_SINKS)
+            .and_then(|mut statement| statement.execute(params![failed.join(","), row]));
+        if let Err(e) = result {
+            error!("Failed to record the delivery of detection {}: {}", row, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(event: &'static str) -> AlertPayload {
+        AlertPayload {
+            event,
+            priority: "normal",
+            timestamp: 1_700_000_000,
+            offset_ms: None,
+            frequency_hz: Some(1664.78),
+            level_dbfs: Some(-12.5),
+            audio_wav_base64: None,
+            contact_id: None,
+            fsk_data_hex: None,
+            caller: None
+        }
+    }
+
+    fn failed_sinks(sink: &SqliteSink) -> Vec<Option<String>> {
+        let mut statement = sink.connection.prepare("SELECT failed_sinks FROM detections ORDER BY id").unwrap();
+        statement.query_map([], |row| row.get(0)).unwrap().collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn inserts_detections_to_query_back() {
+        let mut sink = SqliteSink::new(":memory:").unwrap();
+        sink.on_detection(&payload("trigger")).unwrap();
+        let panic = AlertPayload { priority: "high", offset_ms: Some(640), frequency_hz: None, ..payload("panic") };
+        sink.on_detection(&panic).unwrap();
+        sink.on_detection(&payload("trigger")).unwrap();
+
+        let mut statement = sink.connection
+            .prepare("SELECT event, priority, offset_ms, frequency_hz, level_dbfs FROM detections ORDER BY id")
+            .unwrap();
+        let rows: Vec<String> = statement
+            .query_map([], |row| {
+                let (event, priority): (String, String) = (row.get(0)?, row.get(1)?);
+                let (offset, frequency, level): (Option<i64>, Option<f64>, Option<f64>) =
+                    (row.get(2)?, row.get(3)?, row.get(4)?);
+                Ok(format!("{} {} {:?} {:?} {:?}", event, priority, offset, frequency, level))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(rows, [
+            "trigger normal None Some(1664.78) Some(-12.5)",
+            "panic high Some(640) None Some(-12.5)",
+            "trigger normal None Some(1664.78) Some(-12.5)"
+        ]);
+        let query = "SELECT COUNT(*) FROM detections WHERE event = 'trigger' AND timestamp >= ?1";
+        let triggers: i64 = sink.connection.query_row(query, [1_700_000_000], |row| row.get(0)).unwrap();
+        assert_eq!(triggers, 2);
+    }
+
+    #[test]
+    fn records_the_delivery_outcome() {
+        let mut sink = SqliteSink::new(":memory:").unwrap();
+        sink.on_detection(&payload("trigger")).unwrap();
+        sink.on_delivered(&[]);
+        sink.on_detection(&payload("trigger")).unwrap();
+        sink.on_delivered(&["webhook", "mqtt"]);
+        assert_eq!(failed_sinks(&sink), [Some(String::new()), Some("webhook,mqtt".to_string())]);
+    }
+
+    #[test]
+    fn upgrades_a_database_without_the_outcome() {
+        let path = std::env::temp_dir().join(format!("alarm-modem-test-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let old = Connection::open(path).unwrap();
+        old.execute_batch(&SCHEMA.replace(",\n    failed_sinks TEXT", "")).unwrap();
+        old.execute("INSERT INTO detections (timestamp, event, priority) VALUES (1, 'trigger', 'normal')", []).unwrap();
+        drop(old);
+
+        let sink = SqliteSink::new(path).unwrap();
+        assert_eq!(failed_sinks(&sink), [None]);
+        drop(sink);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result};
+
+// Last-fired timestamps per tone label, stored as `label=unix_millis` lines.
+pub(crate) struct StateFile {
+    path: PathBuf
+}
+
+impl StateFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        StateFile { path: path.into() }
+    }
+
+    pub fn load(&self) -> Result<HashMap<String, SystemTime>> {
+        if !self.path.exists() {
+            return Ok(HashMap::new());
+        }
+
+        let contents = fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read state file {}", self.path.display()))?;
+
+        let mut entries = HashMap::new();
+        for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+            let (label, millis) = line.split_once('=')
+                .with_context(|| format!("Malformed state file line: {}", line))?;
+            let millis = millis.trim().parse::<u64>()
+                .with_context(|| format!("Malformed timestamp for {}", label))?;
+            entries.insert(label.trim().to_string(), UNIX_EPOCH + Duration::from_millis(millis));
+        }
+        Ok(entries)
+    }
+
+    pub fn save(&self, entries: &HashMap<String, SystemTime>) -> Result<()> {
+        let mut contents = String::new();
+        for (label, time) in entries {
+            let millis = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+            contents.push_str(&format!("{}={}\n", label, millis));
+        }
+
+        // Write then rename so a crash mid-write never leaves a truncated file behind.
+        let temp = self.path.with_extension("tmp");
+        fs::write(&temp, contents).with_context(|| format!("Failed to write state file {}", temp.display()))?;
+        fs::rename(&temp, &self.path).with_context(|| format!("Failed to replace state file {}", self.path.display()))
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use log::warn;
+use crate::metrics::Metrics;
+
+const SLOW_WARNING_INTERVAL: Duration = Duration::from_secs(10); // Slow blocks are summarized at most this often
+
+// Running totals for a monitoring session, summarized on shutdown.
+pub(crate) struct SessionStats {
+    started: Instant,
+    last_detection: Option<SystemTime>,
+    detections: BTreeMap<&'static str, u64>,
+    sink_failures: BTreeMap<&'static str, u64>,
+    reconnects: u64,
+    bytes_processed: u64,
+    processing: ProcessingTimes,
+    metrics: Option<Box<dyn Metrics + Send>>
+}
+
+// How long processing read blocks takes against the audio they hold. A block taking longer than its audio lasts
+// means detection is falling behind real time, and the serial buffer will eventually overflow and drop PCM.
+#[derive(Default)]
+struct ProcessingTimes {
+    blocks: u64,
+    total: Duration,
+    max: Duration,
+    slow: u64,
+    slow_unreported: u64,
+    last_warning: Option<Instant>
+}
+
+impl ProcessingTimes {
+    fn record(&mut self, elapsed: Duration, audio: Duration) {
+        self.blocks += 1;
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+        if audio.is_zero() || elapsed <= audio {
+            return;
+        }
+        self.slow += 1;
+        self.slow_unreported += 1;
+        if self.last_warning.is_some_and(|last| last.elapsed() < SLOW_WARNING_INTERVAL) {
+            return;
+        }
+        warn!(
+            "Processing is falling behind real time: {} slow blocks, the latest took {:#?} for {:#?} of audio",
+            self.slow_unreported, elapsed, audio
+        );
+        self.slow_unreported = 0;
+        self.last_warning = Some(Instant::now());
+    }
+}
+
+impl SessionStats {
+    pub fn new(metrics: Option<Box<dyn Metrics + Send>>) -> Self {
+        SessionStats {
+            started: Instant::now(),
+            last_detection: None,
+            detections: BTreeMap::new(),
+            sink_failures: BTreeMap::new(),
+            reconnects: 0,
+            bytes_processed: 0,
+            processing: ProcessingTimes::default(),
+            metrics
+        }
+    }
+
+    fn export(&mut self, name: &str, value: u64) {
+        if let Some(metrics) = self.metrics.as_mut() {
+            metrics.count(name, value);
+        }
+    }
+
+    // The time of the detection, and the tone's frequency and level when it has them, are exported as gauges so
+    // monitoring can tell how long a modem has gone without hearing anything.
+    pub fn record_detection(&mut self, label: &'static str, frequency: Option<f32>, level_dbfs: Option<f32>) {
+        *self.detections.entry(label).or_default() += 1;
+        self.export(&format!("detections.{}", label), 1);
+        let now = SystemTime::now();
+        self.last_detection = Some(now);
+        if let Some(metrics) = self.metrics.as_mut() {
+            let timestamp = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+            metrics.gauge("last_detection_timestamp_seconds", timestamp.as_secs_f64());
+            if let Some(frequency) = frequency {
+                metrics.gauge("last_tone_frequency_hz", frequency as f64);
+            }
+            if let Some(level_dbfs) = level_dbfs {
+                metrics.gauge("last_tone_level_dbfs", level_dbfs as f64);
+            }
+        }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn last_detection(&self) -> Option<SystemTime> {
+        self.last_detection
+    }
+
+    pub fn record_sink_failure(&mut self, sink: &'static str) {
+        *self.sink_failures.entry(sink).or_default() += 1;
+        self.export(&format!("sink_failures.{}", sink), 1);
+    }
+
+    pub fn record_reconnect(&mut self) {
+        self.reconnects += 1;
+        self.export("reconnects", 1);
+    }
+
+    pub fn add_bytes(&mut self, bytes: usize) {
+        self.bytes_processed += bytes as u64;
+        self.export("bytes_processed", bytes as u64);
+    }
+
+    // Time spent decoding and detecting on a block of `audio` duration.
+    pub fn record_processing(&mut self, elapsed: Duration, audio: Duration) {
+        self.processing.record(elapsed, audio);
+        if let Some(metrics) = self.metrics.as_mut() {
+            metrics.timing("block_processing", elapsed);
+            if elapsed > audio && !audio.is_zero() {
+                metrics.count("slow_blocks", 1);
+            }
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let runtime = Duration::from_secs(self.started.elapsed().as_secs());
+        let mut summary = String::new();
+        let _ = writeln!(summary, "Session summary");
+        let _ = writeln!(summary, "  Runtime:         {}", humantime::format_duration(runtime));
+        let _ = writeln!(summary, "  Bytes processed: {}", self.bytes_processed);
+        let _ = writeln!(summary, "  Reconnects:      {}", self.reconnects);
+        if self.processing.blocks > 0 {
+            let average = self.processing.total.div_f64(self.processing.blocks as f64);
+            let _ = writeln!(
+                summary, "  Processing:      {} blocks, {:#?} average, {:#?} max, {} slow",
+                self.processing.blocks, average, self.processing.max, self.processing.slow
+            );
+        }
+
+        let _ = writeln!(summary, "  Detections:      {}", self.detections.values().sum::<u64>());
+        for (label, count) in &self.detections {
+            let _ = writeln!(summary, "    {}: {}", label, count);
+        }
+
+        let _ = writeln!(summary, "  Sink failures:   {}", self.sink_failures.values().sum::<u64>());
+        for (sink, count) in &self.sink_failures {
+            let _ = writeln!(summary, "    {}: {}", sink, count);
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::thread::sleep;
+
+    #[test]
+    fn summarizes_detections_and_failures() {
+        let mut stats = SessionStats::new(None);
+        stats.record_detection("trigger", Some(1665.0), None);
+        stats.record_detection("trigger", None, None);
+        stats.record_detection("panic", None, None);
+        stats.record_sink_failure("webhook");
+        stats.record_reconnect();
+        stats.add_bytes(2048);
+        let summary = stats.summary();
+        assert!(summary.contains("  Bytes processed: 2048\n  Reconnects:      1\n"), "{}", summary);
+        assert!(summary.contains("  Detections:      3\n    panic: 1\n    trigger: 2\n"), "{}", summary);
+        assert!(summary.contains("  Sink failures:   1\n    webhook: 1\n"), "{}", summary);
+        assert!(stats.last_detection().is_some());
+    }
+
+    // Counts the slow blocks reported to the metrics.
+    struct SlowBlocks(Arc<Mutex<u64>>);
+
+    impl Metrics for SlowBlocks {
+        fn count(&mut self, name: &str, value: u64) {
+            if name == "slow_blocks" {
+                *self.0.lock().unwrap() += value;
+            }
+        }
+
+        fn timing(&mut self, _name: &str, _duration: Duration) {}
+
+        fn gauge(&mut self, _name: &str, _value: f64) {}
+    }
+
+    // A block held up past its own audio by a slow step is warned of straight away, and any more within the interval
+    // are saved for the next warning.
+    #[test]
+    fn warns_when_a_block_takes_longer_than_its_audio() {
+        let slow = Arc::new(Mutex::new(0));
+        let mut stats = SessionStats::new(Some(Box::new(SlowBlocks(slow.clone()))));
+        stats.record_processing(Duration::from_millis(1), Duration::from_millis(128));
+        assert!(stats.processing.last_warning.is_none());
+
+        for _ in 0..2 {
+            let started = Instant::now();
+            sleep(Duration::from_millis(5));
+            stats.record_processing(started.elapsed(), Duration::from_millis(1));
+        }
+        assert!(stats.processing.last_warning.is_some());
+        assert_eq!((stats.processing.slow, stats.processing.slow_unreported, *slow.lock().unwrap()), (2, 1, 2));
+        assert!(stats.processing.max >= Duration::from_millis(5));
+        let summary = stats.summary();
+        assert!(summary.contains("  Processing:      3 blocks, ") && summary.contains(" max, 2 slow\n"), "{}", summary);
+    }
+}
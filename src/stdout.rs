@@ -0,0 +1,103 @@
+use std::io::{stdout, Write};
+use anyhow::{Context, Result};
+use crate::config::StdoutFormat;
+use crate::sink::DetectionSink;
+use crate::webhook::AlertPayload;
+
+// Writes one line per detection to stdout for a program reading from a pipe, logs staying on stderr. TSV lines hold
+// the timestamp, event, priority, offset, frequency, level, Contact ID account, code and zone then FSK data, with
+// absent fields left empty.
+pub(crate) struct StdoutSink {
+    format: StdoutFormat
+}
+
+impl StdoutSink {
+    pub fn new(format: StdoutFormat) -> Self {
+        StdoutSink { format }
+    }
+
+    // Flushed per line, a pipe would otherwise hold events back until its buffer filled.
+    fn write_line(&self, payload: &AlertPayload, out: &mut impl Write) -> Result<()> {
+        let line = match self.format {
+            StdoutFormat::Json => serde_json::to_string(payload)?,
+            StdoutFormat::Tsv => tsv_line(payload)
+        };
+        writeln!(out, "{}", line).and_then(|_| out.flush()).context("Failed to write detection to stdout")
+    }
+}
+
+// A field can't be allowed to break the line or shift the columns after it.
+fn tsv_field(value: Option<String>) -> String {
+    value.unwrap_or_default().replace(['\t', '\r', '\n'], " ")
+}
+
+fn tsv_line(payload: &AlertPayload) -> String {
+    let contact_id = payload.contact_id.as_ref();
+    [
+        Some(payload.timestamp.to_string()),
+        Some(payload.event.to_string()),
+        Some(payload.priority.to_string()),
+        payload.offset_ms.map(|offset| offset.to_string()),
+        payload.frequency_hz.map(|frequency| frequency.to_string()),
+        payload.level_dbfs.map(|level| level.to_string()),
+        contact_id.map(|contact_id| contact_id.account.clone()),
+        contact_id.map(|contact_id| contact_id.code.to_string()),
+        contact_id.map(|contact_id| contact_id.zone.to_string()),
+        payload.fsk_data_hex.clone()
+    ].into_iter().map(tsv_field).collect::<Vec<_>>().join("\t")
+}
+
+impl DetectionSink for StdoutSink {
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+
+    fn on_detection(&mut self, payload: &AlertPayload) -> Result<()> {
+        self.write_line(payload, &mut stdout().lock())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contact_id;
+
+    fn payload(event: &'static str) -> AlertPayload {
+        AlertPayload {
+            event,
+            priority: "normal",
+            timestamp: 1_700_000_000,
+            offset_ms: Some(640),
+            frequency_hz: Some(1665.5),
+            level_dbfs: None,
+            audio_wav_base64: None,
+            contact_id: None,
+            fsk_data_hex: Some("04\ta5".to_string()),
+            caller: None
+        }
+    }
+
+    #[test]
+    fn writes_one_line_per_detection() {
+        let parsed = contact_id::parse("1234 18 1130 01 015 E").ok();
+        let report = AlertPayload { contact_id: parsed, ..payload("contact_id") };
+        let detections = [payload("trigger"), report];
+
+        let mut json = Vec::new();
+        let sink = StdoutSink::new(StdoutFormat::Json);
+        detections.iter().for_each(|payload| sink.write_line(payload, &mut json).unwrap());
+        let json = String::from_utf8(json).unwrap();
+        let lines: Vec<serde_json::Value> = json.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!((&lines[0]["event"], &lines[1]["event"]), (&"trigger".into(), &"contact_id".into()));
+
+        let mut tsv = Vec::new();
+        let sink = StdoutSink::new(StdoutFormat::Tsv);
+        detections.iter().for_each(|payload| sink.write_line(payload, &mut tsv).unwrap());
+        let tsv = String::from_utf8(tsv).unwrap();
+        let lines: Vec<&str> = tsv.lines().collect();
+        assert_eq!(lines[0], "1700000000\ttrigger\tnormal\t640\t1665.5\t\t\t\t\t04 a5");
+        assert_eq!(lines[1].split('\t').skip(6).take(3).collect::<Vec<_>>(), ["1234", "130", "15"]);
+        assert!(lines.iter().all(|line| line.split('\t').count() == 10), "{:?}", lines);
+    }
+}
@@ -0,0 +1,173 @@
+use std::net::UdpSocket;
+use std::os::unix::net::UnixDatagram;
+use std::time::UNIX_EPOCH;
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use crate::cef;
+use crate::config::{Config, PayloadFormat};
+use crate::sink::DetectionSink;
+use crate::webhook::AlertPayload;
+
+const APP_NAME: &str = "alarm-modem";
+const LOCAL_SOCKET: &str = "/dev/log";
+const SD_ID: &str = "detection@32473"; // Structured data ID under the example enterprise number from RFC 5424
+
+const FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv", "ftp",
+    "ntp", "audit", "alert", "clock", "local0", "local1", "local2", "local3", "local4", "local5", "local6", "local7"
+];
+const SEVERITIES: [&str; 8] = ["emerg", "alert", "crit", "err", "warning", "notice", "info", "debug"];
+
+enum Transport {
+    Local(UnixDatagram),
+    Udp(UdpSocket)
+}
+
+// Logs each detection as an RFC 5424 message with its fields as structured data, to the local syslog socket or a
+// remote collector over UDP. High priority detections are raised to `alert` if configured below it.
+pub(crate) struct SyslogSink {
+    transport: Transport,
+    facility: u8,
+    severity: u8,
+    hostname: String,
+    format: PayloadFormat
+}
+
+fn position(names: &[&str], name: &str, kind: &str) -> Result<u8> {
+    names.iter()
+        .position(|n| n.eq_ignore_ascii_case(name))
+        .map(|i| i as u8)
+        .ok_or_else(|| anyhow!("Unknown syslog {} {}, expected one of {}", kind, name, names.join(", ")))
+}
+
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    // Safety: the buffer outlives the call and its length is passed, gethostname writes at most that much.
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    match std::str::from_utf8(&buffer[..len]) {
+        Ok(name) if result == 0 && !name.is_empty() => name.to_string(),
+        _ => "-".to_string() // The RFC 5424 nil value
+    }
+}
+
+// Structured data values escape `"`, `\` and `]`.
+fn escape_param(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace(']', "\\]")
+}
+
+impl SyslogSink {
+    pub fn new(config: &Config) -> Result<Self> {
+        let transport = match &config.syslog_addr {
+            Some(addr) => {
+                let socket = UdpSocket::bind("0.0.0.0:0").context("Failed to bind syslog socket")?;
+                socket.connect(addr).with_context(|| format!("Failed to connect syslog socket to {}", addr))?;
+                info!("Logging detections to syslog at {}", addr);
+                Transport::Udp(socket)
+            },
+            None => {
+                let socket = UnixDatagram::unbound().context("Failed to create syslog socket")?;
+                socket.connect(LOCAL_SOCKET).with_context(|| format!("Failed to connect to {}", LOCAL_SOCKET))?;
+                info!("Logging detections to syslog at {}", LOCAL_SOCKET);
+                Transport::Local(socket)
+            }
+        };
+        Ok(SyslogSink {
+            transport,
+            facility: position(&FACILITIES, &config.syslog_facility, "facility")?,
+            severity: position(&SEVERITIES, &config.syslog_severity, "severity")?,
+            hostname: hostname(),
+            format: config.payload_format
+        })
+    }
+
+    fn format(&self, payload: &AlertPayload) -> Result<String> {
+        // Lower numbers are more severe.
+        let severity = if payload.priority == "high" { self.severity.min(1) } else { self.severity };
+        let timestamp = humantime::format_rfc3339_seconds(UNIX_EPOCH + std::time::Duration::from_secs(payload.timestamp));
+        let mut params = format!("event=\"{}\" priority=\"{}\"", escape_param(payload.event), escape_param(payload.priority));
+        if let Some(contact_id) = &payload.contact_id {
+            params.push_str(&format!(
+                " account=\"{}\" code=\"{}\" zone=\"{}\"",
+                escape_param(&contact_id.account), contact_id.code, contact_id.zone
+            ));
+        }
+        let message = match self.format {
+            PayloadFormat::Json => format!("Detected {}", payload.event),
+            PayloadFormat::Cef => cef::render(&serde_json::to_value(payload)?)
+        };
+        Ok(format!(
+            "<{}>1 {} {} {} {} {} [{} {}] {}",
+            self.facility as u16 * 8 + severity as u16,
+            timestamp, self.hostname, APP_NAME, std::process::id(), payload.event, SD_ID, params, message
+        ))
+    }
+}
+
+impl DetectionSink for SyslogSink {
+    fn name(&self) -> &'static str {
+        "syslog"
+    }
+
+    fn on_detection(&mut self, payload: &AlertPayload) -> Result<()> {
+        let message = self.format(payload)?;
+        match &self.transport {
+            Transport::Local(socket) => socket.send(message.as_bytes()).context("Failed to write to syslog")?,
+            Transport::Udp(socket) => socket.send(message.as_bytes()).context("Failed to send to syslog")?
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use crate::audio::{Detection, DetectionEvent};
+    use crate::config::test_config;
+
+    fn payload(detection: Detection) -> AlertPayload {
+        let event = DetectionEvent {
+            detection,
+            timestamp: SystemTime::now(),
+            audio: None,
+            contact_id: None,
+            fsk_data: None,
+            frequency: Some(1665.0),
+            offset: None,
+            level_dbfs: None,
+            caller: None
+        };
+        AlertPayload::from_event(&event, 8000)
+    }
+
+    // local0 at notice is priority 16 * 8 + 5, a high priority detection is raised to alert.
+    #[test]
+    fn sends_rfc_5424_messages_over_udp() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let addr = receiver.local_addr().unwrap().to_string();
+        let config = test_config(&[
+            ("ALARM_SYSLOG_ADDR", &addr),
+            ("ALARM_SYSLOG_FACILITY", "local0"),
+            ("ALARM_SYSLOG_SEVERITY", "notice")
+        ]).unwrap();
+        let mut sink = SyslogSink::new(&config).unwrap();
+        let mut buffer = [0; 2048];
+        let mut receive = || {
+            let n = receiver.recv(&mut buffer).unwrap();
+            String::from_utf8_lossy(&buffer[..n]).into_owned()
+        };
+
+        sink.on_detection(&payload(Detection::Trigger)).unwrap();
+        let message = receive();
+        let fields: Vec<&str> = message.splitn(9, ' ').collect();
+        assert_eq!(fields[0], "<133>1");
+        assert!(fields[1].ends_with('Z'), "{}", fields[1]);
+        assert_eq!(&fields[3..6], [APP_NAME, &std::process::id().to_string(), "trigger"]);
+        assert_eq!(fields[6..].join(" "), "[detection@32473 event=\"trigger\" priority=\"normal\"] Detected trigger");
+
+        sink.on_detection(&payload(Detection::Panic)).unwrap();
+        assert!(receive().starts_with("<129>1 "));
+    }
+}
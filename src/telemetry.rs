@@ -0,0 +1,194 @@
+use anyhow::Result;
+#[cfg(feature = "otel")]
+use std::sync::OnceLock;
+#[cfg(feature = "otel")]
+use anyhow::Context as _;
+#[cfg(feature = "otel")]
+use log::warn;
+#[cfg(feature = "otel")]
+use opentelemetry::trace::{TraceContextExt, Tracer};
+#[cfg(feature = "otel")]
+use opentelemetry::{global, Context, ContextGuard, KeyValue};
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::trace::SdkTracerProvider;
+
+#[cfg(feature = "otel")]
+const SERVICE_NAME: &str = "alarm-modem";
+
+#[cfg(feature = "otel")]
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+// A span of work exported over OTLP when built with the `otel` feature, without it every call is a no-op.
+// Each span is a child of whichever span is current when it starts, and stays current until dropped.
+#[cfg(feature = "otel")]
+pub(crate) struct Span {
+    context: Context,
+    _guard: ContextGuard
+}
+
+#[cfg(not(feature = "otel"))]
+pub(crate) struct Span;
+
+#[cfg(feature = "otel")]
+impl Span {
+    pub fn start(name: &'static str) -> Self {
+        let context = Context::current_with_span(global::tracer(SERVICE_NAME).start(name));
+        let _guard = context.clone().attach();
+        Span { context, _guard }
+    }
+
+    pub fn set_str(&self, key: &'static str, value: &str) {
+        self.context.span().set_attribute(KeyValue::new(key, value.to_string()));
+    }
+
+    pub fn set_i64(&self, key: &'static str, value: i64) {
+        self.context.span().set_attribute(KeyValue::new(key, value));
+    }
+
+    pub fn set_f64(&self, key: &'static str, value: f64) {
+        self.context.span().set_attribute(KeyValue::new(key, value));
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+impl Span {
+    pub fn start(_name: &'static str) -> Self {
+        Span
+    }
+
+    pub fn set_str(&self, _key: &'static str, _value: &str) {}
+
+    pub fn set_i64(&self, _key: &'static str, _value: i64) {}
+
+    pub fn set_f64(&self, _key: &'static str, _value: f64) {}
+}
+
+// The span current where it was taken, for work handed to another thread to carry on under it.
+#[cfg(feature = "otel")]
+pub(crate) struct Parent(Context);
+
+#[cfg(not(feature = "otel"))]
+pub(crate) struct Parent;
+
+#[cfg(feature = "otel")]
+impl Parent {
+    pub fn current() -> Self {
+        Parent(Context::current())
+    }
+
+    pub fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        let _guard = self.0.clone().attach();
+        f()
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+impl Parent {
+    pub fn current() -> Self {
+        Parent
+    }
+
+    pub fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        f()
+    }
+}
+
+// Install the OTLP/HTTP exporter, configured through the standard `OTEL_EXPORTER_OTLP_*` variables.
+#[cfg(feature = "otel")]
+pub(crate) fn init() -> Result<()> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+        .context("Failed to create OTLP span exporter")?;
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(exporter)
+        .with_resource(opentelemetry_sdk::Resource::builder().with_service_name(SERVICE_NAME).build())
+        .build();
+    global::set_tracer_provider(provider.clone());
+    PROVIDER.get_or_init(|| provider);
+    Ok(())
+}
+
+#[cfg(not(feature = "otel"))]
+pub(crate) fn init() -> Result<()> {
+    Ok(())
+}
+
+#[cfg(feature = "otel")]
+pub(crate) fn shutdown() {
+    if let Some(Err(e)) = PROVIDER.get().map(SdkTracerProvider::shutdown) {
+        warn!("Failed to shut down trace exporter: {}", e);
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+pub(crate) fn shutdown() {}
+
+#[cfg(all(test, feature = "otel"))]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::SystemTime;
+    use opentelemetry::trace::SpanId;
+    use opentelemetry_sdk::error::OTelSdkResult;
+    use opentelemetry_sdk::trace::{SpanData, SpanExporter};
+    use crate::audio::{deliver, Detection, DetectionEvent};
+    use crate::config::test_config;
+    use crate::mock_http::MockServer;
+    use crate::sink::DetectionSink;
+    use crate::stats::SessionStats;
+    use crate::webhook::WebhookSink;
+
+    // Keeps every span as it ends.
+    #[derive(Debug, Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for Recorder {
+        async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+            self.0.lock().unwrap().extend(batch);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn detection_spans_nest_within_the_call() {
+        let recorder = Recorder::default();
+        global::set_tracer_provider(SdkTracerProvider::builder().with_simple_exporter(recorder.clone()).build());
+        let server = MockServer::start(200);
+        let config = test_config(&[("ALARM_WEBHOOK_URL", &server.url), ("ALARM_WEBHOOK_MAX_RETRIES", "1")]).unwrap();
+        let mut sinks: Vec<Box<dyn DetectionSink>> = vec![Box::new(WebhookSink::new(&config))];
+        let event = DetectionEvent {
+            detection: Detection::Trigger,
+            timestamp: SystemTime::now(),
+            audio: None,
+            contact_id: None,
+            fsk_data: None,
+            frequency: Some(1665.0),
+            offset: None,
+            level_dbfs: None,
+            caller: None
+        };
+        {
+            let _call = Span::start("call");
+            let _listen = Span::start("listen");
+            deliver(vec![event], &mut sinks, &mut SessionStats::new(None));
+        }
+
+        let spans = recorder.0.lock().unwrap().clone();
+        let call = spans.iter().find(|span| span.name == "call").expect("no call span");
+        let trace = call.span_context.trace_id();
+        let child_of = |parent: SpanId| {
+            let children: Vec<_> = spans.iter()
+                .filter(|span| span.span_context.trace_id() == trace && span.parent_span_id == parent)
+                .collect();
+            assert_eq!(children.len(), 1, "expected one child of {}", parent);
+            children[0]
+        };
+        assert_eq!(call.parent_span_id, SpanId::INVALID);
+        let listen = child_of(call.span_context.span_id());
+        let detection = child_of(listen.span_context.span_id());
+        let webhook = child_of(detection.span_context.span_id());
+        assert_eq!([&*listen.name, &*detection.name, &*webhook.name], ["listen", "detection", "webhook"]);
+        assert!(webhook.attributes.iter().any(|attribute| attribute.key.as_str() == "http.status"));
+    }
+}
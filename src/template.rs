@@ -0,0 +1,152 @@
+use std::fs;
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info};
+use realfft::num_complex::Complex;
+use serde::{Deserialize, Serialize};
+use crate::audio::{bin_width, Detector};
+use crate::input::AudioSource;
+use crate::replay::WavSource;
+
+// Blocks within this share of a recording's loudest block's energy (6 dB) are taken to hold the tone.
+const LEARN_MIN_SHARE: f32 = 0.25;
+// Recommended threshold, as a share of the worst correlation a learned block had with the template.
+const RECOMMENDED_MARGIN: f32 = 0.9;
+
+// The average normalized magnitude spectrum of a panel's genuine tone, which blocks are correlated against in place
+// of the band and power thresholds. Only valid for the transform size (zero padding) it was learned with.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ToneTemplate {
+    bins: Vec<f32>
+}
+
+// Scaled to unit length, so correlation is just a dot product. Silence is left as zeros.
+fn normalize(mut bins: Vec<f32>) -> Vec<f32> {
+    let norm = bins.iter().map(|bin| bin * bin).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        bins.iter_mut().for_each(|bin| *bin /= norm);
+    }
+    bins
+}
+
+impl ToneTemplate {
+    pub fn load(path: &str) -> Result<Self> {
+        let contents = fs::read_to_string(path).with_context(|| format!("Failed to read tone template {}", path))?;
+        let template: ToneTemplate = serde_json::from_str(&contents).with_context(|| format!("Failed to parse tone template {}", path))?;
+        if template.bins.is_empty() {
+            return Err(anyhow!("Tone template {} has no bins", path));
+        }
+        Ok(template)
+    }
+
+    fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, serde_json::to_string(self)?).with_context(|| format!("Failed to write tone template {}", path))
+    }
+
+    pub fn len(&self) -> usize {
+        self.bins.len()
+    }
+
+    // Cosine similarity of a block's magnitude spectrum with the template, from 0 (nothing alike) to 1.
+    fn correlate(&self, magnitudes: &[f32]) -> f32 {
+        normalize(magnitudes.to_vec()).iter().zip(&self.bins).map(|(bin, template)| bin * template).sum()
+    }
+
+    // Frequency of the block's strongest bin, if the block correlates with the template at least `min_correlation`.
+    pub fn detect(&self, fft_output: &[Complex<f32>], min_correlation: f32) -> Option<f32> {
+        let magnitudes: Vec<f32> = fft_output.iter().map(|bin| bin.norm()).collect();
+        let correlation = self.correlate(&magnitudes);
+        if correlation < min_correlation {
+            return None;
+        }
+        let peak = magnitudes.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1))?.0;
+        let frequency = peak as f32 * bin_width(fft_output);
+        debug!("Block correlates {:.3} with the tone template, peaking at {} Hz", correlation, frequency);
+        Some(frequency)
+    }
+}
+
+// Learn a template from recordings of genuine alarm transmissions: the loudest blocks of each are averaged once
+// normalized, so every recording counts by its blocks rather than its volume. Prints how well the learned blocks
+// match the result, with a threshold that would still have accepted all of them.
+pub(crate) fn learn(output: &str, paths: &[String], detector: &mut Detector) -> Result<()> {
+    if paths.is_empty() {
+        return Err(anyhow!("--learn needs at least one WAV recording of the tone"));
+    }
+
+    let mut learned: Vec<Vec<f32>> = Vec::new();
+    for path in paths {
+        let mut source = WavSource::open(path, false)?;
+        detector.start_call();
+        let mut blocks = Vec::new();
+        while let Some(samples) = source.next_samples()? {
+            blocks.extend(detector.block_spectra(&samples));
+        }
+
+        let energy = |block: &Vec<f32>| block.iter().map(|bin| bin * bin).sum::<f32>();
+        let loudest = blocks.iter().map(energy).fold(0.0, f32::max);
+        if loudest == 0.0 {
+            return Err(anyhow!("Recording {} is silent", path));
+        }
+        let before = learned.len();
+        learned.extend(blocks.into_iter().filter(|block| energy(block) >= loudest * LEARN_MIN_SHARE).map(normalize));
+        info!("Learned {} blocks from {}", learned.len() - before, path);
+    }
+
+    let mut sum = vec![0.0; learned[0].len()];
+    for block in &learned {
+        sum.iter_mut().zip(block).for_each(|(total, bin)| *total += bin);
+    }
+    let template = ToneTemplate { bins: normalize(sum) };
+
+    let correlations: Vec<f32> = learned.iter().map(|block| template.correlate(block)).collect();
+    let worst = correlations.iter().copied().fold(1.0, f32::min);
+    let mean = correlations.iter().sum::<f32>() / correlations.len() as f32;
+    template.save(output)?;
+    println!("Blocks learned:              {}", learned.len());
+    println!("Correlation with template:   mean {:.3}, min {:.3}", mean, worst);
+    println!("Recommended min correlation: {:.2}", worst * RECOMMENDED_MARGIN);
+    println!("Saved template to {}", output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::config::test_config;
+    use crate::wav::encode_wav;
+
+    fn tone(frequency: f32, amplitude: f32) -> Vec<i16> {
+        (0..8000).map(|i| (amplitude * (std::f32::consts::TAU * frequency * i as f32 / 8000.0).sin()) as i16).collect()
+    }
+
+    #[test]
+    fn a_learned_template_matches_its_tone_only() {
+        let mut detector = Detector::new(Arc::new(test_config(&[("ALARM_FILTER", "highpass")]).unwrap())).unwrap();
+        let dir = std::env::temp_dir();
+        let paths: Vec<String> = [(1400.0, 800.0), (1400.0, 3000.0)].iter().enumerate()
+            .map(|(i, &(frequency, amplitude))| {
+                let path = dir.join(format!("alarm-modem-{}-learn-{}.wav", std::process::id(), i));
+                fs::write(&path, encode_wav(&tone(frequency, amplitude), 8000)).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+        let output = dir.join(format!("alarm-modem-{}-template.json", std::process::id()));
+        let output = output.to_str().unwrap();
+        learn(output, &paths, &mut detector).unwrap();
+        let template = ToneTemplate::load(output).unwrap();
+        paths.iter().map(String::as_str).chain([output]).for_each(|path| fs::remove_file(path).unwrap());
+
+        // Correlated on the same spectra detection would see, a louder, slightly detuned take still matches.
+        let mut detect = |frequency, amplitude| {
+            detector.start_call();
+            let spectra = detector.block_spectra(&tone(frequency, amplitude));
+            let block: Vec<Complex<f32>> = spectra[2].iter().map(|&magnitude| Complex::new(magnitude, 0.0)).collect();
+            template.detect(&block, 0.8)
+        };
+        let matched = detect(1402.0, 6000.0).expect("the learned tone wasn't matched");
+        assert!((matched - 1402.0).abs() < 8.0, "{}", matched);
+        assert_eq!(detect(1000.0, 3000.0), None);
+        assert_eq!(detect(1680.0, 3000.0), None);
+    }
+}
@@ -0,0 +1,44 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use log::{log_enabled, trace, Level};
+
+const BYTES_PER_LINE: usize = 16;
+
+// Set from the config when the modem is opened.
+pub(crate) static TRACE_SERIAL: AtomicBool = AtomicBool::new(false);
+
+// Classic hex dump: offset, 16 bytes in hex and the printable ASCII alongside, one line per 16 bytes.
+// e.g. `00000000  41 54 0d 0a                                       |AT..|`
+pub(crate) fn hex_dump(bytes: &[u8]) -> String {
+    let mut lines = Vec::new();
+    for (index, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = chunk.iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        lines.push(format!("{:08x}  {:<width$}  |{}|", index * BYTES_PER_LINE, hex.join(" "), ascii, width = BYTES_PER_LINE * 3 - 1));
+    }
+    lines.join("\n")
+}
+
+// Log serial traffic in the given direction when ALARM_TRACE_SERIAL is on and trace logging is enabled.
+pub(crate) fn trace_serial(direction: &str, bytes: &[u8]) {
+    if bytes.is_empty() || !TRACE_SERIAL.load(Ordering::Relaxed) || !log_enabled!(Level::Trace) {
+        return;
+    }
+    trace!("{} {} bytes\n{}", direction, bytes.len(), hex_dump(bytes));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dumps_hex_with_an_ascii_sidebar() {
+        let mut bytes = b"AT+VRX\r\r\nCONNECT\r\n".to_vec();
+        bytes.extend([0x10, 0x03, 0xff, 0x00]);
+        assert_eq!(hex_dump(&bytes), "\
+00000000  41 54 2b 56 52 58 0d 0d 0a 43 4f 4e 4e 45 43 54  |AT+VRX...CONNECT|
+00000010  0d 0a 10 03 ff 00                                |......|");
+        assert_eq!(hex_dump(&[]), "");
+    }
+}
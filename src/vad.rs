@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum VadEvent {
+    SpeechStart,
+    SpeechEnd
+}
+
+// Short-term RMS energy detector, staying active for a hangover period after the energy drops.
+pub(crate) struct Vad {
+    threshold: f32,
+    hangover_samples: usize,
+    quiet_samples: usize,
+    active: bool
+}
+
+impl Vad {
+    pub fn new(threshold: f32, hangover: Duration, sample_rate: f32) -> Self {
+        Vad {
+            threshold,
+            hangover_samples: (hangover.as_secs_f32() * sample_rate) as usize,
+            quiet_samples: 0,
+            active: false
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn process(&mut self, samples: &[i16]) -> Option<VadEvent> {
+        if samples.is_empty() {
+            return None;
+        }
+
+        let energy = samples.iter().map(|&s| (s as f32).powi(2)).sum::<f32>() / samples.len() as f32;
+        if energy.sqrt() >= self.threshold {
+            self.quiet_samples = 0;
+            if !self.active {
+                self.active = true;
+                return Some(VadEvent::SpeechStart);
+            }
+        } else if self.active {
+            self.quiet_samples += samples.len();
+            if self.quiet_samples > self.hangover_samples {
+                self.active = false;
+                return Some(VadEvent::SpeechEnd);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_start_and_end_of_a_tone_between_silences() {
+        let mut vad = Vad::new(100.0, Duration::from_millis(200), 8000.0);
+        let silence = vec![0i16; 800];
+        let tone: Vec<i16> = (0..800).map(|i| (1000.0 * (i as f32 * 0.4).sin()) as i16).collect();
+        let events: Vec<_> = [&silence, &tone, &tone, &silence, &silence, &silence]
+            .into_iter()
+            .map(|block| vad.process(block))
+            .collect();
+        // Each block is 100 ms, so the 200 ms hangover outlasts two quiet blocks and ends on the third.
+        assert_eq!(events, [None, Some(VadEvent::SpeechStart), None, None, None, Some(VadEvent::SpeechEnd)]);
+        assert!(!vad.is_active());
+    }
+}
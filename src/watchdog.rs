@@ -0,0 +1,96 @@
+use std::time::{Duration, Instant};
+use log::debug;
+
+// Keeps the systemd watchdog fed while the read loop is healthy. Without the `systemd` feature, or when the
+// service has no WatchdogSec, every call is a no-op.
+pub(crate) struct Watchdog {
+    interval: Option<Duration>,
+    last_ping: Instant,
+    last_data: Option<Instant>,
+    ready: bool
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        let interval = watchdog_interval();
+        if let Some(interval) = interval {
+            debug!("systemd watchdog enabled, interval {:#?}", interval);
+        }
+        Watchdog { interval, last_ping: Instant::now(), last_data: None, ready: false }
+    }
+
+    // Tell systemd start-up has finished, only the first call notifies.
+    pub fn ready(&mut self) {
+        if !std::mem::replace(&mut self.ready, true) {
+            notify(State::Ready);
+        }
+    }
+
+    pub fn data_received(&mut self) {
+        self.last_data = Some(Instant::now());
+    }
+
+    // Called on every pass of the read loop, pinging at half the interval but only if data arrived within
+    // the last interval, so a loop that's spinning on errors or a dead port still gets restarted.
+    pub fn tick(&mut self) {
+        if should_ping(self.interval, self.last_ping, self.last_data, Instant::now()) {
+            notify(State::Watchdog);
+            self.last_ping = Instant::now();
+        }
+    }
+}
+
+fn should_ping(interval: Option<Duration>, last_ping: Instant, last_data: Option<Instant>, now: Instant) -> bool {
+    let Some(interval) = interval else {
+        return false;
+    };
+    let healthy = last_data.is_some_and(|data| now.duration_since(data) < interval);
+    healthy && now.duration_since(last_ping) >= interval / 2
+}
+
+enum State {
+    Ready,
+    Watchdog
+}
+
+#[cfg(all(unix, feature = "systemd"))]
+fn watchdog_interval() -> Option<Duration> {
+    let mut usec = 0;
+    sd_notify::watchdog_enabled(false, &mut usec).then(|| Duration::from_micros(usec))
+}
+
+#[cfg(not(all(unix, feature = "systemd")))]
+fn watchdog_interval() -> Option<Duration> {
+    None
+}
+
+#[cfg(all(unix, feature = "systemd"))]
+fn notify(state: State) {
+    let state = match state {
+        State::Ready => sd_notify::NotifyState::Ready,
+        State::Watchdog => sd_notify::NotifyState::Watchdog
+    };
+    if let Err(e) = sd_notify::notify(false, &[state]) {
+        debug!("Failed to notify systemd: {}", e);
+    }
+}
+
+#[cfg(not(all(unix, feature = "systemd")))]
+fn notify(_state: State) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pings_only_while_data_is_arriving() {
+        let interval = Some(Duration::from_secs(10));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert!(!should_ping(None, start, Some(at(6)), at(6)), "pinged without a watchdog");
+        assert!(!should_ping(interval, start, None, at(6)), "pinged before any data");
+        assert!(!should_ping(interval, start, Some(at(4)), at(4)), "pinged before half the interval");
+        assert!(should_ping(interval, start, Some(at(4)), at(6)));
+        assert!(!should_ping(interval, start, Some(at(4)), at(14)), "pinged after the data stopped");
+    }
+}
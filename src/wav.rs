@@ -0,0 +1,97 @@
+use anyhow::{anyhow, Context, Result};
+
+// Encode mono 16-bit PCM samples as a complete in-memory WAV file.
+pub(crate) fn encode_wav(samples: &[i16], sample_rate: u32) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());             // Chunk size
+    wav.extend_from_slice(&1u16.to_le_bytes());              // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes());              // Mono
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // Byte rate
+    wav.extend_from_slice(&2u16.to_le_bytes());              // Block align
+    wav.extend_from_slice(&16u16.to_le_bytes());             // Bits per sample
+
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+// Decoded WAV audio, mixed down to mono 16-bit.
+pub(crate) struct WavAudio {
+    pub sample_rate: u32,
+    pub samples: Vec<i16>
+}
+
+// Parse a PCM (8, 16, 24 or 32-bit integer) or 32-bit float WAV file, little-endian RIFF or big-endian RIFX,
+// at any sample rate and channel count.
+pub(crate) fn decode_wav(bytes: &[u8]) -> Result<WavAudio> {
+    let big_endian = match bytes.get(..4) {
+        Some(b"RIFF") => false,
+        Some(b"RIFX") => true,
+        _ => return Err(anyhow!("Not a WAV file, missing RIFF header"))
+    };
+    if bytes.get(8..12) != Some(b"WAVE") {
+        return Err(anyhow!("Not a WAV file, missing WAVE type"));
+    }
+    let read_u16 = |b: &[u8]| if big_endian { u16::from_be_bytes([b[0], b[1]]) } else { u16::from_le_bytes([b[0], b[1]]) };
+    let read_u32 = |b: &[u8]| if big_endian { u32::from_be_bytes([b[0], b[1], b[2], b[3]]) } else { u32::from_le_bytes([b[0], b[1], b[2], b[3]]) };
+
+    // Walk the chunks for the format and the data, skipping anything else (LIST, fact...).
+    let mut format = None;
+    let mut data = None;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let len = read_u32(&bytes[offset + 4..offset + 8]) as usize;
+        let body = &bytes[offset + 8..(offset + 8).saturating_add(len).min(bytes.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => format = Some((read_u16(&body[0..2]), read_u16(&body[2..4]), read_u32(&body[4..8]), read_u16(&body[14..16]))),
+            b"data" => data = Some(body),
+            _ => {}
+        }
+        offset += 8 + len + len % 2; // Chunks are padded to an even length
+    }
+    let (tag, channels, sample_rate, bits) = format.context("WAV file has no fmt chunk")?;
+    let data = data.context("WAV file has no data chunk")?;
+    if channels == 0 || sample_rate == 0 {
+        return Err(anyhow!("WAV file has {} channels at {} Hz", channels, sample_rate));
+    }
+
+    // WAVE_FORMAT_EXTENSIBLE carries the real format in its sub-format GUID, which starts with the same tag.
+    let float = match (tag, bits) {
+        (1 | 0xFFFE, 8 | 16 | 24 | 32) => false,
+        (3, 32) => true,
+        _ => return Err(anyhow!("Unsupported WAV encoding: format {} with {} bits per sample", tag, bits))
+    };
+    let width = bits as usize / 8;
+    let decode = |b: &[u8]| -> f32 {
+        let mut word = [0u8; 4];
+        for (i, &byte) in b.iter().enumerate() {
+            // Place the sample in the top bytes of a little-endian word, so every width scales to the i32 range.
+            let position = if big_endian { width - 1 - i } else { i };
+            word[4 - width + position] = byte;
+        }
+        match (float, width) {
+            (true, _) => f32::from_le_bytes(word) * 32768.0,
+            (false, 1) => (word[3] as f32 - 128.0) * 256.0, // 8-bit WAV is unsigned
+            (false, _) => (i32::from_le_bytes(word) >> 16) as f32
+        }
+    };
+
+    let frame = width * channels as usize;
+    let samples = data.chunks_exact(frame).map(|frame| {
+        let sum: f32 = frame.chunks_exact(width).map(decode).sum();
+        (sum / channels as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16
+    }).collect();
+    Ok(WavAudio { sample_rate, samples })
+}
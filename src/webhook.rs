@@ -0,0 +1,1018 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use hmac::{Hmac, Mac};
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::Sha256;
+use ureq::{Agent, AgentBuilder};
+use crate::audio::{Detection, DetectionEvent};
+use crate::breaker::CircuitBreaker;
+use crate::cef;
+use crate::cid::CallerId;
+use crate::config::{Config, PayloadFormat};
+use crate::contact_id::ContactIdEvent;
+use crate::dedup::{content_key, DedupCache};
+use crate::limiter::RateLimiter;
+use crate::payload_template::PayloadTemplate;
+use crate::sink::DetectionSink;
+use crate::spool::Spool;
+use crate::stats::SessionStats;
+use crate::telemetry::{Parent, Span};
+use crate::wav::encode_wav;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY_JITTER: f64 = 0.1; // Each delay varies by up to this share either way
+const PRIORITY_HIGH: &str = "high";
+const PRIORITY_NORMAL: &str = "normal";
+
+#[derive(Serialize)]
+pub struct AlertPayload {
+    pub event: &'static str,
+    pub priority: &'static str,
+    pub timestamp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_hz: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level_dbfs: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio_wav_base64: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact_id: Option<ContactIdEvent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fsk_data_hex: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caller: Option<CallerId>
+}
+
+impl AlertPayload {
+    pub fn from_event(event: &DetectionEvent, sample_rate: u32) -> Self {
+        AlertPayload {
+            event: event.detection.label(),
+            priority: if event.detection.is_high_priority() { PRIORITY_HIGH } else { PRIORITY_NORMAL },
+            timestamp: event.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            offset_ms: event.offset.map(|offset| offset.as_millis() as u64),
+            // Rounded (a tenth of a dB, finer than that is noise) as f64, so they read the same once in a JSON value.
+            frequency_hz: event.frequency.map(|frequency| (frequency as f64 * 100.0).round() / 100.0),
+            level_dbfs: event.level_dbfs.map(|level| (level as f64 * 10.0).round() / 10.0),
+            audio_wav_base64: event.audio.as_ref().map(|audio| STANDARD.encode(encode_wav(audio, sample_rate))),
+            contact_id: event.contact_id.clone(),
+            fsk_data_hex: event.fsk_data.as_ref().map(|data| data.iter().map(|b| format!("{:02x}", b)).collect()),
+            caller: event.caller.clone()
+        }
+    }
+}
+
+// One webhook carrying every detection collected over a batch interval.
+#[derive(Serialize)]
+struct BatchPayload {
+    event: &'static str,
+    timestamp: u64,
+    events: Vec<Value>
+}
+
+// Wraps a payload the primary URL never accepted, for a last resort delivery to the dead-letter URL.
+#[derive(Serialize)]
+struct DeadLetterPayload<'a> {
+    event: &'static str,
+    timestamp: u64,
+    url: &'a str,
+    payload: Value
+}
+
+// Sent every ALARM_HEARTBEAT_SECS, so monitoring can tell a quiet panel from a listener that has died.
+#[derive(Serialize)]
+struct HeartbeatPayload {
+    event: &'static str,
+    timestamp: u64,
+    uptime_secs: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_detection: Option<u64>
+}
+
+fn is_form(content_type: &str) -> bool {
+    content_type.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case("application/x-www-form-urlencoded")
+}
+
+// Fields as `key=value` pairs, nested objects flattened to `parent.key`.
+fn form_pairs(prefix: Option<&str>, value: &Value, pairs: &mut Vec<(String, String)>) {
+    let Value::Object(fields) = value else {
+        return;
+    };
+    for (key, value) in fields {
+        let key = match prefix {
+            Some(prefix) => format!("{}.{}", prefix, key),
+            None => key.clone()
+        };
+        match value {
+            Value::Null => {}
+            Value::String(text) => pairs.push((key, text.clone())),
+            Value::Object(_) => form_pairs(Some(&key), value, pairs),
+            other => pairs.push((key, other.to_string()))
+        }
+    }
+}
+
+// CEF lines when that format is chosen, otherwise form-encode the payload for form content types or send JSON
+// (including custom vendor types). A template reshapes the payload first.
+fn serialize_payload(
+    payload: &impl Serialize, content_type: &str, format: PayloadFormat, template: Option<&PayloadTemplate>
+) -> Result<String> {
+    if let Some(template) = template {
+        return serialize_payload(&template.render(&serde_json::to_value(payload)?), content_type, format, None);
+    }
+    if format == PayloadFormat::Cef {
+        return Ok(cef::render(&serde_json::to_value(payload)?));
+    }
+    if !is_form(content_type) {
+        return Ok(serde_json::to_string(payload)?);
+    }
+
+    let mut pairs = Vec::new();
+    form_pairs(None, &serde_json::to_value(payload)?, &mut pairs);
+    Ok(form_urlencoded::Serializer::new(String::new()).extend_pairs(pairs).finish())
+}
+
+// Gzip the body when compression is enabled and it's at least the threshold size, returning the content encoding used.
+fn encode_body(body: String, compress_threshold: Option<usize>) -> Result<(Vec<u8>, Option<&'static str>)> {
+    match compress_threshold {
+        Some(threshold) if body.len() >= threshold => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body.as_bytes())?;
+            Ok((encoder.finish()?, Some("gzip")))
+        },
+        _ => Ok((body.into_bytes(), None))
+    }
+}
+
+// The `X-Signature` header: an HMAC-SHA256, keyed with the webhook key, over the Unix timestamp and the body as
+// serialized (before any compression) joined by a `.`, so a receiver can check the body is ours and recent.
+fn signature(key: &str, timestamp: u64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(body.as_bytes());
+    let digest: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("t={},sha256={}", timestamp, digest)
+}
+
+// One agent is shared for the life of the process, keeping up to `pool_size` idle connections alive between alerts (0 disables keepalive).
+pub(crate) fn build_agent(pool_size: usize) -> Agent {
+    AgentBuilder::new()
+        .timeout(REQUEST_TIMEOUT)
+        .max_idle_connections(pool_size)
+        .max_idle_connections_per_host(pool_size)
+        .build()
+}
+
+// Exponential backoff between webhook attempts: the delay starts at `base` and doubles after each failure up to `max`.
+#[derive(Clone)]
+struct RetryPolicy {
+    attempts: u32,
+    base: Duration,
+    max: Duration
+}
+
+impl RetryPolicy {
+    // Jittered so several modems that lost the same uplink don't all retry in step once it's back.
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self.base.saturating_mul(1 << attempt.saturating_sub(1).min(31)).min(self.max);
+        let unit = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        delay.mul_f64(1.0 + RETRY_JITTER * (unit * 2.0 - 1.0))
+    }
+}
+
+impl Endpoint {
+    // POST one payload to `url`, retrying failures. The breaker, when given, is told of every attempt's outcome.
+    fn post(
+        &self, url: &str, payload: &impl Serialize, breaker: Option<&Mutex<CircuitBreaker>>, template: Option<&PayloadTemplate>
+    ) -> bool {
+        let encoded = serialize_payload(payload, &self.content_type, self.format, template)
+            .and_then(|plain| Ok((encode_body(plain.clone(), self.compress_threshold)?, plain)));
+        let ((body, encoding), plain) = match encoded {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                error!("Failed to encode webhook payload: {:#}", e);
+                return false;
+            }
+        };
+
+        let span = Span::start("webhook");
+        let mut attempts = 0u32;
+        while attempts < self.retry.attempts {
+            // An open circuit refuses attempts, so this alert fails fast (to the dead-letter URL, if set) rather than
+            // retrying against an endpoint known to be down.
+            if let Some(breaker) = breaker {
+                if !breaker.lock().map(|mut breaker| breaker.allow()).unwrap_or(true) {
+                    warn!("Webhook circuit is open, not sending to {}", url);
+                    return false;
+                }
+            }
+
+            debug!("Attempting to send webhook request, attempt: {attempts}");
+            attempts += 1;
+            span.set_i64("attempts", attempts as i64);
+            // Signed afresh for each attempt, so a retry isn't turned away as stale.
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            let mut request = self.agent.post(url)
+                .set("Authorization", &self.key)
+                .set("Content-Type", &self.content_type)
+                .set("X-Signature", &signature(&self.key, timestamp, &plain));
+            if let Some(encoding) = encoding {
+                request = request.set("Content-Encoding", encoding);
+            }
+            let response = request.send_bytes(&body);
+
+            match response {
+                Ok(resp) => {
+                    let status = resp.status();
+                    span.set_i64("http.status", status as i64);
+                    if (200..=204).contains(&status) {
+                        info!("Successfully sent webhook to {url}, got back {status}");
+                        if let Some(Ok(mut breaker)) = breaker.map(Mutex::lock) {
+                            breaker.record_success();
+                        }
+                        return true;
+                    }
+                    warn!("Request to {url} failed with status: {status}");
+                },
+                Err(e) => error!("Request to {} failed with error: {}", url, e)
+            }
+            if let Some(Ok(mut breaker)) = breaker.map(Mutex::lock) {
+                if breaker.record_failure() {
+                    return false;
+                }
+            }
+
+            if attempts < self.retry.attempts {
+                let delay = self.retry.delay(attempts);
+                debug!("Sleeping for {:#?} before retrying webhook", delay);
+                sleep(delay);
+            }
+        }
+        error!("Giving up on webhook to {} after {} attempts", url, attempts);
+        false
+    }
+}
+
+// Everything needed to deliver a webhook, cloned into the batching thread.
+#[derive(Clone)]
+struct Endpoint {
+    agent: Agent,
+    url: String,
+    key: String,
+    content_type: String,
+    format: PayloadFormat,
+    compress_threshold: Option<usize>,
+    template: Option<PayloadTemplate>,
+    dead_letter_url: Option<String>,
+    retry: RetryPolicy,
+    breaker: Option<Arc<Mutex<CircuitBreaker>>>, // Shared by every clone, so the batcher and panic URL trip it too
+    spool: Option<Arc<Spool>> // The primary URL's alone, as it's replayed to whichever endpoint drains it
+}
+
+impl Endpoint {
+    // Once every retry to the primary URL has failed, spool the payload if the circuit is open and there's a spool, or
+    // else hand it to the dead-letter URL if there is one, as it was before any template. A success replays the spool.
+    fn send(&self, payload: &impl Serialize) -> bool {
+        if self.post(&self.url, payload, self.breaker.as_deref(), self.template.as_ref()) {
+            self.replay_spool();
+            return true;
+        }
+        let open = self.breaker.as_ref().is_some_and(|breaker| breaker.lock().is_ok_and(|breaker| breaker.is_open()));
+        if let (true, Some(spool)) = (open, &self.spool) {
+            // Held rather than failed, it goes out once the endpoint is back.
+            match serde_json::to_value(payload).map_err(anyhow::Error::from).and_then(|payload| spool.push(&payload)) {
+                Ok(()) => {
+                    info!("Webhook circuit is open, spooled the alert for {}", self.url);
+                    return true;
+                },
+                Err(e) => error!("Failed to spool webhook payload: {:#}", e)
+            }
+        }
+        let Some(dead_letter_url) = &self.dead_letter_url else {
+            return false;
+        };
+
+        let wrapped = match serde_json::to_value(payload) {
+            Ok(payload) => DeadLetterPayload {
+                event: "delivery_failed",
+                timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+                url: &self.url,
+                payload
+            },
+            Err(e) => {
+                error!("Failed to wrap payload for the dead-letter webhook: {:#}", e);
+                return false;
+            }
+        };
+        warn!("Webhook delivery to {} failed, forwarding to the dead-letter URL", self.url);
+        self.post(dead_letter_url, &wrapped, None, None)
+    }
+
+    // Send whatever waited out the open circuit now that the endpoint has taken an alert again, stopping if it fails.
+    fn replay_spool(&self) {
+        let Some(spool) = &self.spool else {
+            return;
+        };
+        match spool.drain(|payload| self.post(&self.url, payload, self.breaker.as_deref(), self.template.as_ref())) {
+            Ok(0) => {}
+            Ok(sent) => info!("Sent {} spooled alerts to {}", sent, self.url),
+            Err(e) => error!("Failed to replay the webhook spool: {:#}", e)
+        }
+    }
+}
+
+// Deliver to every endpoint at once, each retrying on its own thread so one that's down doesn't hold up the rest.
+// Returns the URLs that never accepted the payload.
+fn send_all<'a>(endpoints: &'a [Endpoint], payload: &(impl Serialize + Sync)) -> Vec<&'a str> {
+    let parent = &Parent::current();
+    thread::scope(|scope| {
+        let sends: Vec<_> = endpoints.iter()
+            .map(|endpoint| (endpoint, scope.spawn(move || parent.run(|| endpoint.send(payload)))))
+            .collect();
+        sends.into_iter()
+            .filter_map(|(endpoint, send)| (!send.join().unwrap_or(false)).then_some(endpoint.url.as_str()))
+            .collect()
+    })
+}
+
+// Collects detections on a thread and sends them as one webhook once the interval since the first has passed,
+// so quiet periods send nothing and a burst becomes a single digest.
+struct Batcher {
+    sender: Option<Sender<Value>>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl Batcher {
+    fn spawn(endpoints: Vec<Endpoint>, interval: Duration) -> Self {
+        let (sender, receiver) = channel::<Value>();
+        let thread = thread::spawn(move || {
+            let mut events = Vec::new();
+            let mut deadline: Option<Instant> = None;
+            loop {
+                let received = match deadline {
+                    Some(deadline) => receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                    None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+                };
+                let disconnected = match received {
+                    Ok(event) => {
+                        deadline.get_or_insert_with(|| Instant::now() + interval);
+                        events.push(event);
+                        continue;
+                    },
+                    Err(RecvTimeoutError::Timeout) => false,
+                    Err(RecvTimeoutError::Disconnected) => true
+                };
+
+                if !events.is_empty() {
+                    info!("Sending webhook for {} batched detections", events.len());
+                    let payload = BatchPayload {
+                        event: "batch",
+                        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+                        events: std::mem::take(&mut events)
+                    };
+                    let failed = send_all(&endpoints, &payload);
+                    if !failed.is_empty() {
+                        error!("Failed to send webhook for batched detections to {}", failed.join(", "));
+                    }
+                }
+                deadline = None;
+                if disconnected {
+                    break;
+                }
+            }
+        });
+        Batcher { sender: Some(sender), thread: Some(thread) }
+    }
+
+    fn push(&self, payload: &AlertPayload) -> Result<()> {
+        let sender = self.sender.as_ref().ok_or_else(|| anyhow!("Webhook batcher has stopped"))?;
+        sender.send(serde_json::to_value(payload)?).map_err(|_| anyhow!("Webhook batcher has stopped"))
+    }
+}
+
+// Closing the channel flushes whatever is still batched, wait for it so shutdown doesn't lose those detections.
+impl Drop for Batcher {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            info!("Flushing batched detections");
+            let _ = thread.join();
+        }
+    }
+}
+
+// Timing for merging repeats of a detection into one webhook: it's held for `window` after the first, and never sent
+// sooner than `min_interval` after the previous webhook for the same detection.
+#[derive(Clone, Copy)]
+struct CoalescePolicy {
+    window: Duration,
+    min_interval: Duration
+}
+
+// A detection waiting out its coalescing window, with how many times it has been seen.
+struct Coalescing {
+    payload: Value,
+    count: u64,
+    due: Instant
+}
+
+// Merges repeats of the same detection (the same event, and the same message for decoded ones) on a thread, sending
+// the first of them once due with a `count` of how many there were, so a flapping line sends one webhook rather than
+// a burst.
+struct Coalescer {
+    sender: Option<Sender<(u64, Value)>>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl Coalescer {
+    fn spawn(endpoints: Vec<Endpoint>, policy: CoalescePolicy) -> Self {
+        let (sender, receiver) = channel::<(u64, Value)>();
+        let thread = thread::spawn(move || {
+            let mut pending: HashMap<u64, Coalescing> = HashMap::new();
+            let mut last_sent: HashMap<u64, Instant> = HashMap::new();
+            loop {
+                let received = match pending.values().map(|coalescing| coalescing.due).min() {
+                    Some(due) => receiver.recv_timeout(due.saturating_duration_since(Instant::now())),
+                    None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+                };
+                let now = Instant::now();
+                let disconnected = match received {
+                    Ok((key, payload)) => {
+                        if let Some(coalescing) = pending.get_mut(&key) {
+                            coalescing.count += 1;
+                            continue;
+                        }
+                        let earliest = last_sent.get(&key).map_or(now, |&sent| sent + policy.min_interval);
+                        pending.insert(key, Coalescing { payload, count: 1, due: (now + policy.window).max(earliest) });
+                        continue;
+                    },
+                    Err(RecvTimeoutError::Timeout) => false,
+                    Err(RecvTimeoutError::Disconnected) => true
+                };
+
+                // Everything due is sent, or on shutdown everything still held.
+                let due: Vec<u64> = pending.iter()
+                    .filter(|(_, coalescing)| disconnected || coalescing.due <= now)
+                    .map(|(&key, _)| key)
+                    .collect();
+                for key in due {
+                    let Some(Coalescing { mut payload, count, .. }) = pending.remove(&key) else {
+                        continue;
+                    };
+                    payload["count"] = count.into();
+                    info!("Sending webhook for detection with count {}", count);
+                    let failed = send_all(&endpoints, &payload);
+                    if !failed.is_empty() {
+                        error!("Failed to send webhook for coalesced detections to {}", failed.join(", "));
+                    }
+                    last_sent.insert(key, Instant::now());
+                }
+                last_sent.retain(|_, sent| sent.elapsed() < policy.min_interval);
+                if disconnected {
+                    break;
+                }
+            }
+        });
+        Coalescer { sender: Some(sender), thread: Some(thread) }
+    }
+
+    fn push(&self, payload: &AlertPayload) -> Result<()> {
+        let sender = self.sender.as_ref().ok_or_else(|| anyhow!("Webhook coalescer has stopped"))?;
+        sender.send((content_key(payload), serde_json::to_value(payload)?))
+            .map_err(|_| anyhow!("Webhook coalescer has stopped"))
+    }
+}
+
+// As with the batcher, closing the channel sends whatever is still held.
+impl Drop for Coalescer {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            info!("Flushing coalesced detections");
+            let _ = thread.join();
+        }
+    }
+}
+
+// Where and how often heartbeats go, sent from the read loop outside the limiter, batcher and coalescer.
+struct Heartbeat {
+    endpoint: Endpoint,
+    interval: Duration,
+    last_sent: Option<Instant>
+}
+
+pub(crate) struct WebhookSink {
+    endpoints: Vec<Endpoint>,
+    panic_endpoints: Vec<Endpoint>,
+    limiter: Option<RateLimiter>,
+    dedup: Option<DedupCache>,
+    batcher: Option<Batcher>,
+    coalescer: Option<Coalescer>,
+    heartbeat: Option<Heartbeat>,
+    shared_agent: Option<Agent> // Kept across reloads when given, rather than built afresh from the config
+}
+
+// The webhook URL and every fan-out URL, in that order.
+fn build_endpoints(config: &Config, agent: Agent) -> Vec<Endpoint> {
+    let primary = Endpoint {
+        agent,
+        url: config.webhook_url.clone(),
+        key: config.webhook_key.clone(),
+        content_type: config.webhook_content_type.clone(),
+        format: config.payload_format,
+        compress_threshold: config.webhook_compress.then_some(config.webhook_compress_min_bytes),
+        template: config.webhook_template.clone(),
+        dead_letter_url: config.webhook_dlq_url.clone(),
+        retry: RetryPolicy {
+            attempts: config.webhook_max_retries,
+            base: config.webhook_retry_base,
+            max: config.webhook_retry_max
+        },
+        breaker: config.webhook_breaker_failures
+            .map(|failures| Arc::new(Mutex::new(CircuitBreaker::new(failures, config.webhook_breaker_cooldown)))),
+        spool: config.webhook_spool.as_deref().map(|path| Arc::new(Spool::new(path)))
+    };
+    // Every endpoint has a breaker of its own, as one being down says nothing of the others.
+    let fan_out = config.webhook_urls.iter().map(|target| Endpoint {
+        url: target.url.clone(),
+        key: target.key.clone().unwrap_or_else(|| config.webhook_key.clone()),
+        template: target.template.clone(),
+        breaker: config.webhook_breaker_failures
+            .map(|failures| Arc::new(Mutex::new(CircuitBreaker::new(failures, config.webhook_breaker_cooldown)))),
+        spool: None,
+        ..primary.clone()
+    });
+    std::iter::once(primary.clone()).chain(fan_out).collect()
+}
+
+// Send a `self_test` event to every webhook URL, once each without retries or the dead-letter URL, so a deployment check
+// hears straight away whether they accept it. Returns the URLs that didn't.
+pub(crate) fn send_test(config: &Config) -> Vec<String> {
+    let endpoints: Vec<Endpoint> = build_endpoints(config, build_agent(config.webhook_pool_size)).into_iter()
+        .map(|endpoint| Endpoint {
+            dead_letter_url: None,
+            retry: RetryPolicy { attempts: 1, ..endpoint.retry.clone() },
+            breaker: None,
+            spool: None,
+            ..endpoint
+        })
+        .collect();
+    let payload = AlertPayload {
+        event: "self_test",
+        priority: PRIORITY_NORMAL,
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        offset_ms: None,
+        frequency_hz: None,
+        level_dbfs: None,
+        audio_wav_base64: None,
+        contact_id: None,
+        fsk_data_hex: None,
+        caller: None
+    };
+    send_all(&endpoints, &payload).into_iter().map(String::from).collect()
+}
+
+impl WebhookSink {
+    pub fn new(config: &Config) -> Self {
+        WebhookSink::build(config, None)
+    }
+
+    // Deliver through an agent shared with other sinks, e.g. those of every modem in a fleet, pooling connections.
+    #[cfg(feature = "tokio")]
+    pub fn with_agent(config: &Config, agent: Agent) -> Self {
+        WebhookSink::build(config, Some(agent))
+    }
+
+    fn build(config: &Config, shared_agent: Option<Agent>) -> Self {
+        let agent = shared_agent.clone().unwrap_or_else(|| build_agent(config.webhook_pool_size));
+        let endpoints = build_endpoints(config, agent);
+        let panic_endpoints = match &config.panic_webhook_url {
+            Some(url) => vec![Endpoint { url: url.clone(), spool: None, ..endpoints[0].clone() }],
+            None => endpoints.clone()
+        };
+        // Heartbeats keep their own payload rather than the template, which could otherwise make them look like alarms.
+        // They get no share of the breaker either, so a receiver failing them can't open the circuit on alerts.
+        let heartbeat = config.heartbeat_interval.map(|interval| Heartbeat {
+            endpoint: Endpoint {
+                url: config.heartbeat_url.clone().unwrap_or_else(|| endpoints[0].url.clone()),
+                template: None,
+                dead_letter_url: None,
+                breaker: None,
+                spool: None,
+                ..endpoints[0].clone()
+            },
+            interval,
+            last_sent: None
+        });
+        WebhookSink {
+            batcher: config.batch_interval.map(|interval| Batcher::spawn(endpoints.clone(), interval)),
+            coalescer: (config.webhook_coalesce.is_some() || config.webhook_min_interval.is_some()).then(|| {
+                let policy = CoalescePolicy {
+                    window: config.webhook_coalesce.unwrap_or_default(),
+                    min_interval: config.webhook_min_interval.unwrap_or_default()
+                };
+                Coalescer::spawn(endpoints.clone(), policy)
+            }),
+            panic_endpoints,
+            endpoints,
+            heartbeat,
+            shared_agent,
+
+            // A limit of 0 leaves webhook delivery unlimited.
+            limiter: (config.max_events_per_min > 0).then(|| RateLimiter::per_minute(config.max_events_per_min)),
+            dedup: config.dedup_window.map(DedupCache::new)
+        }
+    }
+}
+
+impl DetectionSink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn on_detection(&mut self, payload: &AlertPayload) -> Result<()> {
+        // The answer tone only identifies the call type, it isn't an alarm.
+        if payload.event == Detection::AnswerTone.label() {
+            return Ok(());
+        }
+        // Ahead of the priority check, a retransmitted alarm is no more urgent the second time.
+        if self.dedup.as_mut().is_some_and(|dedup| dedup.is_duplicate(payload)) {
+            return Ok(());
+        }
+        // High priority alerts skip the limiter and batcher, going straight out (to their own URL when one is set).
+        if payload.priority == PRIORITY_HIGH {
+            info!("Sending webhook for high priority detection");
+            let failed = send_all(&self.panic_endpoints, payload);
+            if !failed.is_empty() {
+                return Err(anyhow!("Failed to send webhook for high priority detection to {}", failed.join(", ")));
+            }
+            return Ok(());
+        }
+        if let Some(limiter) = self.limiter.as_mut() {
+            if !limiter.try_acquire() {
+                return Ok(());
+            }
+        }
+
+        if let Some(batcher) = &self.batcher {
+            return batcher.push(payload);
+        }
+        if let Some(coalescer) = &self.coalescer {
+            return coalescer.push(payload);
+        }
+
+        info!("Sending webhook for detection");
+        let failed = send_all(&self.endpoints, payload);
+        if !failed.is_empty() {
+            return Err(anyhow!("Failed to send webhook for detection to {}", failed.join(", ")));
+        }
+        Ok(())
+    }
+
+    // The first heartbeat goes out straight away, then every interval after, whether or not it was accepted.
+    fn tick(&mut self, stats: &SessionStats) {
+        let Some(heartbeat) = self.heartbeat.as_mut() else {
+            return;
+        };
+        if heartbeat.last_sent.is_some_and(|sent| sent.elapsed() < heartbeat.interval) {
+            return;
+        }
+        heartbeat.last_sent = Some(Instant::now());
+        let payload = HeartbeatPayload {
+            event: "heartbeat",
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            uptime_secs: stats.uptime().as_secs(),
+            last_detection: stats.last_detection()
+                .map(|detection| detection.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs())
+        };
+        debug!("Sending heartbeat to {}", heartbeat.endpoint.url);
+        if !heartbeat.endpoint.send(&payload) {
+            warn!("Heartbeat to {} was not accepted", heartbeat.endpoint.url);
+        }
+    }
+
+    // Rebuilding flushes anything batched to the old endpoint before the new URL, key and limits apply. The heartbeat
+    // keeps its schedule.
+    fn reload(&mut self, config: &Config) {
+        let last_heartbeat = self.heartbeat.as_ref().and_then(|heartbeat| heartbeat.last_sent);
+        *self = WebhookSink::build(config, self.shared_agent.take());
+        if let Some(heartbeat) = self.heartbeat.as_mut() {
+            heartbeat.last_sent = last_heartbeat;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use flate2::read::GzDecoder;
+    use crate::config::test_config;
+    use crate::mock_http::MockServer;
+    use crate::wav::decode_wav;
+
+    fn event(detection: Detection) -> DetectionEvent {
+        DetectionEvent {
+            detection,
+            timestamp: SystemTime::now(),
+            audio: None,
+            contact_id: None,
+            fsk_data: None,
+            frequency: Some(1665.0),
+            offset: None,
+            level_dbfs: None,
+            caller: None
+        }
+    }
+
+    // The primary endpoint of a config delivering to the server, with one attempt unless the settings say otherwise.
+    fn endpoint(server: &MockServer, settings: &[(&str, &str)]) -> Endpoint {
+        let target = [("ALARM_WEBHOOK_URL", server.url.as_str()), ("ALARM_WEBHOOK_MAX_RETRIES", "1")];
+        let settings: Vec<_> = target.iter().chain(settings).copied().collect();
+        build_endpoints(&test_config(&settings).unwrap(), build_agent(1)).remove(0)
+    }
+
+    #[test]
+    fn attached_audio_decodes_to_the_recorded_wav() {
+        let audio: Vec<i16> = (0..4000).map(|i| (i % 200) as i16).collect();
+        let payload = AlertPayload::from_event(&DetectionEvent { audio: Some(audio.clone()), ..event(Detection::Trigger) }, 8000);
+        let wav = STANDARD.decode(payload.audio_wav_base64.unwrap()).unwrap();
+        assert_eq!(wav.len(), 44 + audio.len() * 2);
+        let decoded = decode_wav(&wav).unwrap();
+        assert_eq!((decoded.sample_rate, decoded.samples), (8000, audio));
+        assert!(AlertPayload::from_event(&event(Detection::Trigger), 8000).audio_wav_base64.is_none());
+    }
+
+    #[test]
+    fn compresses_only_large_bodies() {
+        let server = MockServer::start(200);
+        let endpoint = endpoint(&server, &[("ALARM_WEBHOOK_COMPRESS", "1"), ("ALARM_WEBHOOK_COMPRESS_MIN_BYTES", "1024")]);
+        let recording = DetectionEvent { audio: Some(vec![1000; 4000]), ..event(Detection::Trigger) };
+        let large = AlertPayload::from_event(&recording, 8000);
+        let small = AlertPayload::from_event(&event(Detection::Trigger), 8000);
+        assert!(endpoint.send(&large) && endpoint.send(&small));
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("content-encoding"), Some("gzip"));
+        let mut body = String::new();
+        GzDecoder::new(requests[0].body.as_slice()).read_to_string(&mut body).unwrap();
+        assert_eq!(body, serde_json::to_string(&large).unwrap());
+        assert_eq!(requests[1].header("content-encoding"), None);
+        assert_eq!(requests[1].json(), serde_json::to_value(&small).unwrap());
+    }
+
+    #[test]
+    fn consecutive_sends_reuse_the_connection() {
+        let server = MockServer::start(200);
+        let endpoint = endpoint(&server, &[]);
+        let payload = AlertPayload::from_event(&event(Detection::Trigger), 8000);
+        assert!((0..3).all(|_| endpoint.send(&payload)));
+        assert_eq!(server.requests().len(), 3);
+        assert_eq!(server.connections(), 1);
+    }
+
+    #[test]
+    fn form_content_type_sends_a_form_body() {
+        let server = MockServer::start(200);
+        let endpoint = endpoint(&server, &[("ALARM_WEBHOOK_CONTENT_TYPE", "application/x-www-form-urlencoded")]);
+        let caller = CallerId { number: "555 0100".to_string(), name: Some("Panel & Co".to_string()), date: None, time: None };
+        let detection = DetectionEvent {
+            timestamp: UNIX_EPOCH + Duration::from_secs(1000),
+            caller: Some(caller),
+            ..event(Detection::Trigger)
+        };
+        assert!(endpoint.send(&AlertPayload::from_event(&detection, 8000)));
+
+        let request = &server.requests()[0];
+        assert_eq!(request.header("content-type"), Some("application/x-www-form-urlencoded"));
+        assert_eq!(String::from_utf8_lossy(&request.body),
+            "caller.name=Panel+%26+Co&caller.number=555+0100&event=trigger&frequency_hz=1665.0&priority=normal&timestamp=1000");
+    }
+
+    #[test]
+    fn batcher_sends_the_interval_as_one_digest() {
+        let server = MockServer::start(200);
+        let batcher = Batcher::spawn(vec![endpoint(&server, &[])], Duration::from_millis(300));
+        for detection in [Detection::Trigger, Detection::Trigger, Detection::Panic] {
+            batcher.push(&AlertPayload::from_event(&event(detection), 8000)).unwrap();
+        }
+        thread::sleep(Duration::from_millis(100));
+        assert!(server.requests().is_empty(), "sent before the interval was up");
+
+        thread::sleep(Duration::from_millis(500));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        let batch = requests[0].json();
+        let events: Vec<_> = batch["events"].as_array().unwrap().iter().map(|event| event["event"].as_str()).collect();
+        assert_eq!(batch["event"].as_str(), Some("batch"));
+        assert_eq!(events, [Some("trigger"), Some("trigger"), Some("panic")]);
+    }
+
+    // A panel repeating one Contact ID report within the window is reported once, while a different report is not
+    // held back by it.
+    #[test]
+    fn repeated_contact_id_reports_are_sent_once() {
+        let server = MockServer::start(200);
+        let config = test_config(&[
+            ("ALARM_WEBHOOK_URL", &server.url),
+            ("ALARM_WEBHOOK_MAX_RETRIES", "1"),
+            ("ALARM_DEDUP_WINDOW_SECS", "60")
+        ]).unwrap();
+        let mut sink = WebhookSink::new(&config);
+        let (alarm, restore) = ("1234 18 1130 01 015 E", "1234 18 3130 01 015 C");
+        for message in [alarm, alarm, restore, alarm] {
+            let parsed = crate::contact_id::parse(message).ok();
+            let report = DetectionEvent { contact_id: parsed, ..event(Detection::ContactId) };
+            sink.on_detection(&AlertPayload::from_event(&report, 8000)).unwrap();
+        }
+        let sent: Vec<_> = server.requests().iter().map(|request| request.json()["contact_id"].clone()).collect();
+        let qualifiers: Vec<_> = sent.iter().map(|contact_id| contact_id["qualifier"].clone()).collect();
+        assert_eq!(qualifiers, ["new", "restore"]);
+    }
+
+    // Every endpoint gets the detection with its own key, and only the one that refused it is reported.
+    #[test]
+    fn fans_out_to_every_endpoint() {
+        let (primary, other) = (MockServer::start(200), MockServer::start(200));
+        let refused = "http://127.0.0.1:9/";
+        let config = test_config(&[
+            ("ALARM_WEBHOOK_URL", &primary.url),
+            ("ALARM_WEBHOOK_URLS", &format!("{}|other-key,{}", other.url, refused)),
+            ("ALARM_WEBHOOK_MAX_RETRIES", "1")
+        ]).unwrap();
+        let error = WebhookSink::new(&config).on_detection(&AlertPayload::from_event(&event(Detection::Trigger), 8000));
+        assert_eq!(error.err().unwrap().to_string(), format!("Failed to send webhook for detection to {}", refused));
+        for (server, key) in [(&primary, "test"), (&other, "other-key")] {
+            let requests = server.requests();
+            assert_eq!(requests.len(), 1);
+            assert_eq!(requests[0].header("authorization"), Some(key));
+            assert_eq!(requests[0].json()["event"], "trigger");
+        }
+    }
+
+    // Detections repeating within the window go out as one, counting them all, once the window has passed.
+    #[test]
+    fn coalesces_repeats_within_the_window() {
+        let server = MockServer::start(200);
+        let config = test_config(&[
+            ("ALARM_WEBHOOK_URL", &server.url),
+            ("ALARM_WEBHOOK_MAX_RETRIES", "1"),
+            ("ALARM_WEBHOOK_COALESCE_MS", "200")
+        ]).unwrap();
+        let mut sink = WebhookSink::new(&config);
+        let payload = AlertPayload::from_event(&event(Detection::Trigger), 8000);
+        for _ in 0..3 {
+            sink.on_detection(&payload).unwrap();
+        }
+        assert!(server.requests().is_empty(), "sent before the window closed");
+        thread::sleep(Duration::from_millis(500));
+        drop(sink);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].json()["event"], "trigger");
+        assert_eq!(requests[0].json()["count"], 3);
+    }
+
+    #[test]
+    fn high_priority_detections_bypass_the_rate_limit() {
+        let server = MockServer::start(200);
+        let config = test_config(&[
+            ("ALARM_WEBHOOK_URL", &server.url),
+            ("ALARM_WEBHOOK_MAX_RETRIES", "1"),
+            ("ALARM_MAX_EVENTS_PER_MIN", "1")
+        ]).unwrap();
+        let mut sink = WebhookSink::new(&config);
+        for detection in [Detection::Trigger, Detection::Trigger, Detection::Panic, Detection::Panic] {
+            sink.on_detection(&AlertPayload::from_event(&event(detection), 8000)).unwrap();
+        }
+        let sent: Vec<_> = server.requests().iter().map(|request| request.json()["priority"].clone()).collect();
+        assert_eq!(sent, ["normal", "high", "high"], "the second trigger should have been limited");
+    }
+
+    // Only once every retry to the primary has failed does the dead-letter URL get the payload, wrapped as a failure.
+    // Each retry waits twice as long as the last up to the cap, give or take the jitter.
+    #[test]
+    fn retry_delays_double_up_to_the_cap() {
+        let retry = RetryPolicy { attempts: 8, base: Duration::from_millis(500), max: Duration::from_secs(5) };
+        for (attempt, expected) in [(1, 500), (2, 1000), (3, 2000), (4, 4000), (5, 5000), (40, 5000)] {
+            let (delay, expected) = (retry.delay(attempt).as_secs_f64() * 1000.0, expected as f64);
+            assert!((delay - expected).abs() <= expected * RETRY_JITTER + 1e-6, "attempt {}: {} ms", attempt, delay);
+        }
+    }
+
+    // Only a 2xx is a delivery, anything else fails the send without taking the caller down with it.
+    #[test]
+    fn delivers_only_on_a_success_status() {
+        let payload = AlertPayload::from_event(&event(Detection::Trigger), 8000);
+        for (status, delivered) in [(200, true), (204, true), (404, false), (500, false)] {
+            let server = MockServer::start(status);
+            assert_eq!(endpoint(&server, &[]).send(&payload), delivered, "status {}", status);
+            let requests = server.requests();
+            assert_eq!(requests.len(), 1);
+            assert_eq!(requests[0].header("authorization"), Some("test"));
+            assert_eq!(requests[0].json()["event"], "trigger");
+        }
+    }
+
+    // The signature is the HMAC a receiver computes over the timestamp and the body as received, or as decompressed.
+    #[test]
+    fn signs_the_timestamped_body() {
+        assert_eq!(
+            signature("test", 1_700_000_000, r#"{"event":"trigger"}"#),
+            "t=1700000000,sha256=e9d746f97386c94b8e1a9f2fbcd1cbab772c4fa2890186974aa4c0eae68e56ce"
+        );
+
+        let server = MockServer::start(200);
+        let endpoint = endpoint(&server, &[("ALARM_WEBHOOK_COMPRESS", "1"), ("ALARM_WEBHOOK_COMPRESS_MIN_BYTES", "0")]);
+        assert!(endpoint.send(&AlertPayload::from_event(&event(Detection::Trigger), 8000)));
+        let request = &server.requests()[0];
+        let mut body = String::new();
+        GzDecoder::new(request.body.as_slice()).read_to_string(&mut body).unwrap();
+        let header = request.header("x-signature").expect("not signed");
+        let timestamp = header.strip_prefix("t=").and_then(|rest| rest.split(',').next()).unwrap().parse().unwrap();
+        assert_eq!(header, signature("test", timestamp, &body));
+    }
+
+    // Alerts sent while the circuit is open wait in the spool, and follow the probe that finds the endpoint back.
+    #[test]
+    fn an_open_circuit_spools_alerts_until_it_closes() {
+        let server = MockServer::start(500);
+        let path = std::env::temp_dir().join(format!("alarm-modem-{}-webhook-spool.jsonl", std::process::id()));
+        let endpoint = endpoint(&server, &[
+            ("ALARM_WEBHOOK_BREAKER_FAILURES", "1"),
+            ("ALARM_WEBHOOK_BREAKER_SECS", "1"),
+            ("ALARM_WEBHOOK_SPOOL", path.to_str().unwrap())
+        ]);
+        let alert = |ms| {
+            let detection = DetectionEvent { offset: Some(Duration::from_millis(ms)), ..event(Detection::Trigger) };
+            AlertPayload::from_event(&detection, 8000)
+        };
+
+        // The first failure opens the circuit, so neither alert is lost and only the first was attempted.
+        assert!(endpoint.send(&alert(1)) && endpoint.send(&alert(2)));
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        server.set_status(200);
+        sleep(Duration::from_millis(1100));
+        assert!(endpoint.send(&alert(3)));
+        let offsets: Vec<_> = server.requests().iter().map(|request| request.json()["offset_ms"].clone()).collect();
+        assert_eq!(offsets, [1, 3, 1, 2]);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn failed_deliveries_go_to_the_dead_letter_url() {
+        let (primary, dead_letter) = (MockServer::start(500), MockServer::start(200));
+        let endpoint = endpoint(&primary, &[
+            ("ALARM_WEBHOOK_DLQ_URL", dead_letter.url.as_str()),
+            ("ALARM_WEBHOOK_MAX_RETRIES", "3"),
+            ("ALARM_WEBHOOK_RETRY_BASE_MS", "1")
+        ]);
+        assert!(endpoint.send(&AlertPayload::from_event(&event(Detection::Trigger), 8000)));
+
+        assert_eq!(primary.requests().len(), 3);
+        let forwarded = dead_letter.requests();
+        assert_eq!(forwarded.len(), 1);
+        let wrapped = forwarded[0].json();
+        assert_eq!(wrapped["event"], "delivery_failed");
+        assert_eq!(wrapped["url"].as_str(), Some(primary.url.as_str()));
+        assert_eq!(wrapped["payload"]["event"], "trigger");
+    }
+
+    // A heartbeat has the webhook's retries, but a receiver failing it doesn't open the circuit on alerts.
+    #[test]
+    fn heartbeats_retry_beside_the_breaker() {
+        let server = MockServer::start(500);
+        let config = test_config(&[
+            ("ALARM_WEBHOOK_URL", &server.url),
+            ("ALARM_WEBHOOK_MAX_RETRIES", "3"),
+            ("ALARM_WEBHOOK_RETRY_BASE_MS", "1"),
+            ("ALARM_WEBHOOK_BREAKER_FAILURES", "1"),
+            ("ALARM_HEARTBEAT_SECS", "60")
+        ]).unwrap();
+        let mut sink = WebhookSink::new(&config);
+        sink.tick(&SessionStats::new(None));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests.iter().all(|request| request.json()["event"] == "heartbeat"));
+
+        server.set_status(200);
+        sink.on_detection(&AlertPayload::from_event(&event(Detection::Trigger), 8000)).unwrap();
+        assert_eq!(server.requests().len(), 4);
+    }
+}
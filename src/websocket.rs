@@ -0,0 +1,127 @@
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::thread::sleep;
+use std::time::Duration;
+use anyhow::Result;
+use log::{info, warn};
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Error, Message, WebSocket};
+use crate::audio::{Capture, PcmFormat};
+use crate::input::AudioSource;
+use crate::READ_TIMEOUT;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+// Binary PCM frames from an audio-over-WebSocket bridge, for modems that aren't attached locally. Text frames are
+// ignored, and a dropped connection is reopened rather than ending the input.
+pub(crate) struct WebSocketSource {
+    url: String,
+    capture: Capture,
+    socket: Option<WebSocket<MaybeTlsStream<TcpStream>>>,
+    partial: Vec<u8> // Bytes of a sample frame split across messages
+}
+
+impl WebSocketSource {
+    pub fn new(url: &str, capture: Capture) -> Self {
+        WebSocketSource { url: url.to_string(), capture, socket: None, partial: Vec::new() }
+    }
+
+    fn connect(&mut self) -> Option<&mut WebSocket<MaybeTlsStream<TcpStream>>> {
+        if self.socket.is_none() {
+            match tungstenite::connect(self.url.as_str()) {
+                Ok((socket, _)) => {
+                    // Time out reads so a quiet stream still lets the loop notice a shutdown.
+                    if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+                        if let Err(e) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
+                            warn!("Failed to set WebSocket read timeout: {}", e);
+                        }
+                    }
+                    info!("Connected to audio WebSocket {}", self.url);
+                    self.partial.clear();
+                    self.socket = Some(socket);
+                },
+                Err(e) => {
+                    warn!("Failed to connect to audio WebSocket {}, retrying in {:#?}: {}", self.url, RECONNECT_DELAY, e);
+                    sleep(RECONNECT_DELAY);
+                }
+            }
+        }
+        self.socket.as_mut()
+    }
+}
+
+impl AudioSource for WebSocketSource {
+    fn name(&self) -> &'static str {
+        "WebSocket"
+    }
+
+    fn format(&self) -> PcmFormat {
+        self.capture.format
+    }
+
+    fn next_samples(&mut self) -> Result<Option<Vec<i16>>> {
+        let Some(socket) = self.connect() else {
+            return Ok(Some(Vec::new()));
+        };
+
+        let data = match socket.read() {
+            Ok(Message::Binary(data)) => data,
+            Ok(_) => return Ok(Some(Vec::new())),
+            Err(Error::Io(ref e)) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(Some(Vec::new())),
+            Err(e) => {
+                warn!("Audio WebSocket {} closed: {}", self.url, e);
+                self.socket = None;
+                return Ok(Some(Vec::new()));
+            }
+        };
+
+        self.partial.extend_from_slice(&data);
+        Ok(Some(self.capture.take_samples(&mut self.partial)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use crate::audio::Detector;
+    use crate::config::test_config;
+
+    #[test]
+    fn tone_frames_make_a_detection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}/", listener.local_addr().unwrap());
+        let bytes: Vec<u8> = (0..8192)
+            .map(|i| (1000.0 * (2.0 * std::f32::consts::PI * 1665.0 * i as f32 / 8000.0).sin()) as i16)
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        let bridge = thread::spawn(move || {
+            let mut socket = tungstenite::accept(listener.accept().unwrap().0).unwrap();
+            socket.send(Message::text("hello")).unwrap();
+            // Odd sized frames, so samples are split across messages.
+            for frame in bytes.chunks(1001) {
+                socket.send(Message::binary(frame.to_vec())).unwrap();
+            }
+            socket
+        });
+
+        let config = Arc::new(test_config(&[
+            ("ALARM_TONE_MIN_POWER", "1e6"),
+            ("ALARM_TONE_MAX_POWER", "1e20"),
+            ("ALARM_ANSWER_TONE_MIN_POWER", "1e10"),
+            ("ALARM_MIN_GAP_MS", "100")
+        ]).unwrap());
+        let mut source = WebSocketSource::new(&url, Capture::new(PcmFormat::SignedLe16, &config));
+        let mut samples = Vec::new();
+        while samples.len() < 8192 {
+            samples.extend(source.next_samples().unwrap().unwrap());
+        }
+        assert_eq!(samples.len(), 8192);
+
+        let events = Detector::new(config).unwrap().process(&samples);
+        assert!(events.iter().any(|event| event.detection.label() == "trigger"), "no detection from the stream");
+        drop(bridge.join().unwrap());
+    }
+}